@@ -0,0 +1,169 @@
+"""Writes the small sample files the decoding tests load
+
+    python3 fixtures/generate.py fixtures
+
+FLAC and AIFF hold a tone, the OGG and MP3 are silent frames built by hand
+so no encoder is needed to make them
+"""
+import math, struct, os, sys
+out = sys.argv[1] if len(sys.argv) > 1 else os.path.dirname(os.path.abspath(__file__))
+os.makedirs(out, exist_ok=True)
+
+def tone(frames, channels, rate, amp=0.5):
+    return [[int(amp * 32767 * math.sin(2 * math.pi * 440 * (c + 1) * i / rate)) for c in range(channels)] for i in range(frames)]
+
+# ---------- MSB-first bit writer (FLAC, MP3)
+class MsbWriter:
+    def __init__(self): self.bits = []
+    def put(self, v, n):
+        for i in range(n - 1, -1, -1): self.bits.append((v >> i) & 1)
+    def align(self):
+        while len(self.bits) % 8: self.bits.append(0)
+    def bytes(self):
+        self.align()
+        return bytes(int(''.join(map(str, self.bits[i:i + 8])), 2) for i in range(0, len(self.bits), 8))
+
+def crc8(data):
+    c = 0
+    for b in data:
+        c ^= b
+        for _ in range(8):
+            c = ((c << 1) ^ 0x07) & 0xFF if c & 0x80 else (c << 1) & 0xFF
+    return c
+
+def crc16(data):
+    c = 0
+    for b in data:
+        c ^= b << 8
+        for _ in range(8):
+            c = ((c << 1) ^ 0x8005) & 0xFFFF if c & 0x8000 else (c << 1) & 0xFFFF
+    return c
+
+def flac(path, frames, channels, rate, block=256):
+    samples = tone(frames, channels, rate)
+    w = MsbWriter()
+    w.put(0, 1); w.put(0, 7); w.put(34, 24)  # last block flag set below
+    si = MsbWriter()
+    si.put(block, 16); si.put(block, 16); si.put(0, 24); si.put(0, 24)
+    si.put(rate, 20); si.put(channels - 1, 3); si.put(15, 5); si.put(frames, 36)
+    si.put(0, 128)
+    data = bytearray(b'fLaC')
+    data += bytes([0x80, 0, 0, 34]) + si.bytes()
+    for n, start in enumerate(range(0, frames, block)):
+        chunk = samples[start:start + block]
+        h = MsbWriter()
+        h.put(0x3FFE, 14); h.put(0, 1); h.put(0, 1)
+        h.put(0b0111, 4); h.put(0, 4)
+        h.put(channels - 1, 4); h.put(0b100, 3); h.put(0, 1)
+        assert n < 128
+        h.put(n, 8)
+        h.put(len(chunk) - 1, 16)
+        hb = h.bytes()
+        f = MsbWriter()
+        for c in range(channels):
+            f.put(0, 1); f.put(1, 6); f.put(0, 1)
+            for s in chunk:
+                f.put(s[c] & 0xFFFF, 16)
+        frame = hb + bytes([crc8(hb)]) + f.bytes()
+        frame += struct.pack('>H', crc16(frame))
+        data += frame
+    open(path, 'wb').write(data)
+
+def ext80(v):
+    exp = int(math.floor(math.log2(v)))
+    mant = int(v / 2 ** exp * (1 << 63))
+    return struct.pack('>HQ', exp + 16383, mant)
+
+def aiff(path, frames, channels, rate):
+    samples = tone(frames, channels, rate)
+    pcm = b''.join(struct.pack('>h', s) for fr in samples for s in fr)
+    comm = struct.pack('>hIh', channels, frames, 16) + ext80(rate)
+    ssnd = struct.pack('>II', 0, 0) + pcm
+    body = b'AIFF' + b'COMM' + struct.pack('>I', len(comm)) + comm + b'SSND' + struct.pack('>I', len(ssnd)) + ssnd
+    open(path, 'wb').write(b'FORM' + struct.pack('>I', len(body)) + body)
+
+def mp3(path, nframes):
+    # MPEG-1 layer III, 128kbps 44.1kHz mono, every granule silent
+    header = bytes([0xFF, 0xFB, 0x90, 0xC0])
+    size = 144 * 128000 // 44100
+    frame = header + bytes(size - 4)
+    open(path, 'wb').write(frame * nframes)
+
+# ---------- LSB-first bit writer (Ogg Vorbis)
+class LsbWriter:
+    def __init__(self): self.bits = []
+    def put(self, v, n):
+        for i in range(n): self.bits.append((v >> i) & 1)
+    def put_bytes(self, b):
+        for x in b: self.put(x, 8)
+    def bytes(self):
+        while len(self.bits) % 8: self.bits.append(0)
+        return bytes(sum(self.bits[i + j] << j for j in range(8)) for i in range(0, len(self.bits), 8))
+
+def ogg_crc(data):
+    c = 0
+    for b in data:
+        c ^= b << 24
+        for _ in range(8):
+            c = ((c << 1) ^ 0x04C11DB7) & 0xFFFFFFFF if c & 0x80000000 else (c << 1) & 0xFFFFFFFF
+    return c
+
+def ogg_page(packets, flags, granule, seq, serial=0x72646d):
+    lacing = []
+    for p in packets:
+        n = len(p)
+        while n >= 255:
+            lacing.append(255); n -= 255
+        lacing.append(n)
+    hdr = b'OggS' + bytes([0, flags]) + struct.pack('<qIII', granule, serial, seq, 0) + bytes([len(lacing)]) + bytes(lacing)
+    page = bytearray(hdr + b''.join(packets))
+    struct.pack_into('<I', page, 22, ogg_crc(page))
+    return bytes(page)
+
+def vorbis(path, packets, channels, rate):
+    ident = LsbWriter()
+    ident.put(1, 8); ident.put_bytes(b'vorbis')
+    ident.put(0, 32); ident.put(channels, 8); ident.put(rate, 32)
+    ident.put(0, 32); ident.put(0, 32); ident.put(0, 32)
+    ident.put(8, 4); ident.put(11, 4); ident.put(1, 1)
+    comment = LsbWriter()
+    vendor = b'rdum fixtures'
+    comment.put(3, 8); comment.put_bytes(b'vorbis')
+    comment.put(len(vendor), 32); comment.put_bytes(vendor); comment.put(0, 32); comment.put(1, 1)
+    setup = LsbWriter()
+    setup.put(5, 8); setup.put_bytes(b'vorbis')
+    # one codebook of two 1 bit entries
+    setup.put(0, 8)
+    setup.put(0x564342, 24); setup.put(1, 16); setup.put(2, 24)
+    setup.put(0, 1); setup.put(0, 1); setup.put(0, 5); setup.put(0, 5); setup.put(0, 4)
+    # time domain placeholders
+    setup.put(0, 6); setup.put(0, 16)
+    # a floor 1 with no partitions
+    setup.put(0, 6); setup.put(1, 16)
+    setup.put(0, 5); setup.put(1, 2); setup.put(7, 4)
+    # a residue 0 with one class and no books
+    setup.put(0, 6); setup.put(0, 16)
+    setup.put(0, 24); setup.put(128, 24); setup.put(31, 24); setup.put(0, 6); setup.put(0, 8)
+    setup.put(0, 3); setup.put(0, 1)
+    # one mapping onto them
+    setup.put(0, 6); setup.put(0, 16); setup.put(0, 1); setup.put(0, 1); setup.put(0, 2)
+    setup.put(0, 8); setup.put(0, 8); setup.put(0, 8)
+    # one mode of short blocks
+    setup.put(0, 6); setup.put(0, 1); setup.put(0, 16); setup.put(0, 16); setup.put(0, 8)
+    setup.put(1, 1)
+    audio = []
+    for _ in range(packets):
+        p = LsbWriter()
+        p.put(0, 1)
+        for _ in range(channels):
+            p.put(0, 1)
+        audio.append(p.bytes())
+    data = ogg_page([ident.bytes()], 0x02, 0, 0)
+    data += ogg_page([comment.bytes(), setup.bytes()], 0x00, 0, 1)
+    data += ogg_page(audio, 0x04, (packets - 1) * 128, 2)
+    open(path, 'wb').write(data)
+
+flac(os.path.join(out, 'tone.flac'), 1024, 2, 44100)
+aiff(os.path.join(out, 'tone.aiff'), 500, 1, 22050)
+vorbis(os.path.join(out, 'silence.ogg'), 41, 2, 48000)
+mp3(os.path.join(out, 'silence.mp3'), 4)
@@ -0,0 +1,731 @@
+use rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::cpal::{self, BufferSize, FromSample, SizedSample, SupportedBufferSize};
+use rodio::dynamic_mixer::{self, DynamicMixer, DynamicMixerController};
+use crate::recorder::{RecordTap, Recording};
+use crate::reverb::{Freeverb, Reverb};
+use crate::sequencer::Division;
+use crate::voices::{VoiceHandle, VoiceMixer, VoiceStart, Voices};
+use rodio::Source;
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering};
+use std::sync::{mpsc, Arc};
+use std::time::{Duration, Instant};
+
+/// The device stopping pulling samples for this long means the stream is dead
+const STALL_TIMEOUT: Duration = Duration::from_millis(500);
+/// Wait before the first attempt to get the device back, doubled after each
+/// failed attempt up to the max
+const RETRY_START: Duration = Duration::from_millis(500);
+const RETRY_MAX: Duration = Duration::from_secs(8);
+/// The latency test click, a short burst of a sine
+const PROBE_RATE: u32 = 44100;
+const PROBE_FREQ: f32 = 1000.0;
+const PROBE_LEN: usize = PROBE_RATE as usize / 100;
+/// Length of an analog sync pulse
+const SYNC_PULSE: Duration = Duration::from_millis(5);
+/// Longest echo the delay line holds, longer times are cut down to it
+const MAX_DELAY_SECS: f64 = 4.0;
+pub const MAX_DELAY_FEEDBACK: u8 = 90;
+/// Master volume changes glide over about this long so they don't click
+const MASTER_GLIDE_SECS: f32 = 0.01;
+
+/// How much audio the device is handed at a time
+///
+/// Every sound waits at least a buffer before it's heard, so smaller is
+/// tighter to play along with. But the whole buffer has to be mixed before
+/// the device runs out, and on a Pi a busy moment can miss that with a
+/// small one, which clicks. Safe is the one to start with there and
+/// low latency is worth trying once the kit is loaded and nothing else
+/// heavy is running
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum BufferMode {
+    /// 1024 frames, about 23ms at 44.1kHz
+    #[default]
+    Safe = 0,
+    /// 128 frames, about 3ms at 44.1kHz
+    LowLatency = 1,
+}
+
+impl BufferMode {
+    fn frames(self) -> u32 {
+        match self {
+            BufferMode::Safe => 1024,
+            BufferMode::LowLatency => 128,
+        }
+    }
+}
+
+impl From<i64> for BufferMode {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => BufferMode::LowLatency,
+            _ => BufferMode::Safe,
+        }
+    }
+}
+
+impl From<u32> for BufferMode {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => BufferMode::LowLatency,
+            _ => BufferMode::Safe,
+        }
+    }
+}
+
+/// Buffer sizes a stream can be asked for in frames, outside this is
+/// either more than a device will take or a sure way to get dropouts
+pub const MIN_BUFFER_FRAMES: u32 = 16;
+pub const MAX_BUFFER_FRAMES: u32 = 8192;
+/// Sample rates a stream can be asked for
+pub const MIN_SAMPLE_RATE: u32 = 8000;
+pub const MAX_SAMPLE_RATE: u32 = 192000;
+
+/// What the output stream is asked for, the device has the final say and
+/// what it actually opened with is on the AudioOutput
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct StreamRequest {
+    pub buffer_mode: BufferMode,
+    /// exact frames per buffer, over the buffer mode's when set
+    pub buffer_frames: Option<u32>,
+    /// the device's default rate when unset
+    pub sample_rate: Option<u32>,
+    /// fewest output channels, for tracks routed past the first pair. The
+    /// device's default is used when it has enough
+    pub channels: Option<u16>,
+}
+
+impl StreamRequest {
+    fn frames(&self) -> u32 {
+        self.buffer_frames.unwrap_or(self.buffer_mode.frames())
+    }
+
+    /// The device's config at the rate and channels asked for, or its
+    /// default config if neither is asked for or the device can't do them
+    fn config_for(&self, device: &cpal::Device) -> Result<cpal::SupportedStreamConfig, Box<dyn Error>> {
+        let default = device.default_output_config()?;
+        let channels = self.channels.unwrap_or(0).max(default.channels());
+        if self.sample_rate.is_none() && channels == default.channels() {
+            return Ok(default);
+        }
+        let rate = self.sample_rate.unwrap_or(default.sample_rate().0);
+        // the fewest channels that are enough, a stream as wide as the
+        // device goes costs more to mix
+        let supported = device.supported_output_configs()?
+            .filter(|range| {
+                range.channels() >= channels
+                    && range.sample_format() == default.sample_format()
+                    && (range.min_sample_rate().0..=range.max_sample_rate().0).contains(&rate)
+            })
+            .min_by_key(|range| range.channels());
+        match supported {
+            Some(range) => Ok(range.with_sample_rate(cpal::SampleRate(rate))),
+            None => {
+                println!("Output can't run {} channels at {}Hz, using its default of {} at {}Hz",
+                    channels, rate, default.channels(), default.sample_rate().0);
+                Ok(default)
+            }
+        }
+    }
+}
+
+/// Analog sync pulses for gear with a sync in jack, like pocket operators
+/// and volcas, sent on one channel of the output in place of the mix
+///
+/// The channel is cabled to the sync in and whatever's plugged into the
+/// rest gets the drums as usual
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct SyncOut {
+    pub enabled: bool,
+    /// output channel counting from 0, the left
+    pub channel: u16,
+    /// pulses per quarter note, pocket operators and volcas take 2
+    pub ppqn: u8,
+}
+
+impl Default for SyncOut {
+    fn default() -> Self {
+        SyncOut { enabled: false, channel: 0, ppqn: 2 }
+    }
+}
+
+/// Tempo synced echo shared by every track, tracks pick how much of
+/// themselves they send to it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Delay {
+    /// echo time as a note length, an eighth echoes twice a beat in 4/4
+    pub division: Division,
+    /// percent of each echo fed back into the next, 0 is a single echo
+    pub feedback_pct: u8,
+}
+
+impl Default for Delay {
+    fn default() -> Self {
+        Delay { division: Division::E, feedback_pct: 35 }
+    }
+}
+
+impl Delay {
+    /// Echo time at the tempo, bar is the length of a bar
+    pub fn time(&self, bar: Duration) -> Duration {
+        bar.div_f64(self.division as u8 as f64).min(Duration::from_secs_f64(MAX_DELAY_SECS))
+    }
+
+    pub fn feedback(&self) -> f32 {
+        self.feedback_pct.min(MAX_DELAY_FEEDBACK) as f32 / 100.0
+    }
+}
+
+/// Ring buffer of the delay bus, the output stream and exports each run one
+pub struct DelayLine {
+    /// interleaved, long enough for the longest echo
+    buffer: Vec<f32>,
+    pos: usize,
+}
+
+impl DelayLine {
+    pub fn new(sample_rate: u32, channels: u16) -> Self {
+        DelayLine {
+            buffer: vec![0.0; (MAX_DELAY_SECS * sample_rate as f64) as usize * channels as usize],
+            pos: 0,
+        }
+    }
+
+    /// Takes the next sample sent to the bus and returns the echo to mix
+    /// in, len is the echo time in samples across all channels
+    pub fn process(&mut self, send: f32, len: usize, feedback: f32) -> f32 {
+        let len = len.clamp(1, self.buffer.len());
+        let echo = self.buffer[(self.pos + self.buffer.len() - len) % self.buffer.len()];
+        self.buffer[self.pos] = send + echo * feedback;
+        self.pos = (self.pos + 1) % self.buffer.len();
+        echo
+    }
+}
+
+/// Effect buses tracks send copies of their hits to, only the effect
+/// of what's sent is heard
+#[derive(Debug, Clone, Copy)]
+pub enum SendBus {
+    Delay = 0,
+    Reverb = 1,
+}
+
+pub const SEND_BUSES: usize = 2;
+
+/// Sources outside the tracks, like the latency test click, are added to
+/// this and summed into the stream
+type Mixer = Arc<DynamicMixerController<f32>>;
+
+/// Recordings of the mix hand their tap to the stream through this
+type Taps = mpsc::Sender<RecordTap>;
+
+/// Stands in for rodio's stream handle, voices started here play on our stream
+#[derive(Clone)]
+pub struct AudioHandle {
+    mixer: Mixer,
+    voices: Voices,
+    /// recordings of the mix start by handing the stream a tap
+    taps: Taps,
+    channels: u16,
+    sample_rate: u32,
+    stats: Arc<StreamStats>,
+}
+
+impl AudioHandle {
+    /// Starts a voice in the voice mixer, the handle changes it as it plays
+    ///
+    /// While a pulse is being scheduled the voice is timed from the frame
+    /// the pulse is due at rather than from when the mixer gets to it
+    pub fn play<S: Source<Item = f32> + Send + 'static>(&self, source: S, start: VoiceStart) -> VoiceHandle {
+        self.voices.play(source, start, self.pulse_frame())
+    }
+
+    /// Frames the device has pulled since the stream opened, the clock
+    /// pulses are scheduled against
+    pub fn frames_pulled(&self) -> u64 {
+        self.stats.pulled.load(Ordering::Relaxed)
+    }
+
+    /// Frame the mixer would be on if it ran steadily rather than a buffer
+    /// at a time, worked out from the time since the last buffer
+    pub fn stream_position(&self) -> f64 {
+        let stats = &self.stats;
+        let pulled = stats.pulled.load(Ordering::Relaxed);
+        let buffer = stats.last_buffer.load(Ordering::Relaxed);
+        let since_us = (stats.origin.elapsed().as_micros() as u64).saturating_sub(stats.last_callback_us.load(Ordering::Relaxed));
+        let since = since_us as f64 * self.sample_rate as f64 / 1_000_000.0;
+        // stops a buffer on if the stream does
+        pulled.saturating_sub(buffer) as f64 + since.min(buffer as f64)
+    }
+
+    /// Frames the device pulled in its last buffer, 0 until it's pulled one
+    pub fn last_buffer_frames(&self) -> u64 {
+        self.stats.last_buffer.load(Ordering::Relaxed)
+    }
+
+    /// Sets the frame the pulse being scheduled is due at, voices and sync
+    /// pulses started until it's cleared with None play from there
+    pub fn set_pulse_frame(&self, frame: Option<u64>) {
+        self.stats.pulse_frame.store(frame.unwrap_or(u64::MAX), Ordering::Relaxed);
+    }
+
+    fn pulse_frame(&self) -> Option<u64> {
+        match self.stats.pulse_frame.load(Ordering::Relaxed) {
+            u64::MAX => None,
+            frame => Some(frame),
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    pub fn channels(&self) -> u16 {
+        self.channels
+    }
+
+    /// Starts writing the mix to recordings/{fname}.wav from the next
+    /// buffer, at the stream's rate and channels
+    pub fn start_recording(&self, fname: &str) -> Result<Recording, Box<dyn Error>> {
+        let (recording, tap) = Recording::start(fname, self.channels, self.sample_rate)?;
+        self.taps.send(tap).map_err(|_| "audio output is closed")?;
+        Ok(recording)
+    }
+
+    /// Plays a test click and times it from now until the device says it
+    /// comes out, the result shows up in measured_latency
+    pub fn measure_latency(&self) {
+        let stats = &self.stats;
+        stats.probe_scheduled_us.store(stats.origin.elapsed().as_micros() as u64, Ordering::Relaxed);
+        stats.probe_started.store(false, Ordering::Relaxed);
+        stats.probing.store(true, Ordering::Relaxed);
+        self.mixer.add(Probe { stats: stats.clone(), idx: 0 });
+    }
+
+    /// The tail already in the room carries on with the new settings
+    pub fn set_reverb(&self, reverb: Reverb) {
+        self.stats.reverb.store(reverb.clamped().to_bits(), Ordering::Relaxed);
+    }
+
+    /// Linear gain on the whole mix, effects included
+    pub fn set_master_gain(&self, gain: f32) {
+        self.stats.master_gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Sets the echo time and how much of each echo feeds the next, the
+    /// echoes already in the line play out at the new time
+    pub fn set_delay(&self, time: Duration, feedback: f32) {
+        self.stats.delay_us.store(time.as_micros() as u64, Ordering::Relaxed);
+        self.stats.delay_feedback.store(feedback.to_bits(), Ordering::Relaxed);
+    }
+
+    /// Gives a channel over to sync pulses, None puts the mix back on it
+    pub fn set_sync_channel(&self, channel: Option<u16>) {
+        let channel = channel.map_or(0, |channel| channel as u32 + 1);
+        self.stats.sync_channel.store(channel, Ordering::Relaxed);
+    }
+
+    /// Starts a sync pulse on the frame the pulse being scheduled is due
+    /// at, or at the start of the next buffer outside of one
+    pub fn sync_pulse(&self) {
+        let frame = self.pulse_frame().unwrap_or(self.frames_pulled());
+        self.stats.sync_at.store(frame, Ordering::Relaxed);
+    }
+
+    /// Result of the last latency test, None until one has finished
+    ///
+    /// Without playback timestamps from the backend this leaves out the
+    /// device's own latency
+    pub fn measured_latency(&self) -> Option<Duration> {
+        match self.stats.probe_result_us.load(Ordering::Relaxed) {
+            0 => None,
+            us => Some(Duration::from_micros(us)),
+        }
+    }
+}
+
+/// What the stream callback reports back
+struct StreamStats {
+    /// frames the device has pulled, it stops going up when the stream dies
+    pulled: AtomicU64,
+    last_buffer: AtomicU64,
+    /// when the last buffer was asked for
+    last_callback_us: AtomicU64,
+    /// frame the pulse being scheduled is due at, u64::MAX outside of one
+    pulse_frame: AtomicU64,
+    /// time from the callback to the audio it wrote being played
+    latency_us: AtomicU64,
+    /// the device went away
+    failed: AtomicBool,
+    /// times below are in us since this
+    origin: Instant,
+    /// a latency test is waiting for its click to be mixed
+    probing: AtomicBool,
+    probe_scheduled_us: AtomicU64,
+    /// set by the click when the mixer pulls its first sample
+    probe_started: AtomicBool,
+    probe_result_us: AtomicU64,
+    /// channel given over to sync pulses plus one, 0 is none
+    sync_channel: AtomicU32,
+    /// frame the last sync pulse asked for starts on, u64::MAX before any
+    sync_at: AtomicU64,
+    /// echo time of the delay bus
+    delay_us: AtomicU64,
+    /// bits of the f32 feedback
+    delay_feedback: AtomicU32,
+    /// reverb settings packed by Reverb::to_bits
+    reverb: AtomicU32,
+    /// bits of the f32 master gain
+    master_gain: AtomicU32,
+}
+
+impl Default for StreamStats {
+    fn default() -> Self {
+        StreamStats {
+            pulled: AtomicU64::new(0),
+            last_buffer: AtomicU64::new(0),
+            last_callback_us: AtomicU64::new(0),
+            pulse_frame: AtomicU64::new(u64::MAX),
+            latency_us: AtomicU64::new(0),
+            failed: AtomicBool::new(false),
+            origin: Instant::now(),
+            probing: AtomicBool::new(false),
+            probe_scheduled_us: AtomicU64::new(0),
+            probe_started: AtomicBool::new(false),
+            probe_result_us: AtomicU64::new(0),
+            sync_channel: AtomicU32::new(0),
+            sync_at: AtomicU64::new(u64::MAX),
+            delay_us: AtomicU64::new(0),
+            delay_feedback: AtomicU32::new(0),
+            reverb: AtomicU32::new(Reverb::default().to_bits()),
+            master_gain: AtomicU32::new(1.0f32.to_bits()),
+        }
+    }
+}
+
+/// The latency test click, it flags when the mixer first pulls from it
+/// so the callback can tell where in the buffer it landed
+struct Probe {
+    stats: Arc<StreamStats>,
+    idx: usize,
+}
+
+impl Iterator for Probe {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.idx >= PROBE_LEN {
+            return None;
+        }
+        if self.idx == 0 {
+            self.stats.probe_started.store(true, Ordering::Relaxed);
+        }
+        let t = self.idx as f32 / PROBE_RATE as f32;
+        self.idx += 1;
+        Some((t * PROBE_FREQ * std::f32::consts::TAU).sin() * 0.5)
+    }
+}
+
+impl Source for Probe {
+    fn current_frame_len(&self) -> Option<usize> {
+        Some(PROBE_LEN - self.idx)
+    }
+
+    fn channels(&self) -> u16 {
+        1
+    }
+
+    fn sample_rate(&self) -> u32 {
+        PROBE_RATE
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        Some(Duration::from_secs_f32(PROBE_LEN as f32 / PROBE_RATE as f32))
+    }
+}
+
+/// The audio system streams are opened on
+///
+/// Built with the jack feature that's a JACK server when one's running,
+/// for its small buffers and for routing the output anywhere in the JACK
+/// graph, and the system's default otherwise
+pub fn host() -> cpal::Host {
+    #[cfg(feature = "jack")]
+    match cpal::host_from_id(cpal::HostId::Jack) {
+        Ok(host) => return host,
+        Err(e) => println!("JACK isn't available, using the default audio host: {}", e),
+    }
+    cpal::default_host()
+}
+
+/// Names of the output devices on the system
+pub fn list_devices() -> Result<Vec<String>, Box<dyn Error>> {
+    Ok(host().output_devices()?
+        .filter_map(|device| device.name().ok())
+        .collect())
+}
+
+/// The output stream and a watch on whether it's still running
+///
+/// We open the stream ourselves rather than through rodio so the buffer
+/// size can be set. It can't leave the thread it was opened on, so this
+/// lives with the sequencer on the main thread and only the handle goes
+/// in the context
+pub struct AudioOutput {
+    _stream: cpal::Stream,
+    pub handle: AudioHandle,
+    pub device_name: String,
+    pub request: StreamRequest,
+    /// frames per buffer the stream opened with, None if the device
+    /// wouldn't take a fixed size and picked its own
+    pub buffer_frames: Option<u32>,
+    /// rate the stream opened at
+    pub sample_rate: u32,
+    stats: Arc<StreamStats>,
+    last_pulled: u64,
+    last_progress: Instant,
+}
+
+impl AudioOutput {
+    pub fn open_default(request: StreamRequest) -> Result<Self, Box<dyn Error>> {
+        let device = host().default_output_device()
+            .ok_or("no audio output device")?;
+        Self::open_device(device, request)
+    }
+
+    /// Opens the output device with the name given
+    pub fn open(name: &str, request: StreamRequest) -> Result<Self, Box<dyn Error>> {
+        let device = host().output_devices()?
+            .find(|device| device.name().is_ok_and(|device_name| device_name == name))
+            .ok_or(format!("no audio output named {}", name))?;
+        Self::open_device(device, request)
+    }
+
+    fn open_device(device: cpal::Device, request: StreamRequest) -> Result<Self, Box<dyn Error>> {
+        let device_name = device.name()?;
+        let supported = request.config_for(&device)?;
+        let frames = match supported.buffer_size() {
+            SupportedBufferSize::Range { min, max } => request.frames().clamp(*min, *max),
+            SupportedBufferSize::Unknown => request.frames(),
+        };
+        let mut config = supported.config();
+        config.buffer_size = BufferSize::Fixed(frames);
+        let stats = Arc::new(StreamStats::default());
+
+        // Not every backend takes a fixed size, the device's own is better than nothing
+        let (stream, mixer, voices, taps, buffer_frames) = match build_stream(&device, &config, supported.sample_format(), stats.clone()) {
+            Ok((stream, mixer, voices, taps)) => (stream, mixer, voices, taps, Some(frames)),
+            Err(e) => {
+                println!("Failed to open {} with {} frame buffers, using its default: {}", device_name, frames, e);
+                config.buffer_size = BufferSize::Default;
+                let (stream, mixer, voices, taps) = build_stream(&device, &config, supported.sample_format(), stats.clone())?;
+                (stream, mixer, voices, taps, None)
+            }
+        };
+        stream.play()?;
+        Ok(AudioOutput {
+            _stream: stream,
+            handle: AudioHandle {
+                mixer,
+                voices,
+                taps,
+                channels: config.channels,
+                sample_rate: config.sample_rate.0,
+                stats: stats.clone(),
+            },
+            device_name,
+            request,
+            buffer_frames,
+            sample_rate: config.sample_rate.0,
+            stats,
+            last_pulled: 0,
+            last_progress: Instant::now(),
+        })
+    }
+
+    /// False once the device has gone or stopped pulling samples, which is
+    /// what an unplugged device looks like from here
+    pub fn is_alive(&mut self) -> bool {
+        let pulled = self.stats.pulled.load(Ordering::Relaxed);
+        if pulled != self.last_pulled {
+            self.last_pulled = pulled;
+            self.last_progress = Instant::now();
+        }
+        !self.stats.failed.load(Ordering::Relaxed) && self.last_progress.elapsed() < STALL_TIMEOUT
+    }
+
+    /// Output latency as last reported by the device
+    pub fn latency(&self) -> Duration {
+        Duration::from_micros(self.stats.latency_us.load(Ordering::Relaxed))
+    }
+}
+
+/// Opens the stream with the track voices and the mix of everything else
+/// summed into it
+fn build_stream(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    sample_format: cpal::SampleFormat,
+    stats: Arc<StreamStats>,
+) -> Result<(cpal::Stream, Mixer, Voices, Taps), Box<dyn Error>> {
+    let (mixer, mixer_rx) = dynamic_mixer::mixer::<f32>(config.channels, config.sample_rate.0);
+    let (voices, voice_mixer) = VoiceMixer::new(config.channels, config.sample_rate.0);
+    let (taps, tap_rx) = mpsc::channel();
+    let stream = match sample_format {
+        cpal::SampleFormat::F32 => build_typed_stream::<f32>(device, config, mixer_rx, voice_mixer, tap_rx, stats)?,
+        cpal::SampleFormat::I16 => build_typed_stream::<i16>(device, config, mixer_rx, voice_mixer, tap_rx, stats)?,
+        cpal::SampleFormat::U16 => build_typed_stream::<u16>(device, config, mixer_rx, voice_mixer, tap_rx, stats)?,
+        cpal::SampleFormat::I32 => build_typed_stream::<i32>(device, config, mixer_rx, voice_mixer, tap_rx, stats)?,
+        format => return Err(format!("unsupported sample format {}", format).into()),
+    };
+    Ok((stream, mixer, voices, taps))
+}
+
+fn build_typed_stream<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    mut mixer_rx: DynamicMixer<f32>,
+    mut voice_mixer: VoiceMixer,
+    tap_rx: mpsc::Receiver<RecordTap>,
+    stats: Arc<StreamStats>,
+) -> Result<cpal::Stream, Box<dyn Error>>
+where
+    T: SizedSample + FromSample<f32>,
+{
+    let channels = config.channels as u64;
+    let sample_rate = config.sample_rate.0 as u64;
+    let error_stats = stats.clone();
+    let sync_len = (SYNC_PULSE.as_secs_f64() * sample_rate as f64) as u64;
+    let mut sync_sent = u64::MAX;
+    // frames left of the sync pulse playing
+    let mut sync_left = 0;
+    // allocated up front, the callback mustn't
+    let mut frame = vec![0.0; voice_mixer.frame_len()];
+    let mut delay = DelayLine::new(config.sample_rate.0, config.channels);
+    let mut reverb_bits = stats.reverb.load(Ordering::Relaxed);
+    let mut reverb = Freeverb::new(config.sample_rate.0, config.channels, Reverb::from_bits(reverb_bits));
+    // one pole glide from the gain playing to the one asked for
+    let glide = 1.0 - (-1.0 / (MASTER_GLIDE_SECS * sample_rate as f32)).exp();
+    let mut master_gain = f32::from_bits(stats.master_gain.load(Ordering::Relaxed));
+    // recording of the mix, if one's running
+    let mut tap: Option<RecordTap> = None;
+    Ok(device.build_output_stream::<T, _, _>(
+        config,
+        move |data: &mut [T], info: &cpal::OutputCallbackInfo| {
+            let callback_us = stats.origin.elapsed().as_micros() as u64;
+            let timestamp = info.timestamp();
+            if let Some(latency) = timestamp.playback.duration_since(&timestamp.callback) {
+                stats.latency_us.store(latency.as_micros() as u64, Ordering::Relaxed);
+            }
+            let mut probing = stats.probing.load(Ordering::Relaxed);
+            let sync_channel = stats.sync_channel.load(Ordering::Relaxed) as u64;
+            let sync_at = stats.sync_at.load(Ordering::Relaxed);
+            let first_frame = stats.pulled.load(Ordering::Relaxed);
+            let delay_len = (stats.delay_us.load(Ordering::Relaxed) * sample_rate / 1_000_000 * channels) as usize;
+            let feedback = f32::from_bits(stats.delay_feedback.load(Ordering::Relaxed));
+            if stats.reverb.load(Ordering::Relaxed) != reverb_bits {
+                reverb_bits = stats.reverb.load(Ordering::Relaxed);
+                reverb.set(Reverb::from_bits(reverb_bits));
+            }
+            let target_gain = f32::from_bits(stats.master_gain.load(Ordering::Relaxed));
+            if let Ok(new_tap) = tap_rx.try_recv() {
+                tap = Some(new_tap);
+            }
+            if tap.as_ref().is_some_and(|tap| tap.stopping()) {
+                if let Some(tap) = tap.take() {
+                    tap.finish();
+                }
+            }
+            for (i, sample) in data.iter_mut().enumerate() {
+                let channel = i as u64 % channels;
+                if channel == 0 {
+                    if sync_at != sync_sent && first_frame + i as u64 / channels >= sync_at {
+                        sync_sent = sync_at;
+                        sync_left = sync_len;
+                    }
+                    voice_mixer.mix_frame(&mut frame);
+                    master_gain += (target_gain - master_gain) * glide;
+                }
+                // the frame is the main mix then each bus
+                let bus = |bus: SendBus| frame[(1 + bus as usize) * channels as usize + channel as usize];
+                let echo = delay.process(bus(SendBus::Delay), delay_len, feedback);
+                let room = reverb.process(channel as usize, bus(SendBus::Reverb));
+                let mixed = (mixer_rx.next().unwrap_or(0.0) + frame[channel as usize] + echo + room) * master_gain;
+                if let Some(tap) = tap.as_mut() {
+                    tap.push(mixed);
+                }
+                *sample = if channel + 1 == sync_channel {
+                    T::from_sample(if sync_left > 0 { 1.0 } else { 0.0 })
+                } else {
+                    T::from_sample(mixed)
+                };
+                if channel == channels - 1 {
+                    sync_left = sync_left.saturating_sub(1);
+                }
+                // The click is heard a device latency after the start of
+                // this buffer plus however far into it the click starts
+                if probing && stats.probe_started.load(Ordering::Relaxed) {
+                    let offset_us = i as u64 / channels * 1_000_000 / sample_rate;
+                    let heard_us = callback_us + offset_us + stats.latency_us.load(Ordering::Relaxed);
+                    let scheduled_us = stats.probe_scheduled_us.load(Ordering::Relaxed);
+                    stats.probe_result_us.store(heard_us.saturating_sub(scheduled_us).max(1), Ordering::Relaxed);
+                    stats.probing.store(false, Ordering::Relaxed);
+                    probing = false;
+                }
+            }
+            stats.pulled.fetch_add(data.len() as u64 / channels, Ordering::Relaxed);
+            stats.last_buffer.store(data.len() as u64 / channels, Ordering::Relaxed);
+            stats.last_callback_us.store(callback_us, Ordering::Relaxed);
+        },
+        move |err| {
+            println!("Audio output error: {}", err);
+            if let cpal::StreamError::DeviceNotAvailable = err {
+                error_stats.failed.store(true, Ordering::Relaxed);
+            }
+        },
+        None,
+    )?)
+}
+
+/// Backs off between attempts to open the output again after losing it
+///
+/// The device that was lost is tried first, failing that the default
+pub struct Reacquire {
+    device_name: String,
+    request: StreamRequest,
+    pub attempts: u32,
+    wait: Duration,
+    next_try: Instant,
+}
+
+impl Reacquire {
+    pub fn new(device_name: String, request: StreamRequest) -> Self {
+        Reacquire {
+            device_name,
+            request,
+            attempts: 0,
+            wait: RETRY_START,
+            next_try: Instant::now() + RETRY_START,
+        }
+    }
+
+    /// Tries to open the output if the wait is up
+    pub fn poll(&mut self) -> Option<AudioOutput> {
+        if Instant::now() < self.next_try {
+            return None;
+        }
+        self.attempts += 1;
+        let output = AudioOutput::open(&self.device_name, self.request)
+            .or_else(|_| AudioOutput::open_default(self.request));
+        match output {
+            Ok(output) => Some(output),
+            Err(e) => {
+                println!("Failed to reopen audio output (attempt {}): {}", self.attempts, e);
+                self.wait = (self.wait * 2).min(RETRY_MAX);
+                self.next_try = Instant::now() + self.wait;
+                None
+            }
+        }
+    }
+}
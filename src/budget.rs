@@ -0,0 +1,165 @@
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// Bars of pulse timings kept for the histogram
+const BUDGET_BARS: usize = 16;
+/// Upper edges of the histogram buckets, in percent of the pulse interval.
+/// The last has no edge and catches the pulses that overran
+const BUCKET_EDGES: [u16; 5] = [10, 25, 50, 75, 100];
+const BUCKETS: usize = BUCKET_EDGES.len() + 1;
+
+/// Pulse timings over one bar
+#[derive(Default, Clone, Copy)]
+struct BarBudget {
+    pulses: [u32; BUCKETS],
+    peak_pct: f32,
+}
+
+/// How much of each pulse interval goes on processing the pulse
+///
+/// Anything past 100% runs into the next pulse and pushes it late, and
+/// on a Pi that tends to happen in bursts, like a pattern loading, long
+/// before it shows in the average latency
+#[derive(Default)]
+pub struct PulseBudget {
+    /// finished bars, oldest first
+    bars: VecDeque<BarBudget>,
+    current: BarBudget,
+    last_pct: f32,
+}
+
+/// Pulses that used up to a share of their interval
+#[derive(Debug, Clone, Serialize)]
+pub struct BudgetBucket {
+    /// percent of the interval, None for the pulses that overran
+    pub upto_pct: Option<u16>,
+    pub pulses: u32,
+}
+
+/// Budget histogram over the last few bars played
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct BudgetReport {
+    /// the last pulse
+    pub pct: f32,
+    pub peak_pct: f32,
+    /// finished bars the histogram covers, on top of the one playing
+    pub bars: usize,
+    pub buckets: Vec<BudgetBucket>,
+}
+
+impl PulseBudget {
+    /// Counts a pulse that took used out of its interval, bar_done once
+    /// it was the last of its bar
+    pub fn record(&mut self, used: Duration, interval: Duration, bar_done: bool) {
+        if interval.is_zero() {
+            return;
+        }
+        let pct = used.as_secs_f32() / interval.as_secs_f32() * 100.0;
+        let bucket = BUCKET_EDGES.iter().position(|&edge| pct < edge as f32).unwrap_or(BUCKETS - 1);
+        self.current.pulses[bucket] += 1;
+        self.current.peak_pct = self.current.peak_pct.max(pct);
+        self.last_pct = pct;
+        if bar_done {
+            self.bars.push_back(std::mem::take(&mut self.current));
+            if self.bars.len() > BUDGET_BARS {
+                self.bars.pop_front();
+            }
+        }
+    }
+
+    pub fn last_pct(&self) -> f32 {
+        self.last_pct
+    }
+
+    /// Highest over the bars kept
+    pub fn peak_pct(&self) -> f32 {
+        self.bars.iter().fold(self.current.peak_pct, |peak, bar| peak.max(bar.peak_pct))
+    }
+
+    pub fn report(&self) -> BudgetReport {
+        let mut pulses = self.current.pulses;
+        for bar in &self.bars {
+            pulses.iter_mut().zip(bar.pulses).for_each(|(total, count)| *total += count);
+        }
+        BudgetReport {
+            pct: self.last_pct,
+            peak_pct: self.peak_pct(),
+            bars: self.bars.len(),
+            buckets: pulses.iter().enumerate().map(|(i, &pulses)| BudgetBucket {
+                upto_pct: BUCKET_EDGES.get(i).copied(),
+                pulses,
+            }).collect(),
+        }
+    }
+}
+
+/// Pulse scheduling over one bar
+#[derive(Default)]
+struct BarJitter {
+    late_us: Vec<i64>,
+    missed: u32,
+}
+
+/// How far off when each pulse was scheduled is from when it was due to be
+///
+/// Pulses are scheduled a couple of buffers ahead of the audio, so a late
+/// one is still heard on time. It's missed its deadline once the stream has
+/// mixed past the frame it was due at before it's scheduled, those hits
+/// are heard late
+#[derive(Default)]
+pub struct PulseJitter {
+    /// finished bars, oldest first
+    bars: VecDeque<BarJitter>,
+    current: BarJitter,
+    total_missed: u64,
+    /// over the bars kept, worked out as each one finishes
+    report: JitterReport,
+}
+
+/// Pulse jitter over the last few bars played
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct JitterReport {
+    /// earliest, latest and 99th percentile a pulse was scheduled by after
+    /// it was due to be, early is negative
+    pub min_us: i64,
+    pub max_us: i64,
+    pub p99_us: i64,
+    /// pulses that missed their deadline over the bars kept
+    pub missed: u32,
+    /// and since the sequencer started
+    pub total_missed: u64,
+}
+
+impl PulseJitter {
+    /// Counts a pulse scheduled late_us after it was due to be, None if it
+    /// started playback so had nothing to be late against. bar_done once it
+    /// was the last of its bar
+    pub fn record(&mut self, late_us: Option<i64>, missed: bool, bar_done: bool) {
+        self.current.late_us.extend(late_us);
+        if missed {
+            self.current.missed += 1;
+            self.total_missed += 1;
+        }
+        if bar_done {
+            self.bars.push_back(std::mem::take(&mut self.current));
+            if self.bars.len() > BUDGET_BARS {
+                self.bars.pop_front();
+            }
+            let mut late_us: Vec<i64> = self.bars.iter().flat_map(|bar| bar.late_us.iter().copied()).collect();
+            late_us.sort_unstable();
+            let p99 = (late_us.len() * 99).div_ceil(100).saturating_sub(1);
+            self.report = JitterReport {
+                min_us: late_us.first().copied().unwrap_or(0),
+                max_us: late_us.last().copied().unwrap_or(0),
+                p99_us: late_us.get(p99).copied().unwrap_or(0),
+                missed: self.bars.iter().map(|bar| bar.missed).sum(),
+                total_missed: self.total_missed,
+            };
+        }
+    }
+
+    pub fn report(&self) -> JitterReport {
+        JitterReport { total_missed: self.total_missed, ..self.report }
+    }
+}
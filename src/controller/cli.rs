@@ -1,21 +1,51 @@
+use crate::i18n::{Language, Msg};
+use crate::project::Project;
 use crate::sequencer;
+use crate::sync::SyncStatus;
+use super::format;
+use serde::{Serialize, Deserialize};
 use std::sync::mpsc;
 use std::time::{Instant, Duration};
 
-use std::io;
+use std::io::{self, Write};
 
 use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind};
+use crossterm::execute;
+use crossterm::terminal::{EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::widgets;
 use ratatui::{
     buffer::Buffer,
-    layout::Rect,
+    layout::{Constraint, Layout, Rect},
     style::Stylize,
     symbols::border,
-    text::{Line, Text},
+    text::{Line, Span, Text},
     widgets::{Block, Paragraph, Widget},
     DefaultTerminal, Frame,
 };
 
+/// How the TUI shows the state, set with tui_mode in project.json and
+/// switched with the A key
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum TuiMode {
+    #[default]
+    Standard = 0,
+    /// no color, every cue is spelled out or has its own symbol
+    HighContrast = 1,
+    /// no screen at all, a line is printed for each change so a terminal
+    /// screen reader reads them as they come
+    Plain = 2,
+}
+
+impl TuiMode {
+    fn next(self) -> Self {
+        match self {
+            TuiMode::Standard => TuiMode::HighContrast,
+            TuiMode::HighContrast => TuiMode::Plain,
+            TuiMode::Plain => TuiMode::Standard,
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct CLIController {
     state_rx: mpsc::Receiver<sequencer::StateUpdate>,
@@ -24,17 +54,26 @@ pub struct CLIController {
     refresh_interval: Duration,
     last_refresh: Instant,
     last_state: sequencer::SeqState,
+    mode: TuiMode,
 }
 
 impl CLIController {
     pub fn new(rx: mpsc::Receiver<sequencer::StateUpdate>, tx: mpsc::Sender<sequencer::Command>) -> Self {
+        let mode = match Project::load() {
+            Ok(project) => project.tui_mode,
+            Err(e) => {
+                println!("Failed to load project: {}", e);
+                TuiMode::default()
+            }
+        };
         CLIController {
             state_rx: rx,
             cmd_tx: tx,
             exit: false,
             refresh_interval: Duration::from_secs_f32(1.0/12.0),
             last_refresh: Instant::now(),
-            last_state: sequencer::SeqState::default()
+            last_state: sequencer::SeqState::default(),
+            mode,
         }
     }
 
@@ -44,23 +83,50 @@ impl CLIController {
 
     /// runs the application's main loop until the user quits
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        if self.mode == TuiMode::Plain {
+            execute!(io::stdout(), LeaveAlternateScreen)?;
+        }
         while !self.exit {
             let now = Instant::now();
             if let Ok(state) = self.state_rx.try_recv() {
                 match state {
-                    sequencer::StateUpdate::SeqState(state) => self.last_state = state,
+                    sequencer::StateUpdate::SeqState(state) => {
+                        if self.mode == TuiMode::Plain {
+                            for event in events(&self.last_state, &state) {
+                                say(&event)?;
+                            }
+                        }
+                        self.last_state = *state;
+                    },
                     _ => {}
                 }
             }
-            if now.duration_since(self.last_refresh) > self.refresh_interval {
+            if self.mode != TuiMode::Plain && now.duration_since(self.last_refresh) > self.refresh_interval {
                 terminal.draw(|frame| self.draw(frame))?;
                 self.last_refresh = now;
             }
-            self.handle_events()?;
+            self.handle_events(terminal)?;
         }
         Ok(())
     }
 
+    /// Moves to the next mode, leaving the screen for plain text and
+    /// coming back to it after
+    fn switch_mode(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
+        self.mode = self.mode.next();
+        match self.mode {
+            TuiMode::Plain => {
+                execute!(io::stdout(), LeaveAlternateScreen)?;
+                say(&status(&self.last_state))
+            },
+            TuiMode::Standard => {
+                execute!(io::stdout(), EnterAlternateScreen)?;
+                terminal.clear()
+            },
+            TuiMode::HighContrast => Ok(()),
+        }
+    }
+
     fn draw(&self, frame: &mut Frame) {
         frame.render_widget(self, frame.area());
     }
@@ -72,24 +138,37 @@ impl CLIController {
         }
     }
 
-    fn handle_key_event(&mut self, key_event: KeyEvent) {
+    /// Steps the master volume a dB at a time
+    fn nudge_volume(&self, step: i8) {
+        let volume_db = self.last_state.master_volume_db.saturating_add(step)
+            .clamp(sequencer::MIN_MASTER_VOLUME_DB, sequencer::MAX_MASTER_VOLUME_DB);
+        self.cmd_tx.send(sequencer::Command::SetMasterVolume(volume_db)).expect("Bad volume command")
+    }
+
+    fn handle_key_event(&mut self, key_event: KeyEvent, terminal: &mut DefaultTerminal) -> io::Result<()> {
         match key_event.code {
             KeyCode::Char('q') => self.exit(),
+            KeyCode::Char('a') => self.switch_mode(terminal)?,
+            KeyCode::Char('s') if self.mode == TuiMode::Plain => say(&status(&self.last_state))?,
             KeyCode::Char(c) if c.is_digit(10) => self.send_play_sample_cmd(c),
             KeyCode::Char('p') => self.cmd_tx.send(if self.last_state.playing { sequencer::Command::StopSequencer } else { sequencer::Command::PlaySequencer }).expect("Bad stuff"),
+            KeyCode::Char('r') => self.cmd_tx.send(sequencer::Command::SetRecord(!self.last_state.recording)).expect("Bad record command"),
+            KeyCode::Char('-') => self.nudge_volume(-1),
+            KeyCode::Char('+') | KeyCode::Char('=') => self.nudge_volume(1),
             _ => {}
         }
+        Ok(())
     }
 
     /// updates the application's state based on user input
-    fn handle_events(&mut self) -> io::Result<()> {
+    fn handle_events(&mut self, terminal: &mut DefaultTerminal) -> io::Result<()> {
         if let Ok(is_event) = event::poll(Duration::ZERO) {
             if is_event {
                 match event::read()? {
                     // it's important to check that the event is a key press event as
                     // crossterm also emits key release and repeat events on Windows.
                     Event::Key(key_event) if key_event.kind == KeyEventKind::Press => {
-                        self.handle_key_event(key_event)
+                        self.handle_key_event(key_event, terminal)?
                     },
                     _ => {}
                 };
@@ -99,26 +178,172 @@ impl CLIController {
     }
 }
 
+/// Prints a line of plain mode, the terminal is still raw so it needs
+/// its own carriage return
+fn say(line: &str) -> io::Result<()> {
+    let mut stdout = io::stdout();
+    write!(stdout, "{}\r\n", line)?;
+    stdout.flush()
+}
+
+/// Transport, recording and any audio error, each spelled out with its
+/// own symbol so none of it rests on color
+fn status_parts(state: &sequencer::SeqState) -> Vec<(&'static str, String)> {
+    let lang = Language::from(state.language as i64);
+    let transport = if state.playing {
+        ("▶", Msg::Playing)
+    } else if state.paused {
+        ("‖", Msg::Paused)
+    } else {
+        ("■", Msg::Stopped)
+    };
+    let mut parts = vec![(transport.0, lang.text(transport.1).to_string())];
+    if state.recording {
+        parts.push(("●", lang.text(Msg::Recording).to_string()));
+    }
+    if let Some(error) = &state.audio_error {
+        parts.push(("!", error.clone()));
+    }
+    parts
+}
+
+fn status(state: &sequencer::SeqState) -> String {
+    let lang = Language::from(state.language as i64);
+    let mut parts: Vec<String> = status_parts(state).into_iter()
+        .map(|(symbol, text)| format!("{} {}", symbol, text))
+        .collect();
+    parts.push(lang.text_with(Msg::Pattern, &state.pattern_name));
+    parts.push(lang.text_with(Msg::Tempo, &format::tempo(state.tempo)));
+    parts.join(", ")
+}
+
+/// Lines for plain mode saying what changed between two states
+///
+/// Tempo is only told in whole BPM so a ramp doesn't flood the reader
+fn events(old: &sequencer::SeqState, new: &sequencer::SeqState) -> Vec<String> {
+    let lang = Language::from(new.language as i64);
+    let mut events = vec![];
+    if (old.playing, old.paused) != (new.playing, new.paused) {
+        events.push(status_parts(new)[0].1.clone());
+    }
+    if old.recording != new.recording {
+        events.push(lang.text(if new.recording { Msg::Recording } else { Msg::RecordingOff }).to_string());
+    }
+    if old.pattern_id != new.pattern_id || old.pattern_name != new.pattern_name {
+        events.push(lang.text_with(Msg::Pattern, &new.pattern_name));
+    }
+    if old.tempo.round() != new.tempo.round() {
+        events.push(lang.text_with(Msg::Tempo, &format::tempo(new.tempo)));
+    }
+    if old.division != new.division {
+        events.push(lang.text_with(Msg::Division, &format::division(new.division, lang)));
+    }
+    let clock = |sync: &SyncStatus| (sync.source, sync.external_bpm.is_some(), sync.locked);
+    if clock(&old.sync) != clock(&new.sync) {
+        events.push(format::sync(&new.sync, lang));
+    }
+    if old.master_volume_db != new.master_volume_db {
+        events.push(format!("{} {}", lang.text(Msg::Volume), format::volume(new.master_volume_db)));
+    }
+    if old.audio_error != new.audio_error {
+        events.push(new.audio_error.clone().unwrap_or(lang.text(Msg::AudioOutputBack).to_string()));
+    }
+    for error in new.sample_errors.iter().filter(|error| !old.sample_errors.iter().any(|old| old.path == error.path)) {
+        events.push(error.error.clone());
+    }
+    if let Some(report) = &new.last_export {
+        if old.last_export.as_ref().map(|old| &old.fname) != Some(&report.fname) {
+            events.push(format!("{} {}", lang.text(Msg::LastExport), format::export_report(report, lang)));
+        }
+    }
+    events
+}
+
 impl Widget for &CLIController {
     fn render(self, area: Rect, buf: &mut Buffer) {
+        let state = &self.last_state;
+        let lang = Language::from(state.language as i64);
+        let high_contrast = self.mode == TuiMode::HighContrast;
+        let border_set = if high_contrast { border::DOUBLE } else { border::THICK };
+        let key = |key: &'static str| if high_contrast { key.bold().reversed() } else { key.blue().bold() };
         let title = Line::from(" Rdum ".bold());
         let instructions = Line::from(vec![
-            " Quit ".into(),
-            "<Q> ".blue().bold(),
+            format!(" {} ", lang.text(Msg::Quit)).into(),
+            key("<Q>"),
+            format!(" {} ", lang.text(Msg::Mode)).into(),
+            key("<A>"),
+            format!(" {} ", lang.text(Msg::Volume)).into(),
+            key("<-/+>"),
+            " ".into(),
         ]);
-        let block = Block::bordered()
-            .title(title.centered())
-            .title_bottom(instructions.centered())
-            .border_set(border::THICK);
+        let [header_area, body_area] = Layout::vertical([Constraint::Length(3), Constraint::Min(0)]).areas(area);
 
-        let something = format!("{:?}", self.last_state).to_string();
+        // The transport header, status first then where the song is
+        let mut header: Vec<Span> = status_parts(state).into_iter().enumerate().flat_map(|(i, (symbol, text))| {
+            let part = format!("{} {}", symbol, text);
+            let part = match (high_contrast, symbol) {
+                (true, "▶" | "●" | "!") => part.bold().reversed(),
+                (false, "▶") => part.green(),
+                (false, "●" | "!") => part.red().bold(),
+                _ => part.into(),
+            };
+            [if i > 0 { "  ".into() } else { "".into() }, part]
+        }).collect();
+        let transport = [
+            format::tempo(state.tempo),
+            format::time_signature(state.beats_per_bar),
+            format::position(&state.position),
+            format::elapsed(state.position.elapsed),
+            format::sync(&state.sync, lang),
+        ];
+        for part in transport {
+            header.push("  │  ".into());
+            header.push(part.bold());
+        }
+        Paragraph::new(Line::from(header))
+            .centered()
+            .block(Block::bordered().title(title.centered()).border_set(border_set))
+            .render(header_area, buf);
 
-        let text = Text::from(something);
+        let mut lines = vec![
+            Line::from(format!(
+                "{}  {}  {} {}",
+                state.pattern_name,
+                lang.text_with(Msg::Division, &format::division(state.division, lang)),
+                lang.text(Msg::Volume),
+                format::volume(state.master_volume_db),
+            )),
+            Line::from(format!(
+                "{} {}  {} {}  {} {}",
+                lang.text(Msg::Latency),
+                format::latency(state.latency),
+                lang.text(Msg::Output),
+                format::latency(state.output_latency),
+                lang.text(Msg::Measured),
+                state.measured_latency.map_or("-".to_string(), format::latency),
+            )),
+            Line::from(format!(
+                "{} {}  {} {}  {} {}..{} (p99 {})  {} {}",
+                lang.text(Msg::Budget),
+                format::budget(state.budget_pct),
+                lang.text(Msg::Peak),
+                format::budget(state.peak_budget_pct),
+                lang.text(Msg::Jitter),
+                format::jitter(state.jitter_min_us),
+                format::jitter(state.jitter_max_us),
+                format::jitter(state.jitter_p99_us),
+                lang.text(Msg::Missed),
+                state.missed_pulses,
+            )),
+        ];
+        if let Some(report) = &state.last_export {
+            lines.push(Line::from(format!("{} {}", lang.text(Msg::LastExport), format::export_report(report, lang))));
+        }
 
-        Paragraph::new(text)
+        Paragraph::new(Text::from(lines))
             .centered()
-            .block(block)
+            .block(Block::bordered().title_bottom(instructions.centered()).border_set(border_set))
             .wrap(widgets::Wrap{ trim: true })
-            .render(area, buf);
+            .render(body_area, buf);
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,111 @@
+use crate::export::ExportReport;
+use crate::i18n::{Language, Msg};
+use crate::sequencer::{TransportPosition, MIN_MASTER_VOLUME_DB};
+use crate::sync::{ClockSource, SyncStatus};
+use std::time::Duration;
+
+// Every surface showing state to the user formats it through here so
+// they all read the same
+
+/// Division as a note length, matching the labels on the web ui
+pub fn division(division: u8, lang: Language) -> String {
+    match division {
+        1 => "1",
+        2 => "1/2",
+        3 => "1/4.",
+        4 => "1/4",
+        6 => "1/8.",
+        8 => "1/8",
+        12 => "1/16.",
+        16 => "1/16",
+        24 => "1/32.",
+        32 => "1/32",
+        _ => return lang.text_with(Msg::PerBar, &division.to_string()),
+    }.to_string()
+}
+
+/// bars:beats counting from 1 like a DAW does
+pub fn position(position: &TransportPosition) -> String {
+    format!("{}:{}", position.bar + 1, position.beat + 1)
+}
+
+/// Beats are always quarter notes
+pub fn time_signature(beats_per_bar: u8) -> String {
+    format!("{}/4", beats_per_bar)
+}
+
+/// Playing time as m:ss, with hours in front once there are any
+pub fn elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs >= 3600 {
+        format!("{}:{:02}:{:02}", secs / 3600, secs / 60 % 60, secs % 60)
+    } else {
+        format!("{}:{:02}", secs / 60, secs % 60)
+    }
+}
+
+/// Where the clock comes from and, for midi, whether we're following it
+pub fn sync(sync: &SyncStatus, lang: Language) -> String {
+    match (sync.source, sync.external_bpm) {
+        (ClockSource::Internal, _) => lang.text(Msg::InternalClock).to_string(),
+        (ClockSource::Midi, None) => lang.text(Msg::NoMidiClock).to_string(),
+        (ClockSource::Midi, Some(bpm)) => format!(
+            "{} {}",
+            lang.text_with(Msg::MidiClock, &tempo(bpm)),
+            lang.text(if sync.locked { Msg::Locked } else { Msg::Drifting }),
+        ),
+    }
+}
+
+pub fn tempo(bpm: f32) -> String {
+    format!("{:.1} BPM", bpm)
+}
+
+pub fn latency(latency: Duration) -> String {
+    format!("{:.2} ms", latency.as_secs_f64() * 1000.0)
+}
+
+/// How late pulses were scheduled, signed as early ones are negative
+pub fn jitter(us: i64) -> String {
+    format!("{:+.2} ms", us as f64 / 1000.0)
+}
+
+/// Share of the pulse interval spent processing
+pub fn budget(pct: f32) -> String {
+    format!("{:.0}%", pct)
+}
+
+/// Gain or level in dB, signed so a cut and a boost are told apart
+pub fn db(db: f64) -> String {
+    if db == f64::NEG_INFINITY {
+        "-inf dB".to_string()
+    } else {
+        format!("{:+.1} dB", db)
+    }
+}
+
+/// Master volume, the bottom of the range is silent
+pub fn volume(volume_db: i8) -> String {
+    if volume_db <= MIN_MASTER_VOLUME_DB {
+        db(f64::NEG_INFINITY)
+    } else {
+        db(volume_db as f64)
+    }
+}
+
+/// Loudness and peak of a bounce
+pub fn export_report(report: &ExportReport, lang: Language) -> String {
+    let loudness = match report.integrated_lufs {
+        Some(lufs) => format!("{:.1} LUFS", lufs),
+        None => lang.text(Msg::Silent).to_string(),
+    };
+    format!(
+        "{}: {}, {} {}, {} {}",
+        report.fname,
+        loudness,
+        lang.text(Msg::Peak),
+        db(report.true_peak_db),
+        lang.text(Msg::Gain),
+        db(report.gain_db),
+    )
+}
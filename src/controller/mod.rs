@@ -1,3 +1,4 @@
 pub mod cli;
+pub mod format;
 pub mod web;
 pub mod zeromq;
@@ -1,10 +1,20 @@
-use std::sync::mpsc;
+use std::collections::VecDeque;
+use std::sync::{mpsc, Arc, Mutex};
 use std::net::SocketAddr;
 use tokio::net::{TcpListener, TcpStream};
-use tokio::sync::broadcast;
+use tokio::sync::{broadcast, Notify};
 use async_tungstenite::{tokio::accept_async, tungstenite::Message};
 use futures::{SinkExt, StreamExt};
-use crate::sequencer::{Command, StateUpdate, Swing};
+use crate::midi_map::MidiTrigger;
+use crate::sample_gain::{Normalize, SampleGain};
+use crate::sequencer::{Command, Direction, Division, Envelope, Filter, FilterMode, FitMode, FxBypass, LoopSync, Polyphony, RecordMode, StateUpdate, StealPolicy, SwapPolicy, Swing, SwingGrid, VoiceMode};
+use crate::audio::BufferMode;
+use crate::sync::ClockSource;
+use crate::i18n::Language;
+use crate::reverb::Reverb;
+use crate::demo::DemoStyle;
+use crate::export::{BitDepth, ExportSettings};
+use crate::eq::TrackEq;
 use serde_json;
 use serde;
 use std::error::Error;
@@ -15,12 +25,38 @@ enum MessageType {
     FileStateUpdate,
     #[serde(rename = "state_update")]
     StateUpdate,
+    #[serde(rename = "trigger_counts_update")]
+    TriggerCountsUpdate,
+    #[serde(rename = "sample_pool_update")]
+    SamplePoolUpdate,
+    #[serde(rename = "state_gap")]
+    StateGap,
+    #[serde(rename = "budget_update")]
+    BudgetUpdate,
+    #[serde(rename = "report_budget")]
+    ReportBudget,
+    #[serde(rename = "report_sample_pool")]
+    ReportSamplePool,
+    #[serde(rename = "set_sample_cache_size")]
+    SetSampleCacheSize,
+    #[serde(rename = "clean_sample_pool")]
+    CleanSamplePool,
     #[serde(rename = "play_sequencer")]
     PlaySequencer,
     #[serde(rename = "stop_sequencer")]
     StopSequencer,
+    #[serde(rename = "play_for_bars")]
+    PlayForBars,
+    #[serde(rename = "set_stop_timer")]
+    SetStopTimer,
+    #[serde(rename = "pause_sequencer")]
+    PauseSequencer,
+    #[serde(rename = "continue_sequencer")]
+    ContinueSequencer,
     #[serde(rename = "set_tempo")]
     SetTempo,
+    #[serde(rename = "set_tempo_slew")]
+    SetTempoSlew,
     #[serde(rename = "set_pattern")]
     SetPattern,
     #[serde(rename = "set_division")]
@@ -29,10 +65,178 @@ enum MessageType {
     PlaySound,
     #[serde(rename = "set_slot_velocity")]
     SetSlotVelocity,
+    #[serde(rename = "set_slot_accent")]
+    SetSlotAccent,
+    #[serde(rename = "set_slot_reverse")]
+    SetSlotReverse,
+    #[serde(rename = "set_slot_ratchet")]
+    SetSlotRatchet,
+    #[serde(rename = "set_slot_ratchet_decay")]
+    SetSlotRatchetDecay,
+    #[serde(rename = "set_accent_velocity")]
+    SetAccentVelocity,
     #[serde(rename = "set_track_length")]
     SetTrackLength,
+    #[serde(rename = "set_track_direction")]
+    SetTrackDirection,
+    #[serde(rename = "set_track_eq")]
+    SetTrackEq,
+    #[serde(rename = "set_track_fx_bypass")]
+    SetTrackFxBypass,
+    #[serde(rename = "set_track_timing_offset")]
+    SetTrackTimingOffset,
+    #[serde(rename = "set_track_loop")]
+    SetTrackLoop,
+    #[serde(rename = "clear_track_loop")]
+    ClearTrackLoop,
+    #[serde(rename = "randomize_track")]
+    RandomizeTrack,
+    #[serde(rename = "randomize_pattern")]
+    RandomizePattern,
+    #[serde(rename = "set_humanize")]
+    SetHumanize,
+    #[serde(rename = "set_groove")]
+    SetGroove,
+    #[serde(rename = "clear_groove")]
+    ClearGroove,
+    #[serde(rename = "add_cc_lane")]
+    AddCcLane,
+    #[serde(rename = "remove_cc_lane")]
+    RemoveCcLane,
+    #[serde(rename = "set_cc_step")]
+    SetCcStep,
+    #[serde(rename = "set_midi_thru")]
+    SetMidiThru,
+    #[serde(rename = "set_pattern_fill")]
+    SetPatternFill,
+    #[serde(rename = "trigger_fill")]
+    TriggerFill,
+    #[serde(rename = "set_deck_b")]
+    SetDeckB,
+    #[serde(rename = "set_crossfade")]
+    SetCrossfade,
+    #[serde(rename = "list_audio_devices")]
+    ListAudioDevices,
+    #[serde(rename = "set_audio_device")]
+    SetAudioDevice,
+    #[serde(rename = "set_sync_out")]
+    SetSyncOut,
+    #[serde(rename = "set_buffer_mode")]
+    SetBufferMode,
+    #[serde(rename = "set_buffer_frames")]
+    SetBufferFrames,
+    #[serde(rename = "set_sample_rate")]
+    SetSampleRate,
+    #[serde(rename = "measure_latency")]
+    MeasureLatency,
+    #[serde(rename = "list_midi_ports")]
+    ListMidiPorts,
+    #[serde(rename = "add_midi_output")]
+    AddMidiOutput,
+    #[serde(rename = "remove_midi_output")]
+    RemoveMidiOutput,
+    #[serde(rename = "set_midi_output")]
+    SetMidiOutput,
+    #[serde(rename = "set_track_midi_note")]
+    SetTrackMidiNote,
+    #[serde(rename = "list_midi_input_ports")]
+    ListMidiInputPorts,
+    #[serde(rename = "add_midi_input")]
+    AddMidiInput,
+    #[serde(rename = "remove_midi_input")]
+    RemoveMidiInput,
+    #[serde(rename = "set_note_input")]
+    SetNoteInput,
+    #[serde(rename = "map_note_to_track")]
+    MapNoteToTrack,
+    #[serde(rename = "learn_midi")]
+    LearnMidi,
+    #[serde(rename = "bind_midi")]
+    BindMidi,
+    #[serde(rename = "list_midi_maps")]
+    ListMidiMaps,
+    #[serde(rename = "save_midi_map")]
+    SaveMidiMap,
+    #[serde(rename = "load_midi_map")]
+    LoadMidiMap,
+    #[serde(rename = "set_track_velocity_variation")]
+    SetTrackVelocityVariation,
+    #[serde(rename = "set_track_pitch")]
+    SetTrackPitch,
+    #[serde(rename = "set_track_reverse")]
+    SetTrackReverse,
+    #[serde(rename = "set_track_envelope")]
+    SetTrackEnvelope,
+    #[serde(rename = "set_track_filter")]
+    SetTrackFilter,
+    #[serde(rename = "set_track_polyphony")]
+    SetTrackPolyphony,
+    #[serde(rename = "set_track_layer")]
+    SetTrackLayer,
+    #[serde(rename = "remove_track_layer")]
+    RemoveTrackLayer,
+    #[serde(rename = "clear_track_layers")]
+    ClearTrackLayers,
+    #[serde(rename = "set_track_loop_sync")]
+    SetTrackLoopSync,
+    #[serde(rename = "set_track_output")]
+    SetTrackOutput,
+    #[serde(rename = "set_sample_gain")]
+    SetSampleGain,
+    #[serde(rename = "set_delay")]
+    SetDelay,
+    #[serde(rename = "set_track_delay_send")]
+    SetTrackDelaySend,
+    #[serde(rename = "set_reverb")]
+    SetReverb,
+    #[serde(rename = "set_track_reverb_send")]
+    SetTrackReverbSend,
+    #[serde(rename = "load_demo")]
+    LoadDemo,
+    #[serde(rename = "mute_tracks")]
+    MuteTracks,
+    #[serde(rename = "set_tracks_gain")]
+    SetTracksGain,
+    #[serde(rename = "clear_tracks")]
+    ClearTracks,
+    #[serde(rename = "set_clock_source")]
+    SetClockSource,
+    #[serde(rename = "set_language")]
+    SetLanguage,
+    #[serde(rename = "set_master_volume")]
+    SetMasterVolume,
+    #[serde(rename = "set_record")]
+    SetRecord,
+    #[serde(rename = "set_record_mode")]
+    SetRecordMode,
+    #[serde(rename = "set_track_erase")]
+    SetTrackErase,
+    #[serde(rename = "set_track_archived")]
+    SetTrackArchived,
+    #[serde(rename = "list_trigger_counts")]
+    ListTriggerCounts,
+    #[serde(rename = "reset_trigger_counts")]
+    ResetTriggerCounts,
+    #[serde(rename = "find_similar_patterns")]
+    FindSimilarPatterns,
+    #[serde(rename = "clear_track")]
+    ClearTrack,
+    #[serde(rename = "clear_pattern")]
+    ClearPattern,
+    #[serde(rename = "set_pattern_name")]
+    SetPatternName,
+    #[serde(rename = "set_pattern_notes")]
+    SetPatternNotes,
+    #[serde(rename = "set_project_notes")]
+    SetProjectNotes,
     #[serde(rename = "add_pattern")]
     AddPattern,
+    #[serde(rename = "duplicate_pattern")]
+    DuplicatePattern,
+    #[serde(rename = "copy_track")]
+    CopyTrack,
+    #[serde(rename = "copy_track_feel")]
+    CopyTrackFeel,
     #[serde(rename = "remove_pattern")]
     RemovePattern,
     #[serde(rename = "select_pattern")]
@@ -47,12 +251,80 @@ enum MessageType {
     ListPatterns,
     #[serde(rename = "list_samples")]
     ListSamples,
+    #[serde(rename = "list_grooves")]
+    ListGrooves,
     #[serde(rename = "set_track_sample")]
     SetTrackSample,
+    #[serde(rename = "randomize_kit")]
+    RandomizeKit,
     #[serde(rename = "add_track")]
     AddTrack,
+    #[serde(rename = "remove_track")]
+    RemoveTrack,
+    #[serde(rename = "move_track")]
+    MoveTrack,
+    #[serde(rename = "set_swap_policy")]
+    SetSwapPolicy,
     #[serde(rename = "set_swing")]
     SetSwing,
+    #[serde(rename = "set_swing_grid")]
+    SetSwingGrid,
+    #[serde(rename = "queue_pattern")]
+    QueuePattern,
+    #[serde(rename = "clear_pattern_queue")]
+    ClearPatternQueue,
+    #[serde(rename = "set_song_mode")]
+    SetSongMode,
+    #[serde(rename = "add_song_entry")]
+    AddSongEntry,
+    #[serde(rename = "set_song_entry")]
+    SetSongEntry,
+    #[serde(rename = "remove_song_entry")]
+    RemoveSongEntry,
+    #[serde(rename = "clear_song")]
+    ClearSong,
+    #[serde(rename = "set_song_loop")]
+    SetSongLoop,
+    #[serde(rename = "clear_song_loop")]
+    ClearSongLoop,
+    #[serde(rename = "export_song")]
+    ExportSong,
+    #[serde(rename = "set_anti_click")]
+    SetAntiClick,
+    #[serde(rename = "set_choke_fade")]
+    SetChokeFade,
+    #[serde(rename = "set_metronome")]
+    SetMetronome,
+    #[serde(rename = "set_metronome_sample")]
+    SetMetronomeSample,
+    #[serde(rename = "set_count_in")]
+    SetCountIn,
+    #[serde(rename = "define_macro")]
+    DefineMacro,
+    #[serde(rename = "remove_macro")]
+    RemoveMacro,
+    #[serde(rename = "run_macro")]
+    RunMacro,
+    #[serde(rename = "export_all_patterns")]
+    ExportAllPatterns,
+    #[serde(rename = "start_recording")]
+    StartRecording,
+    #[serde(rename = "stop_recording")]
+    StopRecording,
+    #[serde(rename = "capture_sample")]
+    CaptureSample,
+    #[serde(rename = "stop_capture")]
+    StopCapture,
+    #[serde(rename = "reload_samples")]
+    ReloadSamples,
+    #[serde(rename = "preview_sample")]
+    PreviewSample,
+    #[serde(rename = "slice_sample")]
+    SliceSample,
+    #[serde(rename = "list_history")]
+    ListHistory,
+    #[serde(rename = "restore_snapshot")]
+    RestoreSnapshot,
 }
 
 #[derive(Debug, serde::Serialize, serde::Deserialize)]
@@ -61,6 +333,31 @@ struct WebSocketMessage {
     msg_type: MessageType,
     payload: serde_json::Value,
 }
+/// Updates waiting to go out on one connection
+///
+/// A phone on a poor connection can't always keep up with the state
+/// updates, so rather than letting them pile up only the latest sequencer
+/// state is kept and the client is told how many it missed. Replies like
+/// the file lists and reports are only sent when asked for and always go
+/// out
+#[derive(Default)]
+struct SendQueue {
+    updates: VecDeque<StateUpdate>,
+    /// updates dropped since the client was last told
+    skipped: u64,
+}
+
+impl SendQueue {
+    fn push(&mut self, update: StateUpdate) {
+        if let StateUpdate::SeqState(_) = update {
+            let queued = self.updates.len();
+            self.updates.retain(|queued| !matches!(queued, StateUpdate::SeqState(_)));
+            self.skipped += (queued - self.updates.len()) as u64;
+        }
+        self.updates.push_back(update);
+    }
+}
+
 pub struct WebController {
     addr: SocketAddr,
     cmd_tx_ch: mpsc::Sender<Command>,
@@ -135,6 +432,14 @@ impl WebController {
     
 }
 
+/// The trackIds array of a payload for the commands on a selection of tracks
+fn track_ids(payload: &serde_json::Map<String, serde_json::Value>) -> Vec<usize> {
+    payload.get("trackIds").unwrap().as_array().unwrap().iter()
+        .filter_map(|id| id.as_u64())
+        .map(|id| id as usize)
+        .collect()
+}
+
 fn handle_command(cmd_tx_ch: mpsc::Sender<Command>, message: WebSocketMessage) -> Result<(), Box<dyn Error>> {
     match message.payload.as_object() {
         Some(payload) => {
@@ -145,10 +450,20 @@ fn handle_command(cmd_tx_ch: mpsc::Sender<Command>, message: WebSocketMessage) -
                 MessageType::StopSequencer => {
                     cmd_tx_ch.send(Command::StopSequencer)?;
                 },
+                MessageType::PauseSequencer => {
+                    cmd_tx_ch.send(Command::PauseSequencer)?;
+                },
+                MessageType::ContinueSequencer => {
+                    cmd_tx_ch.send(Command::ContinueSequencer)?;
+                },
                 MessageType::SetTempo => {
-                    let tempo = payload.get("tempo").unwrap().as_i64().unwrap() as u8;
+                    let tempo = payload.get("tempo").unwrap().as_f64().unwrap() as f32;
                     cmd_tx_ch.send(Command::SetTempo(tempo))?;
                 },
+                MessageType::SetTempoSlew => {
+                    let slew_ms = payload.get("slewMs").unwrap().as_i64().unwrap() as u16;
+                    cmd_tx_ch.send(Command::SetTempoSlew(slew_ms))?;
+                },
                 MessageType::SetPattern => {
                     let pattern_idx = payload.get("pattern_idx").unwrap().as_i64().unwrap() as usize;
                     cmd_tx_ch.send(Command::SetPattern(pattern_idx))?;
@@ -167,13 +482,430 @@ fn handle_command(cmd_tx_ch: mpsc::Sender<Command>, message: WebSocketMessage) -
                     let velocity = payload.get("velocity").unwrap().as_i64().unwrap() as u8;
                     cmd_tx_ch.send(Command::SetSlotVelocity(track_idx, slot_idx, velocity))?;
                 },
+                MessageType::SetSlotAccent => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let slot_idx = payload.get("slotIdx").unwrap().as_i64().unwrap() as usize;
+                    let accent = payload.get("accent").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetSlotAccent(track_idx, slot_idx, accent))?;
+                },
+                MessageType::SetSlotReverse => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let slot_idx = payload.get("slotIdx").unwrap().as_i64().unwrap() as usize;
+                    let reverse = payload.get("reverse").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetSlotReverse(track_idx, slot_idx, reverse))?;
+                },
+                MessageType::SetSlotRatchet => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let slot_idx = payload.get("slotIdx").unwrap().as_i64().unwrap() as usize;
+                    let ratchet = payload.get("ratchet").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetSlotRatchet(track_idx, slot_idx, ratchet))?;
+                },
+                MessageType::SetSlotRatchetDecay => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let slot_idx = payload.get("slotIdx").unwrap().as_i64().unwrap() as usize;
+                    let decay = payload.get("decay").unwrap().as_i64().unwrap().clamp(-100, 100) as i8;
+                    cmd_tx_ch.send(Command::SetSlotRatchetDecay(track_idx, slot_idx, decay))?;
+                },
+                MessageType::SetAccentVelocity => {
+                    let velocity = payload.get("velocity").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetAccentVelocity(velocity))?;
+                },
                 MessageType::SetTrackLength => {
-                    let track_idx = payload.get("track_idx").unwrap().as_i64().unwrap() as usize;
-                    cmd_tx_ch.send(Command::SetTrackLength(track_idx))?;
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let length = payload.get("length").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::SetTrackLength(track_idx, length))?;
+                },
+                MessageType::SetTrackDirection => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let direction = payload.get("direction").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetTrackDirection(track_idx, Direction::from(direction)))?;
+                },
+                MessageType::SetTrackEq => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let db = |key: &str| payload.get(key).unwrap().as_i64().unwrap().clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+                    let eq = TrackEq {
+                        low_db: db("lowDb"),
+                        high_db: db("highDb"),
+                    };
+                    cmd_tx_ch.send(Command::SetTrackEq(track_idx, eq))?;
+                },
+                MessageType::SetTrackFxBypass => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let bypassed = |key: &str| payload.get(key).unwrap().as_bool().unwrap();
+                    let bypass = FxBypass {
+                        eq: bypassed("eq"),
+                        filter: bypassed("filter"),
+                        delay: bypassed("delay"),
+                        reverb: bypassed("reverb"),
+                    };
+                    cmd_tx_ch.send(Command::SetTrackFxBypass(track_idx, bypass))?;
+                },
+                MessageType::SetTrackTimingOffset => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let offset_ms = payload.get("offsetMs").unwrap().as_i64().unwrap() as i16;
+                    cmd_tx_ch.send(Command::SetTrackTimingOffset(track_idx, offset_ms))?;
+                },
+                MessageType::SetTrackLoop => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let start = payload.get("start").unwrap().as_i64().unwrap() as usize;
+                    let end = payload.get("end").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::SetTrackLoop(track_idx, start, end))?;
+                },
+                MessageType::ClearTrackLoop => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::ClearTrackLoop(track_idx))?;
+                },
+                MessageType::RandomizeTrack => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let density = payload.get("density").unwrap().as_f64().unwrap() as f32;
+                    let vel_min = payload.get("velMin").unwrap().as_i64().unwrap() as u8;
+                    let vel_max = payload.get("velMax").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::RandomizeTrack(track_idx, density, vel_min, vel_max))?;
+                },
+                MessageType::RandomizePattern => {
+                    let density = payload.get("density").unwrap().as_f64().unwrap() as f32;
+                    let vel_min = payload.get("velMin").unwrap().as_i64().unwrap() as u8;
+                    let vel_max = payload.get("velMax").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::RandomizePattern(density, vel_min, vel_max))?;
+                },
+                MessageType::SetHumanize => {
+                    let timing_ms = payload.get("timingMs").unwrap().as_i64().unwrap() as u8;
+                    let velocity = payload.get("velocity").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetHumanize(timing_ms, velocity))?;
+                },
+                MessageType::SetGroove => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    let strength = payload.get("strength").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetGroove(name.to_string(), strength))?;
+                },
+                MessageType::ClearGroove => {
+                    cmd_tx_ch.send(Command::ClearGroove)?;
+                },
+                MessageType::AddCcLane => {
+                    let channel = payload.get("channel").unwrap().as_i64().unwrap() as u8;
+                    let controller = payload.get("controller").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::AddCcLane(channel, controller))?;
+                },
+                MessageType::RemoveCcLane => {
+                    let lane_idx = payload.get("laneIdx").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RemoveCcLane(lane_idx))?;
+                },
+                MessageType::SetCcStep => {
+                    let lane_idx = payload.get("laneIdx").unwrap().as_i64().unwrap() as usize;
+                    let step_idx = payload.get("stepIdx").unwrap().as_i64().unwrap() as usize;
+                    // null holds the previous value
+                    let value = payload.get("value").and_then(|value| value.as_i64()).map(|value| value as u8);
+                    cmd_tx_ch.send(Command::SetCcStep(lane_idx, step_idx, value))?;
+                },
+                MessageType::SetMidiThru => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    // null keeps the channel of the input
+                    let channel = payload.get("channel").and_then(|channel| channel.as_i64()).map(|channel| channel as u8);
+                    cmd_tx_ch.send(Command::SetMidiThru(enabled, channel))?;
+                },
+                MessageType::SetPatternFill => {
+                    // null clears the fill
+                    let fill_id = payload.get("patternId").and_then(|id| id.as_i64()).map(|id| id as usize);
+                    cmd_tx_ch.send(Command::SetPatternFill(fill_id))?;
+                },
+                MessageType::SetDeckB => {
+                    // null ends deck mode
+                    let pattern_id = payload.get("patternId").and_then(|id| id.as_i64()).map(|id| id as usize);
+                    cmd_tx_ch.send(Command::SetDeckB(pattern_id))?;
+                },
+                MessageType::SetCrossfade => {
+                    let crossfade = payload.get("crossfade").unwrap().as_f64().unwrap() as f32;
+                    cmd_tx_ch.send(Command::SetCrossfade(crossfade))?;
+                },
+                MessageType::TriggerFill => {
+                    let held = payload.get("held").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::TriggerFill(held))?;
+                },
+                MessageType::ListAudioDevices => {
+                    cmd_tx_ch.send(Command::ListAudioDevices)?;
+                },
+                MessageType::SetAudioDevice => {
+                    let name = payload.get("name").unwrap().as_str().unwrap().to_string();
+                    cmd_tx_ch.send(Command::SetAudioDevice(name))?;
+                },
+                MessageType::MeasureLatency => {
+                    cmd_tx_ch.send(Command::MeasureLatency)?;
+                },
+                MessageType::PlayForBars => {
+                    let bars = payload.get("bars").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::PlayForBars(bars))?;
+                },
+                MessageType::SetStopTimer => {
+                    let secs = payload.get("secs").unwrap().as_i64().unwrap() as u32;
+                    cmd_tx_ch.send(Command::SetStopTimer(secs))?;
+                },
+                MessageType::SetSyncOut => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    let channel = payload.get("channel").unwrap().as_i64().unwrap() as u16;
+                    let ppqn = payload.get("ppqn").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetSyncOut(enabled, channel, ppqn))?;
+                },
+                MessageType::SetBufferMode => {
+                    let mode = payload.get("mode").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetBufferMode(BufferMode::from(mode)))?;
+                },
+                MessageType::SetBufferFrames => {
+                    let frames = payload.get("frames").unwrap().as_u64().unwrap().min(u32::MAX as u64) as u32;
+                    cmd_tx_ch.send(Command::SetBufferFrames(frames))?;
+                },
+                MessageType::SetSampleRate => {
+                    let rate = payload.get("rate").unwrap().as_u64().unwrap().min(u32::MAX as u64) as u32;
+                    cmd_tx_ch.send(Command::SetSampleRate(rate))?;
+                },
+                MessageType::ListMidiPorts => {
+                    cmd_tx_ch.send(Command::ListMidiPorts)?;
+                },
+                MessageType::AddMidiOutput => {
+                    let port_name = payload.get("portName").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::AddMidiOutput(port_name.to_string()))?;
+                },
+                MessageType::RemoveMidiOutput => {
+                    let output_idx = payload.get("outputIdx").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RemoveMidiOutput(output_idx))?;
+                },
+                MessageType::SetMidiOutput => {
+                    let output_idx = payload.get("outputIdx").unwrap().as_i64().unwrap() as usize;
+                    let clock = payload.get("clock").unwrap().as_bool().unwrap();
+                    let transport = payload.get("transport").unwrap().as_bool().unwrap();
+                    let notes = payload.get("notes").unwrap().as_bool().unwrap();
+                    let cc = payload.get("cc").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetMidiOutput(output_idx, clock, transport, notes, cc))?;
+                },
+                MessageType::SetTrackMidiNote => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let note = payload.get("note").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetTrackMidiNote(track_idx, note))?;
+                },
+                MessageType::ListMidiInputPorts => {
+                    cmd_tx_ch.send(Command::ListMidiInputPorts)?;
+                },
+                MessageType::AddMidiInput => {
+                    let port_name = payload.get("portName").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::AddMidiInput(port_name.to_string()))?;
+                },
+                MessageType::RemoveMidiInput => {
+                    let input_idx = payload.get("inputIdx").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RemoveMidiInput(input_idx))?;
+                },
+                MessageType::SetNoteInput => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    // null takes notes on any channel
+                    let channel = payload.get("channel").and_then(|channel| channel.as_i64()).map(|channel| channel as u8);
+                    cmd_tx_ch.send(Command::SetNoteInput(enabled, channel))?;
+                },
+                MessageType::MapNoteToTrack => {
+                    let note = payload.get("note").unwrap().as_i64().unwrap() as u8;
+                    // null goes back to the track sending the note
+                    let track_idx = payload.get("trackId").and_then(|trk| trk.as_i64()).map(|trk| trk as usize);
+                    cmd_tx_ch.send(Command::MapNoteToTrack(note, track_idx))?;
+                },
+                MessageType::LearnMidi => {
+                    // the command is in the same form as lastCmd in the state,
+                    // null stops learning
+                    let command: Option<Command> = serde_json::from_value(payload.get("command").cloned().unwrap_or_default())?;
+                    cmd_tx_ch.send(Command::LearnMidi(command.map(Box::new)))?;
+                },
+                MessageType::BindMidi => {
+                    let trigger: MidiTrigger = serde_json::from_value(payload.get("trigger").unwrap().clone())?;
+                    // null unbinds it
+                    let command: Option<Command> = serde_json::from_value(payload.get("command").cloned().unwrap_or_default())?;
+                    cmd_tx_ch.send(Command::BindMidi(trigger, command.map(Box::new)))?;
+                },
+                MessageType::ListMidiMaps => {
+                    cmd_tx_ch.send(Command::ListMidiMaps)?;
+                },
+                MessageType::SaveMidiMap => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::SaveMidiMap(name.to_string()))?;
+                },
+                MessageType::LoadMidiMap => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::LoadMidiMap(name.to_string()))?;
+                },
+                MessageType::SetRecord => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetRecord(enabled))?;
+                },
+                MessageType::SetRecordMode => {
+                    let mode = payload.get("mode").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetRecordMode(RecordMode::from(mode)))?;
+                },
+                MessageType::SetPatternName => {
+                    let pattern_idx = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
+                    let name = payload.get("name").unwrap().as_str().unwrap().to_string();
+                    cmd_tx_ch.send(Command::SetPatternName(pattern_idx, name))?;
+                },
+                MessageType::SetPatternNotes => {
+                    let notes = payload.get("notes").unwrap().as_str().unwrap().to_string();
+                    cmd_tx_ch.send(Command::SetPatternNotes(notes))?;
+                },
+                MessageType::SetProjectNotes => {
+                    let notes = payload.get("notes").unwrap().as_str().unwrap().to_string();
+                    cmd_tx_ch.send(Command::SetProjectNotes(notes))?;
+                },
+                MessageType::SetTrackArchived => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let archived = payload.get("archived").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetTrackArchived(track_idx, archived))?;
+                },
+                MessageType::SetTrackErase => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let erase = payload.get("erase").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetTrackErase(track_idx, erase))?;
+                },
+                MessageType::SetClockSource => {
+                    let source = payload.get("source").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetClockSource(ClockSource::from(source)))?;
+                },
+                MessageType::SetLanguage => {
+                    let language = payload.get("language").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetLanguage(Language::from(language)))?;
+                },
+                MessageType::SetMasterVolume => {
+                    let volume_db = payload.get("volumeDb").unwrap().as_i64().unwrap().clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+                    cmd_tx_ch.send(Command::SetMasterVolume(volume_db))?;
+                },
+                MessageType::SetTrackVelocityVariation => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let amount = payload.get("amount").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetTrackVelocityVariation(track_idx, amount))?;
+                },
+                MessageType::SetTrackPitch => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let cents = payload.get("cents").unwrap().as_i64().unwrap().clamp(i16::MIN as i64, i16::MAX as i64) as i16;
+                    cmd_tx_ch.send(Command::SetTrackPitch(track_idx, cents))?;
+                },
+                MessageType::SetTrackReverse => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let reverse = payload.get("reverse").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetTrackReverse(track_idx, reverse))?;
+                },
+                MessageType::SetTrackEnvelope => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let ms = |key: &str| payload.get(key).unwrap().as_u64().unwrap().min(u16::MAX as u64) as u16;
+                    let envelope = Envelope {
+                        attack_ms: ms("attackMs"),
+                        hold_ms: ms("holdMs"),
+                        decay_ms: ms("decayMs"),
+                    };
+                    cmd_tx_ch.send(Command::SetTrackEnvelope(track_idx, envelope))?;
+                },
+                MessageType::SetTrackFilter => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let filter = Filter {
+                        mode: FilterMode::from(payload.get("mode").unwrap().as_i64().unwrap()),
+                        cutoff_hz: payload.get("cutoffHz").unwrap().as_u64().unwrap().min(u16::MAX as u64) as u16,
+                        resonance: payload.get("resonance").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8,
+                    };
+                    cmd_tx_ch.send(Command::SetTrackFilter(track_idx, filter))?;
+                },
+                MessageType::SetTrackPolyphony => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let polyphony = Polyphony {
+                        mode: VoiceMode::from(payload.get("mode").unwrap().as_i64().unwrap()),
+                        max_voices: payload.get("maxVoices").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8,
+                        steal: StealPolicy::from(payload.get("steal").unwrap().as_i64().unwrap()),
+                    };
+                    cmd_tx_ch.send(Command::SetTrackPolyphony(track_idx, polyphony))?;
+                },
+                MessageType::SetTrackLayer => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let min_velocity = payload.get("minVelocity").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    let sample_path = payload.get("samplePath").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::SetTrackLayer(track_idx, min_velocity, sample_path.to_string()))?;
+                },
+                MessageType::RemoveTrackLayer => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let min_velocity = payload.get("minVelocity").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::RemoveTrackLayer(track_idx, min_velocity))?;
+                },
+                MessageType::ClearTrackLayers => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::ClearTrackLayers(track_idx))?;
+                },
+                MessageType::SetTrackLoopSync => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let bpm = payload.get("bpm").unwrap().as_f64().unwrap() as f32;
+                    let mode = FitMode::from(payload.get("mode").unwrap().as_i64().unwrap());
+                    let loop_sync = (bpm > 0.0).then_some(LoopSync { bpm, mode });
+                    cmd_tx_ch.send(Command::SetTrackLoopSync(track_idx, loop_sync))?;
+                },
+                MessageType::SetTrackOutput => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let output = payload.get("output").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::SetTrackOutput(track_idx, output))?;
+                },
+                MessageType::SetSampleGain => {
+                    let sample_path = payload.get("samplePath").unwrap().as_str().unwrap().to_string();
+                    let gain = SampleGain {
+                        normalize: Normalize::from(payload.get("normalize").unwrap().as_i64().unwrap()),
+                        target_db: payload.get("targetDb").unwrap().as_f64().unwrap() as f32,
+                        offset_db: payload.get("offsetDb").unwrap().as_f64().unwrap() as f32,
+                    };
+                    cmd_tx_ch.send(Command::SetSampleGain(sample_path, gain))?;
+                },
+                MessageType::SetDelay => {
+                    let division = Division::from(payload.get("division").unwrap().as_i64().unwrap());
+                    let feedback = payload.get("feedback").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::SetDelay(division, feedback))?;
+                },
+                MessageType::SetTrackDelaySend => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let send = payload.get("send").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::SetTrackDelaySend(track_idx, send))?;
+                },
+                MessageType::SetReverb => {
+                    let pct = |key: &str| payload.get(key).unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    let reverb = Reverb {
+                        size_pct: pct("size"),
+                        damping_pct: pct("damping"),
+                        wet_pct: pct("wet"),
+                    };
+                    cmd_tx_ch.send(Command::SetReverb(reverb))?;
+                },
+                MessageType::SetTrackReverbSend => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let send = payload.get("send").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::SetTrackReverbSend(track_idx, send))?;
+                },
+                MessageType::LoadDemo => {
+                    let style = payload.get("style").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::LoadDemo(DemoStyle::from(style)))?;
+                },
+                MessageType::MuteTracks => {
+                    let muted = payload.get("muted").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::MuteTracks(track_ids(payload), muted))?;
+                },
+                MessageType::SetTracksGain => {
+                    let gain_db = payload.get("gainDb").unwrap().as_i64().unwrap().clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+                    cmd_tx_ch.send(Command::SetTracksGain(track_ids(payload), gain_db))?;
+                },
+                MessageType::ClearTracks => {
+                    cmd_tx_ch.send(Command::ClearTracks(track_ids(payload)))?;
                 },
                 MessageType::AddPattern => {
                     cmd_tx_ch.send(Command::AddPattern)?;
                 },
+                MessageType::DuplicatePattern => {
+                    let pattern_id = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::DuplicatePattern(pattern_id))?;
+                },
+                MessageType::CopyTrack => {
+                    let src_pattern = payload.get("srcPatternId").unwrap().as_i64().unwrap() as usize;
+                    let src_track = payload.get("srcTrackId").unwrap().as_i64().unwrap() as usize;
+                    let dst_pattern = payload.get("dstPatternId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::CopyTrack(src_pattern, src_track, dst_pattern))?;
+                },
+                MessageType::CopyTrackFeel => {
+                    let src_track = payload.get("srcTrackId").unwrap().as_i64().unwrap() as usize;
+                    let dst_track = payload.get("dstTrackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::CopyTrackFeel(src_track, dst_track))?;
+                },
                 MessageType::RemovePattern => {
                     let pattern_id = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
                     cmd_tx_ch.send(Command::RemovePattern(pattern_id))?;
@@ -199,6 +931,9 @@ fn handle_command(cmd_tx_ch: mpsc::Sender<Command>, message: WebSocketMessage) -
                 MessageType::ListSamples => {
                     cmd_tx_ch.send(Command::ListSamples)?;
                 },
+                MessageType::ListGrooves => {
+                    cmd_tx_ch.send(Command::ListGrooves)?;
+                },
                 MessageType::SetTrackSample => {
                     let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
                     let sample_path = payload.get("samplePath").unwrap().as_str().unwrap();
@@ -207,10 +942,185 @@ fn handle_command(cmd_tx_ch: mpsc::Sender<Command>, message: WebSocketMessage) -
                 MessageType::AddTrack => {
                     cmd_tx_ch.send(Command::AddTrack)?;
                 },
+                MessageType::ClearTrack => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::ClearTrack(track_idx))?;
+                },
+                MessageType::RandomizeKit => {
+                    cmd_tx_ch.send(Command::RandomizeKit)?;
+                },
+                MessageType::ClearPattern => {
+                    cmd_tx_ch.send(Command::ClearPattern)?;
+                },
+                MessageType::SetSampleCacheSize => {
+                    let mb = payload.get("mb").unwrap().as_i64().unwrap() as u32;
+                    cmd_tx_ch.send(Command::SetSampleCacheSize(mb))?;
+                },
+                MessageType::ReportBudget => {
+                    cmd_tx_ch.send(Command::ReportBudget)?;
+                },
+                MessageType::ReportSamplePool => {
+                    cmd_tx_ch.send(Command::ReportSamplePool)?;
+                },
+                MessageType::CleanSamplePool => {
+                    cmd_tx_ch.send(Command::CleanSamplePool)?;
+                },
+                MessageType::ListTriggerCounts => {
+                    cmd_tx_ch.send(Command::ListTriggerCounts)?;
+                },
+                MessageType::ResetTriggerCounts => {
+                    cmd_tx_ch.send(Command::ResetTriggerCounts)?;
+                },
+                MessageType::FindSimilarPatterns => {
+                    cmd_tx_ch.send(Command::FindSimilarPatterns)?;
+                },
+                MessageType::MoveTrack => {
+                    let from = payload.get("fromTrackId").unwrap().as_i64().unwrap() as usize;
+                    let to = payload.get("toTrackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::MoveTrack(from, to))?;
+                },
+                MessageType::RemoveTrack => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RemoveTrack(track_idx))?;
+                },
+                MessageType::SetSwapPolicy => {
+                    let policy = payload.get("policy").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetSwapPolicy(SwapPolicy::from(policy)))?;
+                },
                 MessageType::SetSwing => {
                     let swing = payload.get("swing").unwrap().as_i64().unwrap();
                     cmd_tx_ch.send(Command::SetSwing(Swing::from(swing)))?;
                 },
+                MessageType::SetSwingGrid => {
+                    let grid = payload.get("grid").unwrap().as_i64().unwrap();
+                    cmd_tx_ch.send(Command::SetSwingGrid(SwingGrid::from(grid)))?;
+                },
+                MessageType::QueuePattern => {
+                    let pattern_id = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::QueuePattern(pattern_id))?;
+                },
+                MessageType::ClearPatternQueue => {
+                    cmd_tx_ch.send(Command::ClearPatternQueue)?;
+                },
+                MessageType::SetSongMode => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetSongMode(enabled))?;
+                },
+                MessageType::AddSongEntry => {
+                    let pattern_id = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
+                    let repeats = payload.get("repeats").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::AddSongEntry(pattern_id, repeats))?;
+                },
+                MessageType::SetSongEntry => {
+                    let entry_idx = payload.get("entryIdx").unwrap().as_i64().unwrap() as usize;
+                    let pattern_id = payload.get("patternId").unwrap().as_i64().unwrap() as usize;
+                    let repeats = payload.get("repeats").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::SetSongEntry(entry_idx, pattern_id, repeats))?;
+                },
+                MessageType::RemoveSongEntry => {
+                    let entry_idx = payload.get("entryIdx").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RemoveSongEntry(entry_idx))?;
+                },
+                MessageType::ClearSong => {
+                    cmd_tx_ch.send(Command::ClearSong)?;
+                },
+                MessageType::SetSongLoop => {
+                    let start = payload.get("start").unwrap().as_i64().unwrap() as usize;
+                    let end = payload.get("end").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::SetSongLoop(start, end))?;
+                },
+                MessageType::ClearSongLoop => {
+                    cmd_tx_ch.send(Command::ClearSongLoop)?;
+                },
+                MessageType::ExportSong => {
+                    let fname = payload.get("fname").unwrap().as_str().unwrap();
+                    // render settings are optional, older clients only send a name
+                    let mut settings = ExportSettings::default();
+                    if let Some(bit_depth) = payload.get("bitDepth").and_then(|v| v.as_i64()) {
+                        settings.bit_depth = BitDepth::from(bit_depth);
+                    }
+                    if let Some(lufs) = payload.get("normalizeLufs").and_then(|v| v.as_f64()) {
+                        settings.normalize_lufs = Some(lufs as f32);
+                    }
+                    cmd_tx_ch.send(Command::ExportSong(fname.to_string(), settings))?;
+                },
+                MessageType::SetAntiClick => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    let fade_ms = payload.get("fadeMs").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetAntiClick(enabled, fade_ms))?;
+                },
+                MessageType::SetChokeFade => {
+                    let fade_ms = payload.get("fadeMs").unwrap().as_u64().unwrap().min(u8::MAX as u64) as u8;
+                    cmd_tx_ch.send(Command::SetChokeFade(fade_ms))?;
+                },
+                MessageType::SetMetronome => {
+                    let enabled = payload.get("enabled").unwrap().as_bool().unwrap();
+                    cmd_tx_ch.send(Command::SetMetronome(enabled))?;
+                },
+                MessageType::SetMetronomeSample => {
+                    let sample_path = payload.get("samplePath").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::SetMetronomeSample(sample_path.to_string()))?;
+                },
+                MessageType::SetCountIn => {
+                    let bars = payload.get("bars").unwrap().as_i64().unwrap() as u8;
+                    cmd_tx_ch.send(Command::SetCountIn(bars))?;
+                },
+                MessageType::DefineMacro => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    // commands are in the same form as lastCmd in the state
+                    let commands: Vec<Command> = serde_json::from_value(payload.get("commands").unwrap().clone())?;
+                    cmd_tx_ch.send(Command::DefineMacro(name.to_string(), commands))?;
+                },
+                MessageType::RemoveMacro => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::RemoveMacro(name.to_string()))?;
+                },
+                MessageType::RunMacro => {
+                    let name = payload.get("name").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::RunMacro(name.to_string()))?;
+                },
+                MessageType::ExportAllPatterns => {
+                    let dir = payload.get("dir").unwrap().as_str().unwrap();
+                    let bars_each = payload.get("barsEach").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::ExportAllPatterns(dir.to_string(), bars_each))?;
+                },
+                MessageType::StartRecording => {
+                    let fname = payload.get("fname").unwrap().as_str().unwrap();
+                    cmd_tx_ch.send(Command::StartRecording(fname.to_string()))?;
+                },
+                MessageType::StopRecording => {
+                    cmd_tx_ch.send(Command::StopRecording)?;
+                },
+                MessageType::CaptureSample => {
+                    let track_idx = payload.get("trackId").unwrap().as_i64().unwrap() as usize;
+                    let fname = payload.get("fname").unwrap().as_str().unwrap();
+                    let threshold_db = payload.get("thresholdDb").unwrap().as_i64().unwrap().clamp(i8::MIN as i64, i8::MAX as i64) as i8;
+                    let overwrite = payload.get("overwrite").and_then(|v| v.as_bool()).unwrap_or(false);
+                    cmd_tx_ch.send(Command::CaptureSample(track_idx, fname.to_string(), threshold_db, overwrite))?;
+                },
+                MessageType::StopCapture => {
+                    cmd_tx_ch.send(Command::StopCapture)?;
+                },
+                MessageType::ReloadSamples => {
+                    cmd_tx_ch.send(Command::ReloadSamples)?;
+                },
+                MessageType::PreviewSample => {
+                    let sample_path = payload.get("samplePath").unwrap().as_str().unwrap();
+                    let vel = payload.get("vel").unwrap().as_i64().unwrap().clamp(0, 127) as u8;
+                    cmd_tx_ch.send(Command::PreviewSample(sample_path.to_string(), vel))?;
+                },
+                MessageType::SliceSample => {
+                    let sample_path = payload.get("samplePath").unwrap().as_str().unwrap();
+                    let slices = payload.get("slices").unwrap().as_u64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::SliceSample(sample_path.to_string(), slices))?;
+                },
+                MessageType::ListHistory => {
+                    cmd_tx_ch.send(Command::ListHistory)?;
+                },
+                MessageType::RestoreSnapshot => {
+                    let snapshot_id = payload.get("snapshotId").unwrap().as_i64().unwrap() as usize;
+                    cmd_tx_ch.send(Command::RestoreSnapshot(snapshot_id))?;
+                },
                 _ => {
                     return Err(format!("Received unknown command: {:?}", message).into())
                 }
@@ -239,39 +1149,42 @@ async fn handle_connection(stream: TcpStream, mut state_rx: broadcast::Receiver<
         return;
     }
     
+    // Updates are taken off the broadcast as soon as they come, even while
+    // the socket is busy sending, so a slow client never lags the channel
+    let queue = Arc::new(Mutex::new(SendQueue::default()));
+    let queued = Arc::new(Notify::new());
+    let forward = tokio::spawn({
+        let (queue, queued) = (queue.clone(), queued.clone());
+        async move {
+            loop {
+                match state_rx.recv().await {
+                    Ok(state) => queue.lock().unwrap().push(state),
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => queue.lock().unwrap().skipped += skipped,
+                    Err(broadcast::error::RecvError::Closed) => break,
+                }
+                queued.notify_one();
+            }
+        }
+    });
+
     // Use select to handle both WebSocket messages and state broadcasts
-    loop {
+    'connection: loop {
         tokio::select! {
-            // Handle incoming state updates
-            state_result = state_rx.recv() => {
-                match state_result {
-                    Ok(state) => {
-                        let msg_type = match state {
-                            StateUpdate::FileState(_) => MessageType::FileStateUpdate,
-                            StateUpdate::SeqState(_) => MessageType::StateUpdate,
-                        };
-                        let payload = match state {
-                            StateUpdate::FileState(file_state) => serde_json::to_value(file_state).unwrap(),
-                            StateUpdate::SeqState(seq_state) => serde_json::to_value(seq_state).unwrap(),
-                        };
-                        let message = WebSocketMessage {
-                            msg_type,
-                            payload,
-                        };
-                        let message_json = serde_json::to_string(&message).unwrap();
-                        if let Err(e) = ws_sender.send(Message::Text(message_json.into())).await {
-                            println!("[{}] WebSocket send error: {:?}", peer, e);
-                            break;
-                        }
-                    },
-                    Err(e) => {
-                        println!("[{}] Broadcast channel error: {:?}", peer, e);
-                        // Don't break on lag error, just resubscribe
-                        if e.to_string().contains("lagged") {
-                            println!("[{}] Receiver lagged, continuing", peer);
-                            continue;
-                        }
-                        break;
+            // Send whatever updates are queued, after a notice if some were dropped
+            _ = queued.notified() => {
+                let (updates, skipped) = {
+                    let mut queue = queue.lock().unwrap();
+                    (std::mem::take(&mut queue.updates), std::mem::take(&mut queue.skipped))
+                };
+                let gap = (skipped > 0).then(|| WebSocketMessage {
+                    msg_type: MessageType::StateGap,
+                    payload: serde_json::json!({"skipped": skipped}),
+                });
+                for message in gap.into_iter().chain(updates.into_iter().map(update_message)) {
+                    let message_json = serde_json::to_string(&message).unwrap();
+                    if let Err(e) = ws_sender.send(Message::Text(message_json.into())).await {
+                        println!("[{}] WebSocket send error: {:?}", peer, e);
+                        break 'connection;
                     }
                 }
             },
@@ -307,5 +1220,27 @@ async fn handle_connection(stream: TcpStream, mut state_rx: broadcast::Receiver<
         }
     }
     
+    forward.abort();
     println!("[{}] WebSocket connection closed", peer);
+}
+
+fn update_message(state: StateUpdate) -> WebSocketMessage {
+    let msg_type = match state {
+        StateUpdate::FileState(_) => MessageType::FileStateUpdate,
+        StateUpdate::SeqState(_) => MessageType::StateUpdate,
+        StateUpdate::TriggerCounts(_) => MessageType::TriggerCountsUpdate,
+        StateUpdate::SamplePool(_) => MessageType::SamplePoolUpdate,
+        StateUpdate::Budget(_) => MessageType::BudgetUpdate,
+    };
+    let payload = match state {
+        StateUpdate::FileState(file_state) => serde_json::to_value(file_state).unwrap(),
+        StateUpdate::SeqState(seq_state) => serde_json::to_value(seq_state).unwrap(),
+        StateUpdate::TriggerCounts(counts) => serde_json::to_value(counts).unwrap(),
+        StateUpdate::SamplePool(report) => serde_json::to_value(report).unwrap(),
+        StateUpdate::Budget(report) => serde_json::to_value(report).unwrap(),
+    };
+    WebSocketMessage {
+        msg_type,
+        payload,
+    }
 }
\ No newline at end of file
@@ -1,4 +1,13 @@
-use crate::sequencer::{SeqState, Command, Division, Swing, StateUpdate};
+use crate::midi_map::MidiTrigger;
+use crate::sample_gain::{Normalize, SampleGain};
+use crate::sequencer::{SeqState, Command, Direction, Division, Envelope, Filter, FilterMode, FitMode, FxBypass, LoopSync, Polyphony, RecordMode, StealPolicy, SwapPolicy, Swing, SwingGrid, StateUpdate, VoiceMode};
+use crate::audio::BufferMode;
+use crate::sync::ClockSource;
+use crate::i18n::Language;
+use crate::reverb::Reverb;
+use crate::demo::DemoStyle;
+use crate::export::{BitDepth, ExportSettings};
+use crate::eq::TrackEq;
 use prost::Message;
 use std::error::Error;
 use std::convert::TryFrom;
@@ -21,13 +30,66 @@ use state::Command as ProtoCommand;
 pub fn serialize_state(state: &SeqState) -> Result<Vec<u8>, Box<dyn Error>> {
     // Convert the Rust State to the Protocol Buffer State
     let proto_state = state::State {
-        tempo: state.tempo as u32,
+        tempo: state.tempo.round() as u32,
+        tempo_bpm: state.tempo,
         trks: state.trks.iter().map(|track| state::TrackState {
             slots: track.slots.iter().map(|&slot| slot as u32).collect(),
+            accents: track.accents.clone(),
+            reverses: track.reverses.clone(),
             name: track.name.clone(),
             idx: track.idx as u64,
             len: track.len as u64,
             sample_path: track.sample_path.clone(),
+            load_error: track.load_error.clone().unwrap_or_default(),
+            direction: track.direction as u32,
+            timing_offset_ms: track.timing_offset_ms as i32,
+            ratchets: track.ratchets.iter().map(|&ratchet| ratchet as u32).collect(),
+            ratchet_decays: track.ratchet_decays.iter().map(|&decay| decay as i32).collect(),
+            loop_points: track.loop_points.map(|(start, end)| state::LoopPoints {
+                start: start as u64,
+                end: end as u64,
+            }),
+            eq_low_db: track.eq.low_db as i32,
+            eq_high_db: track.eq.high_db as i32,
+            eq_bypassed: track.fx_bypass.eq,
+            midi_note: track.midi_note as u32,
+            velocity_variation: track.velocity_variation as u32,
+            pitch_cents: track.pitch_cents as i32,
+            reverse: track.reverse,
+            muted: track.muted,
+            gain_db: track.gain_db as i32,
+            attack_ms: track.envelope.attack_ms as u32,
+            hold_ms: track.envelope.hold_ms as u32,
+            decay_ms: track.envelope.decay_ms as u32,
+            filter_mode: track.filter.mode as u32,
+            filter_cutoff_hz: track.filter.cutoff_hz as u32,
+            filter_resonance: track.filter.resonance as u32,
+            filter_bypassed: track.fx_bypass.filter,
+            delay_send: track.delay_send as u32,
+            delay_bypassed: track.fx_bypass.delay,
+            reverb_send: track.reverb_send as u32,
+            reverb_bypassed: track.fx_bypass.reverb,
+            voice_mode: track.polyphony.mode as u32,
+            max_voices: track.polyphony.max_voices as u32,
+            steal_policy: track.polyphony.steal as u32,
+            layers: track.layers.iter().map(|layer| state::VelocityLayer {
+                min_velocity: layer.min_velocity as u32,
+                sample_path: layer.sample_path.clone(),
+            }).collect(),
+            loop_bpm: track.loop_sync.map_or(0.0, |sync| sync.bpm),
+            loop_fit_mode: track.loop_sync.map_or(0, |sync| sync.mode as u32),
+            output: track.output as u32,
+            sample_normalize: track.sample_gain.normalize as u32,
+            sample_target_db: track.sample_gain.target_db,
+            sample_offset_db: track.sample_gain.offset_db,
+            erasing: track.erasing,
+            archived: track.archived,
+        }).collect(),
+        cc_lanes: state.cc_lanes.iter().map(|lane| state::CcLaneState {
+            channel: lane.channel as u32,
+            controller: lane.controller as u32,
+            values: lane.values.iter().map(|value| value.map_or(-1, |value| value as i32)).collect(),
+            idx: lane.idx as u64,
         }).collect(),
         division: state.division as u32,
         default_len: state.default_len as u64,
@@ -36,11 +98,174 @@ pub fn serialize_state(state: &SeqState) -> Result<Vec<u8>, Box<dyn Error>> {
             nanos: state.latency.subsec_nanos() as i32,
         }),
         playing: state.playing,
+        paused: state.paused,
+        recording: state.recording,
+        record_mode: state.record_mode as u32,
+        audio_error: state.audio_error.clone().unwrap_or_default(),
+        audio_device: state.audio_device.clone(),
+        buffer_mode: state.buffer_mode as u32,
+        language: state.language as u32,
+        buffer_frames: state.buffer_frames,
+        requested_buffer_frames: state.requested_buffer_frames,
+        sample_rate: state.sample_rate,
+        requested_sample_rate: state.requested_sample_rate,
+        output_channels: state.output_channels as u32,
+        output_latency: Some(prost_types::Duration {
+            seconds: state.output_latency.as_secs() as i64,
+            nanos: state.output_latency.subsec_nanos() as i32,
+        }),
+        pending_division: state.pending_division.map_or(-1, |division| division as i32),
+        swing_grid: state.swing_grid as u32,
+        sync: Some(state::SyncStatus {
+            source: state.sync.source as u32,
+            has_external_bpm: state.sync.external_bpm.is_some(),
+            external_bpm: state.sync.external_bpm.unwrap_or_default(),
+            drift_ms: state.sync.drift_ms,
+            locked: state.sync.locked,
+        }),
+        position: Some(state::TransportPosition {
+            bar: state.position.bar as u64,
+            beat: state.position.beat as u32,
+            pulse: state.position.pulse as u32,
+            elapsed: Some(prost_types::Duration {
+                seconds: state.position.elapsed.as_secs() as i64,
+                nanos: state.position.elapsed.subsec_nanos() as i32,
+            }),
+        }),
         pattern_id: state.pattern_id as u64,
         pattern_len: state.pattern_len as u64,
         pattern_name: state.pattern_name.clone(),
+        measured_latency: state.measured_latency.map(|latency| prost_types::Duration {
+            seconds: latency.as_secs() as i64,
+            nanos: latency.subsec_nanos() as i32,
+        }),
+        master_recording: state.master_recording.clone().unwrap_or_default(),
+        capture_track: state.capture_track.map_or(-1, |track| track as i64),
+        capture_triggered: state.capture_triggered,
+        sample_errors: state.sample_errors.iter().map(|error| state::SampleError {
+            path: error.path.clone(),
+            error: error.error.clone(),
+        }).collect(),
+        master_recording_time: state.master_recording_time.map(|time| prost_types::Duration {
+            seconds: time.as_secs() as i64,
+            nanos: time.subsec_nanos() as i32,
+        }),
+        pattern_names: state.pattern_names.clone(),
+        bars_left: state.bars_left.map_or(-1, |bars| bars as i64),
+        stop_in: state.stop_in.map(|stop_in| prost_types::Duration {
+            seconds: stop_in.as_secs() as i64,
+            nanos: stop_in.subsec_nanos() as i32,
+        }),
+        stop_timer_secs: state.stop_timer_secs,
+        sample_count: state.sample_count as u64,
+        sample_bytes: state.sample_bytes as u64,
+        idle_sample_bytes: state.idle_sample_bytes as u64,
+        sample_cache_mb: state.sample_cache_mb,
+        budget_pct: state.budget_pct,
+        peak_budget_pct: state.peak_budget_pct,
+        jitter_min_us: state.jitter_min_us,
+        jitter_max_us: state.jitter_max_us,
+        jitter_p99_us: state.jitter_p99_us,
+        missed_pulses: state.missed_pulses,
+        total_missed_pulses: state.total_missed_pulses,
+        preload: state.preload.as_ref().map(|preload| state::PreloadState {
+            fname: preload.fname.clone(),
+            done: preload.done as u64,
+            total: preload.total as u64,
+            ready: preload.ready,
+        }),
+        page: state.page as u64,
+        pages: state.pages as u64,
+        page_len: state.page_len as u64,
+        pattern_notes: state.pattern_notes.clone(),
+        project_notes: state.project_notes.clone(),
         queued_pattern_id: state.queued_pattern_id as u64,
+        pattern_queue: state.pattern_queue.iter().map(|&id| id as u64).collect(),
+        fill_id: state.fill_id.map_or(-1, |id| id as i64),
+        fill_playing: state.fill_playing,
+        deck_b_id: state.deck_b_id.map_or(-1, |id| id as i64),
+        crossfade: state.crossfade,
+        last_export: state.last_export.as_ref().map(|report| state::ExportReport {
+            fname: report.fname.clone(),
+            has_integrated_lufs: report.integrated_lufs.is_some(),
+            integrated_lufs: report.integrated_lufs.unwrap_or_default(),
+            true_peak_db: report.true_peak_db,
+            gain_db: report.gain_db,
+        }),
         swing: state.swing as u32,
+        tempo_slew: Some(prost_types::Duration {
+            seconds: state.tempo_slew.as_secs() as i64,
+            nanos: state.tempo_slew.subsec_nanos() as i32,
+        }),
+        song_loop: state.song_loop.map(|(start, end)| state::SongLoop {
+            start: start as u64,
+            end: end as u64,
+        }),
+        song_mode: state.song_mode,
+        song: state.song.iter().map(|entry| state::SongEntry {
+            pattern_id: entry.pattern_id as u64,
+            repeats: entry.repeats as u64,
+        }).collect(),
+        song_position: state.song_position as u64,
+        song_bar: state.song_bar as u64,
+        swap_policy: state.swap_policy as u32,
+        anti_click: state.anti_click,
+        anti_click_ms: state.anti_click_ms as u32,
+        choke_fade_ms: state.choke_fade_ms as u32,
+        metronome: state.metronome,
+        metronome_sample: state.metronome_sample.clone(),
+        count_in_bars: state.count_in_bars as u32,
+        count_in_left: state.count_in_left as u32,
+        macros: state.macros.clone(),
+        sync_out: state.sync_out,
+        sync_out_channel: state.sync_out_channel as u32,
+        sync_out_ppqn: state.sync_out_ppqn as u32,
+        delay_division: state.delay_division as u32,
+        delay_feedback_pct: state.delay_feedback_pct as u32,
+        reverb_size_pct: state.reverb.size_pct as u32,
+        reverb_damping_pct: state.reverb.damping_pct as u32,
+        reverb_wet_pct: state.reverb.wet_pct as u32,
+        master_volume_db: state.master_volume_db as i32,
+        beats_per_bar: state.beats_per_bar as u32,
+        midi_thru: state.midi_thru,
+        midi_thru_channel: state.midi_thru_channel.map_or(-1, |channel| channel as i32),
+        midi_outputs: state.midi_outputs.iter().map(|output| state::MidiOutputState {
+            port_name: output.port_name.clone(),
+            connected: output.connected,
+            clock: output.clock,
+            transport: output.transport,
+            notes: output.notes,
+            cc: output.cc,
+            virtual_port: output.virtual_port,
+        }).collect(),
+        midi_inputs: state.midi_inputs.iter().map(|input| state::MidiInputState {
+            port_name: input.port_name.clone(),
+            connected: input.connected,
+        }).collect(),
+        midi_learning: state.midi_learning,
+        midi_bindings: state.midi_bindings.iter().map(|binding| {
+            let (cc, channel, number) = match binding.trigger {
+                MidiTrigger::Note { channel, note } => (false, channel, note),
+                MidiTrigger::Cc { channel, controller } => (true, channel, controller),
+            };
+            state::MidiBindingState {
+                cc,
+                channel: channel as u32,
+                number: number as u32,
+                command: serde_json::to_string(&binding.command).unwrap_or_default(),
+            }
+        }).collect(),
+        note_input: state.note_input,
+        note_input_channel: state.note_input_channel.map_or(-1, |channel| channel as i32),
+        note_map: state.note_map.iter().map(|mapping| state::NoteMapState {
+            note: mapping.note as u32,
+            track: mapping.track as u64,
+        }).collect(),
+        humanize_timing_ms: state.humanize_timing_ms as u32,
+        humanize_velocity: state.humanize_velocity as u32,
+        accent_velocity: state.accent_velocity as u32,
+        groove_name: state.groove_name.clone(),
+        groove_strength: state.groove_strength as u32,
     };
 
     // Serialize the Protocol Buffer message
@@ -64,7 +289,20 @@ pub fn decode_command(msg: &[u8]) -> Result<Command, Box<dyn Error>> {
     proto_message_to_command(&command_msg)
 }
 
+fn track_indices(args: &state::TrackSelectionArgs) -> Vec<usize> {
+    args.track_indices.iter().map(|&idx| idx as usize).collect()
+}
+
 /// Helper function to convert a Protocol Buffer CommandMessage to Rust Command
+fn proto_to_midi_trigger(cc: bool, channel: u32, number: u32) -> MidiTrigger {
+    let (channel, number) = (channel.min(15) as u8, number.min(127) as u8);
+    if cc {
+        MidiTrigger::Cc { channel, controller: number }
+    } else {
+        MidiTrigger::Note { channel, note: number }
+    }
+}
+
 fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command, Box<dyn Error>> {
     // Convert the command type
     let cmd_type = match ProtoCommand::try_from(proto_cmd.command_type) {
@@ -76,13 +314,24 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
     let cmd = match cmd_type {
         ProtoCommand::PlaySequencer => Command::PlaySequencer,
         ProtoCommand::StopSequencer => Command::StopSequencer,
+        ProtoCommand::PauseSequencer => Command::PauseSequencer,
+        ProtoCommand::ContinueSequencer => Command::ContinueSequencer,
         ProtoCommand::SetTempo => {
-            if let Some(command_message::Args::Tempo(tempo)) = &proto_cmd.args {
-                Command::SetTempo(*tempo as u8)
+            if let Some(command_message::Args::TempoBpm(tempo)) = &proto_cmd.args {
+                Command::SetTempo(*tempo)
+            } else if let Some(command_message::Args::Tempo(tempo)) = &proto_cmd.args {
+                Command::SetTempo(*tempo as f32)
             } else {
                 return Err("Missing tempo argument for SetTempo command".into());
             }
         },
+        ProtoCommand::SetTempoSlew => {
+            if let Some(command_message::Args::TempoSlewMs(slew_ms)) = &proto_cmd.args {
+                Command::SetTempoSlew(*slew_ms as u16)
+            } else {
+                return Err("Missing tempo slew argument for SetTempoSlew command".into());
+            }
+        },
         ProtoCommand::SetDivision => {
             if let Some(command_message::Args::Division(div_value)) = &proto_cmd.args {
                 Command::SetDivision(Division::from(*div_value))
@@ -104,14 +353,161 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
                 return Err("Missing arguments for SetSlotVelocity command".into());
             }
         },
+        ProtoCommand::SetSlotAccent => {
+            if let Some(command_message::Args::SlotAccentArgs(slot_accent_args)) = &proto_cmd.args {
+                Command::SetSlotAccent(slot_accent_args.track_index as usize, slot_accent_args.slot_index as usize, slot_accent_args.accent)
+            } else {
+                return Err("Missing arguments for SetSlotAccent command".into());
+            }
+        },
+        ProtoCommand::SetSlotReverse => {
+            if let Some(command_message::Args::SlotReverseArgs(args)) = &proto_cmd.args {
+                Command::SetSlotReverse(args.track_index as usize, args.slot_index as usize, args.reverse)
+            } else {
+                return Err("Missing arguments for SetSlotReverse command".into());
+            }
+        },
+        ProtoCommand::SetSlotRatchet => {
+            if let Some(command_message::Args::SlotRatchetArgs(slot_ratchet_args)) = &proto_cmd.args {
+                Command::SetSlotRatchet(
+                    slot_ratchet_args.track_index as usize,
+                    slot_ratchet_args.slot_index as usize,
+                    slot_ratchet_args.value.clamp(0, u8::MAX as i32) as u8,
+                )
+            } else {
+                return Err("Missing arguments for SetSlotRatchet command".into());
+            }
+        },
+        ProtoCommand::SetSlotRatchetDecay => {
+            if let Some(command_message::Args::SlotRatchetArgs(slot_ratchet_args)) = &proto_cmd.args {
+                Command::SetSlotRatchetDecay(
+                    slot_ratchet_args.track_index as usize,
+                    slot_ratchet_args.slot_index as usize,
+                    slot_ratchet_args.value.clamp(-100, 100) as i8,
+                )
+            } else {
+                return Err("Missing arguments for SetSlotRatchetDecay command".into());
+            }
+        },
+        ProtoCommand::SetAccentVelocity => {
+            if let Some(command_message::Args::AccentVelocity(velocity)) = &proto_cmd.args {
+                Command::SetAccentVelocity(*velocity as u8)
+            } else {
+                return Err("Missing accent velocity argument for SetAccentVelocity command".into());
+            }
+        },
+        ProtoCommand::SetTrackLoop => {
+            if let Some(command_message::Args::TrackLoopArgs(state::TrackLoopArgs {
+                track_index,
+                loop_points: Some(loop_points),
+            })) = &proto_cmd.args {
+                Command::SetTrackLoop(*track_index as usize, loop_points.start as usize, loop_points.end as usize)
+            } else {
+                return Err("Missing arguments for SetTrackLoop command".into());
+            }
+        },
+        ProtoCommand::ClearTrackLoop => {
+            if let Some(command_message::Args::TrackIndex(track_index)) = &proto_cmd.args {
+                Command::ClearTrackLoop(*track_index as usize)
+            } else {
+                return Err("Missing arguments for ClearTrackLoop command".into());
+            }
+        },
         ProtoCommand::SetTrackLength => {
             if let Some(command_message::Args::TrackLengthArgs(track_length_args)) = &proto_cmd.args {
-                Command::SetTrackLength(track_length_args.track_index as usize)
+                Command::SetTrackLength(track_length_args.track_index as usize, track_length_args.track_length as usize)
             } else {
                 return Err("Missing arguments for SetTrackLength command".into());
             }
         },
+        ProtoCommand::SetTrackDirection => {
+            if let Some(command_message::Args::TrackDirectionArgs(track_direction_args)) = &proto_cmd.args {
+                Command::SetTrackDirection(track_direction_args.track_index as usize, Direction::from(track_direction_args.direction))
+            } else {
+                return Err("Missing arguments for SetTrackDirection command".into());
+            }
+        },
+        ProtoCommand::SetTrackEq => {
+            if let Some(command_message::Args::TrackEqArgs(track_eq_args)) = &proto_cmd.args {
+                Command::SetTrackEq(track_eq_args.track_index as usize, TrackEq {
+                    low_db: track_eq_args.low_db.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+                    high_db: track_eq_args.high_db.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+                })
+            } else {
+                return Err("Missing arguments for SetTrackEq command".into());
+            }
+        },
+        ProtoCommand::SetTrackFxBypass => {
+            if let Some(command_message::Args::TrackFxBypassArgs(args)) = &proto_cmd.args {
+                Command::SetTrackFxBypass(args.track_index as usize, FxBypass {
+                    eq: args.eq,
+                    filter: args.filter,
+                    delay: args.delay,
+                    reverb: args.reverb,
+                })
+            } else {
+                return Err("Missing arguments for SetTrackFxBypass command".into());
+            }
+        },
+        ProtoCommand::SetTrackTimingOffset => {
+            if let Some(command_message::Args::TrackTimingOffsetArgs(timing_offset_args)) = &proto_cmd.args {
+                Command::SetTrackTimingOffset(timing_offset_args.track_index as usize, timing_offset_args.offset_ms as i16)
+            } else {
+                return Err("Missing arguments for SetTrackTimingOffset command".into());
+            }
+        },
+        ProtoCommand::RandomizeTrack => {
+            if let Some(command_message::Args::RandomizeArgs(randomize_args)) = &proto_cmd.args {
+                Command::RandomizeTrack(
+                    randomize_args.track_index as usize,
+                    randomize_args.density,
+                    randomize_args.vel_min as u8,
+                    randomize_args.vel_max as u8,
+                )
+            } else {
+                return Err("Missing arguments for RandomizeTrack command".into());
+            }
+        },
+        ProtoCommand::RandomizePattern => {
+            if let Some(command_message::Args::RandomizeArgs(randomize_args)) = &proto_cmd.args {
+                Command::RandomizePattern(randomize_args.density, randomize_args.vel_min as u8, randomize_args.vel_max as u8)
+            } else {
+                return Err("Missing arguments for RandomizePattern command".into());
+            }
+        },
+        ProtoCommand::SetHumanize => {
+            if let Some(command_message::Args::HumanizeArgs(humanize_args)) = &proto_cmd.args {
+                Command::SetHumanize(humanize_args.timing_ms as u8, humanize_args.velocity as u8)
+            } else {
+                return Err("Missing arguments for SetHumanize command".into());
+            }
+        },
         ProtoCommand::AddPattern => Command::AddPattern,
+        ProtoCommand::DuplicatePattern => {
+            if let Some(command_message::Args::PatternIndex(pattern_index)) = &proto_cmd.args {
+                Command::DuplicatePattern(*pattern_index as usize)
+            } else {
+                return Err("Missing arguments for DuplicatePattern command".into());
+            }
+        },
+        ProtoCommand::CopyTrack => {
+            if let Some(command_message::Args::CopyTrackArgs(args)) = &proto_cmd.args {
+                Command::CopyTrack(
+                    args.src_pattern_index as usize,
+                    args.src_track_index as usize,
+                    args.dst_pattern_index as usize,
+                )
+            } else {
+                return Err("Missing arguments for CopyTrack command".into());
+            }
+        },
+        ProtoCommand::CopyTrackFeel => {
+            if let Some(command_message::Args::TrackFeelArgs(args)) = &proto_cmd.args {
+                Command::CopyTrackFeel(args.src_track_index as usize, args.dst_track_index as usize)
+            } else {
+                return Err("Missing arguments for CopyTrackFeel command".into());
+            }
+        },
         ProtoCommand::RemovePattern => {
             if let Some(command_message::Args::PatternIndex(pattern_index)) = &proto_cmd.args {
                 Command::RemovePattern(*pattern_index as usize)
@@ -126,6 +522,14 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
                 return Err("Missing arguments for SelectPattern command".into());
             }
         },
+        ProtoCommand::QueuePattern => {
+            if let Some(command_message::Args::PatternIndex(pattern_index)) = &proto_cmd.args {
+                Command::QueuePattern(*pattern_index as usize)
+            } else {
+                return Err("Missing arguments for QueuePattern command".into());
+            }
+        },
+        ProtoCommand::ClearPatternQueue => Command::ClearPatternQueue,
         ProtoCommand::SetPatternLength => {
             if let Some(command_message::Args::PatternLength(pattern_length)) = &proto_cmd.args {
                 Command::SetPatternLength(*pattern_length as usize)
@@ -143,6 +547,37 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
         },
         ProtoCommand::ListPatterns => Command::ListPatterns,
         ProtoCommand::ListSamples => Command::ListSamples,
+        ProtoCommand::ListGrooves => Command::ListGrooves,
+        ProtoCommand::SetGroove => {
+            if let Some(command_message::Args::GrooveArgs(groove_args)) = &proto_cmd.args {
+                Command::SetGroove(groove_args.name.clone(), groove_args.strength as u8)
+            } else {
+                return Err("Missing arguments for SetGroove command".into());
+            }
+        },
+        ProtoCommand::ClearGroove => Command::ClearGroove,
+        ProtoCommand::AddCcLane => {
+            if let Some(command_message::Args::CcLaneArgs(cc_lane_args)) = &proto_cmd.args {
+                Command::AddCcLane(cc_lane_args.channel as u8, cc_lane_args.controller as u8)
+            } else {
+                return Err("Missing arguments for AddCcLane command".into());
+            }
+        },
+        ProtoCommand::RemoveCcLane => {
+            if let Some(command_message::Args::CcLaneIndex(lane_index)) = &proto_cmd.args {
+                Command::RemoveCcLane(*lane_index as usize)
+            } else {
+                return Err("Missing lane index for RemoveCcLane command".into());
+            }
+        },
+        ProtoCommand::SetCcStep => {
+            if let Some(command_message::Args::CcStepArgs(cc_step_args)) = &proto_cmd.args {
+                let value = (cc_step_args.value >= 0).then_some(cc_step_args.value as u8);
+                Command::SetCcStep(cc_step_args.lane_index as usize, cc_step_args.step_index as usize, value)
+            } else {
+                return Err("Missing arguments for SetCcStep command".into());
+            }
+        },
         ProtoCommand::SetSwing => {
             if let Some(command_message::Args::Swing(swing)) = &proto_cmd.args {
                 Command::SetSwing(Swing::from(*swing))
@@ -150,7 +585,624 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
                 return Err("Missing swing argument for SetSwing command".into());
             }
         },
+        ProtoCommand::SetSwingGrid => {
+            if let Some(command_message::Args::SwingGrid(swing_grid)) = &proto_cmd.args {
+                Command::SetSwingGrid(SwingGrid::from(*swing_grid as i64))
+            } else {
+                return Err("Missing swing grid argument for SetSwingGrid command".into());
+            }
+        },
+        ProtoCommand::SetSongMode => {
+            if let Some(command_message::Args::SongMode(song_mode)) = &proto_cmd.args {
+                Command::SetSongMode(*song_mode)
+            } else {
+                return Err("Missing song mode argument for SetSongMode command".into());
+            }
+        },
+        ProtoCommand::AddSongEntry => {
+            if let Some(command_message::Args::SongEntryArgs(song_entry_args)) = &proto_cmd.args {
+                Command::AddSongEntry(song_entry_args.pattern_id as usize, song_entry_args.repeats as usize)
+            } else {
+                return Err("Missing arguments for AddSongEntry command".into());
+            }
+        },
+        ProtoCommand::SetSongEntry => {
+            if let Some(command_message::Args::SongEntryArgs(song_entry_args)) = &proto_cmd.args {
+                Command::SetSongEntry(
+                    song_entry_args.entry_index as usize,
+                    song_entry_args.pattern_id as usize,
+                    song_entry_args.repeats as usize,
+                )
+            } else {
+                return Err("Missing arguments for SetSongEntry command".into());
+            }
+        },
+        ProtoCommand::RemoveSongEntry => {
+            if let Some(command_message::Args::SongEntryIndex(entry_index)) = &proto_cmd.args {
+                Command::RemoveSongEntry(*entry_index as usize)
+            } else {
+                return Err("Missing arguments for RemoveSongEntry command".into());
+            }
+        },
+        ProtoCommand::ClearSong => Command::ClearSong,
+        ProtoCommand::SetSongLoop => {
+            if let Some(command_message::Args::SongLoop(song_loop)) = &proto_cmd.args {
+                Command::SetSongLoop(song_loop.start as usize, song_loop.end as usize)
+            } else {
+                return Err("Missing arguments for SetSongLoop command".into());
+            }
+        },
+        ProtoCommand::ClearSongLoop => Command::ClearSongLoop,
+        ProtoCommand::ExportSong => {
+            if let Some(command_message::Args::ExportFname(export_fname)) = &proto_cmd.args {
+                Command::ExportSong(export_fname.clone(), ExportSettings::default())
+            } else if let Some(command_message::Args::ExportArgs(export_args)) = &proto_cmd.args {
+                Command::ExportSong(export_args.fname.clone(), ExportSettings {
+                    bit_depth: BitDepth::from(export_args.bit_depth),
+                    normalize_lufs: export_args.normalize.then_some(export_args.normalize_lufs),
+                })
+            } else {
+                return Err("Missing arguments for ExportSong command".into());
+            }
+        },
+        ProtoCommand::ExportAllPatterns => {
+            if let Some(command_message::Args::ExportPatternsArgs(export_patterns_args)) = &proto_cmd.args {
+                Command::ExportAllPatterns(export_patterns_args.dir.clone(), export_patterns_args.bars_each as usize)
+            } else {
+                return Err("Missing arguments for ExportAllPatterns command".into());
+            }
+        },
+        ProtoCommand::StartRecording => {
+            if let Some(command_message::Args::RecordingFname(fname)) = &proto_cmd.args {
+                Command::StartRecording(fname.clone())
+            } else {
+                return Err("Missing file name for StartRecording command".into());
+            }
+        },
+        ProtoCommand::StopRecording => Command::StopRecording,
+        ProtoCommand::CaptureSample => {
+            if let Some(command_message::Args::SampleCaptureArgs(args)) = &proto_cmd.args {
+                Command::CaptureSample(
+                    args.track_index as usize,
+                    args.fname.clone(),
+                    args.threshold_db.clamp(i8::MIN as i32, i8::MAX as i32) as i8,
+                    args.overwrite,
+                )
+            } else {
+                return Err("Missing arguments for CaptureSample command".into());
+            }
+        },
+        ProtoCommand::StopCapture => Command::StopCapture,
+        ProtoCommand::ReloadSamples => Command::ReloadSamples,
+        ProtoCommand::PreviewSample => {
+            if let Some(command_message::Args::PreviewSampleArgs(args)) = &proto_cmd.args {
+                Command::PreviewSample(args.sample_path.clone(), args.velocity.min(127) as u8)
+            } else {
+                return Err("Missing arguments for PreviewSample command".into());
+            }
+        },
+        ProtoCommand::SliceSample => {
+            if let Some(command_message::Args::SliceSampleArgs(args)) = &proto_cmd.args {
+                Command::SliceSample(args.sample_path.clone(), args.slices as usize)
+            } else {
+                return Err("Missing arguments for SliceSample command".into());
+            }
+        },
+        ProtoCommand::SetAntiClick => {
+            if let Some(command_message::Args::AntiClickArgs(anti_click_args)) = &proto_cmd.args {
+                Command::SetAntiClick(anti_click_args.enabled, anti_click_args.fade_ms as u8)
+            } else {
+                return Err("Missing arguments for SetAntiClick command".into());
+            }
+        },
+        ProtoCommand::SetMetronome => {
+            if let Some(command_message::Args::Metronome(enabled)) = &proto_cmd.args {
+                Command::SetMetronome(*enabled)
+            } else {
+                return Err("Missing arguments for SetMetronome command".into());
+            }
+        },
+        ProtoCommand::SetMetronomeSample => {
+            if let Some(command_message::Args::MetronomeSample(sample_path)) = &proto_cmd.args {
+                Command::SetMetronomeSample(sample_path.clone())
+            } else {
+                return Err("Missing arguments for SetMetronomeSample command".into());
+            }
+        },
+        ProtoCommand::SetCountIn => {
+            if let Some(command_message::Args::CountInBars(bars)) = &proto_cmd.args {
+                Command::SetCountIn(*bars as u8)
+            } else {
+                return Err("Missing arguments for SetCountIn command".into());
+            }
+        },
+        ProtoCommand::DefineMacro => {
+            if let Some(command_message::Args::MacroArgs(macro_args)) = &proto_cmd.args {
+                let commands = macro_args.commands.iter()
+                    .map(proto_message_to_command)
+                    .collect::<Result<Vec<Command>, _>>()?;
+                Command::DefineMacro(macro_args.name.clone(), commands)
+            } else {
+                return Err("Missing arguments for DefineMacro command".into());
+            }
+        },
+        ProtoCommand::RemoveMacro => {
+            if let Some(command_message::Args::MacroName(name)) = &proto_cmd.args {
+                Command::RemoveMacro(name.clone())
+            } else {
+                return Err("Missing arguments for RemoveMacro command".into());
+            }
+        },
+        ProtoCommand::RunMacro => {
+            if let Some(command_message::Args::MacroName(name)) = &proto_cmd.args {
+                Command::RunMacro(name.clone())
+            } else {
+                return Err("Missing arguments for RunMacro command".into());
+            }
+        },
+        ProtoCommand::ListHistory => Command::ListHistory,
+        ProtoCommand::ListAudioDevices => Command::ListAudioDevices,
+        ProtoCommand::SetAudioDevice => {
+            if let Some(command_message::Args::AudioDeviceName(name)) = &proto_cmd.args {
+                Command::SetAudioDevice(name.clone())
+            } else {
+                return Err("Missing device name for SetAudioDevice command".into());
+            }
+        },
+        ProtoCommand::MeasureLatency => Command::MeasureLatency,
+        ProtoCommand::PlayForBars => {
+            if let Some(command_message::Args::Bars(bars)) = &proto_cmd.args {
+                Command::PlayForBars(*bars as usize)
+            } else {
+                return Err("Missing bars for PlayForBars command".into());
+            }
+        },
+        ProtoCommand::SetStopTimer => {
+            if let Some(command_message::Args::StopTimerSecs(secs)) = &proto_cmd.args {
+                Command::SetStopTimer(*secs)
+            } else {
+                return Err("Missing seconds for SetStopTimer command".into());
+            }
+        },
+        ProtoCommand::SetSyncOut => {
+            if let Some(command_message::Args::SyncOutArgs(args)) = &proto_cmd.args {
+                Command::SetSyncOut(args.enabled, args.channel as u16, args.ppqn as u8)
+            } else {
+                return Err("Missing arguments for SetSyncOut command".into());
+            }
+        },
+        ProtoCommand::SetBufferMode => {
+            if let Some(command_message::Args::BufferMode(mode)) = &proto_cmd.args {
+                Command::SetBufferMode(BufferMode::from(*mode))
+            } else {
+                return Err("Missing buffer mode for SetBufferMode command".into());
+            }
+        },
+        ProtoCommand::SetBufferFrames => {
+            if let Some(command_message::Args::BufferFrames(frames)) = &proto_cmd.args {
+                Command::SetBufferFrames(*frames)
+            } else {
+                return Err("Missing frames for SetBufferFrames command".into());
+            }
+        },
+        ProtoCommand::SetSampleRate => {
+            if let Some(command_message::Args::SampleRate(rate)) = &proto_cmd.args {
+                Command::SetSampleRate(*rate)
+            } else {
+                return Err("Missing rate for SetSampleRate command".into());
+            }
+        },
+        ProtoCommand::ListMidiPorts => Command::ListMidiPorts,
+        ProtoCommand::AddMidiOutput => {
+            if let Some(command_message::Args::MidiPortName(port_name)) = &proto_cmd.args {
+                Command::AddMidiOutput(port_name.clone())
+            } else {
+                return Err("Missing port name for AddMidiOutput command".into());
+            }
+        },
+        ProtoCommand::RemoveMidiOutput => {
+            if let Some(command_message::Args::MidiOutputIndex(idx)) = &proto_cmd.args {
+                Command::RemoveMidiOutput(*idx as usize)
+            } else {
+                return Err("Missing output index for RemoveMidiOutput command".into());
+            }
+        },
+        ProtoCommand::SetMidiOutput => {
+            if let Some(command_message::Args::MidiOutputArgs(args)) = &proto_cmd.args {
+                Command::SetMidiOutput(args.index as usize, args.clock, args.transport, args.notes, args.cc)
+            } else {
+                return Err("Missing arguments for SetMidiOutput command".into());
+            }
+        },
+        ProtoCommand::SetRecord => {
+            if let Some(command_message::Args::Record(record)) = &proto_cmd.args {
+                Command::SetRecord(*record)
+            } else {
+                return Err("Missing record argument for SetRecord command".into());
+            }
+        },
+        ProtoCommand::SetRecordMode => {
+            if let Some(command_message::Args::RecordMode(mode)) = &proto_cmd.args {
+                Command::SetRecordMode(RecordMode::from(*mode))
+            } else {
+                return Err("Missing record mode for SetRecordMode command".into());
+            }
+        },
+        ProtoCommand::SetPatternName => {
+            if let Some(command_message::Args::PatternNameArgs(args)) = &proto_cmd.args {
+                Command::SetPatternName(args.pattern_index as usize, args.name.clone())
+            } else {
+                return Err("Missing arguments for SetPatternName command".into());
+            }
+        },
+        ProtoCommand::SetPatternNotes => {
+            if let Some(command_message::Args::Notes(notes)) = &proto_cmd.args {
+                Command::SetPatternNotes(notes.clone())
+            } else {
+                return Err("Missing notes for SetPatternNotes command".into());
+            }
+        },
+        ProtoCommand::SetProjectNotes => {
+            if let Some(command_message::Args::Notes(notes)) = &proto_cmd.args {
+                Command::SetProjectNotes(notes.clone())
+            } else {
+                return Err("Missing notes for SetProjectNotes command".into());
+            }
+        },
+        ProtoCommand::SetTrackArchived => {
+            if let Some(command_message::Args::TrackArchivedArgs(args)) = &proto_cmd.args {
+                Command::SetTrackArchived(args.track_index as usize, args.archived)
+            } else {
+                return Err("Missing arguments for SetTrackArchived command".into());
+            }
+        },
+        ProtoCommand::SetTrackErase => {
+            if let Some(command_message::Args::TrackEraseArgs(args)) = &proto_cmd.args {
+                Command::SetTrackErase(args.track_index as usize, args.erase)
+            } else {
+                return Err("Missing arguments for SetTrackErase command".into());
+            }
+        },
+        ProtoCommand::SetClockSource => {
+            if let Some(command_message::Args::ClockSource(source)) = &proto_cmd.args {
+                Command::SetClockSource(ClockSource::from(*source))
+            } else {
+                return Err("Missing clock source for SetClockSource command".into());
+            }
+        },
+        ProtoCommand::SetLanguage => {
+            if let Some(command_message::Args::Language(language)) = &proto_cmd.args {
+                Command::SetLanguage(Language::from(*language))
+            } else {
+                return Err("Missing language for SetLanguage command".into());
+            }
+        },
+        ProtoCommand::SetChokeFade => {
+            if let Some(command_message::Args::ChokeFadeMs(ms)) = &proto_cmd.args {
+                Command::SetChokeFade((*ms).min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing fade for SetChokeFade command".into());
+            }
+        },
+        ProtoCommand::SetMasterVolume => {
+            if let Some(command_message::Args::MasterVolumeDb(volume_db)) = &proto_cmd.args {
+                Command::SetMasterVolume((*volume_db).clamp(i8::MIN as i32, i8::MAX as i32) as i8)
+            } else {
+                return Err("Missing volume for SetMasterVolume command".into());
+            }
+        },
+        ProtoCommand::SetTrackVelocityVariation => {
+            if let Some(command_message::Args::TrackVelocityVariationArgs(args)) = &proto_cmd.args {
+                Command::SetTrackVelocityVariation(args.track_index as usize, args.amount as u8)
+            } else {
+                return Err("Missing arguments for SetTrackVelocityVariation command".into());
+            }
+        },
+        ProtoCommand::SetTrackPitch => {
+            if let Some(command_message::Args::TrackPitchArgs(args)) = &proto_cmd.args {
+                Command::SetTrackPitch(args.track_index as usize, args.cents.clamp(i16::MIN as i32, i16::MAX as i32) as i16)
+            } else {
+                return Err("Missing arguments for SetTrackPitch command".into());
+            }
+        },
+        ProtoCommand::SetTrackReverse => {
+            if let Some(command_message::Args::TrackReverseArgs(args)) = &proto_cmd.args {
+                Command::SetTrackReverse(args.track_index as usize, args.reverse)
+            } else {
+                return Err("Missing arguments for SetTrackReverse command".into());
+            }
+        },
+        ProtoCommand::SetTrackEnvelope => {
+            if let Some(command_message::Args::TrackEnvelopeArgs(args)) = &proto_cmd.args {
+                let ms = |ms: u32| ms.min(u16::MAX as u32) as u16;
+                Command::SetTrackEnvelope(args.track_index as usize, Envelope {
+                    attack_ms: ms(args.attack_ms),
+                    hold_ms: ms(args.hold_ms),
+                    decay_ms: ms(args.decay_ms),
+                })
+            } else {
+                return Err("Missing arguments for SetTrackEnvelope command".into());
+            }
+        },
+        ProtoCommand::SetTrackFilter => {
+            if let Some(command_message::Args::TrackFilterArgs(args)) = &proto_cmd.args {
+                Command::SetTrackFilter(args.track_index as usize, Filter {
+                    mode: FilterMode::from(args.mode as i64),
+                    cutoff_hz: args.cutoff_hz.min(u16::MAX as u32) as u16,
+                    resonance: args.resonance.min(u8::MAX as u32) as u8,
+                })
+            } else {
+                return Err("Missing arguments for SetTrackFilter command".into());
+            }
+        },
+        ProtoCommand::SetTrackPolyphony => {
+            if let Some(command_message::Args::TrackPolyphonyArgs(args)) = &proto_cmd.args {
+                Command::SetTrackPolyphony(args.track_index as usize, Polyphony {
+                    mode: VoiceMode::from(args.mode as i64),
+                    max_voices: args.max_voices.min(u8::MAX as u32) as u8,
+                    steal: StealPolicy::from(args.steal as i64),
+                })
+            } else {
+                return Err("Missing arguments for SetTrackPolyphony command".into());
+            }
+        },
+        ProtoCommand::SetTrackLayer => {
+            if let Some(command_message::Args::TrackLayerArgs(args)) = &proto_cmd.args {
+                Command::SetTrackLayer(args.track_index as usize, args.min_velocity.min(u8::MAX as u32) as u8, args.sample_path.clone())
+            } else {
+                return Err("Missing arguments for SetTrackLayer command".into());
+            }
+        },
+        ProtoCommand::RemoveTrackLayer => {
+            if let Some(command_message::Args::TrackLayerArgs(args)) = &proto_cmd.args {
+                Command::RemoveTrackLayer(args.track_index as usize, args.min_velocity.min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing arguments for RemoveTrackLayer command".into());
+            }
+        },
+        ProtoCommand::ClearTrackLayers => {
+            if let Some(command_message::Args::TrackIndex(track_index)) = &proto_cmd.args {
+                Command::ClearTrackLayers(*track_index as usize)
+            } else {
+                return Err("Missing arguments for ClearTrackLayers command".into());
+            }
+        },
+        ProtoCommand::SetTrackLoopSync => {
+            if let Some(command_message::Args::TrackLoopSyncArgs(args)) = &proto_cmd.args {
+                let loop_sync = (args.bpm > 0.0).then_some(LoopSync { bpm: args.bpm, mode: FitMode::from(args.mode as i64) });
+                Command::SetTrackLoopSync(args.track_index as usize, loop_sync)
+            } else {
+                return Err("Missing arguments for SetTrackLoopSync command".into());
+            }
+        },
+        ProtoCommand::SetTrackOutput => {
+            if let Some(command_message::Args::TrackOutputArgs(args)) = &proto_cmd.args {
+                Command::SetTrackOutput(args.track_index as usize, args.output.min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing arguments for SetTrackOutput command".into());
+            }
+        },
+        ProtoCommand::SetSampleGain => {
+            if let Some(command_message::Args::SampleGainArgs(args)) = &proto_cmd.args {
+                Command::SetSampleGain(args.sample_path.clone(), SampleGain {
+                    normalize: Normalize::from(args.normalize as i64),
+                    target_db: args.target_db,
+                    offset_db: args.offset_db,
+                })
+            } else {
+                return Err("Missing arguments for SetSampleGain command".into());
+            }
+        },
+        ProtoCommand::SetDelay => {
+            if let Some(command_message::Args::DelayArgs(args)) = &proto_cmd.args {
+                Command::SetDelay(Division::from(args.division), args.feedback_pct.min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing arguments for SetDelay command".into());
+            }
+        },
+        ProtoCommand::SetTrackDelaySend => {
+            if let Some(command_message::Args::TrackDelaySendArgs(args)) = &proto_cmd.args {
+                Command::SetTrackDelaySend(args.track_index as usize, args.send.min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing arguments for SetTrackDelaySend command".into());
+            }
+        },
+        ProtoCommand::SetReverb => {
+            if let Some(command_message::Args::ReverbArgs(args)) = &proto_cmd.args {
+                Command::SetReverb(Reverb {
+                    size_pct: args.size_pct.min(u8::MAX as u32) as u8,
+                    damping_pct: args.damping_pct.min(u8::MAX as u32) as u8,
+                    wet_pct: args.wet_pct.min(u8::MAX as u32) as u8,
+                })
+            } else {
+                return Err("Missing arguments for SetReverb command".into());
+            }
+        },
+        ProtoCommand::SetTrackReverbSend => {
+            if let Some(command_message::Args::TrackReverbSendArgs(args)) = &proto_cmd.args {
+                Command::SetTrackReverbSend(args.track_index as usize, args.send.min(u8::MAX as u32) as u8)
+            } else {
+                return Err("Missing arguments for SetTrackReverbSend command".into());
+            }
+        },
+        ProtoCommand::LoadDemo => {
+            if let Some(command_message::Args::DemoStyle(style)) = &proto_cmd.args {
+                Command::LoadDemo(DemoStyle::from(*style))
+            } else {
+                return Err("Missing demo style for LoadDemo command".into());
+            }
+        },
+        ProtoCommand::MuteTracks => {
+            if let Some(command_message::Args::TrackSelectionArgs(args)) = &proto_cmd.args {
+                Command::MuteTracks(track_indices(args), args.muted)
+            } else {
+                return Err("Missing arguments for MuteTracks command".into());
+            }
+        },
+        ProtoCommand::SetTracksGain => {
+            if let Some(command_message::Args::TrackSelectionArgs(args)) = &proto_cmd.args {
+                Command::SetTracksGain(track_indices(args), args.gain_db.clamp(i8::MIN as i32, i8::MAX as i32) as i8)
+            } else {
+                return Err("Missing arguments for SetTracksGain command".into());
+            }
+        },
+        ProtoCommand::ClearTracks => {
+            if let Some(command_message::Args::TrackSelectionArgs(args)) = &proto_cmd.args {
+                Command::ClearTracks(track_indices(args))
+            } else {
+                return Err("Missing arguments for ClearTracks command".into());
+            }
+        },
+        ProtoCommand::SetTrackMidiNote => {
+            if let Some(command_message::Args::TrackMidiNoteArgs(args)) = &proto_cmd.args {
+                Command::SetTrackMidiNote(args.track_index as usize, args.note as u8)
+            } else {
+                return Err("Missing arguments for SetTrackMidiNote command".into());
+            }
+        },
+        ProtoCommand::ListMidiInputPorts => Command::ListMidiInputPorts,
+        ProtoCommand::AddMidiInput => {
+            if let Some(command_message::Args::MidiPortName(port_name)) = &proto_cmd.args {
+                Command::AddMidiInput(port_name.clone())
+            } else {
+                return Err("Missing port name for AddMidiInput command".into());
+            }
+        },
+        ProtoCommand::RemoveMidiInput => {
+            if let Some(command_message::Args::MidiInputIndex(idx)) = &proto_cmd.args {
+                Command::RemoveMidiInput(*idx as usize)
+            } else {
+                return Err("Missing input index for RemoveMidiInput command".into());
+            }
+        },
+        ProtoCommand::SetNoteInput => {
+            if let Some(command_message::Args::NoteInputArgs(args)) = &proto_cmd.args {
+                let channel = (args.channel >= 0).then_some(args.channel as u8);
+                Command::SetNoteInput(args.enabled, channel)
+            } else {
+                return Err("Missing arguments for SetNoteInput command".into());
+            }
+        },
+        ProtoCommand::MapNoteToTrack => {
+            if let Some(command_message::Args::NoteMapArgs(args)) = &proto_cmd.args {
+                let track_idx = (args.track_index >= 0).then_some(args.track_index as usize);
+                Command::MapNoteToTrack(args.note as u8, track_idx)
+            } else {
+                return Err("Missing arguments for MapNoteToTrack command".into());
+            }
+        },
+        ProtoCommand::LearnMidi => {
+            if let Some(command_message::Args::MidiLearn(args)) = &proto_cmd.args {
+                let command = args.command.as_deref().map(proto_message_to_command).transpose()?;
+                Command::LearnMidi(command.map(Box::new))
+            } else {
+                return Err("Missing arguments for LearnMidi command".into());
+            }
+        },
+        ProtoCommand::BindMidi => {
+            if let Some(command_message::Args::MidiBind(args)) = &proto_cmd.args {
+                let trigger = proto_to_midi_trigger(args.cc, args.channel, args.number);
+                let command = args.command.as_deref().map(proto_message_to_command).transpose()?;
+                Command::BindMidi(trigger, command.map(Box::new))
+            } else {
+                return Err("Missing arguments for BindMidi command".into());
+            }
+        },
+        ProtoCommand::ListMidiMaps => Command::ListMidiMaps,
+        ProtoCommand::SaveMidiMap => {
+            if let Some(command_message::Args::MidiMapName(name)) = &proto_cmd.args {
+                Command::SaveMidiMap(name.clone())
+            } else {
+                return Err("Missing name for SaveMidiMap command".into());
+            }
+        },
+        ProtoCommand::LoadMidiMap => {
+            if let Some(command_message::Args::MidiMapName(name)) = &proto_cmd.args {
+                Command::LoadMidiMap(name.clone())
+            } else {
+                return Err("Missing name for LoadMidiMap command".into());
+            }
+        },
+        ProtoCommand::SetPatternFill => {
+            if let Some(command_message::Args::FillPatternId(fill_id)) = &proto_cmd.args {
+                Command::SetPatternFill((*fill_id >= 0).then_some(*fill_id as usize))
+            } else {
+                return Err("Missing fill pattern id for SetPatternFill command".into());
+            }
+        },
+        ProtoCommand::SetDeckB => {
+            if let Some(command_message::Args::DeckBPatternId(pattern_id)) = &proto_cmd.args {
+                Command::SetDeckB((*pattern_id >= 0).then_some(*pattern_id as usize))
+            } else {
+                return Err("Missing pattern id for SetDeckB command".into());
+            }
+        },
+        ProtoCommand::SetCrossfade => {
+            if let Some(command_message::Args::Crossfade(crossfade)) = &proto_cmd.args {
+                Command::SetCrossfade(*crossfade)
+            } else {
+                return Err("Missing crossfade for SetCrossfade command".into());
+            }
+        },
+        ProtoCommand::TriggerFill => {
+            if let Some(command_message::Args::FillHeld(held)) = &proto_cmd.args {
+                Command::TriggerFill(*held)
+            } else {
+                return Err("Missing held argument for TriggerFill command".into());
+            }
+        },
+        ProtoCommand::SetMidiThru => {
+            if let Some(command_message::Args::MidiThruArgs(midi_thru_args)) = &proto_cmd.args {
+                let channel = (midi_thru_args.channel >= 0).then_some(midi_thru_args.channel as u8);
+                Command::SetMidiThru(midi_thru_args.enabled, channel)
+            } else {
+                return Err("Missing arguments for SetMidiThru command".into());
+            }
+        },
+        ProtoCommand::RestoreSnapshot => {
+            if let Some(command_message::Args::SnapshotId(snapshot_id)) = &proto_cmd.args {
+                Command::RestoreSnapshot(*snapshot_id as usize)
+            } else {
+                return Err("Missing snapshot id for RestoreSnapshot command".into());
+            }
+        },
         ProtoCommand::AddTrack => Command::AddTrack,
+        ProtoCommand::ClearPattern => Command::ClearPattern,
+        ProtoCommand::RandomizeKit => Command::RandomizeKit,
+        ProtoCommand::FindSimilarPatterns => Command::FindSimilarPatterns,
+        ProtoCommand::ListTriggerCounts => Command::ListTriggerCounts,
+        ProtoCommand::ResetTriggerCounts => Command::ResetTriggerCounts,
+        ProtoCommand::ReportSamplePool => Command::ReportSamplePool,
+        ProtoCommand::ReportBudget => Command::ReportBudget,
+        ProtoCommand::SetSampleCacheSize => {
+            if let Some(command_message::Args::SampleCacheMb(mb)) = &proto_cmd.args {
+                Command::SetSampleCacheSize(*mb)
+            } else {
+                return Err("Missing size for SetSampleCacheSize command".into());
+            }
+        },
+        ProtoCommand::CleanSamplePool => Command::CleanSamplePool,
+        ProtoCommand::ClearTrack => {
+            if let Some(command_message::Args::TrackIndex(track_index)) = &proto_cmd.args {
+                Command::ClearTrack(*track_index as usize)
+            } else {
+                return Err("Missing arguments for ClearTrack command".into());
+            }
+        },
+        ProtoCommand::MoveTrack => {
+            if let Some(command_message::Args::MoveTrackArgs(args)) = &proto_cmd.args {
+                Command::MoveTrack(args.from_track_index as usize, args.to_track_index as usize)
+            } else {
+                return Err("Missing arguments for MoveTrack command".into());
+            }
+        },
+        ProtoCommand::RemoveTrack => {
+            if let Some(command_message::Args::TrackIndex(track_index)) = &proto_cmd.args {
+                Command::RemoveTrack(*track_index as usize)
+            } else {
+                return Err("Missing arguments for RemoveTrack command".into());
+            }
+        },
         ProtoCommand::SetTrackSample => {
             if let Some(command_message::Args::TrackSampleArgs(track_sample_args)) = &proto_cmd.args {
                 Command::SetTrackSample(track_sample_args.track_index as usize, track_sample_args.sample_path.clone())
@@ -158,6 +1210,13 @@ fn proto_message_to_command(proto_cmd: &state::CommandMessage) -> Result<Command
                 return Err("Missing arguments for SetTrackSample command".into());
             }
         },
+        ProtoCommand::SetSwapPolicy => {
+            if let Some(command_message::Args::SwapPolicy(swap_policy)) = &proto_cmd.args {
+                Command::SetSwapPolicy(SwapPolicy::from(*swap_policy))
+            } else {
+                return Err("Missing swap policy argument for SetSwapPolicy command".into());
+            }
+        },
         _ => return Err("Unspecified command type".into()),
     };
     
@@ -194,7 +1253,7 @@ impl ZeroMQController {
         loop {
             if let Ok(state) = self.state_rx_ch.try_recv() {
                 match state {
-                    StateUpdate::SeqState(state) => self.last_state = state,
+                    StateUpdate::SeqState(state) => self.last_state = *state,
                     _ => {}
                 }
             }
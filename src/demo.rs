@@ -0,0 +1,134 @@
+use crate::sequencer::{ChokeGrp, Division, Humanize, SavedPattern, SavedTrack, Slot, Swing, SwingGrid};
+use serde::{Serialize, Deserialize};
+
+/// The kit that ships with rdum, everything here is built from it
+const KICK: &str = "kit0/kick.wav";
+const SNARE: &str = "kit0/snare.wav";
+const HAT: &str = "kit0/hat.wav";
+const OPEN_HAT: &str = "kit0/open_hat.wav";
+
+/// Starter grooves so a first run has something to play
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub enum DemoStyle {
+    House = 0,
+    HipHop = 1,
+    Breakbeat = 2,
+}
+
+impl From<i64> for DemoStyle {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => DemoStyle::HipHop,
+            2 => DemoStyle::Breakbeat,
+            _ => DemoStyle::House,
+        }
+    }
+}
+
+impl From<u32> for DemoStyle {
+    fn from(value: u32) -> Self {
+        DemoStyle::from(value as i64)
+    }
+}
+
+/// A demo project, its patterns go in the song chain in order, each
+/// for its number of repeats
+pub struct Demo {
+    pub tempo: f32,
+    pub swing: Swing,
+    pub patterns: Vec<(String, SavedPattern, usize)>,
+}
+
+/// A track from a row of steps, one character a 16th
+///
+/// X is an accented hit, x a hit, o a ghost note and anything else a rest
+fn track(sample_path: &str, steps: &str) -> SavedTrack {
+    SavedTrack {
+        slots: steps.chars().map(|step| Slot {
+            velocity: match step {
+                'X' | 'x' => 110,
+                'o' => 50,
+                _ => 0,
+            },
+            accent: step == 'X',
+            ..Slot::default()
+        }).collect(),
+        sample_path: sample_path.to_string(),
+        ..SavedTrack::default()
+    }
+}
+
+/// A bar of 16ths on kick, snare, hat and open hat, the hats choke each other
+fn pattern(kick: &str, snare: &str, hat: &str, open_hat: &str) -> SavedPattern {
+    SavedPattern {
+        tracks: vec![track(KICK, kick), track(SNARE, snare), track(HAT, hat), track(OPEN_HAT, open_hat)],
+        choke_grps: vec![ChokeGrp::new(vec![2, 3])],
+        division: Division::S,
+        humanize: Humanize::default(),
+        groove: None,
+        cc_lanes: vec![],
+        notes: String::new(),
+        swing_grid: SwingGrid::default(),
+    }
+}
+
+impl Demo {
+    pub fn new(style: DemoStyle) -> Self {
+        match style {
+            DemoStyle::House => Demo {
+                tempo: 124.0,
+                swing: Swing::Off,
+                patterns: vec![
+                    ("house".to_string(), pattern(
+                        "X...x...X...x...",
+                        "....x.......x...",
+                        "oo.ooo.ooo.ooo.o",
+                        "..x...x...x...x.",
+                    ), 3),
+                    ("house fill".to_string(), pattern(
+                        "X...x...X...x.x.",
+                        "....x.......xoxX",
+                        "oo.ooo.ooo.o....",
+                        "..x...x...x.....",
+                    ), 1),
+                ],
+            },
+            DemoStyle::HipHop => Demo {
+                tempo: 90.0,
+                swing: Swing::Half,
+                patterns: vec![
+                    ("hip-hop".to_string(), pattern(
+                        "X......x..x.....",
+                        "....X.......X...",
+                        "x.x.x.x.x.x.x.o.",
+                        "..............x.",
+                    ), 3),
+                    ("hip-hop fill".to_string(), pattern(
+                        "X......x..x..x..",
+                        "....X.......X.oo",
+                        "x.x.x.x.x.x.....",
+                        "................",
+                    ), 1),
+                ],
+            },
+            DemoStyle::Breakbeat => Demo {
+                tempo: 136.0,
+                swing: Swing::Off,
+                patterns: vec![
+                    ("breakbeat".to_string(), pattern(
+                        "X.x.......xx....",
+                        "....X..o.o..X..o",
+                        "x.x.x.x.x.x.x.x.",
+                        "................",
+                    ), 3),
+                    ("breakbeat fill".to_string(), pattern(
+                        "X.x.......x.....",
+                        "....X..o.o..XoXx",
+                        "x.x.x.x.x.x.....",
+                        "..........x.....",
+                    ), 1),
+                ],
+            },
+        }
+    }
+}
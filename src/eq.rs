@@ -0,0 +1,139 @@
+use rodio::Source;
+use serde::{Serialize, Deserialize};
+use std::time::Duration;
+
+/// Most a track's EQ boosts or cuts either band by
+pub const MAX_EQ_DB: i8 = 12;
+/// Corners of the shelves, below the low one is the body of a kick and
+/// above the high one the snap and air
+const LOW_SHELF_HZ: f32 = 250.0;
+const HIGH_SHELF_HZ: f32 = 4000.0;
+
+/// Two band shelving EQ on a track's hits, for tilting a sample darker or
+/// brighter without a full filter section
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub struct TrackEq {
+    /// in dB, 0 leaves the band as it is
+    pub low_db: i8,
+    pub high_db: i8,
+}
+
+impl TrackEq {
+    pub fn clamped(self) -> Self {
+        TrackEq {
+            low_db: self.low_db.clamp(-MAX_EQ_DB, MAX_EQ_DB),
+            high_db: self.high_db.clamp(-MAX_EQ_DB, MAX_EQ_DB),
+        }
+    }
+}
+
+/// A shelf biquad from the RBJ cookbook, with a slope of 1 so it's as
+/// steep as it goes without overshooting
+#[derive(Clone)]
+struct Shelf {
+    b: [f32; 3],
+    a: [f32; 2],
+    /// last two inputs and outputs per channel
+    x: Vec<[f32; 2]>,
+    y: Vec<[f32; 2]>,
+}
+
+impl Shelf {
+    /// Boosts or cuts below the corner, or above it if high, None when flat
+    fn new(high: bool, gain_db: i8, corner_hz: f32, sample_rate: u32, channels: usize) -> Option<Self> {
+        if gain_db == 0 {
+            return None;
+        }
+        let a = 10f32.powf(gain_db.clamp(-MAX_EQ_DB, MAX_EQ_DB) as f32 / 40.0);
+        // corners near nyquist blow up, so they're kept a little under
+        let corner = corner_hz.min(sample_rate as f32 * 0.45);
+        let (sin, cos) = (2.0 * std::f32::consts::PI * corner / sample_rate as f32).sin_cos();
+        let beta = 2.0 * a.sqrt() * sin / std::f32::consts::SQRT_2;
+        // the high shelf is the low one with the sign of cos flipped
+        let (cos, sign) = if high { (-cos, -1.0) } else { (cos, 1.0) };
+        let b = [
+            a * ((a + 1.0) - (a - 1.0) * cos + beta),
+            sign * 2.0 * a * ((a - 1.0) - (a + 1.0) * cos),
+            a * ((a + 1.0) - (a - 1.0) * cos - beta),
+        ];
+        let a0 = (a + 1.0) + (a - 1.0) * cos + beta;
+        Some(Shelf {
+            b: b.map(|b| b / a0),
+            a: [sign * -2.0 * ((a - 1.0) + (a + 1.0) * cos) / a0, ((a + 1.0) + (a - 1.0) * cos - beta) / a0],
+            x: vec![[0.0; 2]; channels],
+            y: vec![[0.0; 2]; channels],
+        })
+    }
+
+    fn process(&mut self, ch: usize, input: f32) -> f32 {
+        let (x, y) = (&mut self.x[ch], &mut self.y[ch]);
+        let out = self.b[0] * input + self.b[1] * x[0] + self.b[2] * x[1] - self.a[0] * y[0] - self.a[1] * y[1];
+        *x = [input, x[0]];
+        *y = [out, y[0]];
+        out
+    }
+}
+
+/// Running state of a track's EQ on one voice, flat bands are left out
+#[derive(Clone)]
+pub struct Shelves {
+    bands: Vec<Shelf>,
+}
+
+impl Shelves {
+    pub fn new(eq: TrackEq, sample_rate: u32, channels: usize) -> Self {
+        let bands = [
+            Shelf::new(false, eq.low_db, LOW_SHELF_HZ, sample_rate, channels),
+            Shelf::new(true, eq.high_db, HIGH_SHELF_HZ, sample_rate, channels),
+        ];
+        Shelves { bands: bands.into_iter().flatten().collect() }
+    }
+
+    pub fn process(&mut self, ch: usize, input: f32) -> f32 {
+        self.bands.iter_mut().fold(input, |s, band| band.process(ch, s))
+    }
+}
+
+/// Source wrapper running a track's EQ over a voice
+pub struct Equalized<S> {
+    inner: S,
+    shelves: Shelves,
+    /// samples played, across all channels
+    played: usize,
+}
+
+impl<S: Source<Item = f32>> Equalized<S> {
+    pub fn new(inner: S, eq: TrackEq) -> Self {
+        let shelves = Shelves::new(eq, inner.sample_rate(), inner.channels() as usize);
+        Equalized { inner, shelves, played: 0 }
+    }
+}
+
+impl<S: Source<Item = f32>> Iterator for Equalized<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let s = self.inner.next()?;
+        let ch = self.played % self.inner.channels() as usize;
+        self.played += 1;
+        Some(self.shelves.process(ch, s))
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Equalized<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
@@ -0,0 +1,572 @@
+use crate::eq::Shelves;
+use crate::audio::{Delay, DelayLine, SendBus, SEND_BUSES};
+use crate::loudness;
+use crate::reverb::{Freeverb, Reverb};
+use crate::sequencer::{file_stem, Biquad, BufferedSample, Envelope, Filter, Pattern, Polyphony, SongEntry, TickParams, VoiceSettings};
+use rodio::Source;
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::error::Error;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::sync::Arc;
+use std::time::Duration;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+
+/// Bounces are always rendered as stereo at this rate
+const SAMPLE_RATE: u32 = 44100;
+const CHANNELS: usize = 2;
+/// How long the last hits are allowed to ring out after the final bar
+const TAIL_SECS: f64 = 2.0;
+/// Normalizing won't push the true peak past this
+const PEAK_CEILING_DB: f64 = -1.0;
+
+/// Sample format of a bounce
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum BitDepth {
+    /// TPDF dithered down from the float mix
+    Int16 = 16,
+    Int24 = 24,
+    #[default]
+    Float32 = 32,
+}
+
+impl From<i64> for BitDepth {
+    fn from(value: i64) -> Self {
+        match value {
+            16 => BitDepth::Int16,
+            24 => BitDepth::Int24,
+            _ => BitDepth::Float32,
+        }
+    }
+}
+
+impl From<u32> for BitDepth {
+    fn from(value: u32) -> Self {
+        BitDepth::from(value as i64)
+    }
+}
+
+/// Render settings for an export
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ExportSettings {
+    pub bit_depth: BitDepth,
+    /// integrated loudness in LUFS to normalize the bounce to
+    pub normalize_lufs: Option<f32>,
+}
+
+/// Measurements of a finished export, taken after any normalizing
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct ExportReport {
+    pub fname: String,
+    /// None if the bounce was silent
+    pub integrated_lufs: Option<f64>,
+    pub true_peak_db: f64,
+    /// gain applied by normalizing
+    pub gain_db: f64,
+}
+
+/// A marker in the bounce where a song entry starts
+struct Section {
+    name: String,
+    start_frame: usize,
+}
+
+/// A playing sample, each track gets as many as its polyphony allows
+/// just like live playback
+struct Voice {
+    sample: Arc<BufferedSample>,
+    /// position in source frames, fractional since the source rate may differ
+    /// 
+    /// Starts negative when the hit is delayed from its pulse
+    pos: f64,
+    gain: f32,
+    envelope: Envelope,
+    /// runs on the output frames, None when the track's filter is off
+    filter: Option<Biquad>,
+    /// the track's EQ, run at the output rate after the filter
+    eq: Shelves,
+    /// source frames stepped per output frame
+    step: f64,
+    /// anti-click fade length in output frames, 0 cuts instantly
+    fade_len: usize,
+    /// output frames played, for the envelope and fade in
+    played: usize,
+    /// output frames left of the fade out once the voice is stopped
+    fading: Option<usize>,
+    /// length of that fade out, chokes fade over their own length
+    fade_out_len: usize,
+    /// level into each effect bus on top of gain, by bus
+    sends: [f32; SEND_BUSES],
+}
+
+impl Voice {
+    /// Speed is the track's tuning, above 1 plays faster and higher
+    fn new(sample: Arc<BufferedSample>, delay: f64, gain: f32, envelope: Envelope, filter: Filter, eq: Shelves, speed: f64, fade_len: usize) -> Self {
+        let step = sample.sample_rate() as f64 * speed / SAMPLE_RATE as f64;
+        Voice {
+            pos: -delay * SAMPLE_RATE as f64 * step,
+            sample,
+            gain,
+            envelope,
+            filter: Biquad::new(filter, SAMPLE_RATE, CHANNELS),
+            eq,
+            step,
+            fade_len,
+            played: 0,
+            fading: None,
+            fade_out_len: fade_len,
+            sends: [0.0; SEND_BUSES],
+        }
+    }
+
+    /// Sends the voice to the effect buses at these levels, 1 is all of it
+    fn with_sends(mut self, sends: [f32; SEND_BUSES]) -> Self {
+        self.sends = sends;
+        self
+    }
+
+    /// Starts fading the voice out, returns false if it should just be cut
+    fn stop(&mut self) -> bool {
+        if self.fade_len == 0 {
+            return false;
+        }
+        self.fading.get_or_insert(self.fade_len);
+        true
+    }
+
+    /// Starts fading the voice out over the choke fade, even with anti-click off
+    fn choke(&mut self, choke_len: usize) {
+        if self.fading.is_none() {
+            self.fade_out_len = choke_len.max(1);
+            self.fading = Some(self.fade_out_len);
+        }
+    }
+
+    /// Mixes the voice into interleaved stereo buffers for the mix and the
+    /// effect buses, returns false once the sample has ended
+    fn mix_into(&mut self, out: &mut [f32], sends_out: &mut [&mut [f32]; SEND_BUSES]) -> bool {
+        let channels = self.sample.channels() as usize;
+        let step = self.step;
+        for (i, frame) in out.chunks_mut(CHANNELS).enumerate() {
+            if self.pos < 0.0 {
+                self.pos += step;
+                continue;
+            }
+            let idx = self.pos as usize;
+            let Some(mut left) = self.sample.sample_at(idx * channels) else {
+                return false;
+            };
+            // mono samples go to both sides
+            let mut right = if channels > 1 { self.sample.sample_at(idx * channels + 1).unwrap_or(left) } else { left };
+            if let Some(filter) = self.filter.as_mut() {
+                left = filter.process(0, left);
+                right = filter.process(1, right);
+            }
+            left = self.eq.process(0, left);
+            right = self.eq.process(1, right);
+            let Some(envelope_gain) = self.envelope.gain_at(self.played as f32 / SAMPLE_RATE as f32) else {
+                return false;
+            };
+            let mut gain = self.gain * envelope_gain;
+            if self.played < self.fade_len {
+                gain *= self.played as f32 / self.fade_len as f32;
+            }
+            self.played += 1;
+            if let Some(remaining) = self.fading.as_mut() {
+                if *remaining == 0 {
+                    return false;
+                }
+                *remaining -= 1;
+                gain *= *remaining as f32 / self.fade_out_len as f32;
+            }
+            frame[0] += left * gain;
+            frame[1] += right * gain;
+            for (send_out, send) in sends_out.iter_mut().zip(self.sends) {
+                send_out[i * CHANNELS] += left * gain * send;
+                send_out[i * CHANNELS + 1] += right * gain * send;
+            }
+            self.pos += step;
+        }
+        true
+    }
+}
+
+/// `Renderer` bounces patterns offline
+///
+/// It runs the same pulse logic as the live sequencer but mixes the triggered
+/// samples into a buffer instead of the output stream, so it can go as fast
+/// as the CPU allows
+pub struct Renderer {
+    patterns: Vec<Pattern>,
+    params: TickParams,
+    /// interleaved stereo frames
+    buffer: Vec<f32>,
+    /// what the voices send to each effect bus, lined up with the buffer
+    send_buffers: [Vec<f32>; SEND_BUSES],
+    delay_line: DelayLine,
+    /// echo time in samples across both channels
+    delay_len: usize,
+    delay_feedback: f32,
+    reverb: Reverb,
+    freeverb: Freeverb,
+    /// voices by track id in the order they started, with the velocity
+    /// each was played at
+    voices: Vec<Vec<(Voice, u8)>>,
+    /// stopped voices finishing their anti-click fade
+    fading: Vec<Voice>,
+    /// anti-click fade length in frames, 0 cuts voices instantly
+    fade_len: usize,
+    /// fade out length in frames of a choked voice
+    choke_len: usize,
+    sections: Vec<Section>,
+    /// position in frames, kept fractional so pulses don't drift
+    /// when their length isn't a whole number of frames
+    time: f64,
+}
+
+impl Renderer {
+    pub fn new(patterns: Vec<Pattern>, params: TickParams, voice_settings: VoiceSettings, delay: Delay, reverb: Reverb) -> Self {
+        let frames = |time: Duration| (time.as_secs_f64() * SAMPLE_RATE as f64) as usize;
+        let delay_time = delay.time(params.pulse_interval * params.ppb as u32);
+        Renderer {
+            delay_line: DelayLine::new(SAMPLE_RATE, CHANNELS as u16),
+            delay_len: (delay_time.as_secs_f64() * SAMPLE_RATE as f64) as usize * CHANNELS,
+            delay_feedback: delay.feedback(),
+            reverb,
+            freeverb: Freeverb::new(SAMPLE_RATE, CHANNELS as u16, reverb),
+            patterns,
+            params,
+            buffer: vec![],
+            send_buffers: Default::default(),
+            voices: vec![],
+            fading: vec![],
+            fade_len: voice_settings.anti_click.map_or(0, frames),
+            choke_len: frames(voice_settings.choke_fade),
+            sections: vec![],
+            time: 0.0,
+        }
+    }
+
+    /// Mixes all playing voices into the buffer up to the given frame
+    fn mix_until(&mut self, end_frame: usize) {
+        let start_frame = self.buffer.len() / CHANNELS;
+        if end_frame <= start_frame {
+            return;
+        }
+        self.buffer.resize(end_frame * CHANNELS, 0.0);
+        self.send_buffers.iter_mut().for_each(|send_buffer| send_buffer.resize(end_frame * CHANNELS, 0.0));
+        let out = &mut self.buffer[start_frame * CHANNELS..];
+        let [delay_buffer, reverb_buffer] = &mut self.send_buffers;
+        let mut sends_out = [&mut delay_buffer[start_frame * CHANNELS..], &mut reverb_buffer[start_frame * CHANNELS..]];
+        for track_voices in self.voices.iter_mut() {
+            track_voices.retain_mut(|(v, _)| v.mix_into(out, &mut sends_out));
+        }
+        self.fading.retain_mut(|v| v.mix_into(out, &mut sends_out));
+        for (i, sample) in out.iter_mut().enumerate() {
+            *sample += self.delay_line.process(sends_out[SendBus::Delay as usize][i], self.delay_len, self.delay_feedback);
+            *sample += self.freeverb.process(i % CHANNELS, sends_out[SendBus::Reverb as usize][i]);
+        }
+    }
+
+    /// Stops all of a track's voices, letting them fade if anti-click is on
+    fn stop_voice(&mut self, track_id: usize) {
+        let Some(track_voices) = self.voices.get_mut(track_id) else {
+            return;
+        };
+        for (mut voice, _) in track_voices.drain(..) {
+            if voice.stop() {
+                self.fading.push(voice);
+            }
+        }
+    }
+
+    /// Makes room for a new hit on a track, stealing a voice if it has to
+    fn steal_voice(&mut self, track_id: usize, polyphony: Polyphony) {
+        let track_voices = &mut self.voices[track_id];
+        let velocities: Vec<u8> = track_voices.iter().map(|(_, vel)| *vel).collect();
+        if let Some(stolen) = polyphony.steal(&velocities) {
+            let (mut voice, _) = track_voices.remove(stolen);
+            if voice.stop() {
+                self.fading.push(voice);
+            }
+        }
+    }
+
+    /// Fades out all of a track's voices over the choke fade
+    fn choke_voices(&mut self, track_id: usize) {
+        let Some(track_voices) = self.voices.get_mut(track_id) else {
+            return;
+        };
+        for (mut voice, _) in track_voices.drain(..) {
+            voice.choke(self.choke_len);
+            self.fading.push(voice);
+        }
+    }
+
+    /// Cuts the voices of a pattern's looping tracks
+    fn stop_loops(&mut self, pattern_id: usize) {
+        let looping: Vec<usize> = self.patterns[pattern_id].tracks.iter().enumerate()
+            .filter(|(_, track)| track.loop_points.is_some())
+            .map(|(i, _)| i)
+            .collect();
+        for i in looping {
+            self.stop_voice(i);
+        }
+    }
+
+    /// Renders one bar of a pattern, playheads carry on from the previous bar
+    fn render_bar(&mut self, pattern_id: usize) {
+        let pulse_frames = self.params.pulse_interval.as_secs_f64() * SAMPLE_RATE as f64;
+        for pulse_idx in 0..self.params.ppb {
+            let pattern = &mut self.patterns[pattern_id];
+            let triggered = pattern.tick(pulse_idx, &self.params);
+            if self.voices.len() < pattern.tracks.len() {
+                self.voices.resize_with(pattern.tracks.len(), Vec::new);
+            }
+            let mut triggered_ids = vec![];
+            let mut voices = vec![];
+            for trigger in triggered {
+                let track = &pattern.tracks[trigger.track_id];
+                let sample = Arc::new((**track.sample_for(trigger.velocity)).clone().with_loop(track.loop_points).with_reverse(trigger.reverse));
+                let gain = trigger.velocity as f32 / 127.0 * track.gain();
+                let eq = Shelves::new(track.active_eq(), SAMPLE_RATE, CHANNELS);
+                let voice = Voice::new(sample, trigger.delay.as_secs_f64(), gain, track.envelope, track.active_filter(), eq, track.speed() as f64, self.fade_len)
+                    .with_sends(track.send_levels().map(|level| level as f32 / 100.0));
+                voices.push((trigger.track_id, voice, trigger.velocity, track.polyphony));
+                triggered_ids.push(trigger.track_id);
+            }
+            let choked: Vec<usize> = (0..pattern.tracks.len())
+                .filter(|&i| pattern.is_trk_choked(&triggered_ids, i))
+                .collect();
+            for (track_id, voice, velocity, polyphony) in voices {
+                self.steal_voice(track_id, polyphony);
+                self.voices[track_id].push((voice, velocity));
+            }
+            for i in choked {
+                self.choke_voices(i);
+            }
+            self.time += pulse_frames;
+            self.mix_until(self.time as usize);
+        }
+    }
+
+    /// Renders the whole song chain, each entry for its number of repeats
+    ///
+    /// Looping voices are cut when the pattern changes like they are live
+    ///
+    /// The loop region is ignored since we want the arrangement start to
+    /// finish. With an empty chain the given pattern is bounced for a bar
+    fn render_song(&mut self, entries: &[SongEntry], pattern_id: usize) {
+        let entries = if entries.is_empty() {
+            vec![SongEntry { pattern_id, repeats: 1 }]
+        } else {
+            entries.to_vec()
+        };
+        let mut last_pattern_id = None;
+        for entry in entries.iter() {
+            if entry.pattern_id >= self.patterns.len() {
+                continue;
+            }
+            // the live sequencer only resets playheads when the pattern changes
+            if last_pattern_id != Some(entry.pattern_id) {
+                self.patterns[entry.pattern_id].reset_playheads();
+                if let Some(last_pattern_id) = last_pattern_id {
+                    self.stop_loops(last_pattern_id);
+                }
+            }
+            last_pattern_id = Some(entry.pattern_id);
+            self.sections.push(Section {
+                name: self.patterns[entry.pattern_id].name.clone(),
+                start_frame: self.time as usize,
+            });
+            // repeats are times through the pattern like they are live
+            for _ in 0..entry.repeats.max(1) * self.patterns[entry.pattern_id].bars() {
+                self.render_bar(entry.pattern_id);
+            }
+        }
+        if let Some(last_pattern_id) = last_pattern_id {
+            self.stop_loops(last_pattern_id);
+        }
+        self.render_tail();
+    }
+
+    /// Lets whatever is still playing ring out
+    fn render_tail(&mut self) {
+        let tail_frames = (TAIL_SECS * SAMPLE_RATE as f64) as usize;
+        self.mix_until(self.time as usize + tail_frames);
+    }
+
+    /// Decodes streamed samples whole, the renderer reads them faster than
+    /// they could come off the disk
+    fn load_streamed(&mut self) -> Result<(), Box<dyn Error>> {
+        for track in self.patterns.iter_mut().flat_map(|pattern| pattern.tracks.iter_mut()) {
+            if track.sample.streamed() {
+                track.sample = Arc::new(track.sample.whole()?);
+            }
+            for (_, sample) in track.layers.iter_mut().filter(|(_, sample)| sample.streamed()) {
+                *sample = Arc::new(sample.whole()?);
+            }
+        }
+        Ok(())
+    }
+
+    /// Clears the bounce so the renderer can start on a new one
+    fn reset(&mut self) {
+        self.buffer.clear();
+        self.send_buffers.iter_mut().for_each(|send_buffer| send_buffer.clear());
+        self.delay_line = DelayLine::new(SAMPLE_RATE, CHANNELS as u16);
+        self.freeverb = Freeverb::new(SAMPLE_RATE, CHANNELS as u16, self.reverb);
+        self.voices.clear();
+        self.fading.clear();
+        self.sections.clear();
+        self.time = 0.0;
+    }
+
+    fn write_wav(&self, path: &str, settings: &ExportSettings) -> Result<(), Box<dyn Error>> {
+        let spec = hound::WavSpec {
+            channels: CHANNELS as u16,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: settings.bit_depth as u16,
+            sample_format: match settings.bit_depth {
+                BitDepth::Float32 => hound::SampleFormat::Float,
+                _ => hound::SampleFormat::Int,
+            },
+        };
+        let mut writer = hound::WavWriter::create(path, spec)?;
+        match settings.bit_depth {
+            BitDepth::Float32 => {
+                for s in self.buffer.iter() {
+                    writer.write_sample(*s)?;
+                }
+            },
+            BitDepth::Int24 => {
+                for s in self.buffer.iter() {
+                    writer.write_sample(quantize(*s, 24, false))?;
+                }
+            },
+            BitDepth::Int16 => {
+                for s in self.buffer.iter() {
+                    writer.write_sample(quantize(*s, 16, true) as i16)?;
+                }
+            },
+        }
+        writer.finalize()?;
+        Ok(())
+    }
+
+    /// Writes a cue sheet with an index per song section so DAWs can
+    /// import the section markers along with the audio
+    fn write_cue(&self, path: &str, wav_fname: &str) -> Result<(), Box<dyn Error>> {
+        let mut file = BufWriter::new(File::create(path)?);
+        writeln!(file, "FILE \"{}\" WAVE", wav_fname)?;
+        for (i, section) in self.sections.iter().enumerate() {
+            // cue time is mm:ss:ff with 75 frames a second
+            let cue_frames = section.start_frame as u64 * 75 / SAMPLE_RATE as u64;
+            writeln!(file, "  TRACK {:02} AUDIO", i + 1)?;
+            writeln!(file, "    TITLE \"{}\"", section.name)?;
+            writeln!(
+                file,
+                "    INDEX 01 {:02}:{:02}:{:02}",
+                cue_frames / 75 / 60,
+                cue_frames / 75 % 60,
+                cue_frames % 75
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Scales the bounce to the target loudness, the gain is held back if
+    /// it would push the true peak over the ceiling. Returns the gain in dB
+    fn normalize(&mut self, target_lufs: f64) -> f64 {
+        let Some(lufs) = loudness::integrated_lufs(&self.buffer, CHANNELS, SAMPLE_RATE) else {
+            return 0.0;
+        };
+        let peak = loudness::true_peak_db(&self.buffer, CHANNELS);
+        let gain_db = (target_lufs - lufs).min(PEAK_CEILING_DB - peak);
+        let gain = 10f64.powf(gain_db / 20.0) as f32;
+        self.buffer.iter_mut().for_each(|s| *s *= gain);
+        gain_db
+    }
+
+    /// Measures the bounce as it stands
+    fn report(&self, fname: String, gain_db: f64) -> ExportReport {
+        ExportReport {
+            fname,
+            integrated_lufs: loudness::integrated_lufs(&self.buffer, CHANNELS, SAMPLE_RATE),
+            true_peak_db: loudness::true_peak_db(&self.buffer, CHANNELS),
+            gain_db,
+        }
+    }
+
+    /// Bounces the song to exports/{fname}.wav with a matching cue sheet
+    pub fn export_song(
+        mut self,
+        entries: &[SongEntry],
+        pattern_id: usize,
+        fname: &str,
+        settings: &ExportSettings,
+    ) -> Result<ExportReport, Box<dyn Error>> {
+        let fname = file_stem(fname)?;
+        self.load_streamed()?;
+        self.render_song(entries, pattern_id);
+        let gain_db = match settings.normalize_lufs {
+            Some(target) => self.normalize(target as f64),
+            None => 0.0,
+        };
+        std::fs::create_dir_all(format!("{PWD}/exports"))?;
+        let wav_fname = format!("{}.wav", fname);
+        self.write_wav(&format!("{PWD}/exports/{}", wav_fname), settings)?;
+        self.write_cue(&format!("{PWD}/exports/{}.cue", fname), &wav_fname)?;
+        Ok(self.report(wav_fname, gain_db))
+    }
+
+    /// Bounces every pattern to its own wav in exports/{dir}, named after the pattern
+    ///
+    /// Each pattern plays from the top for the given number of bars
+    pub fn export_patterns(mut self, dir: &str, bars_each: usize) -> Result<Vec<ExportReport>, Box<dyn Error>> {
+        let dir = format!("{PWD}/exports/{}", file_stem(dir)?);
+        std::fs::create_dir_all(&dir)?;
+        self.load_streamed()?;
+        let mut reports = vec![];
+        let mut used_fnames = HashSet::new();
+        for pattern_id in 0..self.patterns.len() {
+            self.reset();
+            self.patterns[pattern_id].reset_playheads();
+            for _ in 0..bars_each.max(1) {
+                self.render_bar(pattern_id);
+            }
+            self.stop_loops(pattern_id);
+            self.render_tail();
+
+            // patterns can share a name, later ones get numbered, and one
+            // without a name goes by its position
+            let name = file_stem(&self.patterns[pattern_id].name).unwrap_or_else(|_| format!("pattern-{}", pattern_id + 1));
+            let mut wav_fname = format!("{}.wav", name);
+            let mut n = 2;
+            while !used_fnames.insert(wav_fname.clone()) {
+                wav_fname = format!("{}-{}.wav", name, n);
+                n += 1;
+            }
+            self.write_wav(&format!("{}/{}", dir, wav_fname), &ExportSettings::default())?;
+            reports.push(self.report(wav_fname, 0.0));
+        }
+        Ok(reports)
+    }
+}
+
+/// Converts a float sample to an integer of the given bit depth
+///
+/// With dither, triangular noise of +-1 LSB is added before rounding so the
+/// quantization error turns into a steady noise floor instead of distortion
+/// that follows the signal, which is what you hear on fading tails
+fn quantize(s: f32, bits: u32, dither: bool) -> i32 {
+    let max = ((1i64 << (bits - 1)) - 1) as f32;
+    let noise = if dither {
+        rand::random::<f32>() - rand::random::<f32>()
+    } else {
+        0.0
+    };
+    (s * max + noise).round().clamp(-max - 1.0, max) as i32
+}
@@ -0,0 +1,113 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::Duration;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+
+/// A groove template loaded from the grooves directory
+///
+/// Both lists are per step and wrap around when the track is longer
+/// than the template, which is the usual case for a 2 or 4 step feel
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Groove {
+    /// how late each step lands as a fraction of a step, 0-1
+    ///
+    /// Like humanize, grooves can only drag since the pulse has already
+    /// passed by the time we know a step is due
+    pub timing: Vec<f32>,
+    /// velocity scale of each step
+    pub velocity: Vec<f32>,
+}
+
+impl Groove {
+    /// Loads grooves/{name}.json
+    pub fn load(name: &str) -> Result<Arc<Self>, Box<dyn Error>> {
+        let file = std::fs::File::open(format!("{PWD}/grooves/{}.json", name))?;
+        let file = std::io::BufReader::new(file);
+        let groove: Groove = serde_json::from_reader(file)?;
+        Ok(Arc::new(groove))
+    }
+
+    /// Returns the groove delay and velocity for a slot at full strength
+    fn step(&self, slot_idx: usize, vel: u8, step_len: Duration) -> (u8, Duration) {
+        let timing = if self.timing.is_empty() {
+            0.0
+        } else {
+            self.timing[slot_idx % self.timing.len()].clamp(0.0, 1.0)
+        };
+        let scale = if self.velocity.is_empty() {
+            1.0
+        } else {
+            self.velocity[slot_idx % self.velocity.len()].max(0.0)
+        };
+        let vel = (vel as f32 * scale).round().clamp(1.0, 127.0) as u8;
+        (vel, step_len.mul_f32(timing))
+    }
+}
+
+/// Reference a pattern keeps to its groove
+///
+/// Only the name and strength are saved with the pattern, so a pattern
+/// loaded on a machine with the same groove library plays with the same feel
+#[derive(Clone)]
+pub struct PatternGroove {
+    pub name: String,
+    /// percentage the groove is applied by, 0-100
+    pub strength: u8,
+    /// None if the groove couldn't be found in the library, the reference
+    /// is still kept so saving the pattern doesn't lose it
+    pub template: Option<Arc<Groove>>,
+}
+
+impl PatternGroove {
+    /// The template is read beforehand, so a pattern can be made without
+    /// waiting on the disk
+    pub fn new(name: String, strength: u8, template: Option<Arc<Groove>>) -> Self {
+        PatternGroove {
+            name,
+            strength: strength.min(100),
+            template,
+        }
+    }
+
+    /// Applies the groove scaled by its strength, the slot itself is untouched
+    pub fn apply(&self, slot_idx: usize, vel: u8, step_len: Duration) -> (u8, Duration) {
+        match &self.template {
+            Some(groove) => {
+                let strength = self.strength as f32 / 100.0;
+                let (groove_vel, delay) = groove.step(slot_idx, vel, step_len);
+                let vel = vel as f32 + (groove_vel as f32 - vel as f32) * strength;
+                (vel.round() as u8, delay.mul_f32(strength))
+            },
+            None => (vel, Duration::ZERO),
+        }
+    }
+
+    pub fn to_saved(&self) -> SavedGroove {
+        SavedGroove {
+            name: self.name.clone(),
+            strength: self.strength,
+        }
+    }
+}
+
+/// Groove reference as stored in a saved pattern
+#[derive(Clone, Serialize, Deserialize, Hash)]
+pub struct SavedGroove {
+    pub name: String,
+    pub strength: u8,
+}
+
+/// Lists the groove names available in the grooves directory
+pub fn list_grooves() -> Result<Vec<String>, Box<dyn Error>> {
+    let grooves = std::fs::read_dir(format!("{PWD}/grooves"))?;
+    let grooves = grooves.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "json" {
+            return None;
+        }
+        Some(path.file_stem()?.to_str()?.to_string())
+    }).collect();
+    Ok(grooves)
+}
@@ -0,0 +1,176 @@
+use crate::sequencer::{SavedPattern, SongEntry, Swing};
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+use std::hash::{Hash, Hasher};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Time between automatic snapshots while the project is being edited
+const SNAPSHOT_INTERVAL: Duration = Duration::from_secs(60);
+/// Oldest snapshots are deleted once there are more than this
+const MAX_SNAPSHOTS: usize = 120;
+
+/// A pattern as it was when the snapshot was taken
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotPattern {
+    pub name: String,
+    pub pattern: SavedPattern,
+    /// pattern files don't keep the fill as it's an index into the project
+    #[serde(default)]
+    pub fill: Option<usize>,
+}
+
+/// Everything a snapshot brings back on restore
+#[derive(Serialize, Deserialize)]
+pub struct SnapshotState {
+    pub patterns: Vec<SnapshotPattern>,
+    pub pattern_id: usize,
+    pub song: Vec<SongEntry>,
+    pub tempo: f32,
+    pub swing: Swing,
+}
+
+impl SnapshotState {
+    pub fn content_hash(&self) -> Result<u64, Box<dyn Error>> {
+        let mut hash = std::hash::DefaultHasher::new();
+        serde_json::to_vec(self)?.hash(&mut hash);
+        Ok(hash.finish())
+    }
+}
+
+/// The whole project at a point in time
+#[derive(Serialize, Deserialize)]
+pub struct Snapshot {
+    pub id: usize,
+    /// snapshot the project was at before this one
+    ///
+    /// Restoring an old snapshot and editing from there starts a new branch
+    /// off it, the later snapshots of the old branch are kept
+    pub parent: Option<usize>,
+    /// seconds since the unix epoch
+    pub taken: u64,
+    pub state: SnapshotState,
+}
+
+impl Snapshot {
+    /// File name is {id}-{parent}-{taken}.json so a history browser can draw
+    /// the tree from the listing alone, root snapshots have no parent
+    fn fname(&self) -> String {
+        let parent = self.parent.map(|id| id.to_string()).unwrap_or("root".to_string());
+        format!("{}-{}-{}.json", self.id, parent, self.taken)
+    }
+}
+
+/// A snapshot the history has taken on, to be written out to the history
+/// directory off the command loop
+pub struct SnapshotWrite {
+    fname: String,
+    snapshot: Snapshot,
+    /// oldest snapshot, dropped to make room for this one
+    evicted: Option<String>,
+}
+
+impl SnapshotWrite {
+    pub fn save(self) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(format!("{PWD}/history/{}", self.fname))?;
+        let file = std::io::BufWriter::new(file);
+        serde_json::to_writer(file, &self.snapshot)?;
+        if let Some(oldest) = self.evicted {
+            std::fs::remove_file(format!("{PWD}/history/{}", oldest))?;
+        }
+        Ok(())
+    }
+}
+
+/// Periodic whole project snapshots kept in the history directory
+#[derive(Default)]
+pub struct History {
+    /// file names of the snapshots, oldest first
+    snapshots: Vec<String>,
+    /// snapshot the project was last taken or restored at
+    head: Option<usize>,
+    next_id: usize,
+    /// hash of the head state so an unchanged project isn't saved again
+    last_hash: Option<u64>,
+    last_taken: Option<Instant>,
+}
+
+impl History {
+    /// Picks up the snapshots of earlier sessions
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        std::fs::create_dir_all(format!("{PWD}/history"))?;
+        let mut snapshots: Vec<(usize, String)> = std::fs::read_dir(format!("{PWD}/history"))?
+            .filter_map(|entry| {
+                let fname = entry.ok()?.file_name().to_str()?.to_string();
+                let id = fname.split('-').next()?.parse().ok()?;
+                Some((id, fname))
+            })
+            .collect();
+        snapshots.sort();
+        let head = snapshots.last().map(|(id, _)| *id);
+        Ok(History {
+            snapshots: snapshots.into_iter().map(|(_, fname)| fname).collect(),
+            head,
+            next_id: head.map_or(0, |id| id + 1),
+            last_hash: None,
+            last_taken: None,
+        })
+    }
+
+    pub fn files(&self) -> Vec<String> {
+        self.snapshots.clone()
+    }
+
+    pub fn is_due(&self) -> bool {
+        self.last_taken.is_none_or(|taken| taken.elapsed() >= SNAPSHOT_INTERVAL)
+    }
+
+    /// Makes a snapshot of the state the head unless nothing changed since
+    /// the last one, going by its content_hash
+    ///
+    /// Only the listing is updated, the snapshot handed back still has to
+    /// be saved so the file is written without holding up the sequencer
+    pub fn take(&mut self, state: SnapshotState, hash: u64) -> Result<Option<SnapshotWrite>, Box<dyn Error>> {
+        self.last_taken = Some(Instant::now());
+        if self.last_hash == Some(hash) {
+            return Ok(None);
+        }
+        let snapshot = Snapshot {
+            id: self.next_id,
+            parent: self.head,
+            taken: SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs(),
+            state,
+        };
+        let fname = snapshot.fname();
+
+        self.snapshots.push(fname.clone());
+        self.head = Some(snapshot.id);
+        self.next_id += 1;
+        self.last_hash = Some(hash);
+        let evicted = (self.snapshots.len() > MAX_SNAPSHOTS).then(|| self.snapshots.remove(0));
+        Ok(Some(SnapshotWrite { fname, snapshot, evicted }))
+    }
+
+    /// File the snapshot is in, to be read with read_snapshot
+    pub fn fname(&self, id: usize) -> Result<String, Box<dyn Error>> {
+        self.snapshots.iter()
+            .find(|fname| fname.split('-').next() == Some(&id.to_string()))
+            .cloned()
+            .ok_or(format!("no snapshot {}", id).into())
+    }
+
+    /// Makes a snapshot that was read back and restored the head, the next
+    /// snapshot taken will branch from it
+    pub fn restored(&mut self, id: usize, hash: u64) {
+        self.head = Some(id);
+        self.last_hash = Some(hash);
+        self.last_taken = Some(Instant::now());
+    }
+}
+
+/// Reads a snapshot from the history directory, done off the command loop
+pub fn read_snapshot(fname: &str) -> Result<Snapshot, Box<dyn Error>> {
+    let file = std::fs::File::open(format!("{PWD}/history/{}", fname))?;
+    let file = std::io::BufReader::new(file);
+    Ok(serde_json::from_reader(file)?)
+}
@@ -0,0 +1,208 @@
+use serde::{Serialize, Deserialize};
+
+/// Language of the text shown to users, set with language in project.json
+/// or the SetLanguage command
+///
+/// Only what reaches the user through a controller is translated, the
+/// console log stays in English for whoever is looking after the box
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum Language {
+    #[default]
+    English = 0,
+    Spanish = 1,
+    German = 2,
+    French = 3,
+}
+
+impl From<i64> for Language {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => Language::Spanish,
+            2 => Language::German,
+            3 => Language::French,
+            _ => Language::English,
+        }
+    }
+}
+
+impl From<u32> for Language {
+    fn from(value: u32) -> Self {
+        Language::from(value as i64)
+    }
+}
+
+/// Text shown to users, {} marks where a value goes
+#[derive(Debug, Clone, Copy)]
+pub enum Msg {
+    Quit,
+    Latency,
+    Output,
+    Measured,
+    Budget,
+    Peak,
+    Jitter,
+    Missed,
+    Gain,
+    LastExport,
+    Silent,
+    /// a division that isn't a note length
+    PerBar,
+    /// the output device name
+    LostAudioOutput,
+    AudioOutputBack,
+    Playing,
+    Paused,
+    Stopped,
+    Recording,
+    RecordingOff,
+    /// the pattern name
+    Pattern,
+    /// the tempo
+    Tempo,
+    /// the division
+    Division,
+    Volume,
+    InternalClock,
+    /// the tempo of the clock
+    MidiClock,
+    NoMidiClock,
+    Locked,
+    Drifting,
+    /// switches the TUI between its modes
+    Mode,
+    /// says the status line again
+    Status,
+}
+
+impl Language {
+    pub fn text(self, msg: Msg) -> &'static str {
+        use Language::*;
+        match (msg, self) {
+            (Msg::Quit, English) => "Quit",
+            (Msg::Quit, Spanish) => "Salir",
+            (Msg::Quit, German) => "Beenden",
+            (Msg::Quit, French) => "Quitter",
+            (Msg::Latency, English) => "latency",
+            (Msg::Latency, Spanish) => "latencia",
+            (Msg::Latency, German) => "Latenz",
+            (Msg::Latency, French) => "latence",
+            (Msg::Output, English) => "output",
+            (Msg::Output, Spanish) => "salida",
+            (Msg::Output, German) => "Ausgang",
+            (Msg::Output, French) => "sortie",
+            (Msg::Measured, English) => "measured",
+            (Msg::Measured, Spanish) => "medida",
+            (Msg::Measured, German) => "gemessen",
+            (Msg::Measured, French) => "mesurée",
+            (Msg::Budget, English) => "budget",
+            (Msg::Budget, Spanish) => "carga",
+            (Msg::Budget, German) => "Auslastung",
+            (Msg::Budget, French) => "charge",
+            (Msg::Peak, English) => "peak",
+            (Msg::Peak, Spanish) => "pico",
+            (Msg::Peak, German) => "Spitze",
+            (Msg::Peak, French) => "crête",
+            (Msg::Jitter, English) => "jitter",
+            (Msg::Jitter, Spanish) => "jitter",
+            (Msg::Jitter, German) => "Jitter",
+            (Msg::Jitter, French) => "gigue",
+            (Msg::Missed, English) => "missed",
+            (Msg::Missed, Spanish) => "perdidos",
+            (Msg::Missed, German) => "verpasst",
+            (Msg::Missed, French) => "manqués",
+            (Msg::Gain, English) => "gain",
+            (Msg::Gain, Spanish) => "ganancia",
+            (Msg::Gain, German) => "Verstärkung",
+            (Msg::Gain, French) => "gain",
+            (Msg::LastExport, English) => "last export",
+            (Msg::LastExport, Spanish) => "última exportación",
+            (Msg::LastExport, German) => "letzter Export",
+            (Msg::LastExport, French) => "dernier export",
+            (Msg::Silent, English) => "silent",
+            (Msg::Silent, Spanish) => "silencio",
+            (Msg::Silent, German) => "still",
+            (Msg::Silent, French) => "silence",
+            (Msg::PerBar, English) => "{} per bar",
+            (Msg::PerBar, Spanish) => "{} por compás",
+            (Msg::PerBar, German) => "{} pro Takt",
+            (Msg::PerBar, French) => "{} par mesure",
+            (Msg::LostAudioOutput, English) => "lost audio output {}",
+            (Msg::LostAudioOutput, Spanish) => "se perdió la salida de audio {}",
+            (Msg::LostAudioOutput, German) => "Audioausgang {} verloren",
+            (Msg::LostAudioOutput, French) => "sortie audio {} perdue",
+            (Msg::AudioOutputBack, English) => "audio output back",
+            (Msg::AudioOutputBack, Spanish) => "salida de audio recuperada",
+            (Msg::AudioOutputBack, German) => "Audioausgang wieder da",
+            (Msg::AudioOutputBack, French) => "sortie audio rétablie",
+            (Msg::Playing, English) => "playing",
+            (Msg::Playing, Spanish) => "reproduciendo",
+            (Msg::Playing, German) => "läuft",
+            (Msg::Playing, French) => "lecture",
+            (Msg::Paused, English) => "paused",
+            (Msg::Paused, Spanish) => "en pausa",
+            (Msg::Paused, German) => "pausiert",
+            (Msg::Paused, French) => "en pause",
+            (Msg::Stopped, English) => "stopped",
+            (Msg::Stopped, Spanish) => "detenido",
+            (Msg::Stopped, German) => "gestoppt",
+            (Msg::Stopped, French) => "arrêté",
+            (Msg::Recording, English) => "recording",
+            (Msg::Recording, Spanish) => "grabando",
+            (Msg::Recording, German) => "Aufnahme",
+            (Msg::Recording, French) => "enregistrement",
+            (Msg::RecordingOff, English) => "recording off",
+            (Msg::RecordingOff, Spanish) => "grabación desactivada",
+            (Msg::RecordingOff, German) => "Aufnahme aus",
+            (Msg::RecordingOff, French) => "enregistrement arrêté",
+            (Msg::Pattern, English) => "pattern {}",
+            (Msg::Pattern, Spanish) => "patrón {}",
+            (Msg::Pattern, German) => "Pattern {}",
+            (Msg::Pattern, French) => "motif {}",
+            (Msg::Tempo, English) => "tempo {}",
+            (Msg::Tempo, Spanish) => "tempo {}",
+            (Msg::Tempo, German) => "Tempo {}",
+            (Msg::Tempo, French) => "tempo {}",
+            (Msg::Division, English) => "division {}",
+            (Msg::Division, Spanish) => "división {}",
+            (Msg::Division, German) => "Teilung {}",
+            (Msg::Division, French) => "division {}",
+            (Msg::Volume, English) => "volume",
+            (Msg::Volume, Spanish) => "volumen",
+            (Msg::Volume, German) => "Lautstärke",
+            (Msg::Volume, French) => "volume",
+            (Msg::InternalClock, English) => "internal clock",
+            (Msg::InternalClock, Spanish) => "reloj interno",
+            (Msg::InternalClock, German) => "interner Takt",
+            (Msg::InternalClock, French) => "horloge interne",
+            (Msg::MidiClock, English) => "MIDI clock {}",
+            (Msg::MidiClock, Spanish) => "reloj MIDI {}",
+            (Msg::MidiClock, German) => "MIDI-Takt {}",
+            (Msg::MidiClock, French) => "horloge MIDI {}",
+            (Msg::NoMidiClock, English) => "waiting for MIDI clock",
+            (Msg::NoMidiClock, Spanish) => "esperando reloj MIDI",
+            (Msg::NoMidiClock, German) => "warte auf MIDI-Takt",
+            (Msg::NoMidiClock, French) => "en attente d'horloge MIDI",
+            (Msg::Locked, English) => "locked",
+            (Msg::Locked, Spanish) => "sincronizado",
+            (Msg::Locked, German) => "synchron",
+            (Msg::Locked, French) => "calé",
+            (Msg::Drifting, English) => "drifting",
+            (Msg::Drifting, Spanish) => "desfasado",
+            (Msg::Drifting, German) => "driftet",
+            (Msg::Drifting, French) => "en dérive",
+            (Msg::Mode, English) => "Mode",
+            (Msg::Mode, Spanish) => "Modo",
+            (Msg::Mode, German) => "Modus",
+            (Msg::Mode, French) => "Mode",
+            (Msg::Status, English) => "Status",
+            (Msg::Status, Spanish) => "Estado",
+            (Msg::Status, German) => "Status",
+            (Msg::Status, French) => "État",
+        }
+    }
+
+    /// Text with a value filled in
+    pub fn text_with(self, msg: Msg, value: &str) -> String {
+        self.text(msg).replacen("{}", value, 1)
+    }
+}
@@ -0,0 +1,224 @@
+use crate::audio;
+use crate::sequencer::file_stem;
+use rodio::cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use rodio::cpal::{self, FromSample, SizedSample};
+use std::error::Error;
+use std::fs::{File, OpenOptions};
+use std::io::BufWriter;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Longest a capture runs once armed, the buffer is made this big up front
+const MAX_CAPTURE_SECS: f64 = 30.0;
+/// Audio kept from before the threshold was crossed, so the attack of the
+/// hit that set it off isn't lost
+const PREROLL_SECS: f64 = 0.005;
+/// Kept after the last sample over the threshold, then faded out over
+/// the end of it so the trim doesn't click
+const TAIL_SECS: f64 = 0.02;
+const TAIL_FADE_SECS: f64 = 0.005;
+/// At or below this the capture starts straight away, and quieter than
+/// this is silence when trimming the end
+pub const MIN_THRESHOLD_DB: i8 = -60;
+/// Folder in samples/ captures are saved to
+const CAPTURE_DIR: &str = "recorded";
+
+/// A capture from the default input device into a new sample
+///
+/// The input stream can't leave the thread it was opened on, so a thread
+/// of its own holds it until the capture is stopped, then trims what came
+/// in and writes it to samples/recorded/{fname}.wav
+pub struct SampleCapture {
+    pub track_id: usize,
+    stop_tx: mpsc::Sender<()>,
+    triggered: Arc<AtomicBool>,
+}
+
+impl SampleCapture {
+    /// Arms the input, recording starts once it goes over the threshold in
+    /// dBFS. The thread hands done the path of the new sample within the
+    /// samples folder, or why there isn't one, once it's stopped
+    ///
+    /// Unless told to overwrite, a sample that's already there is an error
+    /// before the input is opened
+    pub fn start<F>(fname: &str, threshold_db: i8, overwrite: bool, track_id: usize, done: F) -> Result<Self, Box<dyn Error>>
+    where
+        F: FnOnce(Result<String, String>) + Send + 'static,
+    {
+        let fname = format!("{}.wav", file_stem(fname)?);
+        let sample_path = format!("{}/{}", CAPTURE_DIR, fname);
+        if !overwrite && std::path::Path::new(&format!("{PWD}/samples/{}", sample_path)).exists() {
+            return Err(format!("{} already exists", sample_path).into());
+        }
+        let threshold = if threshold_db <= MIN_THRESHOLD_DB { 0.0 } else { db_to_gain(threshold_db.min(0)) };
+        let triggered = Arc::new(AtomicBool::new(false));
+        let (stop_tx, stop_rx) = mpsc::channel();
+        let (opened_tx, opened_rx) = mpsc::channel();
+        let thread_triggered = triggered.clone();
+        thread::spawn(move || {
+            let (stream, buffer, channels, sample_rate) = match open_input(threshold, thread_triggered.clone()) {
+                Ok(opened) => {
+                    let _ = opened_tx.send(Ok(()));
+                    opened
+                },
+                Err(e) => {
+                    let _ = opened_tx.send(Err(e.to_string()));
+                    return;
+                }
+            };
+            // stopped or the controller went away, either way it's over
+            let _ = stop_rx.recv_timeout(Duration::from_secs_f64(MAX_CAPTURE_SECS));
+            drop(stream);
+            if !thread_triggered.load(Ordering::Relaxed) {
+                done(Err("nothing came in over the threshold".to_string()));
+                return;
+            }
+            let mut samples = std::mem::take(&mut *buffer.lock().unwrap());
+            trim_tail(&mut samples, channels as usize, sample_rate, threshold);
+            done(write_capture(&sample_path, &samples, channels, sample_rate, overwrite)
+                .map(|_| sample_path)
+                .map_err(|e| e.to_string()));
+        });
+        opened_rx.recv()?.map_err(|e| format!("failed to open audio input: {}", e))?;
+        Ok(SampleCapture { track_id, stop_tx, triggered })
+    }
+
+    /// Whether the input has gone over the threshold yet
+    pub fn triggered(&self) -> bool {
+        self.triggered.load(Ordering::Relaxed)
+    }
+
+    /// Ends the capture, the sample is written off the command loop
+    pub fn stop(self) {
+        let _ = self.stop_tx.send(());
+    }
+}
+
+fn db_to_gain(db: i8) -> f32 {
+    10f32.powf(db as f32 / 20.0)
+}
+
+type Capture = Arc<Mutex<Vec<f32>>>;
+
+/// Opens the default input and starts it, returns the stream, where the
+/// samples go and the format they come in
+fn open_input(threshold: f32, triggered: Arc<AtomicBool>) -> Result<(cpal::Stream, Capture, u16, u32), Box<dyn Error>> {
+    let device = audio::host().default_input_device()
+        .ok_or("no audio input device")?;
+    let supported = device.default_input_config()?;
+    let config = supported.config();
+    let frames = |secs: f64| (secs * config.sample_rate.0 as f64) as usize * config.channels as usize;
+    let buffer = Arc::new(Mutex::new(Vec::with_capacity(frames(MAX_CAPTURE_SECS))));
+    let capture = Capturer {
+        buffer: buffer.clone(),
+        channels: config.channels as usize,
+        preroll_len: frames(PREROLL_SECS),
+        threshold,
+        triggered,
+    };
+    let stream = match supported.sample_format() {
+        cpal::SampleFormat::F32 => build_input_stream::<f32>(&device, &config, capture)?,
+        cpal::SampleFormat::I16 => build_input_stream::<i16>(&device, &config, capture)?,
+        cpal::SampleFormat::U16 => build_input_stream::<u16>(&device, &config, capture)?,
+        cpal::SampleFormat::I32 => build_input_stream::<i32>(&device, &config, capture)?,
+        format => return Err(format!("unsupported sample format {}", format).into()),
+    };
+    stream.play()?;
+    Ok((stream, buffer, config.channels, config.sample_rate.0))
+}
+
+/// What the input callback fills
+struct Capturer {
+    /// allocated up front for the longest capture, the callback mustn't
+    buffer: Capture,
+    channels: usize,
+    preroll_len: usize,
+    threshold: f32,
+    triggered: Arc<AtomicBool>,
+}
+
+impl Capturer {
+    fn take<T>(&mut self, data: &[T])
+    where
+        T: SizedSample,
+        f32: FromSample<T>,
+    {
+        // only taken by the capture thread once the stream is dropped
+        let Ok(mut buffer) = self.buffer.try_lock() else {
+            return;
+        };
+        let mut triggered = self.triggered.load(Ordering::Relaxed);
+        for frame in data.chunks(self.channels) {
+            if buffer.len() + frame.len() > buffer.capacity() {
+                break;
+            }
+            let start = buffer.len();
+            buffer.extend(frame.iter().map(|s| s.to_sample::<f32>()));
+            if !triggered && buffer[start..].iter().any(|s| s.abs() >= self.threshold) {
+                triggered = true;
+                self.triggered.store(true, Ordering::Relaxed);
+            }
+        }
+        // until it's set off only the preroll is worth keeping
+        if !triggered && buffer.len() > self.preroll_len {
+            let excess = buffer.len() - self.preroll_len;
+            buffer.drain(..excess);
+        }
+    }
+}
+
+fn build_input_stream<T>(device: &cpal::Device, config: &cpal::StreamConfig, mut capture: Capturer) -> Result<cpal::Stream, Box<dyn Error>>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    Ok(device.build_input_stream::<T, _, _>(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| capture.take(data),
+        move |err| println!("Audio input error: {}", err),
+        None,
+    )?)
+}
+
+/// Cuts the silence off the end, leaving a short tail that fades out
+fn trim_tail(samples: &mut Vec<f32>, channels: usize, sample_rate: u32, threshold: f32) {
+    let floor = threshold.max(db_to_gain(MIN_THRESHOLD_DB));
+    let Some(last) = samples.iter().rposition(|s| s.abs() >= floor) else {
+        return;
+    };
+    let tail = (TAIL_SECS * sample_rate as f64) as usize * channels;
+    let end = (last / channels * channels + channels + tail).min(samples.len());
+    samples.truncate(end);
+    let fade = ((TAIL_FADE_SECS * sample_rate as f64) as usize * channels).min(samples.len());
+    let fade_start = samples.len() - fade;
+    for (i, s) in samples[fade_start..].iter_mut().enumerate() {
+        *s *= 1.0 - (i / channels) as f32 / (fade / channels).max(1) as f32;
+    }
+}
+
+/// Only creates the file when not overwriting, in case something took the
+/// name while the capture was running
+fn write_capture(sample_path: &str, samples: &[f32], channels: u16, sample_rate: u32, overwrite: bool) -> Result<(), Box<dyn Error>> {
+    std::fs::create_dir_all(format!("{PWD}/samples/{}", CAPTURE_DIR))?;
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let path = format!("{PWD}/samples/{}", sample_path);
+    let file = if overwrite {
+        File::create(path)?
+    } else {
+        OpenOptions::new().write(true).create_new(true).open(path)?
+    };
+    let mut writer = hound::WavWriter::new(BufWriter::new(file), spec)?;
+    for s in samples {
+        writer.write_sample(*s)?;
+    }
+    writer.finalize()?;
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use rand::seq::IndexedRandom;
+
+/// Kinds of drum sound by the words their folders or files are named
+/// with, open hats come before hats so they aren't taken for closed ones
+const CATEGORIES: [&str; 12] = [
+    "kick", "snare", "clap", "rim", "open_hat", "hat", "tom", "crash", "ride", "cymbal", "shaker", "perc",
+];
+
+/// What kind of sound a sample is, going by its folder first so a library
+/// sorted into kicks/, snares/ and so on works, then by its file name for
+/// kits with one of each like kit0/kick.wav
+fn category(sample_path: &str) -> Option<&'static str> {
+    let sample_path = sample_path.to_lowercase();
+    let (folder, file) = sample_path.rsplit_once('/').unwrap_or(("", &sample_path));
+    for name in [folder, file] {
+        if let Some(category) = CATEGORIES.into_iter().find(|category| name.contains(category)) {
+            return Some(category);
+        }
+    }
+    None
+}
+
+/// A new sample for each track of the same kind as the one it has, picked
+/// at random out of the sample files. Tracks on a sample that can't be
+/// told apart, or with nothing else of its kind, get None
+pub fn randomize(track_samples: &[String], sample_files: &[String]) -> Vec<Option<String>> {
+    let mut rng = rand::rng();
+    track_samples.iter().map(|current| {
+        let kind = category(current)?;
+        let candidates: Vec<&String> = sample_files.iter()
+            .filter(|sample_path| *sample_path != current && category(sample_path) == Some(kind))
+            .collect();
+        candidates.choose(&mut rng).map(|sample_path| sample_path.to_string())
+    }).collect()
+}
@@ -0,0 +1,146 @@
+/// Loudness below this is treated as silence and is gated out
+const ABSOLUTE_GATE: f64 = -70.0;
+/// Blocks quieter than the ungated loudness by this much are gated out
+const RELATIVE_GATE: f64 = -10.0;
+/// Blocks are 400ms long and start every 100ms
+const BLOCK_SECS: f64 = 0.4;
+const BLOCK_STEP_SECS: f64 = 0.1;
+/// Times the signal is oversampled to find peaks between samples
+const OVERSAMPLE: usize = 4;
+/// Taps either side of the interpolation point
+const INTERP_TAPS: isize = 12;
+
+/// Two pole filter in direct form 1
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 3],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    /// High shelf that models the acoustic effect of the head
+    fn shelf(sample_rate: f64) -> Self {
+        let (gain_db, q, fc) = (3.999_843_853_973_347, 0.707_175_236_955_419_6, 1_681.974_450_955_533);
+        let k = (std::f64::consts::PI * fc / sample_rate).tan();
+        let vh = 10f64.powf(gain_db / 20.0);
+        let vb = vh.powf(0.499_666_774_154_541_6);
+        Biquad::new(
+            [vh + vb * k / q + k * k, 2.0 * (k * k - vh), vh - vb * k / q + k * k],
+            [1.0 + k / q + k * k, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+        )
+    }
+
+    /// High pass that rolls off the lows the ear is less sensitive to
+    fn high_pass(sample_rate: f64) -> Self {
+        let (q, fc) = (0.500_327_037_323_877_3, 38.135_470_876_024_44);
+        let k = (std::f64::consts::PI * fc / sample_rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+        // the gain is left unnormalized as the standard does
+        Biquad::new(
+            [a0, -2.0 * a0, a0],
+            [a0, 2.0 * (k * k - 1.0), 1.0 - k / q + k * k],
+        )
+    }
+
+    /// Normalizes the coefficients by a0
+    fn new(b: [f64; 3], a: [f64; 3]) -> Self {
+        Biquad {
+            b: [b[0] / a[0], b[1] / a[0], b[2] / a[0]],
+            a: [1.0, a[1] / a[0], a[2] / a[0]],
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[1] * self.y[0] - self.a[2] * self.y[1];
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Integrated loudness in LUFS of an interleaved buffer, as in ITU-R BS.1770
+///
+/// Returns None when everything is gated out, which is the case for silence
+/// and anything shorter than a single block
+pub fn integrated_lufs(buffer: &[f32], channels: usize, sample_rate: u32) -> Option<f64> {
+    let frames = buffer.len() / channels;
+    // K-weighted squares of each channel
+    let weighted: Vec<Vec<f64>> = (0..channels).map(|ch| {
+        let mut shelf = Biquad::shelf(sample_rate as f64);
+        let mut high_pass = Biquad::high_pass(sample_rate as f64);
+        (0..frames).map(|i| {
+            let y = high_pass.process(shelf.process(buffer[i * channels + ch] as f64));
+            y * y
+        }).collect()
+    }).collect();
+
+    let block_len = (BLOCK_SECS * sample_rate as f64) as usize;
+    let block_step = (BLOCK_STEP_SECS * sample_rate as f64) as usize;
+    if frames < block_len {
+        return None;
+    }
+    // mean square of each block summed over channels, all channels weigh 1 in stereo
+    let blocks: Vec<f64> = (0..=(frames - block_len) / block_step).map(|j| {
+        let start = j * block_step;
+        weighted.iter().map(|sq| {
+            sq[start..start + block_len].iter().sum::<f64>() / block_len as f64
+        }).sum()
+    }).collect();
+
+    let loudness = |power: f64| -0.691 + 10.0 * power.log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = blocks.iter().cloned().filter(|&p| p > 0.0 && loudness(p) > threshold).collect();
+        if gated.is_empty() {
+            None
+        } else {
+            Some(gated.iter().sum::<f64>() / gated.len() as f64)
+        }
+    };
+    let ungated = gated_mean(ABSOLUTE_GATE)?;
+    let relative = loudness(ungated) + RELATIVE_GATE;
+    gated_mean(relative.max(ABSOLUTE_GATE)).map(loudness)
+}
+
+/// True peak in dBTP of an interleaved buffer
+///
+/// Peaks can land between samples and clip on playback even when no sample
+/// is over, so the signal is oversampled with windowed sinc interpolation
+pub fn true_peak_db(buffer: &[f32], channels: usize) -> f64 {
+    let frames = buffer.len() / channels;
+    let sinc = |x: f64| {
+        if x == 0.0 {
+            1.0
+        } else {
+            let px = std::f64::consts::PI * x;
+            px.sin() / px
+        }
+    };
+    // Hann window over the interpolation span
+    let window = |x: f64| 0.5 + 0.5 * (std::f64::consts::PI * x / (INTERP_TAPS as f64 + 1.0)).cos();
+    let mut peak = 0f64;
+    for ch in 0..channels {
+        let at = |i: isize| {
+            if i < 0 || i as usize >= frames {
+                0.0
+            } else {
+                buffer[i as usize * channels + ch] as f64
+            }
+        };
+        for n in 0..frames as isize {
+            peak = peak.max(at(n).abs());
+            for phase in 1..OVERSAMPLE {
+                let frac = phase as f64 / OVERSAMPLE as f64;
+                let s: f64 = (-INTERP_TAPS + 1..=INTERP_TAPS).map(|k| {
+                    let x = k as f64 - frac;
+                    at(n + k) * sinc(x) * window(x)
+                }).sum();
+                peak = peak.max(s.abs());
+            }
+        }
+    }
+    20.0 * peak.max(f64::MIN_POSITIVE).log10()
+}
@@ -1,26 +1,52 @@
 mod sequencer;
 mod controller;
+mod audio;
+mod budget;
+mod demo;
+mod export;
+mod groove;
+mod history;
+mod i18n;
+mod input;
+mod kit;
+mod loudness;
+mod eq;
+mod metronome;
+mod midi;
+mod midi_map;
+mod project;
+mod recorder;
+mod reverb;
+mod sample_cache;
+mod sample_gain;
+mod sample_pool;
+mod sample_stream;
+mod similarity;
+mod slicer;
+mod stretch;
+#[cfg(feature = "soak")]
+mod soak;
+mod sync;
+mod voices;
 
 use ratatui;                                                                                           
-use rodio::OutputStream;                                                                                     
+use audio::AudioOutput;                                                                                     
 use std::{thread, time::Duration, io};
-use std::sync::Arc;
 use std::error::Error;
 use sequencer::Command;
 use controller::cli::CLIController;
 use crossterm::{event::{self, Event, KeyCode}, terminal};
-use midir::MidiOutput;
+use midir::{MidiInput, MidiOutput};
 
-use sequencer::ChokeGrp;
+use demo::DemoStyle;
                                                                                                                                              
 fn main() -> Result<(), Box<dyn Error>> {      
     let pwd = env!("CARGO_MANIFEST_DIR");       
     println!("{}", pwd);                                                                             
     // Set up the audio output                                                                                                                
-    let (_stream, stream_handle) = OutputStream::try_default()?;
-    let stream_handle = Arc::new(stream_handle);                                                                                                                                                                                             
+    let audio = AudioOutput::open_default(audio::StreamRequest::default())?;                                                                                                                                                                                             
 
-    let mut seq = sequencer::Sequencer::new(stream_handle);
+    let mut seq = sequencer::Sequencer::new(audio);
 
     let midi_out = MidiOutput::new("Sequencer")?;
     for port in midi_out.ports() {
@@ -29,31 +55,34 @@ fn main() -> Result<(), Box<dyn Error>> {
     // let port = midi_out.find_port_by_id("16:0".to_string()).unwrap();
     // seq.connect_midi(port).unwrap();
 
+    let midi_in = MidiInput::new("Sequencer")?;
+    for port in midi_in.ports() {
+        println!("{}", port.id());
+    }
+    // let port = midi_in.find_port_by_id("20:0".to_string()).unwrap();
+    // seq.connect_midi_input(port).unwrap();
+
     let seq_state_rx = seq.get_state_rx();
     let seq_cmd_tx = seq.get_command_tx();
     let mut ctrl = CLIController::new(seq_state_rx, seq_cmd_tx);
 
-    // seq.set_tempo(90);
-    seq.set_division(sequencer::Division::E);
-
-    let trk_hat = seq.add_track("kit0/hat.wav".to_string())?;
-    trk_hat.set_slots_vel(&[50, 0, 0, 0, 0, 127, 32, 0]);
-
-    let trk_kick = seq.add_track("kit0/kick.wav".to_string())?;
-    trk_kick.set_slots_vel(&[127, 0, 0, 90, 127, 0, 0, 75]);
-
-    let trk_snare = seq.add_track("kit0/snare.wav".to_string())?;
-    trk_snare.set_slots_vel(&[0, 0, 127, 0, 0, 47, 127, 0]);         
-
-    let trk_open_hat = seq.add_track("kit0/open_hat.wav".to_string())?;
-    trk_open_hat.set_slots_vel(&[0, 0, 0, 0, 0, 0, 0, 127]);         
-
     let seq_ctx_handle = seq.ctx.clone();
 
     seq_ctx_handle.with_lock(|props| {
-        props.patterns[0].choke_grps.push(ChokeGrp::new(vec![0, 3]));
+        props.load_demo(DemoStyle::House);
     });
 
+    // rdum --soak [minutes] [seed]
+    #[cfg(feature = "soak")]
+    if let Some(pos) = std::env::args().position(|arg| arg == "--soak") {
+        let args: Vec<String> = std::env::args().skip(pos + 1).collect();
+        let minutes: u64 = args.first().and_then(|m| m.parse().ok()).unwrap_or(10);
+        let seed: u64 = args.get(1).and_then(|s| s.parse().ok()).unwrap_or_else(rand::random);
+        // nothing reads state during a soak, it would only pile up
+        drop(ctrl);
+        return soak::run(seq, Duration::from_secs(minutes * 60), seed);
+    }
+
     let mut web_ctrl = controller::web::WebController::new(seq.get_command_tx(), seq.get_state_rx());
     thread::spawn(move || {
         web_ctrl.run();
@@ -0,0 +1,110 @@
+use crate::audio::AudioHandle;
+use crate::sample_cache::SampleCache;
+use crate::sequencer::BufferedSample;
+use crate::voices::{VoiceHandle, VoiceStart};
+use std::error::Error;
+use std::sync::Arc;
+
+/// Rate the built in clicks are generated at
+const CLICK_RATE: u32 = 44100;
+const CLICK_SECS: f32 = 0.03;
+/// Velocities of the downbeat and the other beats
+const ACCENT_VELOCITY: f32 = 1.0;
+const BEAT_VELOCITY: f32 = 0.6;
+
+/// Generates a short decaying sine blip
+fn click(freq: f32) -> Arc<BufferedSample> {
+    let len = (CLICK_SECS * CLICK_RATE as f32) as usize;
+    let buffer = (0..len).map(|i| {
+        let t = i as f32 / CLICK_RATE as f32;
+        (2.0 * std::f32::consts::PI * freq * t).sin() * (-t * 150.0).exp()
+    }).collect();
+    Arc::new(BufferedSample::from_buffer(buffer, 1, CLICK_RATE))
+}
+
+/// `Metronome` clicks on every beat and counts in playback
+///
+/// It plays its own voice on the output so it's never part of a pattern,
+/// and isn't included in exports
+pub struct Metronome {
+    pub enabled: bool,
+    /// empty when using the built in clicks
+    pub sample_path: String,
+    /// downbeat and other beat sounds, the same sample for both if one is set
+    accent_click: Arc<BufferedSample>,
+    beat_click: Arc<BufferedSample>,
+    /// the last click, cut by the next one
+    voice: Option<VoiceHandle>,
+    /// bars clicked before playback starts
+    pub count_in_bars: u8,
+    /// bars left of the count in under way
+    pub count_in_left: u8,
+}
+
+impl Default for Metronome {
+    fn default() -> Self {
+        Metronome {
+            enabled: false,
+            sample_path: String::new(),
+            accent_click: click(2000.0),
+            beat_click: click(1000.0),
+            voice: None,
+            count_in_bars: 0,
+            count_in_left: 0,
+        }
+    }
+}
+
+impl Metronome {
+    /// Uses a sample from the samples directory as the click,
+    /// an empty path goes back to the built in clicks
+    pub fn set_sample(&mut self, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        if sample_path.is_empty() {
+            let default = Metronome::default();
+            self.accent_click = default.accent_click;
+            self.beat_click = default.beat_click;
+        } else {
+            let sample = samples.load(&sample_path)?;
+            self.accent_click = sample.clone();
+            self.beat_click = sample;
+        }
+        self.sample_path = sample_path;
+        Ok(())
+    }
+
+    /// Arms the count in, returns true if there is one
+    pub fn start_count_in(&mut self) -> bool {
+        self.count_in_left = self.count_in_bars;
+        self.count_in_left > 0
+    }
+
+    pub fn counting_in(&self) -> bool {
+        self.count_in_left > 0
+    }
+
+    /// Counts off a bar of the count in
+    pub fn end_bar(&mut self) {
+        self.count_in_left = self.count_in_left.saturating_sub(1);
+    }
+
+    /// Forgets the last click when the output changes
+    pub fn drop_voice(&mut self) {
+        self.voice = None;
+    }
+
+    /// Plays the click for a beat if the metronome is on or counting in
+    pub fn click(&mut self, stream: &AudioHandle, beat: u8) {
+        if !self.enabled && !self.counting_in() {
+            return;
+        }
+        let (sample, vel) = if beat == 0 {
+            (&self.accent_click, ACCENT_VELOCITY)
+        } else {
+            (&self.beat_click, BEAT_VELOCITY)
+        };
+        if let Some(voice) = self.voice.take() {
+            voice.stop();
+        }
+        self.voice = Some(stream.play((**sample).clone().voice(), VoiceStart { gain: vel, ..VoiceStart::default() }));
+    }
+}
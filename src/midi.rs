@@ -0,0 +1,282 @@
+use midir::{MidiInput, MidiInputConnection, MidiOutput, MidiOutputConnection};
+use serde::{Serialize, Deserialize};
+use std::collections::BTreeMap;
+use std::error::Error;
+
+/// Echoes incoming midi to the output so a keyboard plugged into us can
+/// still reach the gear downstream
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct MidiThru {
+    pub enabled: bool,
+    /// channel 0-15 everything is moved onto, None keeps the original channel
+    pub channel: Option<u8>,
+}
+
+impl MidiThru {
+    /// The message to pass on, if any
+    ///
+    /// Only channel messages go through. Clock and transport from the input
+    /// would fight our own clock, and sysex is device specific
+    pub fn remap(&self, message: &[u8]) -> Option<Vec<u8>> {
+        let status = *message.first()?;
+        if !self.enabled || !(0x80..0xF0).contains(&status) {
+            return None;
+        }
+        let mut message = message.to_vec();
+        if let Some(channel) = self.channel {
+            message[0] = (status & 0xF0) | channel.min(15);
+        }
+        Some(message)
+    }
+}
+
+/// Plays tracks from the notes coming in, so a pad controller or keyboard
+/// can play the kit live
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct NoteInput {
+    pub enabled: bool,
+    /// channel 0-15 notes are taken from, None takes them on any
+    pub channel: Option<u8>,
+    /// notes that play a track by its index, any other note plays the
+    /// track that sends it
+    #[serde(default)]
+    pub notes: BTreeMap<u8, usize>,
+}
+
+impl NoteInput {
+    /// Note and velocity of a note on it takes, a note on at velocity 0 is
+    /// a note off
+    pub fn note_on(&self, message: &[u8]) -> Option<(u8, u8)> {
+        let [status, note, velocity] = *message else {
+            return None;
+        };
+        if !self.enabled || status & 0xF0 != 0x90 || velocity == 0 {
+            return None;
+        }
+        if self.channel.is_some_and(|channel| channel != status & 0x0F) {
+            return None;
+        }
+        Some((note, velocity))
+    }
+}
+
+/// Channel track notes go out on, 10 is the general midi drum channel
+pub const DRUM_CHANNEL: u8 = 9;
+/// Note of the first track, general midi kick
+pub const BASE_NOTE: u8 = 36;
+/// Name other apps on the machine see our virtual ports under
+pub const VIRTUAL_PORT_NAME: &str = "rdum";
+
+/// Kinds of message a destination can be set to receive
+#[derive(Debug, Clone, Copy)]
+pub enum MidiKind {
+    Clock,
+    /// start, stop and continue
+    Transport,
+    /// track notes and anything echoed from the input
+    Note,
+    Cc,
+}
+
+/// An output port and what gets sent to it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiDestination {
+    /// port name as listed by the system, ids can change between sessions
+    pub port_name: String,
+    pub clock: bool,
+    pub transport: bool,
+    pub notes: bool,
+    pub cc: bool,
+    /// our own port for apps on the same machine rather than a device
+    #[serde(default)]
+    pub virtual_port: bool,
+}
+
+impl MidiDestination {
+    /// Clock and transport only, which is what syncing gear wants
+    pub fn new(port_name: String) -> Self {
+        MidiDestination {
+            port_name,
+            clock: true,
+            transport: true,
+            notes: false,
+            cc: false,
+            virtual_port: false,
+        }
+    }
+
+    /// Virtual output that gets everything, a DAW can filter what it doesn't want
+    pub fn virtual_port() -> Self {
+        MidiDestination {
+            port_name: VIRTUAL_PORT_NAME.to_string(),
+            clock: true,
+            transport: true,
+            notes: true,
+            cc: true,
+            virtual_port: true,
+        }
+    }
+
+    fn accepts(&self, kind: MidiKind) -> bool {
+        match kind {
+            MidiKind::Clock => self.clock,
+            MidiKind::Transport => self.transport,
+            MidiKind::Note => self.notes,
+            MidiKind::Cc => self.cc,
+        }
+    }
+}
+
+/// Connections to every midi destination
+///
+/// A destination that fails to connect is kept so its settings aren't lost
+/// while the device is unplugged
+#[derive(Default)]
+pub struct MidiOutputs {
+    destinations: Vec<MidiDestination>,
+    conns: Vec<Option<MidiOutputConnection>>,
+}
+
+impl MidiOutputs {
+    /// Names of the output ports on the system
+    pub fn list_ports() -> Result<Vec<String>, Box<dyn Error>> {
+        let midi_output = MidiOutput::new("Sequencer")?;
+        Ok(midi_output.ports().iter().filter_map(|port| midi_output.port_name(port).ok()).collect())
+    }
+
+    fn connect(destination: &MidiDestination) -> Result<MidiOutputConnection, Box<dyn Error>> {
+        if destination.virtual_port {
+            return Self::create_virtual();
+        }
+        let port_name = &destination.port_name;
+        let midi_output = MidiOutput::new("Sequencer")?;
+        let port = midi_output.ports().into_iter()
+            .find(|port| midi_output.port_name(port).is_ok_and(|name| &name == port_name))
+            .ok_or(format!("no midi output named {}", port_name))?;
+        Ok(midi_output.connect(&port, "Sequencer")?)
+    }
+
+    #[cfg(unix)]
+    fn create_virtual() -> Result<MidiOutputConnection, Box<dyn Error>> {
+        use midir::os::unix::VirtualOutput;
+        Ok(MidiOutput::new(VIRTUAL_PORT_NAME)?.create_virtual(VIRTUAL_PORT_NAME)?)
+    }
+
+    #[cfg(not(unix))]
+    fn create_virtual() -> Result<MidiOutputConnection, Box<dyn Error>> {
+        Err("virtual midi ports aren't supported on this platform".into())
+    }
+
+    pub fn has_virtual(&self) -> bool {
+        self.destinations.iter().any(|destination| destination.virtual_port)
+    }
+
+    pub fn add(&mut self, destination: MidiDestination) {
+        let conn = match Self::connect(&destination) {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                println!("Failed to connect midi output {}: {}", destination.port_name, e);
+                None
+            }
+        };
+        self.destinations.push(destination);
+        self.conns.push(conn);
+    }
+
+    pub fn remove(&mut self, idx: usize) {
+        if idx < self.destinations.len() {
+            self.destinations.remove(idx);
+            self.conns.remove(idx);
+        }
+    }
+
+    pub fn get_mut(&mut self, idx: usize) -> Option<&mut MidiDestination> {
+        self.destinations.get_mut(idx)
+    }
+
+    pub fn destinations(&self) -> &[MidiDestination] {
+        &self.destinations
+    }
+
+    pub fn is_connected(&self, idx: usize) -> bool {
+        self.conns.get(idx).is_some_and(|conn| conn.is_some())
+    }
+
+    /// Sends to every connected destination that takes this kind of message
+    pub fn send(&mut self, kind: MidiKind, message: &[u8]) {
+        for (destination, conn) in self.destinations.iter().zip(self.conns.iter_mut()) {
+            if let Some(conn) = conn {
+                if destination.accepts(kind) {
+                    if let Err(e) = conn.send(message) {
+                        println!("Failed to send midi to {}: {}", destination.port_name, e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Connections to the midi inputs listened on, by port name
+///
+/// Like the outputs, an input that fails to connect is kept so it's there
+/// once the device is plugged back in and the project loaded again
+#[derive(Default)]
+pub struct MidiInputs {
+    port_names: Vec<String>,
+    conns: Vec<Option<MidiInputConnection<()>>>,
+}
+
+impl MidiInputs {
+    /// Names of the input ports on the system
+    pub fn list_ports() -> Result<Vec<String>, Box<dyn Error>> {
+        let midi_input = MidiInput::new("Sequencer")?;
+        Ok(midi_input.ports().iter().filter_map(|port| midi_input.port_name(port).ok()).collect())
+    }
+
+    fn connect<F>(port_name: &str, handler: F) -> Result<MidiInputConnection<()>, Box<dyn Error>>
+    where
+        F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+    {
+        let midi_input = MidiInput::new("Sequencer")?;
+        let port = midi_input.ports().into_iter()
+            .find(|port| midi_input.port_name(port).is_ok_and(|name| name == port_name))
+            .ok_or(format!("no midi input named {}", port_name))?;
+        Ok(midi_input.connect(&port, "Sequencer", handler, ())?)
+    }
+
+    /// Starts listening on the port, every message goes to the handler
+    pub fn add<F>(&mut self, port_name: String, handler: F)
+    where
+        F: FnMut(u64, &[u8], &mut ()) + Send + 'static,
+    {
+        let conn = match Self::connect(&port_name, handler) {
+            Ok(conn) => Some(conn),
+            Err(e) => {
+                println!("Failed to connect midi input {}: {}", port_name, e);
+                None
+            }
+        };
+        self.port_names.push(port_name);
+        self.conns.push(conn);
+    }
+
+    /// Stops listening on an input and hands back its connection
+    ///
+    /// Closing waits for the handler to finish, so it has to be dropped
+    /// somewhere the handler isn't waiting on
+    pub fn remove(&mut self, idx: usize) -> Option<MidiInputConnection<()>> {
+        if idx >= self.port_names.len() {
+            return None;
+        }
+        self.port_names.remove(idx);
+        self.conns.remove(idx)
+    }
+
+    pub fn port_names(&self) -> &[String] {
+        &self.port_names
+    }
+
+    pub fn is_connected(&self, idx: usize) -> bool {
+        self.conns.get(idx).is_some_and(|conn| conn.is_some())
+    }
+}
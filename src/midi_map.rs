@@ -0,0 +1,123 @@
+use crate::sequencer::{file_stem, Command};
+use serde::{Serialize, Deserialize};
+use std::collections::HashSet;
+use std::error::Error;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// A cc counts as pressed from this value up
+const CC_PRESSED: u8 = 64;
+
+/// A note or cc on an input that runs a command
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum MidiTrigger {
+    Note { channel: u8, note: u8 },
+    Cc { channel: u8, controller: u8 },
+}
+
+impl MidiTrigger {
+    /// The trigger a message is for and whether it's held down by it
+    fn from_message(message: &[u8]) -> Option<(Self, bool)> {
+        let [status, number, value] = *message else {
+            return None;
+        };
+        let channel = status & 0x0F;
+        match status & 0xF0 {
+            0x80 => Some((MidiTrigger::Note { channel, note: number }, false)),
+            0x90 => Some((MidiTrigger::Note { channel, note: number }, value > 0)),
+            0xB0 => Some((MidiTrigger::Cc { channel, controller: number }, value >= CC_PRESSED)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MidiBinding {
+    pub trigger: MidiTrigger,
+    pub command: Command,
+}
+
+/// Commands bound to the notes and ccs of a controller
+///
+/// The one in use is kept in project.json, and can be saved to and loaded
+/// from midi_maps/{name}.json to switch between controllers
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MidiMap {
+    pub bindings: Vec<MidiBinding>,
+}
+
+impl MidiMap {
+    /// Binds the command to the trigger, replacing what was bound to it,
+    /// None unbinds it
+    ///
+    /// Commands that edit the map can't be bound, so pressing something
+    /// can't rebind itself
+    pub fn bind(&mut self, trigger: MidiTrigger, command: Option<Command>) {
+        self.bindings.retain(|binding| binding.trigger != trigger);
+        if let Some(command) = command.filter(|command| !matches!(command,
+            Command::LearnMidi(_) | Command::BindMidi(..) | Command::LoadMidiMap(_)
+        )) {
+            self.bindings.push(MidiBinding { trigger, command });
+        }
+    }
+
+    pub fn get(&self, trigger: &MidiTrigger) -> Option<&Command> {
+        self.bindings.iter().find(|binding| binding.trigger == *trigger).map(|binding| &binding.command)
+    }
+
+    /// Loads midi_maps/{name}.json
+    pub fn load(name: &str) -> Result<Self, Box<dyn Error>> {
+        let file = std::fs::File::open(format!("{PWD}/midi_maps/{}.json", file_stem(name)?))?;
+        let file = std::io::BufReader::new(file);
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    /// Saves to midi_maps/{name}.json, writing over a map with the same name
+    pub fn save(&self, name: &str) -> Result<(), Box<dyn Error>> {
+        let name = file_stem(name)?;
+        std::fs::create_dir_all(format!("{PWD}/midi_maps"))?;
+        let file = std::fs::File::create(format!("{PWD}/midi_maps/{}.json", name))?;
+        let file = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+}
+
+pub fn list_midi_maps() -> Result<Vec<String>, Box<dyn Error>> {
+    let maps = match std::fs::read_dir(format!("{PWD}/midi_maps")) {
+        Ok(maps) => maps,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+        Err(e) => return Err(e.into()),
+    };
+    let maps = maps.filter_map(|entry| {
+        let path = entry.ok()?.path();
+        if path.extension()? != "json" {
+            return None;
+        }
+        Some(path.file_stem()?.to_str()?.to_string())
+    }).collect();
+    Ok(maps)
+}
+
+/// What the midi input needs to learn and run bindings
+#[derive(Default)]
+pub struct MidiLearn {
+    /// bound to the next note or cc pressed
+    pub armed: Option<Command>,
+    /// notes and ccs held down, a command runs once per press so a cc
+    /// knob only runs it again once it's been turned back under halfway
+    held: HashSet<MidiTrigger>,
+}
+
+impl MidiLearn {
+    /// The trigger a message is for and whether it's just been pressed
+    pub fn press(&mut self, message: &[u8]) -> Option<(MidiTrigger, bool)> {
+        let (trigger, down) = MidiTrigger::from_message(message)?;
+        let pressed = if down {
+            self.held.insert(trigger)
+        } else {
+            self.held.remove(&trigger);
+            false
+        };
+        Some((trigger, pressed))
+    }
+}
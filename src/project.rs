@@ -0,0 +1,132 @@
+use crate::audio::{BufferMode, Delay, StreamRequest, SyncOut};
+use crate::controller::cli::TuiMode;
+use crate::i18n::Language;
+use crate::midi::{MidiDestination, MidiThru, NoteInput};
+use crate::midi_map::MidiMap;
+use crate::reverb::Reverb;
+use crate::sequencer::Command;
+use crate::sync::ClockSource;
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+
+/// A named list of commands run one after the other
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Macro {
+    pub name: String,
+    pub commands: Vec<Command>,
+}
+
+/// Settings kept between sessions in project.json
+///
+/// Every field has a default so project files from older versions still load
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Project {
+    #[serde(default)]
+    pub macros: Vec<Macro>,
+    #[serde(default)]
+    pub midi_thru: MidiThru,
+    #[serde(default)]
+    pub midi_outputs: Vec<MidiDestination>,
+    /// input port names listened on
+    #[serde(default)]
+    pub midi_inputs: Vec<String>,
+    #[serde(default)]
+    pub note_input: NoteInput,
+    /// commands bound to notes and ccs coming in
+    #[serde(default)]
+    pub midi_map: MidiMap,
+    /// opens virtual midi ports named rdum on startup, not on Windows
+    #[serde(default)]
+    pub virtual_ports: bool,
+    #[serde(default)]
+    pub clock_source: ClockSource,
+    /// output device name, the system default if unset or not found
+    #[serde(default)]
+    pub audio_device: Option<String>,
+    #[serde(default)]
+    pub buffer_mode: BufferMode,
+    /// exact frames per buffer, over buffer_mode when set
+    #[serde(default)]
+    pub buffer_frames: Option<u32>,
+    /// output sample rate, the device's default when unset
+    #[serde(default)]
+    pub sample_rate: Option<u32>,
+    #[serde(default)]
+    pub sync_out: SyncOut,
+    /// the delay bus tracks send to
+    #[serde(default)]
+    pub delay: Delay,
+    /// the reverb bus tracks send to
+    #[serde(default)]
+    pub reverb: Reverb,
+    /// level of the whole mix in whole dB, 0 is unity and -60 silent
+    #[serde(default)]
+    pub master_volume_db: i8,
+    /// playback stops at the end of the bar after playing this long, 0 is off
+    #[serde(default)]
+    pub stop_timer_secs: u32,
+    /// megabytes of samples no track is using kept decoded in case they're
+    /// picked again, 0 frees them straight away
+    #[serde(default)]
+    pub sample_cache_mb: u32,
+    /// free text for the user, arrangement reminders and the like
+    #[serde(default)]
+    pub notes: String,
+    /// of the text controllers show, the console log is always English
+    #[serde(default)]
+    pub language: Language,
+    /// how the TUI draws, high contrast or plain lines for screen readers
+    #[serde(default)]
+    pub tui_mode: TuiMode,
+}
+
+impl Project {
+    /// Loads project.json, a missing file is an empty project
+    pub fn load() -> Result<Self, Box<dyn Error>> {
+        let file = match std::fs::File::open(format!("{PWD}/project.json")) {
+            Ok(file) => file,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Project::default()),
+            Err(e) => return Err(e.into()),
+        };
+        let file = std::io::BufReader::new(file);
+        Ok(serde_json::from_reader(file)?)
+    }
+
+    pub fn save(&self) -> Result<(), Box<dyn Error>> {
+        let file = std::fs::File::create(format!("{PWD}/project.json"))?;
+        let file = std::io::BufWriter::new(file);
+        serde_json::to_writer_pretty(file, self)?;
+        Ok(())
+    }
+
+    /// How the output stream should be opened
+    pub fn stream_request(&self) -> StreamRequest {
+        StreamRequest {
+            buffer_mode: self.buffer_mode,
+            buffer_frames: self.buffer_frames,
+            sample_rate: self.sample_rate,
+            channels: None,
+        }
+    }
+
+    /// Adds a macro, replacing any with the same name
+    ///
+    /// Macros can't run other macros so there's no way to make them loop
+    pub fn set_macro(&mut self, name: String, commands: Vec<Command>) {
+        let commands = commands.into_iter().filter(|cmd| {
+            !matches!(cmd, Command::RunMacro(_) | Command::DefineMacro(..))
+        }).collect();
+        self.remove_macro(&name);
+        self.macros.push(Macro { name, commands });
+    }
+
+    pub fn remove_macro(&mut self, name: &str) {
+        self.macros.retain(|m| m.name != name);
+    }
+
+    pub fn get_macro(&self, name: &str) -> Option<&Macro> {
+        self.macros.iter().find(|m| m.name == name)
+    }
+}
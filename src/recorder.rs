@@ -0,0 +1,130 @@
+use crate::sequencer::file_stem;
+use std::error::Error;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::mpsc::{self, Receiver, SyncSender};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Samples handed from the stream to the writer at a time
+const CHUNK_LEN: usize = 4096;
+/// Chunks made up front, about 3 seconds of stereo at 44.1kHz for the
+/// writer to fall behind by before samples are dropped
+const CHUNKS: usize = 64;
+
+/// Shared between a recording and its tap in the stream
+#[derive(Default)]
+struct RecordControl {
+    stopping: AtomicBool,
+    /// samples lost because the writer fell behind
+    dropped: AtomicU64,
+}
+
+/// The stream callback's end of a recording
+///
+/// The mix goes into chunks made up front that are passed to a writer
+/// thread and handed back once written, so the callback never allocates
+/// or waits on the disk. If the writer falls so far behind there's no
+/// chunk to fill the samples are dropped and counted rather than held up
+pub struct RecordTap {
+    full_tx: SyncSender<Vec<f32>>,
+    free_rx: Receiver<Vec<f32>>,
+    chunk: Option<Vec<f32>>,
+    control: Arc<RecordControl>,
+}
+
+impl RecordTap {
+    /// Takes the next sample of the mix, interleaved like the stream
+    pub fn push(&mut self, sample: f32) {
+        if self.chunk.is_none() {
+            self.chunk = self.free_rx.try_recv().ok();
+        }
+        let Some(chunk) = self.chunk.as_mut() else {
+            self.control.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        };
+        chunk.push(sample);
+        if chunk.len() == CHUNK_LEN {
+            if let Some(chunk) = self.chunk.take() {
+                // there are never more chunks than the channel holds
+                let _ = self.full_tx.try_send(chunk);
+            }
+        }
+    }
+
+    pub fn stopping(&self) -> bool {
+        self.control.stopping.load(Ordering::Relaxed)
+    }
+
+    /// Hands over what's left, the writer finishes the file once the tap
+    /// is dropped
+    pub fn finish(mut self) {
+        if let Some(chunk) = self.chunk.take() {
+            let _ = self.full_tx.try_send(chunk);
+        }
+    }
+}
+
+/// A recording of the master mix being written to recordings/{fname}.wav
+pub struct Recording {
+    pub fname: String,
+    pub started: Instant,
+    control: Arc<RecordControl>,
+}
+
+impl Recording {
+    /// Creates the file and starts its writer, the tap goes to the stream
+    /// to feed it
+    pub fn start(fname: &str, channels: u16, sample_rate: u32) -> Result<(Recording, RecordTap), Box<dyn Error>> {
+        let fname = format!("{}.wav", file_stem(fname)?);
+        std::fs::create_dir_all(format!("{PWD}/recordings"))?;
+        let path = format!("{PWD}/recordings/{}", fname);
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate,
+            bits_per_sample: 32,
+            sample_format: hound::SampleFormat::Float,
+        };
+        let mut writer = hound::WavWriter::create(&path, spec)?;
+        let (full_tx, full_rx) = mpsc::sync_channel::<Vec<f32>>(CHUNKS);
+        let (free_tx, free_rx) = mpsc::sync_channel::<Vec<f32>>(CHUNKS);
+        for _ in 0..CHUNKS {
+            free_tx.send(Vec::with_capacity(CHUNK_LEN))?;
+        }
+        let control = Arc::new(RecordControl::default());
+        let writer_control = control.clone();
+        thread::spawn(move || {
+            // ends once the tap is dropped and everything it sent is written
+            while let Ok(mut chunk) = full_rx.recv() {
+                for s in chunk.iter() {
+                    if let Err(e) = writer.write_sample(*s) {
+                        println!("Failed to write recording {}: {}", path, e);
+                        return;
+                    }
+                }
+                chunk.clear();
+                let _ = free_tx.send(chunk);
+            }
+            match writer.finalize() {
+                Ok(()) => match writer_control.dropped.load(Ordering::Relaxed) {
+                    0 => println!("Recorded {}", path),
+                    dropped => println!("Recorded {}, dropped {} samples the disk couldn't keep up with", path, dropped),
+                },
+                Err(e) => println!("Failed to finish recording {}: {}", path, e),
+            }
+        });
+        let tap = RecordTap { full_tx, free_rx, chunk: None, control: control.clone() };
+        Ok((Recording { fname, started: Instant::now(), control }, tap))
+    }
+
+    pub fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    /// Ends the recording at the next buffer, the file is finished off
+    /// the stream once the last of it is written
+    pub fn stop(self) {
+        self.control.stopping.store(true, Ordering::Relaxed);
+    }
+}
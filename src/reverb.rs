@@ -0,0 +1,144 @@
+use serde::{Serialize, Deserialize};
+
+/// Comb and allpass lengths in frames at 44.1kHz, from Freeverb
+const COMB_TUNING: [usize; 8] = [1116, 1188, 1277, 1356, 1422, 1491, 1557, 1617];
+const ALLPASS_TUNING: [usize; 4] = [556, 441, 341, 225];
+/// Added to the lengths on every other channel so the sides don't match
+const STEREO_SPREAD: usize = 23;
+const TUNING_RATE: f32 = 44100.0;
+/// The combs ring loud, the send is scaled down going in
+const INPUT_GAIN: f32 = 0.015;
+/// Full wet level, Freeverb's own scaling
+const WET_SCALE: f32 = 3.0;
+
+/// Room shared by every track, tracks pick how much of themselves they
+/// send to it
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Reverb {
+    /// percent, a small room up to a hall
+    pub size_pct: u8,
+    /// percent, how quickly the highs die away compared to the lows
+    pub damping_pct: u8,
+    /// percent, level of the reverb in the mix
+    pub wet_pct: u8,
+}
+
+impl Default for Reverb {
+    fn default() -> Self {
+        Reverb { size_pct: 50, damping_pct: 50, wet_pct: 30 }
+    }
+}
+
+impl Reverb {
+    /// Every value capped at 100
+    pub fn clamped(self) -> Self {
+        Reverb {
+            size_pct: self.size_pct.min(100),
+            damping_pct: self.damping_pct.min(100),
+            wet_pct: self.wet_pct.min(100),
+        }
+    }
+
+    /// Packs the settings to hand to the stream callback in one atomic
+    pub fn to_bits(self) -> u32 {
+        self.size_pct as u32 | (self.damping_pct as u32) << 8 | (self.wet_pct as u32) << 16
+    }
+
+    pub fn from_bits(bits: u32) -> Self {
+        Reverb {
+            size_pct: bits as u8,
+            damping_pct: (bits >> 8) as u8,
+            wet_pct: (bits >> 16) as u8,
+        }
+    }
+}
+
+/// Feedback comb with a low pass in its loop, the damping
+struct Comb {
+    buffer: Vec<f32>,
+    idx: usize,
+    store: f32,
+}
+
+impl Comb {
+    fn process(&mut self, x: f32, feedback: f32, damp: f32) -> f32 {
+        let out = self.buffer[self.idx];
+        self.store = out * (1.0 - damp) + self.store * damp;
+        self.buffer[self.idx] = x + self.store * feedback;
+        self.idx = (self.idx + 1) % self.buffer.len();
+        out
+    }
+}
+
+/// Smears the combs' echoes into a wash
+struct Allpass {
+    buffer: Vec<f32>,
+    idx: usize,
+}
+
+impl Allpass {
+    fn process(&mut self, x: f32) -> f32 {
+        let delayed = self.buffer[self.idx];
+        self.buffer[self.idx] = x + delayed * 0.5;
+        self.idx = (self.idx + 1) % self.buffer.len();
+        delayed - x
+    }
+}
+
+/// Schroeder style reverb after Freeverb, parallel combs into allpasses
+///
+/// Each channel has its own set, the output stream and exports each run one
+pub struct Freeverb {
+    channels: Vec<(Vec<Comb>, Vec<Allpass>)>,
+    feedback: f32,
+    damp: f32,
+    wet: f32,
+}
+
+impl Freeverb {
+    pub fn new(sample_rate: u32, channels: u16, reverb: Reverb) -> Self {
+        let scale = |frames: usize| ((frames as f32 * sample_rate as f32 / TUNING_RATE) as usize).max(1);
+        let mut freeverb = Freeverb {
+            channels: (0..channels as usize).map(|ch| {
+                let spread = if ch % 2 == 1 { STEREO_SPREAD } else { 0 };
+                (
+                    COMB_TUNING.iter().map(|&frames| Comb {
+                        buffer: vec![0.0; scale(frames + spread)],
+                        idx: 0,
+                        store: 0.0,
+                    }).collect(),
+                    ALLPASS_TUNING.iter().map(|&frames| Allpass {
+                        buffer: vec![0.0; scale(frames + spread)],
+                        idx: 0,
+                    }).collect(),
+                )
+            }).collect(),
+            feedback: 0.0,
+            damp: 0.0,
+            wet: 0.0,
+        };
+        freeverb.set(reverb);
+        freeverb
+    }
+
+    pub fn set(&mut self, reverb: Reverb) {
+        let reverb = reverb.clamped();
+        self.feedback = 0.7 + reverb.size_pct as f32 / 100.0 * 0.28;
+        self.damp = reverb.damping_pct as f32 / 100.0 * 0.4;
+        self.wet = reverb.wet_pct as f32 / 100.0 * WET_SCALE;
+    }
+
+    /// Takes the next sample sent to the bus on a channel and returns the
+    /// reverb to mix in
+    pub fn process(&mut self, channel: usize, send: f32) -> f32 {
+        let Some((combs, allpasses)) = self.channels.get_mut(channel) else {
+            return 0.0;
+        };
+        let x = send * INPUT_GAIN;
+        let mut out: f32 = combs.iter_mut().map(|comb| comb.process(x, self.feedback, self.damp)).sum();
+        for allpass in allpasses.iter_mut() {
+            out = allpass.process(out);
+        }
+        out * self.wet
+    }
+}
@@ -0,0 +1,218 @@
+use crate::sequencer::BufferedSample;
+use rodio::Source;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+
+/// A decoded sample kept for reuse, last_used orders the evictions
+struct CachedSample {
+    sample: Arc<BufferedSample>,
+    last_used: u64,
+    /// when the file was last changed as of decoding it
+    modified: Option<SystemTime>,
+}
+
+/// Decoded samples by path, so tracks on the same sample share one buffer
+/// and going back to a sample doesn't decode it again
+///
+/// Samples the project isn't using are kept until they take up more than
+/// the cap, then the least recently used go first. Samples in use are
+/// never evicted, the cap only limits what's kept on top of them
+///
+/// Everything in it is converted to the output rate as it's decoded
+pub struct SampleCache {
+    samples: HashMap<String, CachedSample>,
+    /// why each sample that failed to load did, until it loads
+    errors: BTreeMap<String, String>,
+    sample_rate: u32,
+    /// bytes of unused samples kept around, 0 drops them as soon as they're done with
+    cap_bytes: usize,
+    /// bumped on every load, cheaper than asking for the time
+    clock: u64,
+}
+
+/// A sample file that couldn't be loaded, like one in a format that
+/// can't be decoded
+#[derive(Debug, Clone, serde::Serialize)]
+pub struct SampleError {
+    pub path: String,
+    pub error: String,
+}
+
+/// Memory held by decoded samples
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SampleMemory {
+    pub samples: usize,
+    /// every decoded sample, in use or not
+    pub held_bytes: usize,
+    /// the part nothing is using that the cache could let go of
+    pub idle_bytes: usize,
+}
+
+/// When the sample file at the path in the samples directory was last changed
+pub fn modified(sample_path: &str) -> Option<SystemTime> {
+    std::fs::metadata(format!("{PWD}/samples/{}", sample_path)).ok()?.modified().ok()
+}
+
+impl CachedSample {
+    /// Only the cache holds it, no track, metronome or ringing voice
+    fn idle(&self) -> bool {
+        Arc::strong_count(&self.sample) == 1 && !self.sample.shared()
+    }
+}
+
+impl SampleCache {
+    pub fn new(sample_rate: u32) -> Self {
+        SampleCache {
+            samples: HashMap::new(),
+            errors: BTreeMap::new(),
+            sample_rate,
+            cap_bytes: 0,
+            clock: 0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Moves to a new output rate, everything decoded at the old one is
+    /// let go of and has to be loaded again
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        if sample_rate != self.sample_rate {
+            self.sample_rate = sample_rate;
+            self.samples.clear();
+        }
+    }
+
+    /// The sample at the path in the samples directory, decoded if it
+    /// isn't already
+    pub fn load(&mut self, sample_path: &str) -> Result<Arc<BufferedSample>, Box<dyn Error>> {
+        self.clock += 1;
+        if let Some(cached) = self.samples.get_mut(sample_path) {
+            cached.last_used = self.clock;
+            return Ok(cached.sample.clone());
+        }
+        let modified = modified(sample_path);
+        let sample = match BufferedSample::new(sample_path, self.sample_rate) {
+            Ok(sample) => sample,
+            Err(e) => {
+                self.errors.insert(sample_path.to_string(), e.to_string());
+                return Err(e);
+            }
+        };
+        self.errors.remove(sample_path);
+        self.samples.insert(sample_path.to_string(), CachedSample {
+            sample: sample.clone(),
+            last_used: self.clock,
+            modified,
+        });
+        self.trim();
+        Ok(sample)
+    }
+
+    pub fn contains(&self, sample_path: &str) -> bool {
+        self.samples.contains_key(sample_path)
+    }
+
+    /// Adds a sample decoded elsewhere, like a preload off the command loop,
+    /// unless the output rate changed while it was being decoded
+    pub fn insert(&mut self, sample_path: String, sample: Arc<BufferedSample>, modified: Option<SystemTime>) {
+        if sample.sample_rate() != self.sample_rate {
+            return;
+        }
+        self.clock += 1;
+        self.samples.entry(sample_path).or_insert(CachedSample {
+            sample,
+            last_used: self.clock,
+            modified,
+        });
+    }
+
+    /// Takes a sample decoded again after its file changed in place of the
+    /// old one, returns false if the output rate changed while it was
+    /// being decoded
+    pub fn replace(&mut self, sample_path: String, sample: Arc<BufferedSample>, modified: Option<SystemTime>) -> bool {
+        if sample.sample_rate() != self.sample_rate {
+            return false;
+        }
+        self.clock += 1;
+        self.errors.remove(&sample_path);
+        self.samples.insert(sample_path, CachedSample {
+            sample,
+            last_used: self.clock,
+            modified,
+        });
+        true
+    }
+
+    /// Notes a changed file that failed to decode, the old sample is kept
+    /// and it isn't tried again until the file changes again
+    pub fn reload_failed(&mut self, sample_path: String, modified: Option<SystemTime>, error: String) {
+        if let Some(cached) = self.samples.get_mut(&sample_path) {
+            cached.modified = modified;
+        }
+        self.errors.insert(sample_path, error);
+    }
+
+    /// Samples a track or the metronome is on, with when their file was
+    /// changed as of decoding it
+    pub fn in_use(&self) -> Vec<(String, Option<SystemTime>)> {
+        self.samples.iter()
+            .filter(|(_, cached)| Arc::strong_count(&cached.sample) > 1)
+            .map(|(sample_path, cached)| (sample_path.clone(), cached.modified))
+            .collect()
+    }
+
+    /// The samples that failed the last time they were loaded
+    pub fn errors(&self) -> Vec<SampleError> {
+        self.errors.iter()
+            .map(|(path, error)| SampleError { path: path.clone(), error: error.clone() })
+            .collect()
+    }
+
+    /// Forgets the failures of files no longer in the samples folder
+    pub fn retain_errors(&mut self, sample_files: &[String]) {
+        self.errors.retain(|path, _| sample_files.contains(path));
+    }
+
+    pub fn set_cap(&mut self, cap_mb: u32) {
+        self.cap_bytes = cap_mb as usize * 1024 * 1024;
+        self.trim();
+    }
+
+    /// Evicts unused samples, oldest first, until they fit under the cap
+    pub fn trim(&mut self) {
+        let mut idle: Vec<(u64, String, usize)> = self.samples.iter()
+            .filter(|(_, cached)| cached.idle())
+            .map(|(path, cached)| (cached.last_used, path.clone(), cached.sample.bytes()))
+            .collect();
+        let mut idle_bytes: usize = idle.iter().map(|(_, _, bytes)| bytes).sum();
+        if idle_bytes <= self.cap_bytes {
+            return;
+        }
+        idle.sort();
+        for (_, path, bytes) in idle {
+            if idle_bytes <= self.cap_bytes {
+                break;
+            }
+            self.samples.remove(&path);
+            idle_bytes -= bytes;
+        }
+    }
+
+    pub fn memory(&self) -> SampleMemory {
+        self.samples.values().fold(SampleMemory::default(), |mut memory, cached| {
+            let bytes = cached.sample.bytes();
+            memory.samples += 1;
+            memory.held_bytes += bytes;
+            if cached.idle() {
+                memory.idle_bytes += bytes;
+            }
+            memory
+        })
+    }
+}
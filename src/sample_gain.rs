@@ -0,0 +1,118 @@
+use serde::{Serialize, Deserialize};
+use std::error::Error;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Added to a sample's file name for the file its gain is kept in
+const GAIN_SUFFIX: &str = ".gain.json";
+pub const MIN_SAMPLE_GAIN_DB: f32 = -24.0;
+pub const MAX_SAMPLE_GAIN_DB: f32 = 24.0;
+/// Quietest level a sample can be normalized to
+pub const MIN_NORMALIZE_DB: f32 = -40.0;
+/// Most a quiet sample is brought up by normalizing, so a near silent
+/// one isn't turned into a blast of noise
+const MAX_NORMALIZE_BOOST_DB: f32 = 40.0;
+
+/// What a sample is brought to before its offset
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum Normalize {
+    #[default]
+    Off = 0,
+    /// its loudest sample
+    Peak = 1,
+    /// its average level, closer to how loud it sounds
+    Rms = 2,
+}
+
+impl From<i64> for Normalize {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => Normalize::Peak,
+            2 => Normalize::Rms,
+            _ => Normalize::Off,
+        }
+    }
+}
+
+/// Level a sample plays at wherever it's used, so a kit pulled together
+/// from different libraries can be evened out once rather than on the
+/// gain of every track it's on
+///
+/// Kept next to the sample as {sample file}.gain.json, so it moves along
+/// with it and isn't listed as a sample
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub struct SampleGain {
+    pub normalize: Normalize,
+    /// level in dBFS normalizing brings it to
+    #[serde(default)]
+    pub target_db: f32,
+    /// in dB, on top of any normalizing
+    #[serde(default)]
+    pub offset_db: f32,
+}
+
+impl SampleGain {
+    pub fn clamped(self) -> Self {
+        SampleGain {
+            normalize: self.normalize,
+            target_db: self.target_db.clamp(MIN_NORMALIZE_DB, 0.0),
+            offset_db: self.offset_db.clamp(MIN_SAMPLE_GAIN_DB, MAX_SAMPLE_GAIN_DB),
+        }
+    }
+
+    /// Linear gain for the audio, interleaved
+    pub fn linear(&self, buffer: &[f32]) -> f32 {
+        let level = match self.normalize {
+            Normalize::Off => None,
+            Normalize::Peak => Some(buffer.iter().fold(0.0f32, |peak, s| peak.max(s.abs()))),
+            Normalize::Rms => (!buffer.is_empty())
+                .then(|| (buffer.iter().map(|s| s * s).sum::<f32>() / buffer.len() as f32).sqrt()),
+        };
+        let normalize_db = match level.filter(|level| *level > 0.0) {
+            Some(level) => (self.target_db - 20.0 * level.log10()).min(MAX_NORMALIZE_BOOST_DB),
+            None => 0.0,
+        };
+        10f32.powf((normalize_db + self.offset_db) / 20.0)
+    }
+}
+
+fn gain_path(sample_path: &str) -> String {
+    format!("{PWD}/samples/{}{}", sample_path, GAIN_SUFFIX)
+}
+
+/// The gain kept for the sample at the path in the samples directory, one
+/// without any is played as it is
+pub fn load(sample_path: &str) -> SampleGain {
+    let Ok(json) = std::fs::read_to_string(gain_path(sample_path)) else {
+        return SampleGain::default();
+    };
+    match serde_json::from_str::<SampleGain>(&json) {
+        Ok(gain) => gain.clamped(),
+        Err(e) => {
+            println!("Failed to read the gain of {}: {}", sample_path, e);
+            SampleGain::default()
+        }
+    }
+}
+
+/// Saves the gain next to the sample, going back to as it is removes the file
+pub fn save(sample_path: &str, gain: SampleGain) -> Result<(), Box<dyn Error>> {
+    let path = gain_path(sample_path);
+    if gain == SampleGain::default() {
+        if std::path::Path::new(&path).exists() {
+            std::fs::remove_file(path)?;
+        }
+        return Ok(());
+    }
+    std::fs::write(path, serde_json::to_string_pretty(&gain)?)?;
+    Ok(())
+}
+
+/// Moves the gain of a sample along with it, from and to are full paths
+/// of the sample files
+pub fn follow(from: &str, to: &str) -> Result<(), Box<dyn Error>> {
+    let from = format!("{}{}", from, GAIN_SUFFIX);
+    if std::path::Path::new(&from).exists() {
+        std::fs::rename(from, format!("{}{}", to, GAIN_SUFFIX))?;
+    }
+    Ok(())
+}
@@ -0,0 +1,148 @@
+use crate::history::Snapshot;
+use crate::sample_gain;
+use crate::sequencer::SavedPattern;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Unused samples are moved here rather than deleted, outside of the
+/// samples folder so they drop out of the list
+const ARCHIVE_DIR: &str = "sample_archive";
+
+/// A sample in the pool and what uses it
+#[derive(Debug, Clone, Serialize)]
+pub struct SampleUsage {
+    pub sample_path: String,
+    /// "project" for the patterns loaded now, the metronome and macros,
+    /// otherwise the pattern or history file
+    pub used_by: Vec<String>,
+}
+
+/// Which samples are used where, for keeping a small SD card tidy
+///
+/// History snapshots count as a use, a sample moved out from under one
+/// would be missing when it's restored
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct PoolReport {
+    /// every sample in the pool, the unused ones with nothing in used_by
+    pub samples: Vec<SampleUsage>,
+    /// samples nothing uses, cleanup moves these
+    pub orphaned: Vec<String>,
+    /// samples something uses that aren't in the pool
+    pub missing: Vec<String>,
+    /// samples moved to the archive by the cleanup, empty for a report
+    pub archived: Vec<String>,
+}
+
+impl PoolReport {
+    /// Goes through the saved patterns and history for samples they use,
+    /// on top of the ones the project uses now
+    ///
+    /// Files that can't be read are skipped with a message, so a broken
+    /// one could leave a sample it uses looking orphaned
+    pub fn new(pool: &[String], project_refs: &[String]) -> Self {
+        let mut users: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+        let mut add = |sample_path: &str, user: &str| {
+            if !sample_path.is_empty() {
+                users.entry(sample_path.to_string()).or_default().insert(user.to_string());
+            }
+        };
+        project_refs.iter().for_each(|sample_path| add(sample_path, "project"));
+        for (dir, patterns) in [("patterns", saved_patterns()), ("history", snapshot_patterns())] {
+            for (fname, pattern) in patterns {
+                let user = format!("{}/{}", dir, fname);
+                for track in &pattern.tracks {
+                    add(&track.sample_path, &user);
+                    track.layers.iter().for_each(|layer| add(&layer.sample_path, &user));
+                }
+            }
+        }
+
+        let pool_set: BTreeSet<&String> = pool.iter().collect();
+        let samples: Vec<SampleUsage> = pool.iter().map(|sample_path| SampleUsage {
+            sample_path: sample_path.clone(),
+            used_by: users.get(sample_path).map(|users| users.iter().cloned().collect()).unwrap_or_default(),
+        }).collect();
+        PoolReport {
+            orphaned: samples.iter()
+                .filter(|usage| usage.used_by.is_empty())
+                .map(|usage| usage.sample_path.clone())
+                .collect(),
+            missing: users.keys().filter(|sample_path| !pool_set.contains(sample_path)).cloned().collect(),
+            samples,
+            archived: vec![],
+        }
+    }
+}
+
+/// Moves samples into the archive folder keeping their kit subfolder,
+/// returns the ones that moved
+pub fn archive(sample_paths: &[String]) -> Vec<String> {
+    sample_paths.iter().filter(|sample_path| {
+        match archive_one(sample_path) {
+            Ok(()) => true,
+            Err(e) => {
+                println!("Failed to archive sample {}: {}", sample_path, e);
+                false
+            }
+        }
+    }).cloned().collect()
+}
+
+fn archive_one(sample_path: &str) -> Result<(), Box<dyn Error>> {
+    let dest = std::path::PathBuf::from(format!("{PWD}/{ARCHIVE_DIR}/{}", sample_path));
+    if let Some(dir) = dest.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    if dest.exists() {
+        return Err(format!("{} is already in the archive", sample_path).into());
+    }
+    let from = format!("{PWD}/samples/{}", sample_path);
+    std::fs::rename(&from, &dest)?;
+    sample_gain::follow(&from, &dest.to_string_lossy())?;
+    Ok(())
+}
+
+fn read_dir_files(dir: &str) -> Vec<String> {
+    match std::fs::read_dir(format!("{PWD}/{}", dir)) {
+        Ok(entries) => entries
+            .filter_map(|entry| entry.ok()?.file_name().to_str().map(|fname| fname.to_string()))
+            .collect(),
+        Err(e) => {
+            println!("Failed to read {}: {}", dir, e);
+            vec![]
+        }
+    }
+}
+
+fn read_json<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, Box<dyn Error>> {
+    let file = std::fs::File::open(format!("{PWD}/{}", path))?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+}
+
+fn saved_patterns() -> Vec<(String, SavedPattern)> {
+    read_dir_files("patterns").into_iter().filter_map(|fname| {
+        match read_json(&format!("patterns/{}", fname)) {
+            Ok(pattern) => Some((fname, pattern)),
+            Err(e) => {
+                println!("Failed to read pattern {}: {}", fname, e);
+                None
+            }
+        }
+    }).collect()
+}
+
+fn snapshot_patterns() -> Vec<(String, SavedPattern)> {
+    read_dir_files("history").into_iter().filter_map(|fname| {
+        match read_json::<Snapshot>(&format!("history/{}", fname)) {
+            Ok(snapshot) => Some(snapshot.state.patterns.into_iter()
+                .map(|p| (fname.clone(), p.pattern))
+                .collect::<Vec<_>>()),
+            Err(e) => {
+                println!("Failed to read snapshot {}: {}", fname, e);
+                None
+            }
+        }
+    }).flatten().collect()
+}
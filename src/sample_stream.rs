@@ -0,0 +1,127 @@
+use crate::sequencer::BufferedSample;
+use rodio::Source;
+use std::error::Error;
+use std::fs::File;
+use std::sync::mpsc::{self, Receiver, SyncSender, TryRecvError};
+use std::thread;
+use std::time::Duration;
+
+/// Samples read from disk at a time
+const CHUNK_LEN: usize = 4096;
+/// Chunks a voice reads ahead by, about 1.5 seconds of stereo at 44.1kHz
+const CHUNKS: usize = 32;
+
+/// The rest of a long sample after the part kept in memory, read from
+/// disk as a voice plays it
+///
+/// A thread of its own decodes the file into chunks made up front and
+/// passes them over, and they're handed back once played, so the voice
+/// never allocates or waits on the disk. If the disk falls behind the
+/// voice plays silence until it catches up
+pub struct SampleStream {
+    full_rx: Receiver<Vec<f32>>,
+    free_tx: SyncSender<Vec<f32>>,
+    chunk: Vec<f32>,
+    pos: usize,
+}
+
+impl SampleStream {
+    /// Starts reading the file from skip samples in, interleaved like the
+    /// part in memory
+    pub fn start(path: String, skip: usize) -> Self {
+        let (full_tx, full_rx) = mpsc::sync_channel::<Vec<f32>>(CHUNKS);
+        let (free_tx, free_rx) = mpsc::sync_channel::<Vec<f32>>(CHUNKS);
+        for _ in 0..CHUNKS {
+            let _ = free_tx.send(Vec::with_capacity(CHUNK_LEN));
+        }
+        thread::spawn(move || {
+            let mut samples = match decode(&path) {
+                Ok(samples) => samples.skip(skip),
+                Err(e) => {
+                    println!("Failed to stream {}: {}", path, e);
+                    return;
+                }
+            };
+            // ends once the file runs out or the voice is done with it
+            while let Ok(mut chunk) = free_rx.recv() {
+                chunk.clear();
+                chunk.extend(samples.by_ref().take(CHUNK_LEN));
+                if chunk.is_empty() || full_tx.send(chunk).is_err() {
+                    return;
+                }
+            }
+        });
+        SampleStream { full_rx, free_tx, chunk: vec![], pos: 0 }
+    }
+}
+
+fn decode(path: &str) -> Result<impl Iterator<Item = f32>, Box<dyn Error>> {
+    let decoder = rodio::Decoder::new(File::open(path)?)?;
+    Ok(decoder.convert_samples::<f32>())
+}
+
+impl Iterator for SampleStream {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        if self.pos == self.chunk.len() {
+            match self.full_rx.try_recv() {
+                Ok(chunk) => {
+                    let played = std::mem::replace(&mut self.chunk, chunk);
+                    // the first one isn't from the pool
+                    if played.capacity() > 0 {
+                        let _ = self.free_tx.try_send(played);
+                    }
+                    self.pos = 0;
+                },
+                Err(TryRecvError::Empty) => return Some(0.0),
+                Err(TryRecvError::Disconnected) => return None,
+            }
+        }
+        let s = self.chunk[self.pos];
+        self.pos += 1;
+        Some(s)
+    }
+}
+
+/// A sample playing as a voice, carrying on from the stream once it's
+/// through the part in memory
+pub struct SampleVoice {
+    sample: BufferedSample,
+    stream: Option<SampleStream>,
+}
+
+impl SampleVoice {
+    pub fn new(sample: BufferedSample, stream: Option<SampleStream>) -> Self {
+        SampleVoice { sample, stream }
+    }
+}
+
+impl Iterator for SampleVoice {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        match self.sample.next() {
+            Some(s) => Some(s),
+            None => self.stream.as_mut()?.next().map(|s| s * self.sample.gain()),
+        }
+    }
+}
+
+impl Source for SampleVoice {
+    fn current_frame_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> u16 {
+        self.sample.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.sample.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.sample.total_duration()
+    }
+}
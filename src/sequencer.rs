@@ -1,20 +1,78 @@
-use rodio::{OutputStreamHandle, Sink, Source};                                                                                     
+use rodio::Source;                                                                                     
 use std::{sync::mpsc, time::Duration};
 use std::error::Error;
 use std::sync::{Arc, Mutex};
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::fs::{File, OpenOptions};
-use std::time::Instant;
-use std::thread::yield_now;
-use midir::{MidiOutput, MidiOutputPort, MidiOutputConnection};
+use std::time::{Instant, SystemTime};
+use std::collections::{BTreeMap, VecDeque};
+use std::thread;
+use midir::{MidiInput, MidiInputConnection, MidiInputPort, MidiOutput, MidiOutputPort};
 use serde::{Serialize, Deserialize};
 use std::hash::{Hash, Hasher};
+use crate::audio::{self, AudioHandle, AudioOutput, BufferMode, Delay, Reacquire, StreamRequest, SyncOut, MAX_BUFFER_FRAMES, MAX_SAMPLE_RATE, MIN_BUFFER_FRAMES, MIN_SAMPLE_RATE, MAX_DELAY_FEEDBACK, SEND_BUSES};
+use crate::budget::{BudgetReport, PulseBudget, PulseJitter};
+use crate::demo::{Demo, DemoStyle};
+use crate::export::{ExportReport, ExportSettings, Renderer};
+use crate::groove::{self, Groove, PatternGroove, SavedGroove};
+use crate::eq::{Equalized, TrackEq};
+use crate::history::{self, History, SnapshotPattern, SnapshotState};
+use crate::i18n::{Language, Msg};
+use crate::input::{SampleCapture, MIN_THRESHOLD_DB};
+use crate::kit;
+use crate::metronome::Metronome;
+use crate::midi::{MidiDestination, MidiInputs, MidiKind, MidiOutputs, MidiThru, BASE_NOTE, DRUM_CHANNEL, VIRTUAL_PORT_NAME};
+use crate::midi_map::{self, MidiBinding, MidiLearn, MidiMap, MidiTrigger};
+use crate::project::Project;
+use crate::recorder::Recording;
+use crate::reverb::Reverb;
+use crate::sample_cache::{self, SampleCache, SampleError};
+use crate::sample_gain::{self, SampleGain};
+use crate::sample_pool::{self, PoolReport};
+use crate::sample_stream::{SampleStream, SampleVoice};
+use crate::similarity;
+use crate::slicer::{self, MAX_SLICES, MIN_SLICES};
+use crate::stretch;
+use crate::sync::{ClockFollower, ClockSource, SyncStatus};
+use crate::voices::{VoiceHandle, VoiceStart};
 
 const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Pulses per bar, 24 per quarter note as is standard for midi clock
+pub const PPB: u8 = 24 * 4;
+/// Pulses per quarter note beat
+const PPQ: u8 = PPB / 4;
+/// Most commands handled in one go by the command loop, so a macro that
+/// runs itself can't hold the lock forever
+const MAX_COMMAND_BATCH: usize = 256;
+/// Longest the command loop holds the lock in one go, the rest of a batch
+/// waits for the next. It lets go sooner if the pulse thread is waiting
+const MAX_COMMAND_LOCK: Duration = Duration::from_millis(2);
+/// Buffers ahead of the stream a pulse is scheduled, the pulse thread can
+/// wake up to a buffer late before a hit misses its frame
+const SCHEDULE_AHEAD_BUFFERS: u64 = 2;
+/// Fewest frames a pulse is scheduled ahead, for a stream that hasn't
+/// pulled a buffer yet
+const MIN_SCHEDULE_AHEAD: u64 = 256;
+/// Longest the pulse thread waits on the stream's clock, so a stalled
+/// output is still noticed and the pulses go on without it
+const MAX_PULSE_WAIT: Duration = Duration::from_millis(250);
+/// Files in the samples folder that are listed as samples
+const SAMPLE_EXTENSIONS: [&str; 6] = ["wav", "flac", "aif", "aiff", "ogg", "mp3"];
+/// Velocity of the hits that play back a sliced loop
+const SLICE_VELOCITY: u8 = 127;
+/// How often the samples in use are checked for changes on disk
+const SAMPLE_WATCH_INTERVAL: Duration = Duration::from_secs(1);
+/// A changed sample is left this long after it was written before it's
+/// reloaded, in case the editor saving it isn't done
+const SAMPLE_SETTLE: Duration = Duration::from_millis(500);
 
 #[derive(Clone)]
 pub enum StateUpdate {
     FileState(FileState),
-    SeqState(SeqState),
+    SeqState(Box<SeqState>),
+    TriggerCounts(TriggerCounts),
+    SamplePool(PoolReport),
+    Budget(BudgetReport),
 }
 
 #[derive(Debug, Clone, Serialize)]
@@ -23,6 +81,31 @@ pub enum FileType {
     Pattern,
     #[serde(rename = "sample")]
     Sample,
+    #[serde(rename = "groove")]
+    Groove,
+    #[serde(rename = "history")]
+    History,
+    #[serde(rename = "midi_port")]
+    MidiPort,
+    #[serde(rename = "midi_input_port")]
+    MidiInputPort,
+    #[serde(rename = "midi_map")]
+    MidiMap,
+    #[serde(rename = "audio_device")]
+    AudioDevice,
+    /// saved patterns closest to the current one, best first
+    #[serde(rename = "similar_pattern")]
+    SimilarPattern,
+}
+
+/// How many times each step of the current pattern has played, sent on
+/// request rather than with every state as it's only for checking how
+/// random and skipped steps work out over time
+#[derive(Debug, Clone, Serialize)]
+pub struct TriggerCounts {
+    pub pattern_id: usize,
+    /// per track, per step
+    pub tracks: Vec<Vec<u32>>,
 }
 
 /// Struct that allows updating listeners of samples
@@ -34,35 +117,276 @@ pub struct FileState {
     pub files: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub enum Command {
     // Sequencer playback commands
     PlaySequencer,
     StopSequencer,
-    SetTempo(u8),
+    // Plays then stops at the end of the given number of bars
+    PlayForBars(usize),
+    // Stops at the end of the bar once playing for this many seconds, 0 is off
+    SetStopTimer(u32),
+    // Pause holds the playheads where they are for continue to pick up from
+    PauseSequencer,
+    ContinueSequencer,
+    SetTempo(f32),
+    // Time in ms that tempo changes are ramped over, 0 is instant
+    SetTempoSlew(u16),
+    // Velocity added to accented slots
+    SetAccentVelocity(u8),
     SetPattern(usize),
     PlaySound(usize, u8),
     // Track program commands
     SetSlotVelocity(usize, usize, u8),
-    SetTrackLength(usize),
+    SetSlotAccent(usize, usize, bool),
+    // Flips the track's reverse setting for the one step
+    SetSlotReverse(usize, usize, bool),
+    // Number of hits a slot plays within its step
+    SetSlotRatchet(usize, usize, u8),
+    // Percent each ratchet hit changes velocity by, negative gets quieter
+    SetSlotRatchetDecay(usize, usize, i8),
+    // Track id then its length in steps, tracks of different lengths go polymetric
+    SetTrackLength(usize, usize),
+    SetTrackDirection(usize, Direction),
+    // track id, low and high shelf gain in dB
+    SetTrackEq(usize, TrackEq),
+    // Which of the track's effects are bypassed in this pattern
+    SetTrackFxBypass(usize, FxBypass),
+    // Offset in ms from the grid, positive is behind the beat
+    SetTrackTimingOffset(usize, i16),
+    // Zeroes every slot of a track, or of every track in the current pattern
+    ClearTrack(usize),
+    ClearPattern,
+    // track id, density 0-1, min velocity, max velocity
+    RandomizeTrack(usize, f32, u8, u8),
+    // track id, loop start and end frame of the track sample
+    SetTrackLoop(usize, usize, usize),
+    ClearTrackLoop(usize),
     // Sequencer program commands
     AddPattern,
+    // Copies a pattern with its steps, unlike AddPattern which starts blank
+    DuplicatePattern(usize),
+    // Source pattern and track then the pattern the copy is added to
+    CopyTrack(usize, usize, usize),
+    // Source then destination track, the destination takes the source's
+    // velocities and timing but keeps its own steps
+    CopyTrackFeel(usize, usize),
     RemovePattern(usize),
     SelectPattern(usize),
+    // Adds a pattern to play after the queued one, each plays through once
+    QueuePattern(usize),
+    ClearPatternQueue,
     SetPatternLength(usize),
     SavePattern,
+    // Samples are decoded in the background first, while playing the
+    // pattern then takes over on the next downbeat like a queued one
     LoadPattern(String),
     // A single controller can request this but due
     // to state update patterns, all controllers
     // will receive the update
     ListPatterns,
+    // Step play counts of the current pattern, sent as their own update
+    ListTriggerCounts,
+    ResetTriggerCounts,
+    // Saved patterns with hits closest to the current pattern, sent as
+    // file state like ListPatterns
+    FindSimilarPatterns,
     ListSamples,
+    // Megabytes of unused samples kept decoded for reuse, 0 keeps none
+    SetSampleCacheSize(u32),
+    // Which saved patterns and snapshots use each sample, sent as its own
+    // update. Cleaning also moves the unused ones to the sample archive
+    ReportSamplePool,
+    CleanSamplePool,
+    // Histogram of how much of each pulse interval went on processing
+    // over the last bars, sent as its own update
+    ReportBudget,
+    ListGrooves,
     SetSwing(Swing),
+    // Steps swing pushes late, set on the pattern the user is on
+    SetSwingGrid(SwingGrid),
+    // Song commands, entries are (pattern id, repeats) and
+    // loop region is inclusive chain indices
+    SetSongMode(bool),
+    AddSongEntry(usize, usize),
+    SetSongEntry(usize, usize, usize),
+    RemoveSongEntry(usize),
+    ClearSong,
+    SetSongLoop(usize, usize),
+    ClearSongLoop,
+    // Bounces the song chain to exports/ as a wav and cue sheet
+    ExportSong(String, ExportSettings),
+    // Bounces every pattern to its own wav in exports/{dir}, bars per pattern
+    ExportAllPatterns(String, usize),
+    // Writes the master mix to recordings/{fname}.wav as it plays, until StopRecording
+    StartRecording(String),
+    StopRecording,
+    // Track, file name, threshold in dBFS and whether an existing sample
+    // of the same name may be written over, arms the input to record a new
+    // sample for the track from the first sound over the threshold
+    CaptureSample(usize, String, i8, bool),
+    // Ends the capture, the sample is trimmed, saved and put on the track
+    StopCapture,
+    // Decodes every sample in use again from disk, for changes the watcher missed
+    ReloadSamples,
+    // Track, lowest velocity and sample path, the sample plays for hits from
+    // that velocity up to the next layer's in place of one starting there
+    SetTrackLayer(usize, u8, String),
+    // Track and the lowest velocity of the layer to remove
+    RemoveTrackLayer(usize, u8),
+    ClearTrackLayers(usize),
+    // Track and the tempo its sample was played at, None if it isn't a loop.
+    // The loop is fitted to the pattern at our tempo
+    SetTrackLoopSync(usize, Option<LoopSync>),
+    // Track and the pair of output channels it plays on, 0 is outs 1/2
+    SetTrackOutput(usize, u8),
+    // Sample path and the gain it plays at wherever it's used, saved next
+    // to the sample
+    SetSampleGain(String, SampleGain),
+    // Sample path and number of slices, chops a loop into equal slices and
+    // puts them on tracks that play it back in order
+    SliceSample(String, usize),
+    // Sample path and velocity, plays a sample from the library without a
+    // track, an empty path stops the last one
+    PreviewSample(String, u8),
     // Pattern program commands
     SetDivision(Division),
+    // Randomizes every track of the current pattern, same args as RandomizeTrack
+    RandomizePattern(f32, u8, u8),
+    // Max timing jitter in ms, max velocity deviation
+    SetHumanize(u8, u8),
+    // Groove name from the library and strength 0-100
+    SetGroove(String, u8),
+    ClearGroove,
+    // CC lanes of the current pattern, a lane is added with its midi
+    // channel and controller number
+    AddCcLane(u8, u8),
+    RemoveCcLane(usize),
+    // Lane, step and value, None holds the previous value
+    SetCcStep(usize, usize, Option<u8>),
     // Add track uses the last track's sample
     AddTrack,
+    // The last track of a pattern can't be removed, add track copies it
+    RemoveTrack(usize),
+    // From and to track index, the tracks between shift over to make room
+    MoveTrack(usize, usize),
     SetTrackSample(usize, String),
+    // Swaps each track's sample for a random one of the same kind, going
+    // by folder names like kicks/ and snares/, the steps are kept
+    RandomizeKit,
+    SetSwapPolicy(SwapPolicy),
+    // Micro fade on voice start, stop and steal, on/off and length in ms
+    SetAntiClick(bool, u8),
+    // Fade out of a choked voice in ms
+    SetChokeFade(u8),
+    SetMetronome(bool),
+    // Sample path for the click, empty for the built in click
+    SetMetronomeSample(String),
+    // Bars of click before playback starts, 0 is off
+    SetCountIn(u8),
+    // Macros are named lists of commands saved with the project
+    DefineMacro(String, Vec<Command>),
+    RemoveMacro(String),
+    RunMacro(String),
+    // Whole project snapshots are taken every so often while editing
+    ListHistory,
+    RestoreSnapshot(usize),
+    // Echoes midi input to the output, the channel remaps everything onto
+    // one channel and None keeps the original
+    SetMidiThru(bool, Option<u8>),
+    // Pattern id played as the fill of the current pattern, None for no fill
+    SetPatternFill(Option<usize>),
+    // Fill plays from the next bar while held, and for at least a bar
+    TriggerFill(bool),
+    // Pattern played on deck B alongside the current one, None ends deck mode
+    SetDeckB(Option<usize>),
+    // Crossfader between the current pattern and deck B, 0 is all A and 1 all B
+    SetCrossfade(f32),
+    // Midi outputs are added by port name and get clock and transport to begin with
+    ListMidiPorts,
+    AddMidiOutput(String),
+    RemoveMidiOutput(usize),
+    // Output index then whether it gets clock, transport, notes and cc
+    SetMidiOutput(usize, bool, bool, bool, bool),
+    SetTrackMidiNote(usize, u8),
+    // Midi inputs are listened on by port name for clock, transport and notes
+    ListMidiInputPorts,
+    AddMidiInput(String),
+    RemoveMidiInput(usize),
+    // Notes in play tracks live, the channel only takes notes on that
+    // channel and None takes them on any
+    SetNoteInput(bool, Option<u8>),
+    // Note then the track it plays, None goes back to the track sending it
+    MapNoteToTrack(u8, Option<usize>),
+    // Binds the command to the next note or cc pressed on a midi input,
+    // None stops learning
+    LearnMidi(Option<Box<Command>>),
+    // Binds the command to the note or cc, None unbinds it
+    BindMidi(MidiTrigger, Option<Box<Command>>),
+    // Midi maps are saved by name to switch between controllers
+    ListMidiMaps,
+    SaveMidiMap(String),
+    LoadMidiMap(String),
+    // Max velocity deviation either way for each hit of the track
+    SetTrackVelocityVariation(usize, u8),
+    // Tuning in cents, 100 to a semitone, the sample plays faster or slower
+    SetTrackPitch(usize, i16),
+    // Plays the track's sample backwards
+    SetTrackReverse(usize, bool),
+    // Attack, hold and decay in ms shaping each hit
+    SetTrackEnvelope(usize, Envelope),
+    // Low or high pass on the track's hits, or off
+    SetTrackFilter(usize, Filter),
+    // Whether the track's hits cut each other or overlap, and how many
+    SetTrackPolyphony(usize, Polyphony),
+    // Echo time as a division and feedback percent of the delay bus
+    SetDelay(Division, u8),
+    // Percent of the track sent to the delay bus
+    SetTrackDelaySend(usize, u8),
+    // Replaces the patterns with a starter groove on the bundled kit
+    LoadDemo(DemoStyle),
+    // Size, damping and wet level of the reverb bus in percent
+    SetReverb(Reverb),
+    // Percent of the track sent to the reverb bus
+    SetTrackReverbSend(usize, u8),
+    // Follow midi clock on the input or run on our own
+    SetClockSource(ClockSource),
+    // Language of the text controllers show
+    SetLanguage(Language),
+    // Level of the whole mix in whole dB, saved with the project
+    SetMasterVolume(i8),
+    // Arms recording, PlaySound then writes into the nearest slot while playing
+    SetRecord(bool),
+    // Overdub layers hits on what's there, replace clears as it goes
+    SetRecordMode(RecordMode),
+    // Track and whether erase is held on it, its steps clear as the playhead passes
+    SetTrackErase(usize, bool),
+    // Parks a track, it stops playing but keeps its steps and sample
+    SetTrackArchived(usize, bool),
+    // Edits on a selection of tracks at once, tracks the pattern
+    // doesn't have are skipped
+    MuteTracks(Vec<usize>, bool),
+    // Gain in whole dB
+    SetTracksGain(Vec<usize>, i8),
+    ClearTracks(Vec<usize>),
+    // Labels a pattern, the label is also used for its save file
+    SetPatternName(usize, String),
+    // Free text notes on the current pattern and on the project
+    SetPatternNotes(String),
+    SetProjectNotes(String),
+    ListAudioDevices,
+    // Moves playback onto the named output, patterns and transport carry on
+    SetAudioDevice(String),
+    // Reopens the output with safe or low latency buffers
+    SetBufferMode(BufferMode),
+    // Exact frames per buffer, 0 goes back to the buffer mode's
+    SetBufferFrames(u32),
+    // Output sample rate in Hz, 0 is the device's default
+    SetSampleRate(u32),
+    // Plays a test click and reports how long it took to be heard
+    MeasureLatency,
+    // Analog sync pulses on an output channel, on/off, channel and pulses per quarter note
+    SetSyncOut(bool, u16, u8),
     Unspecified,
 }
 
@@ -154,13 +478,227 @@ impl From<u32> for Swing {
     }
 }
 
+/// Which steps swing pushes late, every other step of the pattern or
+/// every other 8th or 16th whatever the pattern's division is
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum SwingGrid {
+    #[default]
+    Division = 0,
+    E = 8,
+    S = 16,
+}
+
+impl From<i64> for SwingGrid {
+    fn from(value: i64) -> Self {
+        match value {
+            8 => SwingGrid::E,
+            16 => SwingGrid::S,
+            _ => SwingGrid::Division,
+        }
+    }
+}
+
+impl SwingGrid {
+    /// Pulses in a step of the grid, None when it follows the division
+    fn step(&self, ppb: u8) -> Option<u8> {
+        match self {
+            SwingGrid::Division => None,
+            grid => Some(ppb / *grid as u8),
+        }
+    }
+}
+
+/// What happens to a ringing voice when its track's sample is swapped mid-playback
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum SwapPolicy {
+    /// old voice fades out over a few ms as the new sample starts
+    #[default]
+    Crossfade = 0,
+    /// old voice plays to its natural end under the new one
+    LetRing = 1,
+}
+
+impl From<i64> for SwapPolicy {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => SwapPolicy::Crossfade,
+            1 => SwapPolicy::LetRing,
+            _ => SwapPolicy::Crossfade,
+        }
+    }
+}
+
+impl From<u32> for SwapPolicy {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => SwapPolicy::Crossfade,
+            1 => SwapPolicy::LetRing,
+            _ => SwapPolicy::Crossfade,
+        }
+    }
+}
+
+/// Order in which a track's playhead walks its slots
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum Direction {
+    #[default]
+    Forward = 0,
+    Reverse = 1,
+    PingPong = 2,
+    Random = 3,
+}
+
+impl From<i64> for Direction {
+    fn from(value: i64) -> Self {
+        match value {
+            0 => Direction::Forward,
+            1 => Direction::Reverse,
+            2 => Direction::PingPong,
+            3 => Direction::Random,
+            _ => Direction::Forward,
+        }
+    }
+}
+
+impl From<u32> for Direction {
+    fn from(value: u32) -> Self {
+        match value {
+            0 => Direction::Forward,
+            1 => Direction::Reverse,
+            2 => Direction::PingPong,
+            3 => Direction::Random,
+            _ => Direction::Forward,
+        }
+    }
+}
+
+/// What recording does to the hits already in the pattern
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq, Default)]
+pub enum RecordMode {
+    /// new hits are layered on the existing ones
+    #[default]
+    Overdub = 0,
+    /// once a track is played into, its steps clear as the playhead
+    /// passes for one time round so the take replaces what was there
+    Replace = 1,
+}
+
+impl From<i64> for RecordMode {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => RecordMode::Replace,
+            _ => RecordMode::Overdub,
+        }
+    }
+}
+
+impl From<u32> for RecordMode {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => RecordMode::Replace,
+            _ => RecordMode::Overdub,
+        }
+    }
+}
+
+/// Effects switched out on a track without losing their settings
+///
+/// It's kept per pattern with the rest of the track, so the filter can be
+/// in on the breakdown and bypassed everywhere else
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq)]
+pub struct FxBypass {
+    pub eq: bool,
+    pub filter: bool,
+    pub delay: bool,
+    pub reverb: bool,
+}
+
 #[derive(Debug, Clone, Default, serde::Serialize)]
 pub struct TrackState {
     pub slots: Vec<u8>,
+    pub accents: Vec<bool>,
+    pub reverses: Vec<bool>,
+    pub ratchets: Vec<u8>,
+    pub ratchet_decays: Vec<i8>,
     pub name: String,
     pub len: usize,
     pub idx: usize,
     pub sample_path: String,
+    /// why the sample didn't load, the track is silent until it has one
+    pub load_error: Option<String>,
+    pub eq: TrackEq,
+    pub fx_bypass: FxBypass,
+    pub direction: u8,
+    pub timing_offset_ms: i16,
+    pub loop_points: Option<(usize, usize)>,
+    pub midi_note: u8,
+    pub velocity_variation: u8,
+    /// tuning in cents
+    pub pitch_cents: i16,
+    pub reverse: bool,
+    pub muted: bool,
+    pub gain_db: i8,
+    pub envelope: Envelope,
+    pub filter: Filter,
+    /// percent sent to the delay and reverb buses
+    pub delay_send: u8,
+    pub reverb_send: u8,
+    pub polyphony: Polyphony,
+    pub layers: Vec<VelocityLayer>,
+    pub loop_sync: Option<LoopSync>,
+    /// pair of output channels it plays on, 0 is outs 1/2
+    pub output: u8,
+    /// gain kept with the track's sample
+    pub sample_gain: SampleGain,
+    /// erase is held on the track
+    pub erasing: bool,
+    /// hidden from the grid and skipped in playback
+    pub archived: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct MidiOutputState {
+    pub port_name: String,
+    /// false while the port can't be found
+    pub connected: bool,
+    pub clock: bool,
+    pub transport: bool,
+    pub notes: bool,
+    pub cc: bool,
+    pub virtual_port: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct MidiInputState {
+    pub port_name: String,
+    /// false while the port can't be found
+    pub connected: bool,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct NoteMapState {
+    pub note: u8,
+    pub track: usize,
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize)]
+pub struct CcLaneState {
+    pub channel: u8,
+    pub controller: u8,
+    pub values: Vec<Option<u8>>,
+    pub idx: usize,
+}
+
+/// Where the transport is, beats are always quarter notes
+#[derive(Debug, Clone, Copy, Default, serde::Serialize)]
+pub struct TransportPosition {
+    /// bars played since play, from 0
+    pub bar: usize,
+    pub beat: u8,
+    /// pulse within the beat
+    pub pulse: u8,
+    /// time spent playing, pauses don't count
+    pub elapsed: Duration,
 }
 
 #[derive(Debug, Clone, Default, serde::Serialize)]
@@ -168,54 +706,428 @@ pub struct TrackState {
 /// 
 /// Refer to the Context struct to see more descriptors
 pub struct SeqState {
-    pub tempo: u8,
+    pub tempo: f32,
     pub trks: Vec<TrackState>,
+    pub cc_lanes: Vec<CcLaneState>,
     pub division: u8,
+    /// division the pattern changes to on the next bar
+    pub pending_division: Option<u8>,
+    /// 0 swings every other step, 8 or 16 every other 8th or 16th
+    pub swing_grid: u8,
     pub default_len: usize,
     pub latency: Duration,
+    /// share of the pulse interval the last pulse took to process, in percent
+    pub budget_pct: f32,
+    /// highest budget_pct over the last bars
+    pub peak_budget_pct: f32,
+    /// earliest, latest and 99th percentile pulses were scheduled by after
+    /// they were due to be over the last bars, in us. Early is negative
+    pub jitter_min_us: i64,
+    pub jitter_max_us: i64,
+    pub jitter_p99_us: i64,
+    /// pulses scheduled after the stream had mixed past them over the last
+    /// bars, their hits were heard late
+    pub missed_pulses: u32,
+    /// and since the sequencer started
+    pub total_missed_pulses: u64,
     pub last_cmd: Command,
     pub playing: bool,
+    pub paused: bool,
+    pub recording: bool,
+    pub record_mode: u8,
+    /// set while the audio output is lost
+    pub audio_error: Option<String>,
+    pub audio_device: String,
+    pub buffer_mode: u8,
+    /// controllers show their text in this
+    pub language: u8,
+    /// frames per buffer, 0 if the device picked its own
+    pub buffer_frames: u32,
+    /// frames per buffer asked for, 0 goes by the buffer mode
+    pub requested_buffer_frames: u32,
+    /// rate the output runs at
+    pub sample_rate: u32,
+    /// rate asked for, 0 is the device's default
+    pub requested_sample_rate: u32,
+    /// channels the output runs with, tracks can go on any pair of them
+    pub output_channels: u16,
+    pub sync_out: bool,
+    pub sync_out_channel: u16,
+    pub sync_out_ppqn: u8,
+    /// echo time of the delay bus as a division
+    pub delay_division: u8,
+    pub delay_feedback_pct: u8,
+    pub reverb: Reverb,
+    /// level of the whole mix in whole dB
+    pub master_volume_db: i8,
+    /// time from mixing to being heard
+    pub output_latency: Duration,
+    /// from a click being played to it being heard, from the last latency test
+    pub measured_latency: Option<Duration>,
+    pub position: TransportPosition,
+    /// quarter notes to a bar, the top of the time signature
+    pub beats_per_bar: u8,
+    /// bars until PlayForBars stops playback
+    pub bars_left: Option<usize>,
+    /// playing time until the stop timer stops playback, at the end of that bar
+    pub stop_in: Option<Duration>,
+    /// stop timer setting, 0 is off
+    pub stop_timer_secs: u32,
+    /// pattern file being loaded, None once it's in
+    pub preload: Option<Preload>,
+    /// decoded samples held in memory
+    pub sample_count: usize,
+    pub sample_bytes: usize,
+    /// the part of sample_bytes no track is using, kept for reuse
+    pub idle_sample_bytes: usize,
+    pub sample_cache_mb: u32,
+    pub sync: SyncStatus,
     pub pattern_id: usize,
     pub pattern_len: usize,
+    /// patterns longer than a bar are shown a bar of steps to a page,
+    /// this is the page being played
+    pub page: usize,
+    pub pages: usize,
+    /// steps to a page
+    pub page_len: usize,
     pub pattern_name: String,
+    /// every pattern's name in order so queues and songs can be labelled
+    pub pattern_names: Vec<String>,
+    pub pattern_notes: String,
+    pub project_notes: String,
     pub queued_pattern_id: usize,
+    pub pattern_queue: Vec<usize>,
+    /// pattern on deck B, None when deck mode is off
+    pub deck_b_id: Option<usize>,
+    /// 0 is all the current pattern and 1 all deck B
+    pub crossfade: f32,
+    /// fill of the main pattern
+    pub fill_id: Option<usize>,
+    /// pattern_id is the fill while this is set
+    pub fill_playing: bool,
     pub swing: u8,
+    pub tempo_slew: Duration,
+    pub song_loop: Option<(usize, usize)>,
+    pub song_mode: bool,
+    pub song: Vec<SongEntry>,
+    pub song_position: usize,
+    /// bars played of the current song entry
+    pub song_bar: usize,
+    pub swap_policy: u8,
+    pub anti_click: bool,
+    pub anti_click_ms: u8,
+    pub choke_fade_ms: u8,
+    pub metronome: bool,
+    pub metronome_sample: String,
+    pub count_in_bars: u8,
+    /// bars left of the count in, playback starts when it hits 0
+    pub count_in_left: u8,
+    pub macros: Vec<String>,
+    pub midi_thru: bool,
+    pub midi_thru_channel: Option<u8>,
+    pub midi_outputs: Vec<MidiOutputState>,
+    pub midi_inputs: Vec<MidiInputState>,
+    /// a command is waiting to be bound to the next note or cc pressed
+    pub midi_learning: bool,
+    pub midi_bindings: Vec<MidiBinding>,
+    pub note_input: bool,
+    pub note_input_channel: Option<u8>,
+    /// notes mapped to a track, the rest play the track sending them
+    pub note_map: Vec<NoteMapState>,
+    pub humanize_timing_ms: u8,
+    pub humanize_velocity: u8,
+    pub accent_velocity: u8,
+    pub groove_name: String,
+    pub groove_strength: u8,
+    pub last_export: Option<ExportReport>,
+    /// file the master mix is being recorded to
+    pub master_recording: Option<String>,
+    /// how long it's been recording
+    pub master_recording_time: Option<Duration>,
+    /// track a sample is being captured for
+    pub capture_track: Option<usize>,
+    /// the capture has heard something over its threshold and is recording
+    pub capture_triggered: bool,
+    /// sample files that failed to load and why
+    pub sample_errors: Vec<SampleError>,
 }
 
 #[derive(Clone)]
 /// BufferedSample is a custom Rodio source that holds
 /// the decoded sample data in memory. So it's much faster
+///
+/// Long samples like full loops only keep their start in memory, the rest
+/// is streamed from disk by each voice that plays it
 pub struct BufferedSample {
     sample_rate: u32,
+    /// rate and length of the file it was decoded from, loop points are
+    /// given in its frames so they mean the same whatever it's played at
+    file_rate: u32,
+    file_frames: usize,
     channels: u16,
     current_sample: usize,
     buffer: Arc<Vec<f32>>,
+    /// start and end frame of the looped region, the end is exclusive
+    loop_points: Option<(usize, usize)>,
+    /// plays from the last frame back to the first
+    reversed: bool,
+    /// full path of the file the rest of a long sample is read from
+    streamed_from: Option<Arc<str>>,
+    /// gain kept with the sample, and the linear gain it works out to
+    gain_settings: SampleGain,
+    gain: f32,
 }
 
+/// Samples longer than this are streamed from disk rather than held in memory
+const STREAM_AFTER_SECS: f64 = 20.0;
+/// Start of a streamed sample kept in memory, so a hit doesn't wait on the
+/// disk and the stream has this long to get ahead
+const STREAM_HEAD_SECS: f64 = 2.0;
+
 impl BufferedSample {
-    fn new(fp: &str) -> Result<Arc<Self>, Box<dyn Error>> {
-        let sample = Self::load_from_file(&format!("{PWD}/samples/{fp}").to_string())?;
-        Ok(Arc::new(sample))
+    /// Decodes a sample from the samples directory and converts it to the
+    /// output rate, so playback doesn't have to. Errors name the file
+    ///
+    /// It plays at the gain kept with it, a streamed sample is normalized
+    /// going by the part in memory
+    pub fn new(fp: &str, sample_rate: u32) -> Result<Arc<Self>, Box<dyn Error>> {
+        let sample = Self::load_from_file(&format!("{PWD}/samples/{fp}").to_string(), true)
+            .map_err(|e| format!("{}: {}", fp, e))?;
+        Ok(Arc::new(sample.resampled(sample_rate).with_gain(sample_gain::load(fp))))
+    }
+
+    /// Plays it at the gain, worked out from its own audio when normalizing
+    pub fn with_gain(self, gain: SampleGain) -> Self {
+        BufferedSample {
+            gain_settings: gain,
+            gain: gain.linear(&self.buffer),
+            ..self
+        }
+    }
+
+    pub fn gain(&self) -> f32 {
+        self.gain
+    }
+
+    pub fn gain_settings(&self) -> SampleGain {
+        self.gain_settings
+    }
+
+    /// Wraps audio generated in memory, the buffer is interleaved
+    pub fn from_buffer(buffer: Vec<f32>, channels: u16, sample_rate: u32) -> Self {
+        BufferedSample {
+            sample_rate,
+            file_rate: sample_rate,
+            file_frames: buffer.len() / channels as usize,
+            channels,
+            current_sample: 0,
+            buffer: Arc::new(buffer),
+            loop_points: None,
+            reversed: false,
+            streamed_from: None,
+            gain_settings: SampleGain::default(),
+            gain: 1.0,
+        }
     }
 
-    pub fn load_from_file(fp: &str) -> Result<Self, Box<dyn Error>> {
+    /// Decodes the whole file, or with streaming only the start of one
+    /// longer than STREAM_AFTER_SECS
+    pub fn load_from_file(fp: &str, streaming: bool) -> Result<Self, Box<dyn Error>> {
         let file = File::open(fp)?;
-        let decoder = rodio::Decoder::new(file)?;
+        let decoder = rodio::Decoder::new(file).map_err(|e| format!("can't be decoded, {}", e))?;
         let sample_rate = decoder.sample_rate();
         let channels = decoder.channels();
-        let decoder = decoder.convert_samples::<f32>();
-        let mut buffer = vec![];
-        for d in decoder.buffered() {
-            buffer.push(d);
+        let secs = |secs: f64| (secs * sample_rate as f64) as usize * channels as usize;
+        let mut decoder = decoder.convert_samples::<f32>();
+        let limit = if streaming { secs(STREAM_AFTER_SECS) } else { usize::MAX };
+        let mut buffer: Vec<f32> = decoder.by_ref().take(limit).collect();
+        if buffer.is_empty() {
+            return Err("has no audio in it".into());
         }
+        let mut streamed_from = None;
+        if streaming && decoder.next().is_some() {
+            buffer.truncate(secs(STREAM_HEAD_SECS));
+            buffer.shrink_to_fit();
+            streamed_from = Some(Arc::from(fp));
+        }
+        let file_frames = buffer.len() / channels as usize;
         let buffer = Arc::new(buffer);
         Ok(BufferedSample {
             sample_rate,
+            file_rate: sample_rate,
+            file_frames,
             channels,
             current_sample: 0,
             buffer,
+            loop_points: None,
+            reversed: false,
+            streamed_from,
+            gain_settings: SampleGain::default(),
+            gain: 1.0,
         })
     }
+
+    /// Whether the rest of it is read from disk as it plays
+    pub fn streamed(&self) -> bool {
+        self.streamed_from.is_some()
+    }
+
+    /// The whole of a streamed sample decoded into memory, for an export
+    /// that reads it at its own pace
+    pub fn whole(&self) -> Result<Self, Box<dyn Error>> {
+        match &self.streamed_from {
+            Some(path) => Ok(BufferedSample {
+                gain_settings: self.gain_settings,
+                gain: self.gain,
+                ..Self::load_from_file(&path[..], false)?
+            }),
+            None => Ok(self.clone()),
+        }
+    }
+
+    /// Plays it as a voice, one of a streamed sample starts reading the
+    /// rest of the file
+    pub fn voice(self) -> SampleVoice {
+        let stream = self.streamed_from.as_ref()
+            .map(|path| SampleStream::start(path.to_string(), self.buffer.len()));
+        SampleVoice::new(self, stream)
+    }
+
+    pub fn frames(&self) -> usize {
+        self.buffer.len() / self.channels as usize
+    }
+
+    /// Length in frames of the file it came from, what loop points are checked against
+    pub fn file_frames(&self) -> usize {
+        self.file_frames
+    }
+
+    /// Converts the audio to another rate with cubic interpolation
+    ///
+    /// Done once when a sample is loaded, rather than by rodio's linear
+    /// conversion on every voice, which costs more the more tracks there are
+    /// at a different rate to the output and dulls their top end
+    ///
+    /// A streamed sample is left at its file's rate to match the rest of it
+    /// coming off the disk, and converted as it plays
+    pub fn resampled(self, sample_rate: u32) -> Self {
+        if sample_rate == self.sample_rate || sample_rate == 0 || self.streamed() {
+            return self;
+        }
+        let channels = self.channels as usize;
+        let frames = self.frames();
+        let step = self.sample_rate as f64 / sample_rate as f64;
+        let len = (frames as f64 / step).ceil() as usize;
+        let at = |frame: isize, ch: usize| self.buffer[frame.clamp(0, frames as isize - 1) as usize * channels + ch];
+        let mut buffer = Vec::with_capacity(len * channels);
+        for i in 0..len {
+            let pos = i as f64 * step;
+            let frame = pos as isize;
+            let t = (pos - frame as f64) as f32;
+            for ch in 0..channels {
+                let (y0, y1, y2, y3) = (at(frame - 1, ch), at(frame, ch), at(frame + 1, ch), at(frame + 2, ch));
+                // Catmull-Rom through the two frames either side
+                let c1 = 0.5 * (y2 - y0);
+                let c2 = y0 - 2.5 * y1 + 2.0 * y2 - 0.5 * y3;
+                let c3 = 0.5 * (y3 - y0) + 1.5 * (y1 - y2);
+                buffer.push(((c3 * t + c2) * t + c1) * t + y1);
+            }
+        }
+        BufferedSample {
+            sample_rate,
+            current_sample: 0,
+            buffer: Arc::new(buffer),
+            ..self
+        }
+    }
+
+    /// Changes its length by ratio without changing the pitch, loop points
+    /// move with the audio. A streamed sample is left as it is
+    pub fn stretched(&self, ratio: f64) -> Self {
+        if self.streamed() || self.frames() == 0 {
+            return self.clone();
+        }
+        let buffer = stretch::stretch(&self.buffer, self.channels as usize, self.sample_rate, ratio);
+        BufferedSample {
+            file_rate: (self.file_rate as f64 / ratio).round().max(1.0) as u32,
+            current_sample: 0,
+            buffer: Arc::new(buffer),
+            ..self.clone()
+        }
+    }
+
+    /// Where a frame of the file it came from is in the buffer
+    fn frame_from_file(&self, file_frame: usize) -> usize {
+        (file_frame as u64 * self.sample_rate as u64 / self.file_rate as u64) as usize
+    }
+
+    /// Memory held by the decoded audio
+    pub fn bytes(&self) -> usize {
+        self.buffer.len() * std::mem::size_of::<f32>()
+    }
+
+    /// Whether a copy of this sample, like a voice still ringing, shares its buffer
+    pub fn shared(&self) -> bool {
+        Arc::strong_count(&self.buffer) > 1
+    }
+
+    /// Plays the region between the loop points over and over once reached,
+    /// they're in frames of the file it came from. A streamed sample can't
+    /// jump back, so it ignores them
+    pub fn with_loop(mut self, loop_points: Option<(usize, usize)>) -> Self {
+        self.loop_points = loop_points
+            .filter(|_| !self.streamed())
+            .map(|(start, end)| (self.frame_from_file(start), self.frame_from_file(end).min(self.frames())))
+            .filter(|(start, end)| start < end);
+        self
+    }
+
+    /// Plays the sample backwards, once through since the loop points are
+    /// dropped. A reversed loop would start on its end and never reach the
+    /// attack, which isn't much use on a drum. A streamed sample plays
+    /// forwards, it would have to be read from the end
+    pub fn with_reverse(mut self, reversed: bool) -> Self {
+        if reversed && !self.streamed() {
+            self.reversed = true;
+            self.loop_points = None;
+        }
+        self
+    }
+
+    /// Returns the interleaved sample at idx at the sample's gain, following
+    /// the loop if there is one
+    pub fn sample_at(&self, idx: usize) -> Option<f32> {
+        self.raw_sample_at(idx).map(|s| s * self.gain)
+    }
+
+    /// The end of the loop is crossfaded into the audio leading up to the
+    /// loop start, so the jump back lands on a continuous waveform
+    fn raw_sample_at(&self, idx: usize) -> Option<f32> {
+        if self.reversed {
+            let channels = self.channels as usize;
+            let frames = self.frames();
+            let frame = idx / channels;
+            if frame >= frames {
+                return None;
+            }
+            return self.buffer.get((frames - 1 - frame) * channels + idx % channels).copied();
+        }
+        let Some((start, end)) = self.loop_points else {
+            return self.buffer.get(idx).copied();
+        };
+        let channels = self.channels as usize;
+        let (frame, ch) = (idx / channels, idx % channels);
+        let len = end - start;
+        let frame = if frame < end { frame } else { start + (frame - start) % len };
+        let s = self.buffer[frame * channels + ch];
+        let fade = (self.sample_rate as usize / 200).min(start).min(len / 2);
+        if fade > 0 && frame >= end - fade {
+            let t = (frame + fade - end) as f32 / fade as f32;
+            let lead = self.buffer[(frame - len) * channels + ch];
+            return Some(s * (1.0 - t) + lead * t);
+        }
+        Some(s)
+    }
 }
 
 impl Iterator for BufferedSample
@@ -223,10 +1135,7 @@ impl Iterator for BufferedSample
     type Item = f32;
 
     fn next(&mut self) -> Option<f32> {
-        if self.current_sample >= self.buffer.len() {
-            return None
-        }
-        let b = self.buffer[self.current_sample];
+        let b = self.sample_at(self.current_sample)?;
         self.current_sample += 1;
         Some(b)
     }
@@ -246,142 +1155,1301 @@ impl Source for BufferedSample {
     }
 
     fn total_duration(&self) -> Option<Duration> {
+        if self.loop_points.is_some() || self.streamed() {
+            return None;
+        }
         Some(Duration::from_millis(self.buffer.len() as u64 / self.channels as u64 * 1000 / self.sample_rate as u64))
     }
 }
 
-#[derive(Clone, Serialize, Deserialize, Hash)]
-pub struct Slot {
-    pub velocity: u8,
+/// Attack, hold and decay shaping a track's hits, all in ms
+///
+/// All 0 is off. With hold and decay both 0 the sample rings out after the
+/// attack, otherwise it's cut off once the decay is done
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq)]
+pub struct Envelope {
+    pub attack_ms: u16,
+    pub hold_ms: u16,
+    pub decay_ms: u16,
 }
 
-/// Struct for saving track data to file
-#[derive(Clone, Serialize, Deserialize, Hash)]
-pub struct SavedTrack {
-    pub slots: Vec<Slot>,
-    pub sample_path: String,
+impl Envelope {
+    /// Level at secs into the hit, None once it has decayed to nothing
+    ///
+    /// The decay is a curve rather than a straight line, which is closer
+    /// to how a drum dies away
+    pub fn gain_at(&self, secs: f32) -> Option<f32> {
+        let ms = secs * 1000.0;
+        let (attack, hold, decay) = (self.attack_ms as f32, self.hold_ms as f32, self.decay_ms as f32);
+        if ms < attack {
+            return Some(ms / attack);
+        }
+        if (self.hold_ms == 0 && self.decay_ms == 0) || ms < attack + hold {
+            return Some(1.0);
+        }
+        let left = 1.0 - (ms - attack - hold) / decay;
+        (left > 0.0).then_some(left * left)
+    }
 }
 
-/// `Track` contains data that allows the sequencer to play a sample 
-/// 
-/// It has a vector of velocities that determine when a sample is triggered, an audio sink to queue it,
-/// and a reference to the sample itself
-/// Tracks also can have their own length, leading to interesting pattern variations
-#[derive(Clone)]
-pub struct Track {
-    pub slots: Vec<Slot>,
-    pub sample: Arc<BufferedSample>,
-    pub sample_path: String,
-    pub idx: usize,
-    pub len: usize,
-    pub sink: Arc<Sink>,
-    pub name: String,
+/// Source wrapper applying a track's envelope to a voice
+pub struct Enveloped<S> {
+    inner: S,
+    envelope: Envelope,
+    /// samples played, across all channels
+    played: usize,
 }
 
-impl Track {
-    pub fn new(len: usize, sample_path: String, sink: Arc<Sink>) -> Result<Self, Box<dyn Error>> {
-        let name = sample_path.split('/').last().unwrap().split('.').next().unwrap().to_string();
-        let mut slots = vec![];
-        for _ in 0..len {
-            slots.push(Slot {
-                velocity: 0
-            });
-        }
-        let sample = BufferedSample::new(&sample_path)?;
-        Ok(Track {
-            slots,
-            sample,
-            sample_path,
-            idx: 0, 
-            len,
-            sink,
-            name
-        })
+impl<S: Source<Item = f32>> Enveloped<S> {
+    pub fn new(inner: S, envelope: Envelope) -> Self {
+        Enveloped { inner, envelope, played: 0 }
     }
+}
 
-    pub fn reset_slots(&mut self) {
-        self.slots.iter_mut().for_each(|slot| {
-            slot.velocity = 0;
-        });
+impl<S: Source<Item = f32>> Iterator for Enveloped<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let s = self.inner.next()?;
+        let frame = self.played / self.inner.channels() as usize;
+        self.played += 1;
+        let gain = self.envelope.gain_at(frame as f32 / self.inner.sample_rate() as f32)?;
+        Some(s * gain)
     }
+}
 
-    pub fn set_len(&mut self, len: usize) {
-        if len > self.len {
-            self.slots.extend(vec![Slot { velocity: 0 }; len - self.len]);
-        } else {
-            self.slots.truncate(len);
-            self.idx = self.idx % len;
-        }
-        self.len = len;
+impl<S: Source<Item = f32>> Source for Enveloped<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
     }
 
-    pub fn set_sample(&mut self, sample_path: String) -> Result<(), Box<dyn Error>> {
-        let sample = BufferedSample::new(&sample_path)?;
-        self.sample = sample;
-        self.sample_path = sample_path;
-        Ok(())
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
     }
 }
 
-/// ChokeGrp allows defining tracks that stop other tracks in
-/// the same choke group when triggered
-#[derive(Clone, Serialize, Deserialize, Hash)]
-pub struct ChokeGrp {
-    pub track_ids: Vec<usize>,
+/// Which way a track's filter cuts, off leaves the hits untouched
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum FilterMode {
+    #[default]
+    Off = 0,
+    LowPass = 1,
+    HighPass = 2,
 }
 
-impl ChokeGrp {
-    pub fn new(tracks: Vec<usize>) -> ChokeGrp {
-        ChokeGrp {
-            track_ids: tracks
+impl From<i64> for FilterMode {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => FilterMode::LowPass,
+            2 => FilterMode::HighPass,
+            _ => FilterMode::Off,
         }
     }
+}
 
-    pub fn add_track(&mut self, track_id: usize) {
-        self.track_ids.push(track_id);
-    }
+/// Highest filter resonance, it's a percentage of the way to a ringing peak
+pub const MAX_RESONANCE: u8 = 100;
 
-    pub fn remove_track(&mut self, track_id: usize) {
-        self.track_ids.retain(|&x| x != track_id);
+/// A resonant filter on a track's hits
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq)]
+pub struct Filter {
+    pub mode: FilterMode,
+    pub cutoff_hz: u16,
+    /// 0 is a flat corner, MAX_RESONANCE a sharp peak at the cutoff
+    pub resonance: u8,
+}
+
+impl Default for Filter {
+    /// Off, with a cutoff that's somewhere useful when it's turned on
+    fn default() -> Self {
+        Filter {
+            mode: FilterMode::Off,
+            cutoff_hz: 1000,
+            resonance: 0,
+        }
     }
+}
 
-    pub fn is_member(&self, track_id: usize) -> bool {
-        self.track_ids.contains(&track_id)
+/// Running state of a filter on a voice, a biquad from the RBJ cookbook
+#[derive(Clone)]
+pub struct Biquad {
+    b: [f32; 3],
+    a: [f32; 2],
+    /// last two inputs and outputs per channel
+    x: Vec<[f32; 2]>,
+    y: Vec<[f32; 2]>,
+}
+
+impl Biquad {
+    /// None when the filter is off
+    pub fn new(filter: Filter, sample_rate: u32, channels: usize) -> Option<Self> {
+        // Cutoffs near nyquist blow up, so they're kept a little under
+        let cutoff = (filter.cutoff_hz as f32).clamp(20.0, sample_rate as f32 * 0.45);
+        let resonance = filter.resonance.min(MAX_RESONANCE) as f32 / MAX_RESONANCE as f32;
+        let q = std::f32::consts::FRAC_1_SQRT_2 + resonance * 7.3;
+        let (sin, cos) = (2.0 * std::f32::consts::PI * cutoff / sample_rate as f32).sin_cos();
+        let alpha = sin / (2.0 * q);
+        let b = match filter.mode {
+            FilterMode::Off => return None,
+            FilterMode::LowPass => [(1.0 - cos) / 2.0, 1.0 - cos, (1.0 - cos) / 2.0],
+            FilterMode::HighPass => [(1.0 + cos) / 2.0, -(1.0 + cos), (1.0 + cos) / 2.0],
+        };
+        let a0 = 1.0 + alpha;
+        Some(Biquad {
+            b: b.map(|b| b / a0),
+            a: [-2.0 * cos / a0, (1.0 - alpha) / a0],
+            x: vec![[0.0; 2]; channels],
+            y: vec![[0.0; 2]; channels],
+        })
     }
 
-    // Returns list of other indices if a given track index is
-    // in the choke group
-    pub fn get_choked_ids(&self, track_id: usize) -> Vec<usize> {
-        if !self.is_member(track_id) {
-            return vec![];
-        }
-        self.track_ids.iter().filter(|&&x| x != track_id).cloned().collect()
+    pub fn process(&mut self, ch: usize, input: f32) -> f32 {
+        let (x, y) = (&mut self.x[ch], &mut self.y[ch]);
+        let out = self.b[0] * input + self.b[1] * x[0] + self.b[2] * x[1] - self.a[0] * y[0] - self.a[1] * y[1];
+        *x = [input, x[0]];
+        *y = [out, y[0]];
+        out
     }
 }
 
-/// Struct for saving pattern data to file
-#[derive(Clone, Serialize, Deserialize, Hash)]
-pub struct SavedPattern {
-    pub tracks: Vec<SavedTrack>,
-    pub choke_grps: Vec<ChokeGrp>,
-    pub division: Division
+/// Source wrapper running a track's filter over a voice
+pub struct Filtered<S> {
+    inner: S,
+    biquad: Option<Biquad>,
+    /// samples played, across all channels
+    played: usize,
 }
 
-/// `Pattern` is a collection of tracks
-/// 
-/// If an empty pattern is saved, this can be considered a kit.
-#[derive(Clone)]
-pub struct Pattern {
-    pub tracks: Vec<Track>,
-    pub choke_grps: Vec<ChokeGrp>,
-    /// defines the note length of a beat
-    /// 
-    /// allowable set{1,2,3,4,6,8,12,16,24,32}
-    pub division: Division,
-    pub name: String,
+impl<S: Source<Item = f32>> Filtered<S> {
+    pub fn new(inner: S, filter: Filter) -> Self {
+        let biquad = Biquad::new(filter, inner.sample_rate(), inner.channels() as usize);
+        Filtered { inner, biquad, played: 0 }
+    }
 }
 
-impl Pattern {
+impl<S: Source<Item = f32>> Iterator for Filtered<S> {
+    type Item = f32;
+
+    fn next(&mut self) -> Option<f32> {
+        let s = self.inner.next()?;
+        let ch = self.played % self.inner.channels() as usize;
+        self.played += 1;
+        match self.biquad.as_mut() {
+            Some(biquad) => Some(biquad.process(ch, s)),
+            None => Some(s),
+        }
+    }
+}
+
+impl<S: Source<Item = f32>> Source for Filtered<S> {
+    fn current_frame_len(&self) -> Option<usize> {
+        self.inner.current_frame_len()
+    }
+
+    fn channels(&self) -> u16 {
+        self.inner.channels()
+    }
+
+    fn sample_rate(&self) -> u32 {
+        self.inner.sample_rate()
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        self.inner.total_duration()
+    }
+}
+
+/// What a track does with a new hit while earlier ones still ring
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum VoiceMode {
+    /// each hit cuts the last, for hats and anything that should cut itself
+    #[default]
+    Mono = 0,
+    /// hits overlap up to the track's max voices, for cymbals and long tails
+    Poly = 1,
+}
+
+impl From<i64> for VoiceMode {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => VoiceMode::Poly,
+            _ => VoiceMode::Mono,
+        }
+    }
+}
+
+/// Which ringing voice a poly track gives up once it has no voices left
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum StealPolicy {
+    /// the hit that started first
+    #[default]
+    Oldest = 0,
+    /// the softest hit, by the velocity it was played at
+    Quietest = 1,
+}
+
+impl From<i64> for StealPolicy {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => StealPolicy::Quietest,
+            _ => StealPolicy::Oldest,
+        }
+    }
+}
+
+/// Most voices a poly track can have ringing at once
+pub const MAX_POLYPHONY: u8 = 16;
+
+/// How many hits of a track ring at once and which goes when there's no room
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq)]
+pub struct Polyphony {
+    pub mode: VoiceMode,
+    /// only read in poly mode
+    pub max_voices: u8,
+    pub steal: StealPolicy,
+}
+
+impl Default for Polyphony {
+    /// Mono, with room for a few voices when it's switched to poly
+    fn default() -> Self {
+        Polyphony {
+            mode: VoiceMode::Mono,
+            max_voices: 4,
+            steal: StealPolicy::Oldest,
+        }
+    }
+}
+
+impl Polyphony {
+    pub fn voices(&self) -> usize {
+        match self.mode {
+            VoiceMode::Mono => 1,
+            VoiceMode::Poly => self.max_voices.clamp(1, MAX_POLYPHONY) as usize,
+        }
+    }
+
+    /// Which voice a new hit takes over from, given the velocity of each
+    /// voice ringing in the order they started. None while there's room
+    pub fn steal(&self, velocities: &[u8]) -> Option<usize> {
+        if velocities.len() < self.voices() {
+            return None;
+        }
+        match self.steal {
+            StealPolicy::Oldest => Some(0),
+            StealPolicy::Quietest => velocities.iter().enumerate().min_by_key(|(_, vel)| **vel).map(|(i, _)| i),
+        }
+    }
+}
+
+/// Output channel pairs a track can be put on, up to 32 channels
+pub const MAX_OUTPUT_PAIRS: u8 = 16;
+
+/// Most velocity layers a track can have on top of its own sample
+pub const MAX_LAYERS: usize = 8;
+
+/// A sample a track plays in place of its own for hits at or above a
+/// velocity, so soft, medium and hard hits can be different recordings
+#[derive(Debug, Clone, Serialize, Deserialize, Hash, PartialEq)]
+pub struct VelocityLayer {
+    /// lowest velocity it plays for, up to the next layer's
+    pub min_velocity: u8,
+    pub sample_path: String,
+}
+
+/// How a loop is fitted to the tempo
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, Hash, PartialEq, Default)]
+pub enum FitMode {
+    /// changes its length and keeps its pitch
+    #[default]
+    Stretch = 0,
+    /// plays it faster or slower like a turntable, the pitch goes with it
+    Repitch = 1,
+}
+
+impl From<i64> for FitMode {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => FitMode::Repitch,
+            _ => FitMode::Stretch,
+        }
+    }
+}
+
+/// A track's sample is a loop played at a known tempo
+#[derive(Debug, Clone, Copy, Serialize, Deserialize, PartialEq)]
+pub struct LoopSync {
+    pub bpm: f32,
+    pub mode: FitMode,
+}
+
+impl Hash for LoopSync {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.bpm.to_bits().hash(state);
+        self.mode.hash(state);
+    }
+}
+
+/// A loop's samples fitted to a tempo, with the ones they came from so
+/// it can tell when they've changed
+#[derive(Clone)]
+struct FittedLoop {
+    tempo: f32,
+    sync: LoopSync,
+    /// the track's own sample then each layer's
+    sources: Vec<Arc<BufferedSample>>,
+    samples: Vec<Arc<BufferedSample>>,
+    /// playback speed on top of the tuning, 1 when stretched
+    speed: f32,
+}
+
+/// How much longer a loop has to be to take the same beats at the tempo,
+/// counting whole beats so a loop trimmed a little long or short still fits
+fn loop_ratio(sample: &BufferedSample, bpm: f32, tempo: f32) -> f64 {
+    let secs = sample.frames() as f64 / sample.sample_rate() as f64;
+    let beats = (secs * bpm as f64 / 60.0).round().max(1.0);
+    beats * 60.0 / tempo as f64 / secs
+}
+
+/// How long the old voice takes to fade when a sample is swapped
+const SWAP_FADE: Duration = Duration::from_millis(10);
+
+/// Fades a voice out if anti-click is on, otherwise cuts it
+fn stop_voice(voice: &VoiceHandle, settings: VoiceSettings) {
+    match settings.anti_click {
+        Some(_) => voice.fade_out(),
+        None => voice.stop(),
+    }
+}
+
+/// Global settings for how voices are started and ended
+#[derive(Clone, Copy)]
+pub struct VoiceSettings {
+    pub swap_policy: SwapPolicy,
+    /// micro fade on every voice start, stop and steal, None cuts instantly
+    pub anti_click: Option<Duration>,
+    /// fade out of a choked voice
+    pub choke_fade: Duration,
+}
+
+#[derive(Clone, Default, Serialize, Deserialize, Hash)]
+pub struct Slot {
+    pub velocity: u8,
+    /// accented hits get the global accent velocity added on
+    #[serde(default)]
+    pub accent: bool,
+    /// hits played within the step, 0 and 1 both play once
+    #[serde(default)]
+    pub ratchet: u8,
+    /// percent each ratchet hit changes velocity by from the one before it
+    #[serde(default)]
+    pub ratchet_decay: i8,
+    /// flips the track's reverse setting for this step
+    #[serde(default)]
+    pub reverse: bool,
+}
+
+/// Most hits a ratchet can squeeze into a step
+pub const MAX_RATCHET: u8 = 8;
+
+/// Loudest a track can be turned up, in dB. Anything at or below the
+/// minimum is silent
+pub const MAX_TRACK_GAIN_DB: i8 = 6;
+pub const MIN_TRACK_GAIN_DB: i8 = -60;
+
+/// Same again for the master volume, on the whole mix
+pub const MAX_MASTER_VOLUME_DB: i8 = 6;
+pub const MIN_MASTER_VOLUME_DB: i8 = -60;
+
+/// Range of the fade on a choked voice in ms, long enough not to click
+/// and short enough that the choke still sounds like a cut
+pub const MIN_CHOKE_FADE_MS: u8 = 5;
+pub const MAX_CHOKE_FADE_MS: u8 = 15;
+const DEFAULT_CHOKE_FADE_MS: u8 = 10;
+
+/// Furthest a track can be tuned either way, in cents. Past two octaves
+/// most drum samples are mush or clicks anyway
+pub const MAX_PITCH_CENTS: i16 = 2400;
+pub const MAX_SEND: u8 = 100;
+
+impl Slot {
+    /// Velocity of the nth hit of a ratchet, the first is the one given
+    fn ratchet_velocity(&self, vel: u8, n: u8) -> u8 {
+        let scale = (1.0 + self.ratchet_decay as f32 / 100.0).max(0.0).powi(n as i32);
+        (vel as f32 * scale).round().clamp(1.0, 127.0) as u8
+    }
+}
+
+/// A ratchet hit waiting for its pulse
+#[derive(Clone)]
+struct Retrigger {
+    /// pulses after the step it belongs to
+    pulse: u8,
+    velocity: u8,
+    delay: Duration,
+    reverse: bool,
+}
+
+/// Struct for saving track data to file
+#[derive(Clone, Default, Serialize, Deserialize, Hash)]
+pub struct SavedTrack {
+    pub slots: Vec<Slot>,
+    pub sample_path: String,
+    // older pattern files won't have these
+    #[serde(default)]
+    pub direction: Direction,
+    #[serde(default)]
+    pub timing_offset_ms: i16,
+    #[serde(default)]
+    pub eq: TrackEq,
+    #[serde(default)]
+    pub fx_bypass: FxBypass,
+    #[serde(default)]
+    pub loop_points: Option<(usize, usize)>,
+    /// older files go by track order
+    #[serde(default)]
+    pub midi_note: Option<u8>,
+    #[serde(default)]
+    pub velocity_variation: u8,
+    #[serde(default)]
+    pub archived: bool,
+    #[serde(default)]
+    pub pitch_cents: i16,
+    #[serde(default)]
+    pub reverse: bool,
+    #[serde(default)]
+    pub muted: bool,
+    #[serde(default)]
+    pub gain_db: i8,
+    #[serde(default)]
+    pub envelope: Envelope,
+    #[serde(default)]
+    pub filter: Filter,
+    #[serde(default)]
+    pub delay_send: u8,
+    #[serde(default)]
+    pub reverb_send: u8,
+    #[serde(default)]
+    pub polyphony: Polyphony,
+    #[serde(default)]
+    pub layers: Vec<VelocityLayer>,
+    #[serde(default)]
+    pub loop_sync: Option<LoopSync>,
+    #[serde(default)]
+    pub output: u8,
+}
+
+/// `Track` contains data that allows the sequencer to play a sample 
+/// 
+/// It has a vector of velocities that determine when a sample is triggered, an audio sink to queue it,
+/// and a reference to the sample itself
+/// Tracks also can have their own length, leading to interesting pattern variations
+#[derive(Clone)]
+pub struct Track {
+    pub slots: Vec<Slot>,
+    pub sample: Arc<BufferedSample>,
+    pub sample_path: String,
+    /// why the sample didn't load, the track plays silence in its place
+    /// until it's given one that does
+    pub load_error: Option<String>,
+    pub idx: usize,
+    pub len: usize,
+    pub name: String,
+    pub direction: Direction,
+    /// low and high shelf gains run over every hit
+    pub eq: TrackEq,
+    /// effects bypassed in this pattern, their settings are kept
+    pub fx_bypass: FxBypass,
+    /// true while a ping-pong track is on its way back down
+    ping_pong_rev: bool,
+    /// constant offset from the grid, positive drags behind the beat
+    /// and negative pushes ahead of it
+    pub timing_offset_ms: i16,
+    /// playhead was just reset and hasn't stepped yet
+    fresh: bool,
+    /// frames of the sample to loop, a looping voice rings until
+    /// the track is triggered again or the sequencer stops
+    pub loop_points: Option<(usize, usize)>,
+    /// note sent on the drum channel when the track plays
+    pub midi_note: u8,
+    /// max amount each hit's velocity is randomly moved either way
+    pub velocity_variation: u8,
+    /// tuning in cents, 100 to a semitone
+    pub pitch_cents: i16,
+    /// plays the sample backwards, steps can flip it back
+    pub reverse: bool,
+    /// steps still advance but don't play or send notes
+    pub muted: bool,
+    /// level on top of the hit velocities
+    pub gain_db: i8,
+    /// shapes every hit, off by default
+    pub envelope: Envelope,
+    pub filter: Filter,
+    /// percent of each hit sent to the delay and reverb buses
+    pub delay_send: u8,
+    pub reverb_send: u8,
+    /// parked out of the way, it's skipped in playback but saved as usual
+    pub archived: bool,
+    /// times each step has played since the counts were last reset,
+    /// ratchet repeats aren't counted
+    pub trigger_counts: Vec<u32>,
+    /// ratchet hits still to play for the last step
+    retriggers: VecDeque<Retrigger>,
+    /// pulses since the last step was played
+    step_pulses: u8,
+    /// slot of the last step played
+    last_idx: Option<usize>,
+    /// slot that was just recorded into ahead of the playhead, its hit
+    /// already played live so the step skips it once
+    skip_idx: Option<usize>,
+    /// erase is held, steps clear as the playhead passes them
+    pub erasing: bool,
+    /// steps left of a replace pass, started by the first hit recorded
+    /// in replace mode and clearing like erase for one time round
+    replace_left: usize,
+    pub polyphony: Polyphony,
+    /// voices still ringing in the order they started, with the velocity
+    /// each was played at. They carry the track's level and sends into
+    /// the effect buses
+    voices: Vec<(VoiceHandle, u8)>,
+    /// sample swapped in during playback, it takes over on the next trigger
+    pending_sample: Option<Arc<BufferedSample>>,
+    /// samples for harder hits by lowest velocity, in order. The track's
+    /// own sample plays for anything softer than the first
+    pub layers: Vec<(VelocityLayer, Arc<BufferedSample>)>,
+    /// the sample is a loop, fitted to the pattern at our tempo
+    pub loop_sync: Option<LoopSync>,
+    /// the samples as fitted, made again once the tempo or they change
+    fitted: Option<FittedLoop>,
+    /// pair of output channels the voices play on, 0 is outs 1/2
+    pub output: u8,
+}
+
+impl Track {
+    pub fn new(len: usize, sample_path: String, samples: &mut SampleCache) -> Result<Self, Box<dyn Error>> {
+        let sample = samples.load(&sample_path)?;
+        Ok(Self::with_sample(len, sample_path, sample))
+    }
+
+    /// Stands in for a track whose sample failed to load, so the tracks
+    /// after it keep their places. It keeps the path and plays silence
+    pub fn silent(len: usize, sample_path: String, sample_rate: u32, error: String) -> Self {
+        let sample = Arc::new(BufferedSample::from_buffer(vec![0.0; 2], 2, sample_rate));
+        Track {
+            load_error: Some(error),
+            ..Self::with_sample(len, sample_path, sample)
+        }
+    }
+
+    fn with_sample(len: usize, sample_path: String, sample: Arc<BufferedSample>) -> Self {
+        let name = sample_path.split('/').last().unwrap().split('.').next().unwrap().to_string();
+        let mut slots = vec![];
+        for _ in 0..len {
+            slots.push(Slot::default());
+        }
+        Track {
+            slots,
+            sample,
+            sample_path,
+            load_error: None,
+            idx: 0, 
+            len,
+            name,
+            direction: Direction::Forward,
+            eq: TrackEq::default(),
+            fx_bypass: FxBypass::default(),
+            ping_pong_rev: false,
+            timing_offset_ms: 0,
+            fresh: true,
+            loop_points: None,
+            midi_note: BASE_NOTE,
+            velocity_variation: 0,
+            pitch_cents: 0,
+            reverse: false,
+            muted: false,
+            gain_db: 0,
+            envelope: Envelope::default(),
+            filter: Filter::default(),
+            delay_send: 0,
+            reverb_send: 0,
+            archived: false,
+            trigger_counts: vec![0; len],
+            retriggers: VecDeque::new(),
+            step_pulses: 0,
+            last_idx: None,
+            skip_idx: None,
+            erasing: false,
+            replace_left: 0,
+            polyphony: Polyphony::default(),
+            voices: vec![],
+            pending_sample: None,
+            layers: vec![],
+            loop_sync: None,
+            fitted: None,
+            output: 0,
+        }
+    }
+
+    /// The sample a hit at the velocity plays, from the highest layer it
+    /// reaches or the track's own
+    pub fn sample_for(&self, vel: u8) -> &Arc<BufferedSample> {
+        let layer = self.layers.iter().rposition(|(layer, _)| vel >= layer.min_velocity);
+        let fitted = self.fitted.as_ref().and_then(|fitted| fitted.samples.get(layer.map_or(0, |i| i + 1)));
+        fitted.unwrap_or_else(|| layer.map_or(&self.sample, |i| &self.layers[i].1))
+    }
+
+    /// Fits a loop to the tempo, stretched or repitched so the beats it
+    /// was played over take as long at our tempo, which matches it to a
+    /// pattern of the same beats. The work's only done again once the
+    /// tempo, the samples or how they're fitted change. Streamed samples
+    /// can't be fitted, their length isn't known until they've played
+    pub fn fit_loop(&mut self, tempo: f32) {
+        let Some(sync) = self.loop_sync else {
+            self.fitted = None;
+            return;
+        };
+        let sources: Vec<Arc<BufferedSample>> = std::iter::once(&self.sample)
+            .chain(self.layers.iter().map(|(_, sample)| sample))
+            .cloned()
+            .collect();
+        if let Some(fitted) = &self.fitted {
+            let same_sources = fitted.sources.len() == sources.len()
+                && fitted.sources.iter().zip(&sources).all(|(a, b)| Arc::ptr_eq(a, b));
+            if fitted.tempo == tempo && fitted.sync == sync && same_sources {
+                return;
+            }
+        }
+        if sources.iter().any(|sample| sample.streamed()) {
+            self.fitted = None;
+            return;
+        }
+        let ratio = loop_ratio(&self.sample, sync.bpm, tempo);
+        let (samples, speed) = match sync.mode {
+            FitMode::Stretch => (sources.iter().map(|sample| Arc::new(sample.stretched(ratio))).collect(), 1.0),
+            FitMode::Repitch => (sources.clone(), (1.0 / ratio) as f32),
+        };
+        self.fitted = Some(FittedLoop { tempo, sync, sources, samples, speed });
+    }
+
+    /// Plays the sample for hits from min_velocity up, in place of a layer
+    /// already starting there
+    pub fn set_layer(&mut self, min_velocity: u8, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        if !(1..=127).contains(&min_velocity) {
+            return Err(format!("Layer velocity {} isn't 1 to 127", min_velocity).into());
+        }
+        let replacing = self.layers.iter().any(|(layer, _)| layer.min_velocity == min_velocity);
+        if !replacing && self.layers.len() >= MAX_LAYERS {
+            return Err(format!("Track already has {} layers", MAX_LAYERS).into());
+        }
+        let sample = samples.load(&sample_path)?;
+        self.layers.retain(|(layer, _)| layer.min_velocity != min_velocity);
+        self.layers.push((VelocityLayer { min_velocity, sample_path }, sample));
+        self.layers.sort_by_key(|(layer, _)| layer.min_velocity);
+        Ok(())
+    }
+
+    pub fn remove_layer(&mut self, min_velocity: u8) -> Result<(), Box<dyn Error>> {
+        let len = self.layers.len();
+        self.layers.retain(|(layer, _)| layer.min_velocity != min_velocity);
+        if self.layers.len() == len {
+            return Err(format!("No layer from velocity {}", min_velocity).into());
+        }
+        Ok(())
+    }
+
+    /// Plays the track sample as a new voice in the voice mixer
+    /// 
+    /// A mono track stops its last voice, a poly one only once it's out of
+    /// voices, and then the one its steal policy picks. With anti-click on
+    /// a stolen voice fades out under the new hit rather than being cut.
+    /// If a sample swap is pending it happens here, and the old voices are
+    /// left to ring out unless the swap policy crossfades them
+    pub fn play(&mut self, stream: &AudioHandle, vel: u8, delay: Duration, reverse: bool, settings: VoiceSettings) {
+        let fade_out = settings.anti_click.unwrap_or(SWAP_FADE);
+        if let Some(sample) = self.pending_sample.take() {
+            self.sample = sample;
+            for (voice, _) in self.voices.drain(..) {
+                if settings.swap_policy == SwapPolicy::Crossfade {
+                    voice.fade_out();
+                }
+            }
+        }
+        self.voices.retain(|(voice, _)| voice.playing());
+        let velocities: Vec<u8> = self.voices.iter().map(|(_, vel)| *vel).collect();
+        if let Some(stolen) = self.polyphony.steal(&velocities) {
+            let (voice, _) = self.voices.remove(stolen);
+            stop_voice(&voice, settings);
+        }
+
+        let sample = (**self.sample_for(vel)).clone().with_loop(self.loop_points).with_reverse(reverse).voice().speed(self.speed());
+        let sample = Equalized::new(Filtered::new(sample, self.active_filter()), self.active_eq());
+        let source = Enveloped::new(sample, self.envelope).amplify(vel as f32 / 127.0);
+        let voice = stream.play(source, VoiceStart {
+            delay,
+            gain: self.gain(),
+            sends: self.sends(),
+            fade_in: settings.anti_click.unwrap_or(Duration::ZERO),
+            fade_out,
+            output: self.output as usize,
+        });
+        self.voices.push((voice, vel));
+    }
+
+    /// Levels into the effect buses for the voices, 1 is all of it
+    fn sends(&self) -> [f32; SEND_BUSES] {
+        self.send_levels().map(|level| level as f32 / 100.0)
+    }
+
+    /// Passes a change to the track's gain or sends on to the voices playing
+    pub fn update_voices(&self) {
+        for (voice, _) in &self.voices {
+            voice.set_gain(self.gain());
+            voice.set_sends(self.sends());
+        }
+    }
+
+    /// Forgets the voices when the output changes, they went with the old stream
+    pub fn drop_voices(&mut self) {
+        self.voices.clear();
+    }
+
+    /// Loads the sample again through the cache after the output rate
+    /// changed. One waiting to take over is loaded instead, and the one
+    /// it replaces is converted from what's in memory to see it out
+    pub fn reload_sample(&mut self, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        for (layer, sample) in self.layers.iter_mut() {
+            *sample = samples.load(&layer.sample_path)?;
+        }
+        let sample = samples.load(&self.sample_path)?;
+        self.load_error = None;
+        if self.pending_sample.is_some() {
+            self.pending_sample = Some(sample);
+            self.sample = Arc::new((*self.sample).clone().resampled(samples.sample_rate()));
+        } else {
+            self.sample = sample;
+        }
+        Ok(())
+    }
+
+    /// Fades out every voice over the choke fade, anti-click or not, since
+    /// cutting a ringing sample dead clicks
+    pub fn choke(&mut self, settings: VoiceSettings) {
+        for (voice, _) in self.voices.drain(..) {
+            voice.fade_out_over(settings.choke_fade);
+        }
+    }
+
+    /// Fades out the voices if it's looping, one shots are left to ring out
+    pub fn stop_loop(&self) {
+        if self.loop_points.is_some() {
+            self.voices.iter().for_each(|(voice, _)| voice.fade_out());
+        }
+    }
+
+    /// Puts in a reloaded copy of its sample, held for the next trigger
+    /// while playing like a queued one. Loop points the new one is too
+    /// short for are dropped
+    pub fn swap_sample(&mut self, sample: Arc<BufferedSample>, playing: bool) {
+        self.load_error = None;
+        if self.loop_points.is_some_and(|(_, end)| sample.streamed() || end > sample.file_frames()) {
+            self.loop_points = None;
+        }
+        if playing {
+            self.pending_sample = Some(sample);
+        } else {
+            self.sample = sample;
+            self.pending_sample = None;
+        }
+    }
+
+    /// Puts in a reloaded copy of a layer's sample, voices already
+    /// playing the old one carry on with it
+    pub fn swap_layer_sample(&mut self, sample_path: &str, sample: &Arc<BufferedSample>) {
+        for (layer, layer_sample) in self.layers.iter_mut() {
+            if layer.sample_path == sample_path {
+                *layer_sample = sample.clone();
+            }
+        }
+    }
+
+    /// Sets the region of the sample to loop in frames, the end is exclusive
+    pub fn set_loop(&mut self, start: usize, end: usize) -> Result<(), Box<dyn Error>> {
+        let sample = self.pending_sample.as_ref().unwrap_or(&self.sample);
+        if sample.streamed() {
+            return Err(format!("{} is streamed from disk, it can't loop", self.sample_path).into());
+        }
+        let frames = sample.file_frames();
+        if start >= end || end > frames {
+            return Err(format!("Invalid loop points {}-{} for a sample of {} frames", start, end, frames).into());
+        }
+        self.loop_points = Some((start, end));
+        Ok(())
+    }
+
+    /// Moves the playhead to the next slot according to the track direction
+    pub fn advance(&mut self) {
+        self.idx = match self.direction {
+            Direction::Forward => (self.idx + 1) % self.len,
+            Direction::Reverse => (self.idx + self.len - 1) % self.len,
+            Direction::PingPong => {
+                // end slots are only played once per lap
+                if self.len < 2 {
+                    0
+                } else if self.ping_pong_rev {
+                    if self.idx == 0 {
+                        self.ping_pong_rev = false;
+                        1
+                    } else {
+                        self.idx - 1
+                    }
+                } else if self.idx + 1 >= self.len {
+                    self.ping_pong_rev = true;
+                    self.len - 2
+                } else {
+                    self.idx + 1
+                }
+            },
+            Direction::Random => rand::random_range(0..self.len),
+        };
+    }
+
+    pub fn reset_playhead(&mut self) {
+        self.idx = 0;
+        self.ping_pong_rev = false;
+        self.fresh = true;
+        self.retriggers.clear();
+        self.last_idx = None;
+        self.skip_idx = None;
+    }
+
+    /// Randomizes a hit's velocity within the track's variation, on top of
+    /// any humanize on the pattern
+    pub fn vary_velocity(&self, vel: u8) -> u8 {
+        if self.velocity_variation == 0 {
+            return vel;
+        }
+        let variation = self.velocity_variation as i16;
+        (vel as i16 + rand::random_range(-variation..=variation)).clamp(1, 127) as u8
+    }
+
+    /// Takes the velocities and accents of another track's steps, and its
+    /// timing offset, without changing which steps play
+    ///
+    /// Steps line up from the start, wrapping round the other track if
+    /// it's shorter. Where the other track rests the step is left as is
+    pub fn copy_feel(&mut self, src: &Track) {
+        if src.slots.is_empty() {
+            return;
+        }
+        for (i, slot) in self.slots.iter_mut().enumerate() {
+            let src_slot = &src.slots[i % src.slots.len()];
+            if slot.velocity > 0 && src_slot.velocity > 0 {
+                slot.velocity = src_slot.velocity;
+                slot.accent = src_slot.accent;
+            }
+        }
+        self.timing_offset_ms = src.timing_offset_ms;
+    }
+
+    /// Writes a hit played live into the slot nearest the playhead,
+    /// step is the number of pulses a step lasts
+    pub fn record(&mut self, vel: u8, step: u8, mode: RecordMode) {
+        let (idx, steps_left) = match self.last_idx {
+            Some(last_idx) if self.step_pulses < step / 2 => (last_idx, self.len - 1),
+            _ => {
+                self.skip_idx = Some(self.idx);
+                (self.idx, self.len)
+            },
+        };
+        if mode == RecordMode::Replace && self.replace_left == 0 {
+            self.replace_left = steps_left;
+        }
+        self.slots[idx].velocity = vel;
+    }
+
+    pub fn to_saved(&self) -> SavedTrack {
+        SavedTrack {
+            slots: self.slots.clone(),
+            sample_path: self.sample_path.clone(),
+            direction: self.direction,
+            timing_offset_ms: self.timing_offset_ms,
+            eq: self.eq,
+            fx_bypass: self.fx_bypass,
+            loop_points: self.loop_points,
+            midi_note: Some(self.midi_note),
+            velocity_variation: self.velocity_variation,
+            archived: self.archived,
+            pitch_cents: self.pitch_cents,
+            reverse: self.reverse,
+            muted: self.muted,
+            gain_db: self.gain_db,
+            envelope: self.envelope,
+            filter: self.filter,
+            delay_send: self.delay_send,
+            reverb_send: self.reverb_send,
+            polyphony: self.polyphony,
+            layers: self.layers.iter().map(|(layer, _)| layer.clone()).collect(),
+            loop_sync: self.loop_sync,
+            output: self.output,
+        }
+    }
+
+    /// The EQ hits go through, flat while it's bypassed
+    pub fn active_eq(&self) -> TrackEq {
+        if self.fx_bypass.eq {
+            return TrackEq::default();
+        }
+        self.eq
+    }
+
+    /// The filter hits go through, off while it's bypassed
+    pub fn active_filter(&self) -> Filter {
+        if self.fx_bypass.filter {
+            return Filter { mode: FilterMode::Off, ..self.filter };
+        }
+        self.filter
+    }
+
+    /// Percent sent to each effect bus, by bus, 0 for a bypassed one
+    pub fn send_levels(&self) -> [u8; SEND_BUSES] {
+        [
+            if self.fx_bypass.delay { 0 } else { self.delay_send },
+            if self.fx_bypass.reverb { 0 } else { self.reverb_send },
+        ]
+    }
+
+    /// Linear gain for the level, silent at the bottom of the range
+    pub fn gain(&self) -> f32 {
+        if self.gain_db <= MIN_TRACK_GAIN_DB {
+            return 0.0;
+        }
+        10f32.powf(self.gain_db as f32 / 20.0)
+    }
+
+    /// Playback speed for the tuning, an octave up plays twice as fast,
+    /// and for a repitched loop
+    pub fn speed(&self) -> f32 {
+        2f32.powf(self.pitch_cents as f32 / 1200.0) * self.fitted.as_ref().map_or(1.0, |fitted| fitted.speed)
+    }
+
+    pub fn set_direction(&mut self, direction: Direction) {
+        self.direction = direction;
+        self.ping_pong_rev = false;
+    }
+
+    pub fn reset_slots(&mut self) {
+        self.slots.iter_mut().for_each(|slot| {
+            *slot = Slot::default();
+        });
+    }
+
+    /// Replaces the slots with random hits, density is the chance of
+    /// each slot getting a hit and velocities land in [vel_min, vel_max]
+    pub fn randomize(&mut self, density: f32, vel_min: u8, vel_max: u8) {
+        // clamp lets NaN through and random_bool panics on it
+        let density = if density.is_nan() { 0.0 } else { density.clamp(0.0, 1.0) };
+        let (vel_min, vel_max) = (vel_min.clamp(1, 127), vel_max.clamp(1, 127));
+        let (vel_min, vel_max) = (vel_min.min(vel_max), vel_min.max(vel_max));
+        self.slots.iter_mut().for_each(|slot| {
+            slot.velocity = if rand::random_bool(density as f64) {
+                rand::random_range(vel_min..=vel_max)
+            } else {
+                0
+            };
+        });
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        if len > self.len {
+            self.slots.extend(vec![Slot::default(); len - self.len]);
+        } else {
+            self.slots.truncate(len);
+            self.idx = self.idx % len;
+            self.last_idx = self.last_idx.filter(|&idx| idx < len);
+            self.skip_idx = self.skip_idx.filter(|&idx| idx < len);
+        }
+        self.trigger_counts.resize(len, 0);
+        self.len = len;
+    }
+
+    pub fn set_sample(&mut self, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        let sample = samples.load(&sample_path)?;
+        self.sample = sample;
+        self.sample_path = sample_path;
+        self.load_error = None;
+        self.pending_sample = None;
+        self.loop_points = None;
+        Ok(())
+    }
+
+    /// Loads a sample now but holds off using it until the next trigger, so
+    /// swapping during playback doesn't disturb a ringing voice
+    pub fn queue_sample(&mut self, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        let sample = samples.load(&sample_path)?;
+        self.pending_sample = Some(sample);
+        self.sample_path = sample_path;
+        self.load_error = None;
+        self.loop_points = None;
+        Ok(())
+    }
+}
+
+/// Random variation applied to hits every time they play
+/// 
+/// It's non-destructive, the programmed slots are left as they are
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, Hash, PartialEq)]
+pub struct Humanize {
+    /// max delay in ms a hit can land behind the grid
+    /// 
+    /// A pulse is far too coarse for this so the voice itself is delayed,
+    /// which means hits only ever drag and never rush
+    pub timing_ms: u8,
+    /// max amount velocity can deviate either way
+    pub velocity: u8,
+}
+
+impl Humanize {
+    /// Returns the jittered velocity and the delay to play the hit after
+    pub fn apply(&self, vel: u8) -> (u8, Duration) {
+        let vel = if self.velocity > 0 {
+            let dev = rand::random_range(-(self.velocity as i16)..=self.velocity as i16);
+            (vel as i16 + dev).clamp(1, 127) as u8
+        } else {
+            vel
+        };
+        let delay = if self.timing_ms > 0 {
+            Duration::from_micros(rand::random_range(0..=self.timing_ms as u64 * 1000))
+        } else {
+            Duration::ZERO
+        };
+        (vel, delay)
+    }
+}
+
+/// Sequencer wide settings that shape how patterns are played
+#[derive(Clone, Copy)]
+pub struct TickParams {
+    /// pulses per bar
+    pub ppb: u8,
+    pub swing: Swing,
+    pub swing_offset: u8,
+    pub accent_velocity: u8,
+    pub pulse_interval: Duration,
+}
+
+/// Turns a name typed by the user into something safe to put in a file
+/// name, anything that isn't alphanumeric or a dash becomes an underscore
+/// so it can't climb out of the folder it's written to
+pub fn file_stem(name: &str) -> Result<String, Box<dyn Error>> {
+    let stem: String = name.chars()
+        .map(|c| if c.is_alphanumeric() || c == '-' { c } else { '_' })
+        .collect();
+    if stem.is_empty() {
+        return Err("name is empty".into());
+    }
+    Ok(stem)
+}
+
+/// Writes a pattern to the patterns folder, never over another file
+fn save_pattern_file(pattern_fname: &str, saved_pattern: &SavedPattern) -> Result<(), Box<dyn Error>> {
+    let file = OpenOptions::new()
+        .create_new(true)
+        .write(true)
+        .open(format!("{PWD}/patterns/{}", pattern_fname))?;
+    let file = std::io::BufWriter::new(file);
+    serde_json::to_writer(file, saved_pattern)?;
+    Ok(())
+}
+
+fn read_saved_pattern(pattern_fname: &str) -> Result<SavedPattern, Box<dyn Error>> {
+    let file = std::fs::File::open(format!("{PWD}/patterns/{}", pattern_fname))?;
+    let file = std::io::BufReader::new(file);
+    Ok(serde_json::from_reader(file)?)
+}
+
+/// Progress of a pattern file loading in the background
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Preload {
+    pub fname: String,
+    /// samples decoded so far, of the ones that weren't already
+    pub done: usize,
+    pub total: usize,
+    /// everything is decoded and the pattern is waiting for the downbeat
+    pub ready: bool,
+}
+
+/// Pattern on deck B in deck mode, the current pattern is deck A
+#[derive(Clone, Copy)]
+struct Deck {
+    pattern_id: usize,
+    /// B starts on the next downbeat so the two decks line up
+    started: bool,
+}
+
+/// A slot that fired on a pulse
+pub struct Trigger {
+    pub track_id: usize,
+    pub velocity: u8,
+    /// how long after the pulse the hit should sound
+    pub delay: Duration,
+    /// plays the sample backwards
+    pub reverse: bool,
+}
+
+/// ChokeGrp allows defining tracks that stop other tracks in
+/// the same choke group when triggered
+#[derive(Clone, Serialize, Deserialize, Hash)]
+pub struct ChokeGrp {
+    pub track_ids: Vec<usize>,
+}
+
+impl ChokeGrp {
+    pub fn new(tracks: Vec<usize>) -> ChokeGrp {
+        ChokeGrp {
+            track_ids: tracks
+        }
+    }
+
+    pub fn add_track(&mut self, track_id: usize) {
+        self.track_ids.push(track_id);
+    }
+
+    pub fn remove_track(&mut self, track_id: usize) {
+        self.track_ids.retain(|&x| x != track_id);
+    }
+
+    pub fn is_member(&self, track_id: usize) -> bool {
+        self.track_ids.contains(&track_id)
+    }
+
+    // Returns list of other indices if a given track index is
+    // in the choke group
+    pub fn get_choked_ids(&self, track_id: usize) -> Vec<usize> {
+        if !self.is_member(track_id) {
+            return vec![];
+        }
+        self.track_ids.iter().filter(|&&x| x != track_id).cloned().collect()
+    }
+}
+
+/// A lane of MIDI CC values stepped through with the pattern, for
+/// automating external gear like a synth's filter cutoff
+/// 
+/// Steps without a value send nothing so the last value is held
+#[derive(Debug, Clone, Serialize, Deserialize, Hash)]
+pub struct CcLane {
+    /// midi channel 0-15
+    pub channel: u8,
+    /// controller number 0-127
+    pub controller: u8,
+    pub values: Vec<Option<u8>>,
+    #[serde(skip)]
+    pub idx: usize,
+}
+
+impl CcLane {
+    pub fn new(channel: u8, controller: u8, len: usize) -> Self {
+        CcLane {
+            channel: channel.min(15),
+            controller: controller.min(127),
+            values: vec![None; len],
+            idx: 0,
+        }
+    }
+
+    pub fn set_value(&mut self, step: usize, value: Option<u8>) {
+        if let Some(v) = self.values.get_mut(step) {
+            *v = value.map(|value| value.min(127));
+        }
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        self.values.resize(len, None);
+        self.idx %= len.max(1);
+    }
+
+    /// Control change message for the current step, if it has a value,
+    /// then moves on to the next step
+    fn advance(&mut self) -> Option<[u8; 3]> {
+        let value = *self.values.get(self.idx)?;
+        self.idx = (self.idx + 1) % self.values.len();
+        value.map(|value| [0xB0 | self.channel, self.controller, value])
+    }
+}
+
+/// Whether a step of `step` pulses falls on this pulse of the bar once
+/// swing on a fixed grid has moved it
+///
+/// Each pair of grid steps is warped rather than only the odd grid step
+/// moved, the first half is stretched by the offset and the second half
+/// squeezed up to make room. Steps on the grid land where they would at
+/// that division and finer steps between them keep their order
+fn swung_step_due(bar_idx: u8, step: u8, grid_step: u8, offset: u8) -> bool {
+    let (bar_idx, step, grid, offset) = (bar_idx as u32, step as u32, grid_step as u32, offset as u32);
+    let swung = |pos: u32| {
+        let (pair, in_pair) = (pos / (grid * 2) * grid * 2, pos % (grid * 2));
+        pair + if in_pair < grid {
+            in_pair * (grid + offset) / grid
+        } else {
+            grid + offset + (in_pair - grid) * (grid - offset) / grid
+        }
+    };
+    // Swing only ever delays, by the offset at most
+    let first = bar_idx.saturating_sub(offset).div_ceil(step) * step;
+    (first..=bar_idx).step_by(step as usize).any(|pos| swung(pos) == bar_idx)
+}
+
+/// Struct for saving pattern data to file
+#[derive(Clone, Serialize, Deserialize, Hash)]
+pub struct SavedPattern {
+    pub tracks: Vec<SavedTrack>,
+    pub choke_grps: Vec<ChokeGrp>,
+    pub division: Division,
+    #[serde(default)]
+    pub humanize: Humanize,
+    #[serde(default)]
+    pub groove: Option<SavedGroove>,
+    #[serde(default)]
+    pub cc_lanes: Vec<CcLane>,
+    #[serde(default)]
+    pub notes: String,
+    #[serde(default)]
+    pub swing_grid: SwingGrid,
+}
+
+/// `Pattern` is a collection of tracks
+/// 
+/// If an empty pattern is saved, this can be considered a kit.
+#[derive(Clone)]
+pub struct Pattern {
+    pub tracks: Vec<Track>,
+    pub choke_grps: Vec<ChokeGrp>,
+    /// defines the note length of a beat
+    /// 
+    /// allowable set{1,2,3,4,6,8,12,16,24,32}
+    pub division: Division,
+    pub name: String,
+    pub humanize: Humanize,
+    /// groove template applied on playback, slots are left as programmed
+    pub groove: Option<PatternGroove>,
+    /// midi cc automation sent out with the pattern
+    pub cc_lanes: Vec<CcLane>,
+    /// pattern played instead while a fill is triggered
+    pub fill: Option<usize>,
+    /// free text for the user, arrangement reminders and the like
+    pub notes: String,
+    /// steps swing pushes late
+    pub swing_grid: SwingGrid,
+}
+
+impl Pattern {
     // Returns list of other track indices across all groups that the given track index chokes
     pub fn get_choked_ids(&self, track_id: usize) -> Vec<usize> {
         let mut choked_ids = self.choke_grps
@@ -393,280 +2461,2187 @@ impl Pattern {
         choked_ids
     }
 
-    // Returns true if the track is in a choke group with any triggered tracks
-    pub fn is_trk_choked(&self, triggered_ids: &Vec<usize>, track_id: usize) -> bool {
-        triggered_ids
-            .iter()
-            .any(|&x| self.get_choked_ids(x).contains(&track_id))
+    // Returns true if the track is in a choke group with any triggered tracks
+    pub fn is_trk_choked(&self, triggered_ids: &Vec<usize>, track_id: usize) -> bool {
+        triggered_ids
+            .iter()
+            .any(|&x| self.get_choked_ids(x).contains(&track_id))
+    }
+
+    pub fn zero_all_tracks(&mut self) {
+        self.tracks.iter_mut().for_each(|track| {
+            track.reset_slots();
+        });
+    }
+
+    /// Bars one time through the pattern takes, set by its longest track
+    ///
+    /// A bar's worth of steps is a page for UIs showing long patterns
+    pub fn bars(&self) -> usize {
+        let len = self.tracks.iter().map(|track| track.len).max().unwrap_or(0);
+        len.div_ceil(self.division as usize).max(1)
+    }
+
+    pub fn reset_playheads(&mut self) {
+        self.tracks.iter_mut().for_each(|track| {
+            track.reset_playhead();
+        });
+        self.cc_lanes.iter_mut().for_each(|lane| {
+            lane.idx = 0;
+        });
+    }
+
+    pub fn to_saved(&self) -> SavedPattern {
+        SavedPattern {
+            tracks: self.tracks.iter().map(|track| track.to_saved()).collect(),
+            choke_grps: self.choke_grps.clone(),
+            division: self.division,
+            humanize: self.humanize,
+            groove: self.groove.as_ref().map(|groove| groove.to_saved()),
+            cc_lanes: self.cc_lanes.clone(),
+            notes: self.notes.clone(),
+            swing_grid: self.swing_grid,
+        }
+    }
+
+    pub fn randomize(&mut self, density: f32, vel_min: u8, vel_max: u8) {
+        self.tracks.iter_mut().for_each(|track| {
+            track.randomize(density, vel_min, vel_max);
+        });
+    }
+
+    /// Moves all playheads along by one pulse, returning a trigger for
+    /// every slot that should be played on it
+    /// 
+    /// This is shared by live playback and offline rendering so they
+    /// always agree on timing
+    pub fn tick(&mut self, pulse_idx: u8, params: &TickParams) -> Vec<Trigger> {
+        let mut triggered = vec![];
+        let step = params.ppb / self.division as u8;
+        let pulse_ms = params.pulse_interval.as_secs_f32() * 1000.0;
+        for (i, t) in self.tracks.iter_mut().enumerate() {
+            if t.archived {
+                continue;
+            }
+            // Ratchet hits left over from the last step
+            t.step_pulses = t.step_pulses.saturating_add(1);
+            while t.retriggers.front().is_some_and(|r| r.pulse <= t.step_pulses) {
+                let r = t.retriggers.pop_front().unwrap();
+                triggered.push(Trigger {
+                    track_id: i,
+                    velocity: r.velocity,
+                    delay: r.delay,
+                    reverse: r.reverse,
+                });
+            }
+
+            // If we're on an odd beat we need to swing it
+            // ie 1 triplet 2. Midi pulse remains on the straight
+            let swing_offset = {
+                if t.idx % 2 == 1 {
+                    params.swing_offset
+                } else {
+                    0
+                }
+            };
+
+            // The timing offset is split into whole pulses that shift when the
+            // track's steps fall and the remainder, which delays the voice
+            // A push can't move a step earlier than the one before it
+            let shift = ((t.timing_offset_ms as f32 / pulse_ms).floor() as i32).max(1 - step as i32);
+            let offset_delay = Duration::from_secs_f32(
+                ((t.timing_offset_ms as f32 - shift as f32 * pulse_ms) / 1000.0).max(0.0)
+            );
+
+            // To handle swing which delays a note, we actually
+            // run the pulse idx to half a bar
+            // Example: The 8th note is allotted 12 pulses
+            // so the pulse counts 0-12 on a straight rhythm which is
+            // ok for that. However, on swinging, we need to delay
+            // to pulse 16 and so have to count two 8th notes 0-24
+            let cycle = step as i32 * 2;
+            let shifted_idx = (pulse_idx as i32 - shift).rem_euclid(cycle) as u8;
+            // A pushed track's first step would have been due before the
+            // playheads were reset, so it plays on the downbeat instead
+            let pushed_start = shift < 0 && t.fresh && pulse_idx as i32 % cycle == 0;
+            let due = match self.swing_grid.step(params.ppb) {
+                Some(grid_step) => {
+                    let grid_offset = params.swing as u8 * (16 / self.swing_grid as u8);
+                    let bar_idx = (pulse_idx as i32 - shift).rem_euclid(params.ppb as i32) as u8;
+                    swung_step_due(bar_idx, step, grid_step, grid_offset)
+                },
+                None => shifted_idx == 0 || shifted_idx == swing_offset + step,
+            };
+            if pushed_start || due {
+                t.fresh = false;
+                t.step_pulses = 0;
+                t.retriggers.clear();
+                t.last_idx = Some(t.idx);
+                let skipped = t.skip_idx.take() == Some(t.idx);
+                // A hit recorded ahead of the playhead is the new take, not
+                // the old step being cleared
+                let replacing = t.replace_left > 0;
+                t.replace_left = t.replace_left.saturating_sub(1);
+                if (t.erasing || replacing) && !skipped {
+                    t.slots[t.idx] = Slot::default();
+                }
+                let slot = &t.slots[t.idx];
+                if slot.velocity > 0 && !skipped && !t.muted {
+                    let vel = if slot.accent {
+                        slot.velocity.saturating_add(params.accent_velocity).min(127)
+                    } else {
+                        slot.velocity
+                    };
+                    let (vel, groove_delay) = match &self.groove {
+                        Some(groove) => groove.apply(t.idx, vel, params.pulse_interval * step as u32),
+                        None => (vel, Duration::ZERO),
+                    };
+                    let (velocity, delay) = self.humanize.apply(vel);
+                    let velocity = t.vary_velocity(velocity);
+                    let delay = delay + offset_delay + groove_delay;
+                    let reverse = t.reverse != slot.reverse;
+                    triggered.push(Trigger {
+                        track_id: i,
+                        velocity,
+                        delay,
+                        reverse,
+                    });
+                    if let Some(count) = t.trigger_counts.get_mut(t.idx) {
+                        *count = count.saturating_add(1);
+                    }
+
+                    // Ratchet hits are spread evenly over the step, each at
+                    // least a pulse apart so they don't cut each other off
+                    let ratchet = slot.ratchet.clamp(1, MAX_RATCHET.min(step));
+                    let ratchet_len = step as f32 / ratchet as f32;
+                    for n in 1..ratchet {
+                        let at = n as f32 * ratchet_len;
+                        t.retriggers.push_back(Retrigger {
+                            pulse: at as u8,
+                            velocity: slot.ratchet_velocity(velocity, n),
+                            delay: delay + params.pulse_interval.mul_f32(at.fract()),
+                            reverse,
+                        });
+                    }
+                }
+
+                t.advance();
+            }
+        }
+        triggered
+    }
+
+    pub fn set_len(&mut self, len: usize) {
+        self.tracks.iter_mut().for_each(|track| {
+            track.set_len(len);
+        });
+        self.cc_lanes.iter_mut().for_each(|lane| {
+            lane.set_len(len);
+        });
+    }
+
+    /// Control change messages due on this pulse
+    /// 
+    /// Lanes step with the division but ignore swing and grooves, a filter
+    /// sweep lagging the beat sounds more like a mistake than a feel
+    pub fn tick_cc(&mut self, pulse_idx: u8, ppb: u8) -> Vec<[u8; 3]> {
+        let step = ppb / self.division as u8;
+        if !pulse_idx.is_multiple_of(step) {
+            return vec![];
+        }
+        self.cc_lanes.iter_mut().filter_map(|lane| lane.advance()).collect()
+    }
+
+    pub fn set_division(&mut self, division: Division) {
+        self.division = division;
+    }
+
+    // sample_path is the relative location of the sample file to the samples directory
+    // This behavior is hardcoded for now
+    pub fn add_track(&mut self, len: usize, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        let mut track = Track::new(len, sample_path, samples)?;
+        track.midi_note = BASE_NOTE.saturating_add(self.tracks.len() as u8).min(127);
+        self.tracks.push(track);
+        Ok(())
+    }
+
+    /// Drops a track and cuts its voice, choke groups are renumbered to match
+    pub fn remove_track(&mut self, track_id: usize) -> Result<(), Box<dyn Error>> {
+        if track_id >= self.tracks.len() {
+            return Err(format!("no track {}", track_id).into());
+        }
+        if self.tracks.len() == 1 {
+            return Err("a pattern needs at least one track".into());
+        }
+        for (voice, _) in self.tracks.remove(track_id).voices {
+            voice.stop();
+        }
+        for choke_grp in self.choke_grps.iter_mut() {
+            choke_grp.track_ids.retain_mut(|id| removed_track_id(track_id, *id).map(|new_id| *id = new_id).is_some());
+        }
+        self.choke_grps.retain(|choke_grp| choke_grp.track_ids.len() > 1);
+        Ok(())
+    }
+
+    /// Moves a track to another position, choke groups follow the tracks
+    pub fn move_track(&mut self, from: usize, to: usize) -> Result<(), Box<dyn Error>> {
+        if from >= self.tracks.len() || to >= self.tracks.len() {
+            return Err(format!("can't move track {} to {}", from, to).into());
+        }
+        let track = self.tracks.remove(from);
+        self.tracks.insert(to, track);
+        for choke_grp in self.choke_grps.iter_mut() {
+            choke_grp.track_ids.iter_mut().for_each(|id| *id = moved_track_id(from, to, *id));
+        }
+        Ok(())
+    }
+
+    pub fn set_track_sample(&mut self, track_id: usize, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        self.tracks[track_id].set_sample(sample_path, samples)
+    }
+
+    pub fn queue_track_sample(&mut self, track_id: usize, sample_path: String, samples: &mut SampleCache) -> Result<(), Box<dyn Error>> {
+        self.tracks[track_id].queue_sample(sample_path, samples)
+    }
+}
+
+/// Where a track ends up after the track at from moves to to
+pub fn moved_track_id(from: usize, to: usize, id: usize) -> usize {
+    if id == from {
+        to
+    } else if from < id && id <= to {
+        id - 1
+    } else if to <= id && id < from {
+        id + 1
+    } else {
+        id
+    }
+}
+
+/// Where a track ends up after the track at removed goes, None for that one
+pub fn removed_track_id(removed: usize, id: usize) -> Option<usize> {
+    match id.cmp(&removed) {
+        std::cmp::Ordering::Less => Some(id),
+        std::cmp::Ordering::Equal => None,
+        std::cmp::Ordering::Greater => Some(id - 1),
+    }
+}
+
+/// An entry of the song chain, plays a pattern a number of times through,
+/// which is a bar each for one bar patterns
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SongEntry {
+    pub pattern_id: usize,
+    pub repeats: usize,
+}
+
+/// `Song` chains patterns into an arrangement
+/// 
+/// When enabled the sequencer walks through the entries, queueing
+/// each pattern in turn at the end of the bar
+#[derive(Default)]
+pub struct Song {
+    pub entries: Vec<SongEntry>,
+    pub enabled: bool,
+    /// index of the entry currently playing
+    pub position: usize,
+    /// times through its pattern the current entry has played
+    pub bars_played: usize,
+    /// inclusive range of chain indices to cycle, e.g. a verse while soloing
+    /// 
+    /// Once the position reaches the end of the region it jumps back to the
+    /// start, clearing it lets the arrangement continue on
+    pub loop_region: Option<(usize, usize)>,
+}
+
+impl Song {
+    pub fn add_entry(&mut self, pattern_id: usize, repeats: usize) {
+        self.entries.push(SongEntry { pattern_id, repeats: repeats.max(1) });
+    }
+
+    pub fn set_entry(&mut self, idx: usize, pattern_id: usize, repeats: usize) {
+        if let Some(entry) = self.entries.get_mut(idx) {
+            *entry = SongEntry { pattern_id, repeats: repeats.max(1) };
+        }
+    }
+
+    pub fn remove_entry(&mut self, idx: usize) {
+        if idx >= self.entries.len() {
+            return;
+        }
+        self.entries.remove(idx);
+        if idx == self.position {
+            // the entry taking its place plays from its first time through
+            self.bars_played = 0;
+        }
+        if idx < self.position || self.position >= self.entries.len() {
+            self.position = self.position.saturating_sub(1);
+        }
+        // a region that can't be remapped sensibly is dropped
+        self.loop_region = match self.loop_region {
+            Some((start, end)) if idx > end => Some((start, end)),
+            Some((start, end)) if idx < start => Some((start - 1, end - 1)),
+            Some((start, end)) if start < end => Some((start, end - 1)),
+            _ => None,
+        };
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.position = 0;
+        self.bars_played = 0;
+        self.loop_region = None;
+    }
+
+    /// Drops entries of a removed pattern and shifts the ids after it
+    pub fn remove_pattern(&mut self, pattern_id: usize) {
+        let mut idx = 0;
+        while idx < self.entries.len() {
+            if self.entries[idx].pattern_id == pattern_id {
+                self.remove_entry(idx);
+            } else {
+                if self.entries[idx].pattern_id > pattern_id {
+                    self.entries[idx].pattern_id -= 1;
+                }
+                idx += 1;
+            }
+        }
+    }
+
+    /// Goes back to the top of the song, returning the first pattern
+    pub fn restart(&mut self) -> Option<usize> {
+        self.position = 0;
+        self.bars_played = 0;
+        self.entries.first().map(|entry| entry.pattern_id)
+    }
+
+    pub fn set_loop_region(&mut self, start: usize, end: usize) {
+        if start <= end && end < self.entries.len() {
+            self.loop_region = Some((start, end));
+        }
+    }
+
+    pub fn clear_loop_region(&mut self) {
+        self.loop_region = None;
+    }
+
+    /// Index of the entry following the current one, None at the end of the song
+    fn next_position(&self) -> Option<usize> {
+        match self.loop_region {
+            Some((start, end)) if self.position == end => Some(start),
+            _ if self.position + 1 < self.entries.len() => Some(self.position + 1),
+            _ => None,
+        }
+    }
+
+    /// Counts a finished time through and returns the pattern that should play next,
+    /// None means the song is over
+    pub fn advance(&mut self) -> Option<usize> {
+        let entry = self.entries.get(self.position)?;
+        self.bars_played += 1;
+        if self.bars_played < entry.repeats {
+            return Some(entry.pattern_id);
+        }
+        self.bars_played = 0;
+        self.position = self.next_position()?;
+        Some(self.entries[self.position].pattern_id)
+    }
+}
+
+/// Slowest and fastest tempo in bpm, the top end is well past
+/// anything musical but keeps the pulse interval sane
+const MIN_TEMPO: f32 = 20.0;
+const MAX_TEMPO: f32 = 999.0;
+
+/// Linear tempo ramp in progress when tempo slew is enabled
+struct TempoRamp {
+    from: f32,
+    to: f32,
+    start: Instant,
+}
+
+/// A file a command leaves for the command loop to write once it has let
+/// go of the lock, so the pulse thread never waits on the disk
+enum DiskWrite {
+    /// the pattern under the file name it's saved as
+    Pattern(String, SavedPattern),
+    MidiMap(String, MidiMap),
+}
+
+/// Struct that describes internal sequencer state that can be
+/// modified by the user as well as connections and channels
+/// 
+/// Note that many parameters are actually pattern-specific
+pub struct Context {
+    pub stream: AudioHandle,
+    pub patterns: Vec<Pattern>,
+    pub saved_patterns: Vec<String>,
+    /// result of the last FindSimilarPatterns
+    pub similar_patterns: Vec<String>,
+    pub sample_files: Vec<String>,
+    pub groove_files: Vec<String>,
+    /// groove templates by name, read off the lock before anything uses them
+    grooves: BTreeMap<String, Arc<Groove>>,
+    /// measurements of the last finished export
+    pub last_export: Option<ExportReport>,
+    /// recording of the master mix in progress
+    pub master_recording: Option<Recording>,
+    /// sample being captured from the input
+    pub sample_capture: Option<SampleCapture>,
+    /// pattern and track the sample of each capture not yet written goes
+    /// on, by capture number, so they follow the track if it moves
+    capture_targets: BTreeMap<u64, (usize, usize)>,
+    next_capture: u64,
+    /// moved on by every start and stop of the master recording, so one
+    /// still opening when it's stopped or started again doesn't stick
+    next_recording: u64,
+    /// library sample being previewed, cut by the next preview
+    audition: Option<VoiceHandle>,
+    pub pattern_id: usize,
+    // If there is no new pattern for queueing it should be
+    // the current pattern since the same pattern is queued
+    // for playing next
+    pub queued_pattern_id: usize,
+    /// patterns lined up after the queued pattern, one is taken each time
+    /// the playing pattern comes round
+    pub pattern_queue: VecDeque<usize>,
+    /// bar of the current pattern being played, from 0
+    pub pattern_bar: usize,
+    /// bars PlayForBars has left to play, the current one included
+    pub bars_left: Option<usize>,
+    /// It's the default length of a new track, unit is beats
+    pub default_len: usize,
+    /// pattern chain arrangement
+    pub song: Song,
+    /// how a ringing voice is treated when its sample is swapped
+    pub swap_policy: SwapPolicy,
+    /// micro fade on voice start, stop and steal to avoid clicks
+    pub anti_click: bool,
+    pub anti_click_len: Duration,
+    /// fade out of a choked voice, always on unlike anti-click
+    pub choke_fade: Duration,
+    /// click track, plays on its own sink outside of any pattern
+    pub metronome: Metronome,
+    /// decoded samples shared between tracks
+    pub samples: SampleCache,
+    /// how close pulses come to running late
+    pub budget: PulseBudget,
+    pub jitter: PulseJitter,
+    /// pattern file being loaded in the background
+    pub preload: Option<Preload>,
+    /// loaded pattern waiting for the next downbeat
+    pending_load: Option<SavedPattern>,
+    /// settings saved between sessions
+    pub project: Project,
+    /// whole project snapshots to go back to
+    pub history: History,
+    pub midi_out: MidiOutputs,
+    /// inputs added by command, the virtual one is the sequencer's
+    pub midi_in: MidiInputs,
+    pub midi_learn: MidiLearn,
+    /// tracks midi clock on the input, following it if it's the clock source
+    pub clock_in: ClockFollower,
+    /// why the audio output is down, playback is held off until it's back
+    pub audio_error: Option<String>,
+    /// name of the output device playing
+    pub audio_device: String,
+    /// buffer size and rate the output is opened with
+    pub stream_request: StreamRequest,
+    /// frames per buffer the output got, None if it's the device's choice
+    pub buffer_frames: Option<u32>,
+    /// rate the output got
+    pub sample_rate: u32,
+    /// time from mixing to being heard as reported by the device
+    pub output_latency: Duration,
+    /// output to switch to, the stream can only be opened on the
+    /// sequencer thread so it picks this up on the next pulse
+    audio_device_request: Option<String>,
+    /// hits played while playing are written into the pattern
+    pub recording: bool,
+    pub record_mode: RecordMode,
+    /// division change waiting for the next bar, with the pattern it's for
+    pending_division: Option<(usize, Division)>,
+    /// swing grid change waiting for the next bar, like the division
+    pending_swing_grid: Option<(usize, SwingGrid)>,
+    /// the fill button is down
+    fill_held: bool,
+    /// a fill was triggered and hasn't started yet
+    fill_pending: bool,
+    /// pattern to go back to once the fill is released, set while a fill plays
+    fill_return: Option<usize>,
+    /// second pattern running alongside the current one in deck mode
+    deck_b: Option<Deck>,
+    /// 0 is all the current pattern and 1 all deck B
+    crossfade: f32,
+    /// commands from a macro waiting to run, they go ahead of the channel
+    pending_cmds: VecDeque<Command>,
+    /// project settings changed by the commands being handled, saved once
+    /// the command loop has let go of the lock
+    project_changed: bool,
+    /// files the commands being handled left to be written after the lock
+    disk_writes: Vec<DiskWrite>,
+    /// false leaves project.json, files and history snapshots alone, the
+    /// soak sets it so it can run on the real install
+    pub persist: bool,
+    /// velocity added to accented slots, like the accent knob on x0x boxes
+    pub accent_velocity: u8,
+    /// swing amount
+    pub swing: Swing,
+    /// actual swing offset from beat
+    pub swing_offset: u8,
+    /// beats per minutes
+    tempo: f32,
+    /// Time a tempo change is spread over so that gear synced to our
+    /// midi clock (delays, arps) doesn't stumble on a sudden jump
+    /// 
+    /// Zero applies tempo changes instantly
+    pub tempo_slew: Duration,
+    tempo_ramp: Option<TempoRamp>,
+    /// calculated based on tempo, the length of one pulse of the sequencer
+    /// 
+    /// note: this is not the same as a beat and has to be a higher frequency
+    /// to handle things like swing
+    pulse_interval: Duration,
+    playing: bool,
+    /// stopped without resetting the playheads
+    paused: bool,
+    /// playback is resuming from a pause, so midi gets continue rather than start
+    resuming: bool,
+    /// position should go back to the top before the next pulse
+    rewind: bool,
+    last_cmd: Command,
+    /// State transmission channel
+    /// 
+    /// Unfortunately the current standard Rust channel only
+    /// allows for a single consumer, so we can't broadcast state
+    /// updates to many listeners except via multiple channels
+    state_tx_ch: Vec<mpsc::Sender<StateUpdate>>,
+}
+
+impl Context {
+    fn set_tempo(&mut self, bpm: f32) {
+        let bpm = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        self.tempo = bpm;
+        self.pulse_interval = Duration::from_secs_f32(5.0 / 2.0 / bpm);
+        self.tempo_ramp = None;
+        self.apply_delay();
+        self.fit_loops();
+    }
+
+    /// Tempo loops are fitted to, where a ramp is headed rather than every
+    /// step along it
+    fn loop_tempo(&self) -> f32 {
+        self.tempo_ramp.as_ref().map_or(self.tempo, |ramp| ramp.to)
+    }
+
+    /// Fits every loop to the tempo ahead of it playing, so the work isn't
+    /// left for the first hit
+    fn fit_loops(&mut self) {
+        let tempo = self.loop_tempo();
+        for pattern in self.patterns.iter_mut() {
+            pattern.tracks.iter_mut().for_each(|track| track.fit_loop(tempo));
+        }
+    }
+
+    /// Sets tempo respecting the slew time, if we're stopped there's
+    /// no clock to protect so the change is instant
+    fn slew_tempo(&mut self, bpm: f32) {
+        if !bpm.is_finite() {
+            return;
+        }
+        let bpm = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        if self.tempo_slew.is_zero() || !self.playing {
+            self.set_tempo(bpm);
+            return;
+        }
+        self.tempo_ramp = Some(TempoRamp {
+            // derive from the pulse interval to pick up mid-ramp changes
+            from: 5.0 / 2.0 / self.pulse_interval.as_secs_f32(),
+            to: bpm,
+            start: Instant::now(),
+        });
+        self.fit_loops();
+    }
+
+    /// Moves the tempo along the current ramp, should be called every pulse
+    fn advance_tempo_ramp(&mut self) {
+        if let Some(ramp) = &self.tempo_ramp {
+            let progress = ramp.start.elapsed().as_secs_f32() / self.tempo_slew.as_secs_f32();
+            if progress >= 1.0 {
+                self.set_tempo(ramp.to);
+            } else {
+                let bpm = ramp.from + (ramp.to - ramp.from) * progress;
+                self.tempo = bpm;
+                self.pulse_interval = Duration::from_secs_f32(5.0 / 2.0 / bpm);
+                self.apply_delay();
+            }
+        }
+    }
+
+    /// Starts playback from the top, with a count in if it's set
+    pub fn play(&mut self) {
+        // Play after a pause starts over rather than continuing
+        if self.paused {
+            self.paused = false;
+            self.rewind = true;
+        }
+        if self.song.enabled {
+            self.restart_song();
+        }
+        if !self.playing {
+            self.metronome.start_count_in();
+        }
+        self.enable_play();
+    }
+
+    /// Whether PlayForBars or the stop timer wants playback to stop at the
+    /// end of this bar, counting it off if so
+    fn stop_due(&mut self, elapsed: Duration) -> bool {
+        if let Some(bars) = self.bars_left.as_mut() {
+            *bars = bars.saturating_sub(1);
+        }
+        let timer = self.project.stop_timer_secs;
+        self.bars_left == Some(0) || (timer > 0 && elapsed >= Duration::from_secs(timer as u64))
     }
 
-    pub fn zero_all_tracks(&mut self) {
-        self.tracks.iter_mut().for_each(|track| {
-            track.reset_slots();
+    pub fn enable_play(&mut self) {
+        if self.audio_error.is_some() {
+            println!("Not playing until the audio output is back");
+            return;
+        }
+        self.playing = true;
+    }
+
+    pub fn disable_play(&mut self) {
+        self.playing = false;
+        self.bars_left = None;
+        self.metronome.count_in_left = 0;
+        self.stop_loops();
+        self.fill_held = false;
+        self.fill_pending = false;
+        if let Some(pattern_id) = self.fill_return.take() {
+            self.pattern_id = pattern_id;
+        }
+        // no next bar to wait for once stopped
+        self.apply_pending_load();
+        self.apply_pending_division();
+        self.apply_pending_swing_grid();
+        self.stop_replacing();
+    }
+
+    pub fn set_record(&mut self, recording: bool) {
+        self.recording = recording;
+        if !recording {
+            self.stop_replacing();
+        }
+    }
+
+    /// Cuts short any replace pass so it doesn't carry on into the next take
+    fn stop_replacing(&mut self) {
+        self.patterns.iter_mut()
+            .flat_map(|pattern| pattern.tracks.iter_mut())
+            .for_each(|track| track.replace_left = 0);
+    }
+
+    /// The pattern the user is on, which isn't the one playing during a fill
+    pub fn main_pattern_id(&self) -> usize {
+        self.fill_return.unwrap_or(self.pattern_id)
+    }
+
+    /// Holds or releases the fill, a quick tap still plays a full bar of it
+    pub fn trigger_fill(&mut self, held: bool) {
+        self.fill_held = held;
+        if held {
+            self.fill_pending = true;
+        }
+    }
+
+    /// Changes the division of the pattern the user is on, while playing it
+    /// waits for the next bar so steps aren't skipped or doubled mid-bar
+    pub fn set_division(&mut self, division: Division) {
+        let pattern_id = self.main_pattern_id();
+        if self.playing {
+            self.pending_division = Some((pattern_id, division));
+        } else {
+            self.patterns[pattern_id].division = division;
+            self.set_swing(self.swing);
+        }
+    }
+
+    fn apply_pending_division(&mut self) {
+        if let Some((pattern_id, division)) = self.pending_division.take() {
+            if let Some(pattern) = self.patterns.get_mut(pattern_id) {
+                pattern.division = division;
+                // the swing offset is in pulses so depends on the division
+                self.set_swing(self.swing);
+            }
+        }
+    }
+
+    /// Changes the swing grid of the pattern the user is on, waiting for the
+    /// next bar while playing as moving steps mid-bar could skip or double one
+    pub fn set_swing_grid(&mut self, swing_grid: SwingGrid) {
+        let pattern_id = self.main_pattern_id();
+        if self.playing {
+            self.pending_swing_grid = Some((pattern_id, swing_grid));
+        } else {
+            self.patterns[pattern_id].swing_grid = swing_grid;
+        }
+    }
+
+    fn apply_pending_swing_grid(&mut self) {
+        if let Some((pattern_id, swing_grid)) = self.pending_swing_grid.take() {
+            if let Some(pattern) = self.patterns.get_mut(pattern_id) {
+                pattern.swing_grid = swing_grid;
+            }
+        }
+    }
+
+    /// Switches patterns on the downbeat, going to the queued pattern
+    /// then in or out of the fill
+    fn switch_patterns(&mut self) {
+        let mut main_id = self.main_pattern_id();
+        if self.queued_pattern_id != main_id {
+            main_id = self.queued_pattern_id;
+        }
+        let wants_fill = std::mem::take(&mut self.fill_pending) || self.fill_held;
+        let fill_id = self.patterns[main_id].fill
+            .filter(|&fill_id| wants_fill && fill_id != main_id && fill_id < self.patterns.len());
+        self.fill_return = fill_id.map(|_| main_id);
+
+        let next_id = fill_id.unwrap_or(main_id);
+        if next_id != self.pattern_id {
+            self.stop_loops();
+            self.pattern_id = next_id;
+            self.reset_playheads();
+        }
+        // The next pattern in line gets queued for the following bar
+        if let Some(pattern_id) = self.pattern_queue.pop_front() {
+            self.queued_pattern_id = pattern_id;
+        }
+    }
+
+    /// Moves playback onto a new output stream, whatever was ringing
+    /// on the old one is lost
+    pub fn set_stream(&mut self, stream: AudioHandle) {
+        for track in self.patterns.iter_mut().flat_map(|pattern| pattern.tracks.iter_mut()) {
+            track.drop_voices();
+        }
+        self.metronome.drop_voice();
+        self.audition = None;
+        if let Some(recording) = self.master_recording.take() {
+            println!("Stopped recording {}, the audio output changed", recording.fname);
+        }
+        if stream.sample_rate() != self.samples.sample_rate() {
+            self.reload_samples(stream.sample_rate());
+        }
+        self.stream = stream;
+        self.apply_sync_out();
+        self.apply_delay();
+        self.stream.set_reverb(self.project.reverb);
+        self.apply_master_volume();
+    }
+
+    /// Converts every sample in use to a new output rate. One that fails to
+    /// load again keeps playing at its old rate, converted as it plays
+    fn reload_samples(&mut self, sample_rate: u32) {
+        self.samples.set_sample_rate(sample_rate);
+        for track in self.patterns.iter_mut().flat_map(|pattern| pattern.tracks.iter_mut()) {
+            if let Err(e) = track.reload_sample(&mut self.samples) {
+                println!("Failed to reload {}: {}", track.sample_path, e);
+            }
+        }
+        let sample_path = self.metronome.sample_path.clone();
+        if let Err(e) = self.metronome.set_sample(sample_path, &mut self.samples) {
+            println!("Failed to reload metronome sample: {}", e);
+        }
+    }
+
+    /// Keeps the delay bus in time, it's set again on every tempo change
+    fn apply_delay(&self) {
+        let delay = self.project.delay;
+        self.stream.set_delay(delay.time(self.pulse_interval * PPB as u32), delay.feedback());
+    }
+
+    /// Silent at the bottom of the range, like track gain
+    fn apply_master_volume(&self) {
+        let volume_db = self.project.master_volume_db;
+        let gain = if volume_db <= MIN_MASTER_VOLUME_DB { 0.0 } else { 10f32.powf(volume_db as f32 / 20.0) };
+        self.stream.set_master_gain(gain);
+    }
+
+    /// Swaps in a sample decoded again after its file changed on disk, on
+    /// every track and the metronome using it
+    fn swap_sample(&mut self, sample_path: String, modified: Option<SystemTime>, res: Result<Arc<BufferedSample>, String>) {
+        let sample = match res {
+            Ok(sample) => sample,
+            Err(e) => {
+                println!("Failed to reload sample: {}", e);
+                self.samples.reload_failed(sample_path, modified, e);
+                return;
+            }
+        };
+        if !self.samples.replace(sample_path.clone(), sample.clone(), modified) {
+            return;
+        }
+        let playing = self.playing;
+        for track in self.patterns.iter_mut().flat_map(|pattern| pattern.tracks.iter_mut()) {
+            if track.sample_path == sample_path {
+                track.swap_sample(sample.clone(), playing);
+            }
+            track.swap_layer_sample(&sample_path, &sample);
+        }
+        if self.metronome.sample_path == sample_path {
+            if let Err(e) = self.metronome.set_sample(sample_path.clone(), &mut self.samples) {
+                println!("Failed to reload metronome sample: {}", e);
+            }
+        }
+        println!("Reloaded {}", sample_path);
+    }
+
+    /// Plays a sample from the library on a voice of its own, outside any
+    /// track so chokes and polyphony leave it be. The last preview fades
+    /// out under it, an empty path only stops that
+    fn preview_sample(&mut self, sample_path: String, vel: u8) -> Result<(), Box<dyn Error>> {
+        if let Some(voice) = self.audition.take() {
+            voice.fade_out_over(self.choke_fade);
+        }
+        if sample_path.is_empty() {
+            return Ok(());
+        }
+        let sample = self.samples.load(&sample_path)?;
+        self.audition = Some(self.stream.play((*sample).clone().voice(), VoiceStart {
+            gain: vel.min(127) as f32 / 127.0,
+            ..VoiceStart::default()
+        }));
+        Ok(())
+    }
+
+    /// Takes in samples written to the samples folder, in place of any
+    /// copy of an older file under the same name
+    fn add_new_samples(&mut self, new_samples: NewSamples) {
+        match new_samples.sample_files {
+            Some(Ok(sample_files)) => self.set_sample_files(sample_files),
+            Some(Err(e)) => println!("Failed to refresh sample files: {}", e),
+            None => (),
+        }
+        for (sample_path, modified, sample) in new_samples.decoded {
+            self.samples.replace(sample_path, sample, modified);
+        }
+    }
+
+    /// Puts the slices of a loop on the pattern it was sliced for, a track
+    /// each with a hit where the slice falls in the loop, so they play it
+    /// back in order across the pattern. Tracks already on a slice, from
+    /// slicing the same loop before, are cleared and used again. The slice
+    /// tracks choke each other, so moving the hits around never overlaps them
+    ///
+    /// The slices should already be decoded, see NewSamples
+    fn finish_slicing(&mut self, res: Result<Vec<String>, String>, pattern_id: usize) {
+        let slice_paths = match res {
+            Ok(slice_paths) => slice_paths,
+            Err(e) => {
+                println!("Failed to slice sample: {}", e);
+                return;
+            }
+        };
+        let playing = self.playing;
+        let Some(pattern) = self.patterns.get_mut(pattern_id) else {
+            println!("Sliced into {} samples but the pattern is gone", slice_paths.len());
+            return;
+        };
+        let len = pattern.tracks.iter().map(|track| track.len).max().unwrap_or(0);
+        let idx = pattern.tracks.first().map_or(0, |track| track.idx);
+        let slices = slice_paths.len();
+        let mut track_ids = vec![];
+        for (i, slice_path) in slice_paths.into_iter().enumerate() {
+            let res = match pattern.tracks.iter().position(|track| track.sample_path == slice_path) {
+                Some(track_id) => pattern.tracks[track_id].set_sample(slice_path, &mut self.samples).map(|_| track_id),
+                None => pattern.add_track(len, slice_path, &mut self.samples).map(|_| {
+                    let track = pattern.tracks.last_mut().unwrap();
+                    if playing {
+                        track.idx = idx % track.len;
+                    }
+                    pattern.tracks.len() - 1
+                }),
+            };
+            match res {
+                Ok(track_id) => {
+                    let track = &mut pattern.tracks[track_id];
+                    track.reset_slots();
+                    let step = i * track.len / slices;
+                    track.slots[step].velocity = SLICE_VELOCITY;
+                    track_ids.push(track_id);
+                },
+                Err(e) => println!("Failed to add slice track: {}", e),
+            }
+        }
+        for choke_grp in pattern.choke_grps.iter_mut() {
+            choke_grp.track_ids.retain(|track_id| !track_ids.contains(track_id));
+        }
+        pattern.choke_grps.retain(|choke_grp| choke_grp.track_ids.len() > 1);
+        if track_ids.len() > 1 {
+            pattern.choke_grps.push(ChokeGrp::new(track_ids));
+        }
+    }
+
+    /// Puts a captured sample on the track it was captured for, if the
+    /// track is still there. It should already be decoded, see NewSamples
+    fn finish_capture(&mut self, res: Result<String, String>, capture_id: u64) {
+        let target = self.capture_targets.remove(&capture_id);
+        if self.sample_capture.as_ref().is_some_and(|capture| target.is_some_and(|(_, track_id)| capture.track_id == track_id)) {
+            self.sample_capture = None;
+        }
+        let sample_path = match res {
+            Ok(sample_path) => sample_path,
+            Err(e) => {
+                println!("Failed to capture sample: {}", e);
+                return;
+            }
+        };
+        let playing = self.playing;
+        let Some((pattern, track_id)) = target.and_then(|(pattern_id, track_id)| {
+            self.patterns.get_mut(pattern_id).filter(|pattern| track_id < pattern.tracks.len()).map(|pattern| (pattern, track_id))
+        }) else {
+            println!("Captured {} but its track is gone", sample_path);
+            return;
+        };
+        let res = if playing {
+            pattern.queue_track_sample(track_id, sample_path, &mut self.samples)
+        } else {
+            pattern.set_track_sample(track_id, sample_path, &mut self.samples)
+        };
+        if let Err(e) = res {
+            println!("Failed to set track sample: {}", e);
+        }
+    }
+
+    /// How the output stream should be opened, wide enough for the output
+    /// every track is on
+    fn stream_request(&self) -> StreamRequest {
+        let pairs = self.patterns.iter()
+            .flat_map(|pattern| pattern.tracks.iter())
+            .map(|track| track.output as u16 + 1)
+            .max()
+            .unwrap_or(1);
+        StreamRequest {
+            channels: (pairs > 1).then_some(pairs * 2),
+            ..self.project.stream_request()
+        }
+    }
+
+    /// Opens the output again wider if a track went on a pair it doesn't
+    /// have, unless that's already been asked for. If the device doesn't
+    /// have enough channels the tracks play on the first pair
+    fn widen_output(&mut self) {
+        let request = self.stream_request();
+        let channels = request.channels.unwrap_or(0);
+        if channels > self.stream.channels() && channels > self.stream_request.channels.unwrap_or(0) {
+            self.stream_request = request;
+            self.audio_device_request = Some(self.audio_device.clone());
+        }
+    }
+
+    /// Opens the output again with the buffer and rate in the project,
+    /// the sequencer thread picks it up on the next pulse
+    fn reopen_stream(&mut self) {
+        self.stream_request = self.stream_request();
+        self.audio_device_request = Some(self.audio_device.clone());
+        self.save_project();
+    }
+
+    fn apply_sync_out(&self) {
+        let sync_out = self.project.sync_out;
+        self.stream.set_sync_channel(sync_out.enabled.then_some(sync_out.channel));
+    }
+
+    pub fn set_sync_out(&mut self, sync_out: SyncOut) -> Result<(), Box<dyn Error>> {
+        if !PPQ.is_multiple_of(sync_out.ppqn) {
+            return Err(format!("{} pulses per quarter note doesn't divide {}", sync_out.ppqn, PPQ).into());
+        }
+        self.project.sync_out = sync_out;
+        self.apply_sync_out();
+        self.save_project();
+        Ok(())
+    }
+
+    pub fn stop_loops(&self) {
+        self.stop_pattern_loops(self.pattern_id);
+    }
+
+    fn stop_pattern_loops(&self, pattern_id: usize) {
+        self.patterns[pattern_id].tracks.iter().for_each(|track| {
+            track.stop_loop();
         });
     }
 
+    /// Puts a pattern on deck B to run alongside the current one, or ends
+    /// deck mode with None
+    pub fn set_deck_b(&mut self, pattern_id: Option<usize>) {
+        if let Some(deck) = self.deck_b.take() {
+            if deck.pattern_id != self.pattern_id {
+                self.stop_pattern_loops(deck.pattern_id);
+            }
+        }
+        self.deck_b = pattern_id
+            .filter(|&id| id < self.patterns.len())
+            .map(|pattern_id| Deck { pattern_id, started: false });
+    }
+
+    /// Deck B's pattern if it's playing on this pulse, starting it on the
+    /// downbeat. B on the same pattern as A has nothing to blend so only
+    /// A plays
+    fn deck_b_playing(&mut self, pulse_idx: u8) -> Option<usize> {
+        let deck = self.deck_b.as_mut()?;
+        if deck.pattern_id == self.pattern_id {
+            deck.started = false;
+            return None;
+        }
+        if pulse_idx == 0 && !deck.started {
+            deck.started = true;
+            self.patterns[deck.pattern_id].reset_playheads();
+        }
+        deck.started.then_some(deck.pattern_id)
+    }
+
+    /// Deck B starts over from the next downbeat, like A does after a stop
+    fn restart_deck_b(&mut self) {
+        if let Some(deck) = self.deck_b.as_mut() {
+            deck.started = false;
+        }
+    }
+
+    /// Gains of decks A and B for the crossfader, equal power so the
+    /// middle isn't quieter than either end
+    fn deck_gains(&self) -> (f32, f32) {
+        let angle = self.crossfade * std::f32::consts::FRAC_PI_2;
+        (angle.cos(), angle.sin())
+    }
+
+    /// Ticks a pattern and plays what it triggered with velocities scaled
+    /// by the gain, returning the note ons for the midi outputs
+    ///
+    /// Hits scaled down to nothing don't play or choke anything
+    fn play_pattern(&mut self, pattern_id: usize, pulse_idx: u8, gain: f32) -> Vec<[u8; 3]> {
+        let params = self.tick_params();
+        let voice_settings = self.voice_settings();
+        let tempo = self.loop_tempo();
+        let pattern = &mut self.patterns[pattern_id];
+        let triggered = pattern.tick(pulse_idx, &params);
+
+        // We use this later to see if we need to choke
+        // any track
+        let mut triggered_ids: Vec<usize> = vec![];
+        let mut notes = vec![];
+        for trigger in triggered {
+            let velocity = (trigger.velocity as f32 * gain).round() as u8;
+            if velocity == 0 && trigger.velocity > 0 {
+                continue;
+            }
+            let track = &mut pattern.tracks[trigger.track_id];
+            track.fit_loop(tempo);
+            track.play(&self.stream, velocity, trigger.delay, trigger.reverse, voice_settings);
+            triggered_ids.push(trigger.track_id);
+            notes.push([0x90 | DRUM_CHANNEL, track.midi_note, velocity.max(1)]);
+        }
+
+        let pattern = &mut self.patterns[pattern_id];
+        for i in 0..pattern.tracks.len() {
+            if pattern.is_trk_choked(&triggered_ids, i) {
+                pattern.tracks[i].choke(voice_settings);
+            }
+        }
+        notes
+    }
+
+    pub fn set_swing(&mut self, swing: Swing) {
+        self.swing = swing;
+        // this truncates the decimal so is equivalent to floor
+        self.swing_offset = self.swing as u8 * (16 / self.patterns[self.pattern_id].division as u8);
+    }
+
     pub fn reset_playheads(&mut self) {
-        self.tracks.iter_mut().for_each(|track| {
-            track.idx = 0;
+        self.patterns[self.pattern_id].reset_playheads();
+        self.pattern_bar = 0;
+    }
+
+    /// Whether the bar playing is the last of the current pattern's loop,
+    /// patterns only change and song entries only count once it's done
+    fn last_bar(&self) -> bool {
+        self.pattern_bar + 1 >= self.patterns[self.pattern_id].bars()
+    }
+
+    /// Commands naming a track or slot the current pattern doesn't have
+    /// are dropped rather than let through to index out of bounds
+    fn has_track(&self, trk: usize) -> bool {
+        trk < self.patterns[self.pattern_id].tracks.len()
+    }
+
+    /// Tracks of the current pattern in a selection, missing ones are skipped
+    fn selected_tracks<'a>(&'a mut self, trks: &'a [usize]) -> impl Iterator<Item = &'a mut Track> {
+        self.patterns[self.pattern_id].tracks.iter_mut()
+            .enumerate()
+            .filter(move |(i, _)| trks.contains(i))
+            .map(|(_, track)| track)
+    }
+
+    fn has_slot(&self, trk: usize, slot: usize) -> bool {
+        self.patterns[self.pattern_id].tracks.get(trk).is_some_and(|track| slot < track.slots.len())
+    }
+
+    /// Switches to a pattern right away if stopped, otherwise when the
+    /// current one next comes round to its start
+    /// 
+    /// Anything lined up in the pattern queue is dropped
+    pub fn select_pattern(&mut self, pattern_id: usize) {
+        if !self.playing {
+            self.pattern_id = pattern_id;
+        }
+        self.queued_pattern_id = pattern_id;
+        self.pattern_queue.clear();
+    }
+
+    /// Lines a pattern up to play after the ones already queued
+    pub fn queue_pattern(&mut self, pattern_id: usize) {
+        if !self.playing {
+            self.select_pattern(pattern_id);
+        } else if self.queued_pattern_id == self.pattern_id && self.pattern_queue.is_empty() {
+            self.queued_pattern_id = pattern_id;
+        } else {
+            self.pattern_queue.push_back(pattern_id);
+        }
+    }
+
+    /// Puts the song back at its first entry
+    pub fn restart_song(&mut self) {
+        if let Some(pattern_id) = self.song.restart() {
+            self.select_pattern(pattern_id);
+        }
+    }
+
+    pub fn voice_settings(&self) -> VoiceSettings {
+        VoiceSettings {
+            swap_policy: self.swap_policy,
+            anti_click: self.anti_click.then_some(self.anti_click_len),
+            choke_fade: self.choke_fade,
+        }
+    }
+
+    pub fn tick_params(&self) -> TickParams {
+        TickParams {
+            ppb: PPB,
+            swing: self.swing,
+            swing_offset: self.swing_offset,
+            accent_velocity: self.accent_velocity,
+            pulse_interval: self.pulse_interval,
+        }
+    }
+
+    // Saves the current pattern under its name
+    // We also save a shortened hash of the file with it
+    //
+    // The file is written once the command loop lets go of the lock
+    pub fn save_pattern(&mut self) -> Result<(), Box<dyn Error>> {
+        let pattern = &self.patterns[self.pattern_id];
+        let saved_pattern = pattern.to_saved();
+        let mut hash = std::hash::DefaultHasher::new();
+        saved_pattern.hash(&mut hash);
+        // converts to hex and truncates
+        let hash = format!("{:x}", hash.finish())[..8].to_string();
+        let name = file_stem(&pattern.name)?;
+        let f_name = format!("{}-{}.json", name, hash);
+        self.disk_writes.push(DiskWrite::Pattern(f_name, saved_pattern));
+        Ok(())
+    }
+
+    /// Reads a pattern file and decodes the samples it needs off the
+    /// command loop, so neither the loop nor the switch waits on the disk
+    ///
+    /// Once done the pattern replaces the current one, on the next
+    /// downbeat if playing
+    pub fn preload_pattern(&mut self, ctx_handle: ContextHandle, pattern_fname: String) {
+        if let Some(preload) = &self.preload {
+            println!("Not loading {}, still loading {}", pattern_fname, preload.fname);
+            return;
+        }
+        self.preload = Some(Preload { fname: pattern_fname.clone(), ..Default::default() });
+        thread::spawn(move || {
+            let saved_pattern = match read_saved_pattern(&pattern_fname) {
+                Ok(saved_pattern) => saved_pattern,
+                Err(e) => {
+                    println!("Failed to load pattern: {}", e);
+                    ctx_handle.with_lock(|ctx| ctx.preload = None);
+                    return;
+                }
+            };
+            let mut sample_paths: Vec<String> = saved_pattern.tracks.iter()
+                .flat_map(|track| std::iter::once(&track.sample_path).chain(track.layers.iter().map(|layer| &layer.sample_path)))
+                .cloned()
+                .collect();
+            sample_paths.sort();
+            sample_paths.dedup();
+            read_grooves(&ctx_handle, saved_pattern.groove.iter().map(|groove| groove.name.clone()).collect());
+            let sample_rate = ctx_handle.with_lock(|ctx| {
+                sample_paths.retain(|sample_path| !ctx.samples.contains(sample_path));
+                if let Some(preload) = ctx.preload.as_mut() {
+                    preload.total = sample_paths.len();
+                }
+                ctx.samples.sample_rate()
+            });
+            for sample_path in sample_paths {
+                // Failures are left for the load to report and skip the track
+                let modified = sample_cache::modified(&sample_path);
+                let sample = BufferedSample::new(&sample_path, sample_rate).ok();
+                ctx_handle.with_lock(|ctx| {
+                    if let Some(sample) = sample {
+                        ctx.samples.insert(sample_path, sample, modified);
+                    }
+                    if let Some(preload) = ctx.preload.as_mut() {
+                        preload.done += 1;
+                    }
+                });
+            }
+            ctx_handle.with_lock(|ctx| {
+                if let Some(preload) = ctx.preload.as_mut() {
+                    preload.ready = true;
+                }
+                ctx.pending_load = Some(saved_pattern);
+                if !ctx.playing {
+                    ctx.apply_pending_load();
+                }
+            });
         });
     }
 
-    pub fn set_len(&mut self, len: usize) {
-        self.tracks.iter_mut().for_each(|track| {
-            track.set_len(len);
-        });
+    /// Swaps in a preloaded pattern for the one the user is on
+    fn apply_pending_load(&mut self) {
+        let Some(saved_pattern) = self.pending_load.take() else {
+            return;
+        };
+        self.preload = None;
+        let pattern_id = self.main_pattern_id();
+        let name = self.patterns[pattern_id].name.clone();
+        self.patterns[pattern_id] = self.pattern_from_saved(saved_pattern, name);
+    }
+
+    /// Adds a copy of a pattern after the last one and moves to it the
+    /// same way AddPattern does
+    ///
+    /// It goes through the saved form so the copy starts with no voices
+    /// rather than sharing the original's
+    pub fn duplicate_pattern(&mut self, idx: usize) {
+        let pattern = &self.patterns[idx];
+        let (saved, name, fill) = (pattern.to_saved(), format!("{} copy", pattern.name), pattern.fill);
+        let mut copy = self.pattern_from_saved(saved, name);
+        copy.fill = fill;
+        let new_id = self.patterns.len();
+        self.patterns.push(copy);
+        if self.playing {
+            self.queued_pattern_id = new_id;
+        } else {
+            self.pattern_id = new_id;
+        }
+    }
+
+    pub fn set_pattern_name(&mut self, idx: usize, name: String) -> Result<(), Box<dyn Error>> {
+        let name = name.trim();
+        if name.is_empty() {
+            return Err("pattern name can't be empty".into());
+        }
+        let pattern = self.patterns.get_mut(idx).ok_or(format!("no pattern {}", idx))?;
+        pattern.name = name.to_string();
+        Ok(())
+    }
+
+    /// Builds a playable pattern from saved data
+    /// 
+    /// Tracks whose sample fails to load are kept silent, so the choke
+    /// groups and anything else going by track index still line up
+    pub fn pattern_from_saved(&mut self, saved_pattern: SavedPattern, name: String) -> Pattern {
+        Pattern {
+            tracks: saved_pattern.tracks.iter().enumerate().map(
+                |(i, track)| self.track_from_saved(track, i)
+            ).collect(),
+            choke_grps: saved_pattern.choke_grps.clone(),
+            division: saved_pattern.division,
+            name,
+            humanize: saved_pattern.humanize,
+            groove: saved_pattern.groove.map(|groove| self.pattern_groove(groove.name, groove.strength)),
+            cc_lanes: saved_pattern.cc_lanes,
+            fill: None,
+            notes: saved_pattern.notes,
+            swing_grid: saved_pattern.swing_grid,
+        }
+    }
+
+    /// Builds a track from saved data, idx is where it goes
+    /// in its pattern which picks the midi note for older files
+    fn track_from_saved(&mut self, track: &SavedTrack, idx: usize) -> Track {
+        let mut t = Track::new(track.slots.len(), track.sample_path.clone(), &mut self.samples)
+            .unwrap_or_else(|e| {
+                println!("Failed to load track sample, it plays silent: {}", e);
+                Track::silent(track.slots.len(), track.sample_path.clone(), self.samples.sample_rate(), e.to_string())
+            });
+        t.slots = track.slots.clone();
+        t.direction = track.direction;
+        t.timing_offset_ms = track.timing_offset_ms;
+        t.eq = track.eq.clamped();
+        t.fx_bypass = track.fx_bypass;
+        t.midi_note = track.midi_note.unwrap_or(BASE_NOTE.saturating_add(idx as u8).min(127));
+        t.velocity_variation = track.velocity_variation;
+        t.pitch_cents = track.pitch_cents;
+        t.reverse = track.reverse;
+        t.muted = track.muted;
+        t.gain_db = track.gain_db.clamp(MIN_TRACK_GAIN_DB, MAX_TRACK_GAIN_DB);
+        t.envelope = track.envelope;
+        t.filter = track.filter;
+        t.delay_send = track.delay_send.min(MAX_SEND);
+        t.reverb_send = track.reverb_send.min(MAX_SEND);
+        t.polyphony = track.polyphony;
+        t.archived = track.archived;
+        t.loop_sync = track.loop_sync;
+        t.output = track.output.min(MAX_OUTPUT_PAIRS - 1);
+        for layer in &track.layers {
+            if let Err(e) = t.set_layer(layer.min_velocity, layer.sample_path.clone(), &mut self.samples) {
+                println!("Failed to set track layer: {}", e);
+            }
+        }
+        // with no sample to check them against they're kept as they were
+        if t.load_error.is_some() {
+            t.loop_points = track.loop_points;
+        } else if let Some((start, end)) = track.loop_points {
+            if let Err(e) = t.set_loop(start, end) {
+                println!("Failed to set track loop: {}", e);
+            }
+        }
+        t
+    }
+
+    /// Adds a copy of a track, steps, sample and length, to the end of
+    /// another pattern
+    pub fn copy_track(&mut self, src_pattern: usize, src_track: usize, dst_pattern: usize) -> Result<(), Box<dyn Error>> {
+        let saved = self.patterns.get(src_pattern)
+            .and_then(|pattern| pattern.tracks.get(src_track))
+            .ok_or(format!("no track {} in pattern {}", src_track, src_pattern))?
+            .to_saved();
+        let dst_len = self.patterns.get(dst_pattern)
+            .ok_or(format!("no pattern {}", dst_pattern))?
+            .tracks.len();
+        let track = self.track_from_saved(&saved, dst_len);
+        self.patterns[dst_pattern].tracks.push(track);
+        Ok(())
+    }
+
+    /// Marks the project to be saved after the commands being handled,
+    /// however many of them changed it
+    fn save_project(&mut self) {
+        self.project_changed = true;
+    }
+
+    fn save_midi_outputs(&mut self) {
+        self.project.midi_outputs = self.midi_out.destinations().to_vec();
+        self.save_project();
+    }
+
+    /// Follows the current pattern's tracks to their new places after one
+    /// moved or was removed, for what outside the pattern points at them
+    /// by index. Captures for a removed track go. The note map, bindings and
+    /// macros are for the whole project and stay on the index they name
+    fn remap_tracks(&mut self, new_id: impl Fn(usize) -> Option<usize>) {
+        let pattern_id = self.pattern_id;
+        let remap = |trk: &mut usize| new_id(*trk).map(|id| *trk = id).is_some();
+        self.capture_targets.retain(|_, (capture_pattern, trk)| *capture_pattern != pattern_id || remap(trk));
+        // the capture running is the last one started
+        if let Some(capture) = self.sample_capture.as_mut() {
+            match self.capture_targets.get(&(self.next_capture - 1)) {
+                Some((_, track_id)) => capture.track_id = *track_id,
+                None => self.sample_capture.take().unwrap().stop(),
+            }
+        }
+    }
+
+    fn save_midi_inputs(&mut self) {
+        self.project.midi_inputs = self.midi_in.port_names().to_vec();
+        self.save_project();
+    }
+
+    /// Track a note coming in plays, mapped notes go to their track and the
+    /// rest to the first track sending that note
+    fn note_input_track(&self, message: &[u8]) -> Option<usize> {
+        let (note, _) = self.project.note_input.note_on(message)?;
+        let tracks = &self.patterns[self.pattern_id].tracks;
+        match self.project.note_input.notes.get(&note) {
+            Some(trk_id) => Some(*trk_id).filter(|trk_id| *trk_id < tracks.len()),
+            None => tracks.iter().position(|trk| trk.midi_note == note),
+        }
+    }
+
+    pub fn snapshot_state(&self) -> SnapshotState {
+        SnapshotState {
+            patterns: self.patterns.iter().map(|pattern| SnapshotPattern {
+                name: pattern.name.clone(),
+                pattern: pattern.to_saved(),
+                fill: pattern.fill,
+            }).collect(),
+            pattern_id: self.pattern_id,
+            song: self.song.entries.clone(),
+            tempo: self.tempo,
+            swing: self.swing,
+        }
+    }
+
+    /// Puts the whole project back how it was at a snapshot, its samples
+    /// should already be decoded, see restore_snapshot
+    fn apply_snapshot(&mut self, id: usize, hash: u64, state: SnapshotState) -> Result<(), Box<dyn Error>> {
+        let patterns: Vec<Pattern> = state.patterns.into_iter()
+            .map(|p| Pattern {
+                fill: p.fill,
+                ..self.pattern_from_saved(p.pattern, p.name)
+            })
+            .collect();
+        if patterns.is_empty() {
+            return Err(format!("snapshot {} has no patterns", id).into());
+        }
+        self.stop_loops();
+        self.patterns = patterns;
+        self.pattern_id = state.pattern_id.min(self.patterns.len() - 1);
+        self.fill_return = None;
+        self.queued_pattern_id = self.pattern_id;
+        self.pattern_queue.clear();
+        self.song.clear();
+        for entry in state.song {
+            if entry.pattern_id < self.patterns.len() {
+                self.song.add_entry(entry.pattern_id, entry.repeats);
+            }
+        }
+        self.slew_tempo(state.tempo);
+        self.set_swing(state.swing);
+        self.history.restored(id, hash);
+        Ok(())
+    }
+
+    /// Replaces the patterns with a demo's and chains them in song mode,
+    /// looping, so there's something to hear straight away
+    pub fn load_demo(&mut self, style: DemoStyle) {
+        let demo = Demo::new(style);
+        let mut repeats = vec![];
+        let patterns: Vec<Pattern> = demo.patterns.into_iter()
+            .map(|(name, saved, times)| {
+                repeats.push(times);
+                self.pattern_from_saved(saved, name)
+            })
+            .collect();
+        self.stop_loops();
+        self.patterns = patterns;
+        self.pattern_id = 0;
+        self.fill_return = None;
+        self.queued_pattern_id = 0;
+        self.pattern_queue.clear();
+        self.song.clear();
+        for (pattern_id, times) in repeats.into_iter().enumerate() {
+            self.song.add_entry(pattern_id, times);
+        }
+        self.song.set_loop_region(0, self.patterns.len() - 1);
+        self.song.enabled = true;
+        self.restart_song();
+        self.slew_tempo(demo.tempo);
+        self.set_swing(demo.swing);
+    }
+
+    pub fn refresh_saved_patterns(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_saved_patterns(list_saved_patterns()?);
+        Ok(())
     }
 
-    pub fn set_division(&mut self, division: Division) {
-        self.division = division;
+    fn set_saved_patterns(&mut self, saved_patterns: Vec<String>) {
+        self.saved_patterns = saved_patterns;
+        self.send_file_state(FileType::Pattern);
     }
 
-    // sample_path is the relative location of the sample file to the samples directory
-    // This behavior is hardcoded for now
-    pub fn add_track(&mut self, stream: Arc<OutputStreamHandle>, len: usize, sample_path: String) -> Result<(), Box<dyn Error>> {
-        let sink = Sink::try_new(&stream)?;
-        let sink = Arc::new(sink);
-        sink.play();
-        let tracks = &mut self.tracks;
-        tracks.push(Track::new(len, sample_path, sink)?);
+    pub fn refresh_sample_files(&mut self) -> Result<(), Box<dyn Error>> {
+        self.set_sample_files(list_sample_files()?);
         Ok(())
     }
 
-    pub fn set_track_sample(&mut self, track_id: usize, sample_path: String) -> Result<(), Box<dyn Error>> {
-        self.tracks[track_id].set_sample(sample_path)
+    fn set_sample_files(&mut self, sample_files: Vec<String>) {
+        self.sample_files = sample_files;
+        self.samples.retain_errors(&self.sample_files);
+        self.send_file_state(FileType::Sample);
     }
-}
-
-/// Struct that describes internal sequencer state that can be
-/// modified by the user as well as connections and channels
-/// 
-/// Note that many parameters are actually pattern-specific
-pub struct Context {
-    pub stream: Arc<OutputStreamHandle>,
-    pub patterns: Vec<Pattern>,
-    pub saved_patterns: Vec<String>,
-    pub sample_files: Vec<String>,
-    pub pattern_id: usize,
-    // If there is no new pattern for queueing it should be
-    // the current pattern since the same pattern is queued
-    // for playing next
-    pub queued_pattern_id: usize,
-    /// It's the default length of a new track, unit is beats
-    pub default_len: usize,
-    /// swing amount
-    pub swing: Swing,
-    /// actual swing offset from beat
-    pub swing_offset: u8,
-    /// beats per minutes
-    tempo: u8,
-    /// calculated based on tempo, the length of one pulse of the sequencer
-    /// 
-    /// note: this is not the same as a beat and has to be a higher frequency
-    /// to handle things like swing
-    pulse_interval: Duration,
-    playing: bool,
-    command_rx_ch: mpsc::Receiver<Command>,
-    last_cmd: Command,
-    /// State transmission channel
-    /// 
-    /// Unfortunately the current standard Rust channel only
-    /// allows for a single consumer, so we can't broadcast state
-    /// updates to many listeners except via multiple channels
-    state_tx_ch: Vec<mpsc::Sender<StateUpdate>>,
-}
 
-impl Context {
-    fn set_tempo(&mut self, bpm: u8) {
-        self.tempo = bpm;
-        self.pulse_interval = Duration::from_secs_f32(5.0 / 2.0 / bpm as f32);
+    /// A groove with its template, if it's been read. One that couldn't
+    /// be was reported then and plays straight
+    fn pattern_groove(&self, name: String, strength: u8) -> PatternGroove {
+        let template = self.grooves.get(&name).cloned();
+        PatternGroove::new(name, strength, template)
     }
 
-    pub fn enable_play(&mut self) {
-        self.playing = true;
+    pub fn refresh_groove_files(&mut self) -> Result<(), Box<dyn Error>> {
+        self.groove_files = groove::list_grooves()?;
+        self.send_file_state(FileType::Groove);
+        Ok(())
     }
 
-    pub fn disable_play(&mut self) {
-        self.playing = false;
+    pub fn send_trigger_counts(&self) {
+        let counts = TriggerCounts {
+            pattern_id: self.pattern_id,
+            tracks: self.patterns[self.pattern_id].tracks.iter()
+                .map(|track| track.trigger_counts.clone())
+                .collect(),
+        };
+        for tx in &self.state_tx_ch {
+            let _ = tx.send(StateUpdate::TriggerCounts(counts.clone()));
+        }
     }
 
-    pub fn set_swing(&mut self, swing: Swing) {
-        self.swing = swing;
-        // this truncates the decimal so is equivalent to floor
-        self.swing_offset = self.swing as u8 * (16 / self.patterns[self.pattern_id].division as u8);
+    /// Samples the project uses right now, loaded tracks, the metronome
+    /// and any macro or midi binding that sets a sample
+    fn sample_refs(&self) -> Vec<String> {
+        let tracks = self.patterns.iter()
+            .flat_map(|pattern| pattern.tracks.iter())
+            .flat_map(|track| std::iter::once(&track.sample_path).chain(track.layers.iter().map(|(layer, _)| &layer.sample_path)))
+            .cloned();
+        let macros = self.project.macros.iter()
+            .flat_map(|m| m.commands.iter())
+            .chain(self.project.midi_map.bindings.iter().map(|binding| &binding.command))
+            .filter_map(|cmd| match cmd {
+                Command::SetTrackSample(_, sample_path) | Command::SetMetronomeSample(sample_path) => Some(sample_path.clone()),
+                _ => None,
+            });
+        tracks.chain(macros).chain(std::iter::once(self.metronome.sample_path.clone())).collect()
     }
 
-    pub fn reset_playheads(&mut self) {
-        self.patterns[self.pattern_id].reset_playheads();
+    /// Builds the sample pool report off the command loop as it reads every
+    /// saved pattern and snapshot, cleaning moves the unused samples after,
+    /// all without the lock
+    fn send_sample_pool(&self, ctx_handle: ContextHandle, clean: bool) {
+        let pool = self.sample_files.clone();
+        let refs = self.sample_refs();
+        thread::spawn(move || {
+            let mut report = PoolReport::new(&pool, &refs);
+            if clean {
+                // A track could have picked one up while the files were read
+                let refs = ctx_handle.with_lock(|ctx| ctx.sample_refs());
+                let orphaned: Vec<String> = report.orphaned.iter()
+                    .filter(|sample_path| !refs.contains(sample_path))
+                    .cloned()
+                    .collect();
+                report.archived = sample_pool::archive(&orphaned);
+                report.samples.retain(|usage| !report.archived.contains(&usage.sample_path));
+                report.orphaned.retain(|sample_path| !report.archived.contains(sample_path));
+            }
+            let sample_files = clean.then(list_sample_files);
+            ctx_handle.with_lock(|ctx| {
+                match sample_files {
+                    Some(Ok(sample_files)) => ctx.set_sample_files(sample_files),
+                    Some(Err(e)) => println!("Failed to refresh sample files: {}", e),
+                    None => (),
+                }
+                for tx in &ctx.state_tx_ch {
+                    let _ = tx.send(StateUpdate::SamplePool(report.clone()));
+                }
+            });
+        });
     }
 
-    // Saves the current pattern with named after its index
-    // We also save a shortened hash of the file with it
-    // but todo, I do think we need to allow specifying a name
-    // or the user will get lost
-    pub fn save_pattern(&mut self) -> Result<(), Box<dyn Error>> {
-        let pattern = &self.patterns[self.pattern_id];
-        let saved_pattern = SavedPattern {
-            tracks: pattern.tracks.iter().map(|track| SavedTrack {
-                slots: track.slots.clone(),
-                sample_path: track.sample_path.clone()
+    /// Subset of the state for the controllers, as of the last pulse
+    fn seq_state(&self, position: TransportPosition, latency: Duration) -> SeqState {
+        let trks: Vec<TrackState> = self
+            .patterns[self.pattern_id]
+            .tracks
+            .iter()
+            .map(|t| {
+                TrackState {
+                    slots: t.slots.iter().map(|s| { s.velocity }).collect(),
+                    accents: t.slots.iter().map(|s| { s.accent }).collect(),
+                    reverses: t.slots.iter().map(|s| { s.reverse }).collect(),
+                    ratchets: t.slots.iter().map(|s| { s.ratchet }).collect(),
+                    ratchet_decays: t.slots.iter().map(|s| { s.ratchet_decay }).collect(),
+                    name: t.name.clone(),
+                    idx: t.idx,
+                    len: t.len,
+                    sample_path: t.sample_path.clone(),
+                    load_error: t.load_error.clone(),
+                    eq: t.eq,
+                    direction: t.direction as u8,
+                    timing_offset_ms: t.timing_offset_ms,
+                    loop_points: t.loop_points,
+                    midi_note: t.midi_note,
+                    velocity_variation: t.velocity_variation,
+                    pitch_cents: t.pitch_cents,
+                    reverse: t.reverse,
+                    muted: t.muted,
+                    gain_db: t.gain_db,
+                    envelope: t.envelope,
+                    filter: t.filter,
+                    delay_send: t.delay_send,
+                    reverb_send: t.reverb_send,
+                    fx_bypass: t.fx_bypass,
+                    polyphony: t.polyphony,
+                    layers: t.layers.iter().map(|(layer, _)| layer.clone()).collect(),
+                    loop_sync: t.loop_sync,
+                    output: t.output,
+                    sample_gain: t.sample.gain_settings(),
+                    erasing: t.erasing,
+                    archived: t.archived,
+                }
+            })
+            .collect();
+
+        let groove = self.patterns[self.pattern_id].groove.as_ref();
+        let sample_memory = self.samples.memory();
+        let jitter = self.jitter.report();
+        SeqState {
+            tempo: self.tempo,
+            trks,
+            cc_lanes: self.patterns[self.pattern_id].cc_lanes.iter().map(|lane| CcLaneState {
+                channel: lane.channel,
+                controller: lane.controller,
+                values: lane.values.clone(),
+                idx: lane.idx,
+            }).collect(),
+            division: self.patterns[self.pattern_id].division as u8,
+            pending_division: self.pending_division.map(|(_, division)| division as u8),
+            swing_grid: self.patterns[self.pattern_id].swing_grid as u8,
+            default_len: self.default_len,
+            latency,
+            budget_pct: self.budget.last_pct(),
+            peak_budget_pct: self.budget.peak_pct(),
+            jitter_min_us: jitter.min_us,
+            jitter_max_us: jitter.max_us,
+            jitter_p99_us: jitter.p99_us,
+            missed_pulses: jitter.missed,
+            total_missed_pulses: jitter.total_missed,
+            last_cmd: self.last_cmd.clone(),
+            playing: self.playing,
+            paused: self.paused,
+            recording: self.recording,
+            record_mode: self.record_mode as u8,
+            audio_error: self.audio_error.clone(),
+            audio_device: self.audio_device.clone(),
+            buffer_mode: self.stream_request.buffer_mode as u8,
+            requested_buffer_frames: self.stream_request.buffer_frames.unwrap_or(0),
+            requested_sample_rate: self.stream_request.sample_rate.unwrap_or(0),
+            sample_rate: self.sample_rate,
+            output_channels: self.stream.channels(),
+            language: self.project.language as u8,
+            sync_out: self.project.sync_out.enabled,
+            sync_out_channel: self.project.sync_out.channel,
+            sync_out_ppqn: self.project.sync_out.ppqn,
+            delay_division: self.project.delay.division as u8,
+            delay_feedback_pct: self.project.delay.feedback_pct,
+            reverb: self.project.reverb,
+            master_volume_db: self.project.master_volume_db,
+            buffer_frames: self.buffer_frames.unwrap_or(0),
+            output_latency: self.output_latency,
+            measured_latency: self.stream.measured_latency(),
+            position,
+            beats_per_bar: PPB / PPQ,
+            bars_left: self.bars_left,
+            stop_in: (self.project.stop_timer_secs > 0).then(|| {
+                Duration::from_secs(self.project.stop_timer_secs as u64).saturating_sub(position.elapsed)
+            }),
+            stop_timer_secs: self.project.stop_timer_secs,
+            preload: self.preload.clone(),
+            sample_count: sample_memory.samples,
+            sample_bytes: sample_memory.held_bytes,
+            idle_sample_bytes: sample_memory.idle_bytes,
+            sample_cache_mb: self.project.sample_cache_mb,
+            sync: self.clock_in.status(),
+            pattern_id: self.pattern_id,
+            pattern_len: self.patterns.len(),
+            page: self.pattern_bar.min(self.patterns[self.pattern_id].bars() - 1),
+            pages: self.patterns[self.pattern_id].bars(),
+            page_len: self.patterns[self.pattern_id].division as usize,
+            pattern_name: self.patterns[self.pattern_id].name.clone(),
+            pattern_names: self.patterns.iter().map(|p| p.name.clone()).collect(),
+            pattern_notes: self.patterns[self.pattern_id].notes.clone(),
+            project_notes: self.project.notes.clone(),
+            queued_pattern_id: self.queued_pattern_id,
+            pattern_queue: self.pattern_queue.iter().cloned().collect(),
+            deck_b_id: self.deck_b.map(|deck| deck.pattern_id),
+            crossfade: self.crossfade,
+            fill_id: self.patterns[self.main_pattern_id()].fill,
+            fill_playing: self.fill_return.is_some(),
+            swing: self.swing as u8,
+            tempo_slew: self.tempo_slew,
+            song_loop: self.song.loop_region,
+            song_mode: self.song.enabled,
+            song: self.song.entries.clone(),
+            song_position: self.song.position,
+            song_bar: self.song.bars_played,
+            swap_policy: self.swap_policy as u8,
+            anti_click: self.anti_click,
+            anti_click_ms: self.anti_click_len.as_millis() as u8,
+            choke_fade_ms: self.choke_fade.as_millis() as u8,
+            metronome: self.metronome.enabled,
+            metronome_sample: self.metronome.sample_path.clone(),
+            count_in_bars: self.metronome.count_in_bars,
+            count_in_left: self.metronome.count_in_left,
+            macros: self.project.macros.iter().map(|m| m.name.clone()).collect(),
+            midi_thru: self.project.midi_thru.enabled,
+            midi_thru_channel: self.project.midi_thru.channel,
+            midi_outputs: self.midi_out.destinations().iter().enumerate().map(|(i, destination)| MidiOutputState {
+                port_name: destination.port_name.clone(),
+                connected: self.midi_out.is_connected(i),
+                clock: destination.clock,
+                transport: destination.transport,
+                notes: destination.notes,
+                cc: destination.cc,
+                virtual_port: destination.virtual_port,
             }).collect(),
-            choke_grps: pattern.choke_grps.clone(),
-            division: pattern.division,
+            midi_inputs: self.midi_in.port_names().iter().enumerate().map(|(i, port_name)| MidiInputState {
+                port_name: port_name.clone(),
+                connected: self.midi_in.is_connected(i),
+            }).collect(),
+            midi_learning: self.midi_learn.armed.is_some(),
+            midi_bindings: self.project.midi_map.bindings.clone(),
+            note_input: self.project.note_input.enabled,
+            note_input_channel: self.project.note_input.channel,
+            note_map: self.project.note_input.notes.iter()
+                .map(|(note, track)| NoteMapState { note: *note, track: *track })
+                .collect(),
+            humanize_timing_ms: self.patterns[self.pattern_id].humanize.timing_ms,
+            humanize_velocity: self.patterns[self.pattern_id].humanize.velocity,
+            accent_velocity: self.accent_velocity,
+            groove_name: groove.map(|g| g.name.clone()).unwrap_or_default(),
+            groove_strength: groove.map(|g| g.strength).unwrap_or_default(),
+            last_export: self.last_export.clone(),
+            master_recording: self.master_recording.as_ref().map(|recording| recording.fname.clone()),
+            master_recording_time: self.master_recording.as_ref().map(|recording| recording.elapsed()),
+            capture_track: self.sample_capture.as_ref().map(|capture| capture.track_id),
+            capture_triggered: self.sample_capture.as_ref().is_some_and(|capture| capture.triggered()),
+            sample_errors: self.samples.errors(),
+        }
+    }
+
+    /// Sends special state update for files only
+    /// This can be triggered if changes occurred in the file system
+    /// Also yes, yes the other state tx is in sequencer and I'm beginning
+    /// to think we should just lock the whole sequencer and forget
+    /// about the context
+    ///
+    /// Ports, devices and midi maps are listed on a thread of their own
+    /// rather than under the lock
+    pub fn send_file_state(&self, file_type: FileType) {
+        let files = match file_type {
+            FileType::Pattern => self.saved_patterns.clone(),
+            FileType::Sample => self.sample_files.clone(),
+            FileType::Groove => self.groove_files.clone(),
+            FileType::History => self.history.files(),
+            FileType::SimilarPattern => self.similar_patterns.clone(),
+            FileType::MidiPort | FileType::MidiInputPort | FileType::MidiMap | FileType::AudioDevice => {
+                let state_tx_ch = self.state_tx_ch.clone();
+                thread::spawn(move || {
+                    let files = list_files(&file_type);
+                    send_files(&state_tx_ch, file_type, files);
+                });
+                return;
+            },
         };
-        let mut hash = std::hash::DefaultHasher::new();
-        saved_pattern.hash(&mut hash);
-        // converts to hex and truncates
-        let hash = format!("{:x}", hash.finish())[..8].to_string();
-        let f_name = format!("{}-{}.json", pattern.name, hash);
-        let f_name = f_name.replace(" ", "_");
-        let file = OpenOptions::new()
-            .create_new(true)
-            .write(true)
-            .open(format!("{PWD}/patterns/{}", &f_name))?;
-        let file = std::io::BufWriter::new(file);
-        serde_json::to_writer(file, &saved_pattern)?;
-        self.refresh_saved_patterns()?;
-        Ok(())
+        send_files(&self.state_tx_ch, file_type, files);
     }
+}
 
-    // Loads pattern from json file
-    // This creates a new sink, and I am not sure old sinks are
-    // destroyed when added to the stream so...maybe the better way I suspect
-    // is to rotate available sinks
-    pub fn load_pattern(&mut self, pattern_fname: String) -> Result<(), Box<dyn Error>> {
-        let pattern = &self.patterns[self.pattern_id];
+fn send_files(state_tx_ch: &[mpsc::Sender<StateUpdate>], file_type: FileType, files: Vec<String>) {
+    for tx in state_tx_ch {
+        let _ = tx.send(StateUpdate::FileState(FileState {
+            file_type: file_type.clone(),
+            files: files.clone(),
+        }));
+    }
+}
 
-        let file = std::fs::File::open(format!("{PWD}/patterns/{}", pattern_fname))?;
-        let file = std::io::BufReader::new(file);
-        let saved_pattern: SavedPattern = serde_json::from_reader(file)?;
-
-        self.patterns[self.pattern_id] = Pattern {
-            tracks: saved_pattern.tracks.iter().filter_map(
-                |track|
-                if let Ok(sink) = Sink::try_new(&self.stream) {
-                    match Track::new(
-                        track.slots.len(),
-                        track.sample_path.clone(),
-                        Arc::new(sink)
-                    ) {
-                        Ok(mut t) => {
-                            t.slots = track.slots.clone();
-                            Some(t)
-                        },
-                        Err(e) => {
-                            println!("Failed to create track: {}", e);
-                            None
-                        }
-                    }
-                } else {
-                    println!("Failed to create sink");
-                    None
-                }
-            ).collect(),
-            choke_grps: saved_pattern.choke_grps.clone(),
-            division: saved_pattern.division,
-            name: pattern.name.clone(),
-        };
-        if self.playing {
-            // just so we send a midi start message out
-            self.enable_play();
-        }
-        Ok(())
+/// Ports, devices and midi maps there are right now
+fn list_files(file_type: &FileType) -> Vec<String> {
+    match file_type {
+        FileType::MidiPort => MidiOutputs::list_ports().unwrap_or_else(|e| {
+            println!("Failed to list midi ports: {}", e);
+            vec![]
+        }),
+        FileType::MidiInputPort => MidiInputs::list_ports().unwrap_or_else(|e| {
+            println!("Failed to list midi input ports: {}", e);
+            vec![]
+        }),
+        FileType::MidiMap => midi_map::list_midi_maps().unwrap_or_else(|e| {
+            println!("Failed to list midi maps: {}", e);
+            vec![]
+        }),
+        FileType::AudioDevice => audio::list_devices().unwrap_or_else(|e| {
+            println!("Failed to list audio devices: {}", e);
+            vec![]
+        }),
+        _ => vec![],
     }
+}
 
-    pub fn refresh_saved_patterns(&mut self) -> Result<(), Box<dyn Error>> {
-        let patterns = std::fs::read_dir(format!("{PWD}/patterns"))?;
-        let patterns = patterns.filter_map(|entry| {
-            if let Ok(entry) = entry {
-                if let Some(path) = entry.path().to_str() {
-                    // Only return file name
-                    Some(path.split('/').last().unwrap().to_string())
-                } else {
-                    None
-                }
+/// File names in the patterns folder
+fn list_saved_patterns() -> Result<Vec<String>, Box<dyn Error>> {
+    let patterns = std::fs::read_dir(format!("{PWD}/patterns"))?;
+    let patterns = patterns.filter_map(|entry| {
+        if let Ok(entry) = entry {
+            if let Some(path) = entry.path().to_str() {
+                // Only return file name
+                Some(path.split('/').last().unwrap().to_string())
             } else {
                 None
             }
-        }).collect();
-        self.saved_patterns = patterns;
-        self.send_file_state(FileType::Pattern);
-        Ok(())
-    }
+        } else {
+            None
+        }
+    }).collect();
+    Ok(patterns)
+}
 
-    // Iterates through samples folder, including subfolders in the path to better
-    // help organize the files into kits.
-    pub fn refresh_sample_files(&mut self) -> Result<(), Box<dyn Error>> {
-        let samples = std::fs::read_dir(format!("{PWD}/samples"))?;
-        let samples = samples.filter_map(|entry| {
-            if let Ok(entry) = entry {
-                // If it's a directory, we need to iterate through it
-                // and get a vector of paths like subfolder/file.wav
-                if entry.path().is_dir() {
-                    let subfolder = entry.path().to_str().unwrap().split('/').last().unwrap().to_string();
-                    if let Ok(files) = std::fs::read_dir(entry.path()) {
-                        let files = files.filter_map(|entry| {
-                            if let Ok(entry) = entry {
-                                if let Some(path) = entry.path().to_str() {
-                                    if entry.path().is_file() {
-                                        Some(format!("{}/{}", subfolder, path.split('/').last().unwrap()))
-                                    } else {
-                                        None
-                                    }
+/// Samples in the samples folder, including subfolders in the path to better
+/// help organize the files into kits
+fn list_sample_files() -> Result<Vec<String>, Box<dyn Error>> {
+    let samples = std::fs::read_dir(format!("{PWD}/samples"))?;
+    let samples = samples.filter_map(|entry| {
+        if let Ok(entry) = entry {
+            // If it's a directory, we need to iterate through it
+            // and get a vector of paths like subfolder/file.wav
+            if entry.path().is_dir() {
+                let subfolder = entry.path().to_str().unwrap().split('/').last().unwrap().to_string();
+                if let Ok(files) = std::fs::read_dir(entry.path()) {
+                    let files = files.filter_map(|entry| {
+                        if let Ok(entry) = entry {
+                            if let Some(path) = entry.path().to_str() {
+                                if entry.path().is_file() {
+                                    Some(format!("{}/{}", subfolder, path.split('/').last().unwrap()))
                                 } else {
                                     None
                                 }
                             } else {
                                 None
                             }
-                        }).collect::<Vec<String>>();
-                        Some(files)
-                    } else {
-                        None
-                    }
-                // It's just a file
-                } else {
-                    if let Some(path) = entry.path().to_str() {
-                        if entry.path().is_file() {
-                            Some(vec![path.to_string().split('/').last().unwrap().to_string()])
                         } else {
                             None
                         }
+                    }).collect::<Vec<String>>();
+                    Some(files)
+                } else {
+                    None
+                }
+            // It's just a file
+            } else {
+                if let Some(path) = entry.path().to_str() {
+                    if entry.path().is_file() {
+                        Some(vec![path.to_string().split('/').last().unwrap().to_string()])
                     } else {
                         None
                     }
+                } else {
+                    None
                 }
-            } else {
-                None
             }
+        } else {
+            None
+        }
+    })
+    .flatten()
+    .filter(|path: &String| {
+        let ext = path.rsplit('.').next().unwrap_or_default().to_lowercase();
+        path.contains('.') && SAMPLE_EXTENSIONS.contains(&ext.as_str())
+    })
+    .collect();
+    Ok(samples)
+}
+
+/// Samples just written to the samples folder, decoded off the lock along
+/// with a fresh listing of the folder
+struct NewSamples {
+    /// None if nothing was written
+    sample_files: Option<Result<Vec<String>, String>>,
+    decoded: Vec<(String, Option<SystemTime>, Arc<BufferedSample>)>,
+}
+
+impl NewSamples {
+    /// Failures are left for the track the sample goes on to report
+    fn load(ctx_handle: &ContextHandle, sample_paths: &[String]) -> Self {
+        let sample_files = (!sample_paths.is_empty()).then(|| list_sample_files().map_err(|e| e.to_string()));
+        let sample_rate = ctx_handle.with_lock(|ctx| ctx.samples.sample_rate());
+        let decoded = sample_paths.iter().filter_map(|sample_path| {
+            let modified = sample_cache::modified(sample_path);
+            let sample = BufferedSample::new(sample_path, sample_rate).ok()?;
+            Some((sample_path.clone(), modified, sample))
+        }).collect();
+        NewSamples { sample_files, decoded }
+    }
+}
+
+/// Decodes the samples again on the calling thread and swaps them in
+/// under the lock, so the command loop doesn't wait on the disk
+fn reload_samples_from_disk(ctx_handle: &ContextHandle, sample_paths: Vec<String>) {
+    let sample_rate = ctx_handle.with_lock(|ctx| ctx.samples.sample_rate());
+    let reloaded: Vec<_> = sample_paths.into_iter().map(|sample_path| {
+        let modified = sample_cache::modified(&sample_path);
+        let res = BufferedSample::new(&sample_path, sample_rate).map_err(|e| e.to_string());
+        (sample_path, modified, res)
+    }).collect();
+    ctx_handle.with_lock(|ctx| {
+        for (sample_path, modified, res) in reloaded {
+            ctx.swap_sample(sample_path, modified, res);
+        }
+    });
+}
+
+/// The samples a command is about to load
+fn samples_to_load(cmd: &Command) -> Vec<String> {
+    match cmd {
+        Command::SetTrackSample(_, sample_path)
+        | Command::SetTrackLayer(_, _, sample_path)
+        | Command::SetMetronomeSample(sample_path)
+        | Command::PreviewSample(sample_path, _) => vec![sample_path.clone()],
+        Command::LoadDemo(style) => Demo::new(*style).patterns.iter()
+            .flat_map(|(_, saved, _)| saved.tracks.iter())
+            .map(|track| track.sample_path.clone())
+            .collect(),
+        _ => vec![],
+    }.into_iter().filter(|sample_path| !sample_path.is_empty()).collect()
+}
+
+/// What a batch of commands needs off the disk, read before the lock is
+/// taken and held until the commands are done with it
+struct Prefetched {
+    /// held so a trim in between doesn't let go of them
+    _samples: Vec<Arc<BufferedSample>>,
+    /// midi maps to load by name, or why they couldn't be read
+    midi_maps: BTreeMap<String, Result<MidiMap, String>>,
+}
+
+impl Prefetched {
+    fn midi_map(&self, name: &str) -> Result<MidiMap, String> {
+        self.midi_maps.get(name).cloned().unwrap_or_else(|| Err(format!("{} wasn't read", name)))
+    }
+}
+
+/// Decodes the samples the commands are about to load and reads the
+/// grooves and midi maps they need before they're handled, so the lock isn't held while
+/// they come off the disk. A kit can't be picked from until the samples
+/// folder has been listed, so that's done too if it hasn't been yet
+///
+/// Failures are left for the command to report
+fn prefetch(ctx_handle: &ContextHandle, cmds: &VecDeque<Command>) -> Prefetched {
+    if cmds.iter().any(|cmd| matches!(cmd, Command::RandomizeKit))
+        && ctx_handle.with_lock(|ctx| ctx.sample_files.is_empty())
+    {
+        match list_sample_files() {
+            Ok(sample_files) => ctx_handle.with_lock(|ctx| ctx.set_sample_files(sample_files)),
+            Err(e) => println!("Failed to list samples: {}", e),
+        }
+    }
+    read_grooves(ctx_handle, cmds.iter().filter_map(|cmd| match cmd {
+        Command::SetGroove(name, _) => Some(name.clone()),
+        _ => None,
+    }).collect());
+    let midi_maps = cmds.iter()
+        .filter_map(|cmd| match cmd {
+            Command::LoadMidiMap(name) => Some((name.clone(), MidiMap::load(name).map_err(|e| e.to_string()))),
+            _ => None,
         })
-        .flatten()
         .collect();
+    Prefetched {
+        _samples: decode_samples(ctx_handle, cmds.iter().flat_map(samples_to_load).collect()),
+        midi_maps,
+    }
+}
 
-        self.sample_files = samples;
-        self.send_file_state(FileType::Sample);
+/// Decodes the samples the cache doesn't have yet off the lock and puts
+/// them in it, handing them back to be held until they're used
+fn decode_samples(ctx_handle: &ContextHandle, mut sample_paths: Vec<String>) -> Vec<Arc<BufferedSample>> {
+    if sample_paths.is_empty() {
+        return vec![];
+    }
+    sample_paths.sort();
+    sample_paths.dedup();
+    let sample_rate = ctx_handle.with_lock(|ctx| {
+        sample_paths.retain(|sample_path| !ctx.samples.contains(sample_path));
+        ctx.samples.sample_rate()
+    });
+    let decoded: Vec<_> = sample_paths.into_iter().filter_map(|sample_path| {
+        let modified = sample_cache::modified(&sample_path);
+        let sample = BufferedSample::new(&sample_path, sample_rate).ok()?;
+        Some((sample_path, modified, sample))
+    }).collect();
+    ctx_handle.with_lock(|ctx| {
+        decoded.into_iter().map(|(sample_path, modified, sample)| {
+            ctx.samples.insert(sample_path, sample.clone(), modified);
+            sample
+        }).collect()
+    })
+}
 
-        Ok(())
+/// Reads the groove templates off the lock and keeps them for the patterns
+/// that use them, again each time in case the file changed
+fn read_grooves(ctx_handle: &ContextHandle, mut names: Vec<String>) {
+    if names.is_empty() {
+        return;
     }
+    names.sort();
+    names.dedup();
+    let grooves: Vec<_> = names.into_iter().filter_map(|name| match Groove::load(&name) {
+        Ok(groove) => Some((name, groove)),
+        Err(e) => {
+            println!("Failed to load groove {}: {}", name, e);
+            None
+        }
+    }).collect();
+    ctx_handle.with_lock(|ctx| ctx.grooves.extend(grooves));
+}
 
-    /// Sends special state update for files only
-    /// This can be triggered if changes occurred in the file system
-    /// Also yes, yes the other state tx is in sequencer and I'm beginning
-    /// to think we should just lock the whole sequencer and forget
-    /// about the context
-    pub fn send_file_state(&self, file_type: FileType) {
-        for tx in &self.state_tx_ch {
-            let _ = tx.send(StateUpdate::FileState(FileState {
-                file_type: file_type.clone(),
-                files: match file_type {
-                    FileType::Pattern => self.saved_patterns.clone(),
-                    FileType::Sample => self.sample_files.clone(),
-                },
-            }));
+/// Reads a snapshot and decodes its samples off the lock, then swaps the
+/// project over to it under the lock
+fn restore_snapshot(ctx_handle: &ContextHandle, id: usize, fname: &str) {
+    let snapshot = match history::read_snapshot(fname) {
+        Ok(snapshot) => snapshot,
+        Err(e) => {
+            println!("Failed to restore snapshot: {}", e);
+            return;
+        }
+    };
+    let hash = match snapshot.state.content_hash() {
+        Ok(hash) => hash,
+        Err(e) => {
+            println!("Failed to restore snapshot: {}", e);
+            return;
+        }
+    };
+    let sample_paths: Vec<String> = snapshot.state.patterns.iter()
+        .flat_map(|p| p.pattern.tracks.iter())
+        .flat_map(|track| std::iter::once(&track.sample_path).chain(track.layers.iter().map(|layer| &layer.sample_path)))
+        .cloned()
+        .collect();
+    let _decoded = decode_samples(ctx_handle, sample_paths);
+    read_grooves(ctx_handle, snapshot.state.patterns.iter().filter_map(|p| p.pattern.groove.as_ref().map(|groove| groove.name.clone())).collect());
+    ctx_handle.with_lock(|ctx| match ctx.apply_snapshot(id, hash, snapshot.state) {
+        Ok(()) => ctx.send_file_state(FileType::History),
+        Err(e) => println!("Failed to restore snapshot: {}", e),
+    });
+}
+
+/// Saves the gain with the sample and puts it on the copy in use, a
+/// sample that isn't loaded picks it up when it is
+fn set_sample_gain(ctx_handle: &ContextHandle, sample_path: String, loaded: Option<Arc<BufferedSample>>, gain: SampleGain) {
+    if let Err(e) = sample_gain::save(&sample_path, gain) {
+        println!("Failed to set sample gain: {}", e);
+        return;
+    }
+    if let Some(sample) = loaded {
+        let sample = Arc::new((*sample).clone().with_gain(gain));
+        let modified = sample_cache::modified(&sample_path);
+        ctx_handle.with_lock(|ctx| ctx.swap_sample(sample_path, modified, Ok(sample)));
+    }
+}
+
+/// Opens the input for a capture off the lock. One stopped, started again
+/// or left without its track by the time it's open is stopped straight away
+fn start_capture(ctx_handle: &ContextHandle, capture_id: u64, fname: &str, threshold_db: i8, overwrite: bool, track_id: usize) {
+    let done_handle = ctx_handle.clone();
+    let capture = SampleCapture::start(fname, threshold_db, overwrite, track_id, move |res| {
+        let new_samples = NewSamples::load(&done_handle, res.as_ref().map(std::slice::from_ref).unwrap_or_default());
+        done_handle.with_lock(|ctx| {
+            ctx.add_new_samples(new_samples);
+            ctx.finish_capture(res, capture_id);
+        })
+    });
+    ctx_handle.with_lock(|ctx| match capture {
+        Ok(mut capture) => match ctx.capture_targets.get(&capture_id) {
+            Some((_, track_id)) if ctx.next_capture == capture_id + 1 => {
+                capture.track_id = *track_id;
+                ctx.sample_capture = Some(capture);
+            },
+            _ => capture.stop(),
+        },
+        Err(e) => {
+            println!("Failed to capture sample: {}", e);
+            ctx.capture_targets.remove(&capture_id);
+        },
+    });
+}
+
+/// Creates the file for a master recording off the lock, one stopped or
+/// started again by the time it's open is stopped straight away
+fn start_recording(ctx_handle: &ContextHandle, stream: &AudioHandle, recording_id: u64, fname: &str) {
+    let res = stream.start_recording(fname);
+    ctx_handle.with_lock(|ctx| match res {
+        Ok(recording) if ctx.next_recording == recording_id => ctx.master_recording = Some(recording),
+        Ok(recording) => recording.stop(),
+        Err(e) => println!("Failed to start recording: {}", e),
+    });
+}
+
+/// Writes what the commands left to be saved once the lock was let go of,
+/// the lists of files are refreshed after
+fn write_files(ctx_handle: &ContextHandle, writes: Vec<DiskWrite>) {
+    for write in writes {
+        match write {
+            DiskWrite::Pattern(fname, saved_pattern) => {
+                let res = save_pattern_file(&fname, &saved_pattern).and_then(|_| list_saved_patterns());
+                match res {
+                    Ok(saved_patterns) => ctx_handle.with_lock(|ctx| ctx.set_saved_patterns(saved_patterns)),
+                    Err(e) => println!("Failed to save pattern: {}", e),
+                }
+            },
+            DiskWrite::MidiMap(name, midi_map) => match midi_map.save(&name) {
+                Ok(()) => ctx_handle.with_lock(|ctx| ctx.send_file_state(FileType::MidiMap)),
+                Err(e) => println!("Failed to save midi map: {}", e),
+            },
+        }
+    }
+}
+
+/// Hashes and writes a snapshot off the lock, only the history's own
+/// bookkeeping is done under it
+fn take_snapshot(ctx_handle: &ContextHandle, state: SnapshotState) {
+    let hash = match state.content_hash() {
+        Ok(hash) => hash,
+        Err(e) => {
+            println!("Failed to take snapshot: {}", e);
+            return;
         }
+    };
+    let write = ctx_handle.with_lock(|ctx| ctx.history.take(state, hash));
+    if let Err(e) = write.and_then(|write| write.map_or(Ok(()), |write| write.save())) {
+        println!("Failed to take snapshot: {}", e);
     }
 }
 
+/// Builds the state after each pulse on a thread of its own, once for
+/// every controller, so the pulse thread only hands over where it's at
+fn publish_state(ctx_handle: ContextHandle, pulses: mpsc::Receiver<(TransportPosition, Duration)>) {
+    thread::spawn(move || {
+        while let Ok((position, latency)) = pulses.recv() {
+            let (state, state_tx_ch) = ctx_handle.with_lock(|ctx| (ctx.seq_state(position, latency), ctx.state_tx_ch.clone()));
+            for tx in &state_tx_ch {
+                let _ = tx.send(StateUpdate::SeqState(Box::new(state.clone())));
+            }
+        }
+    });
+}
+
+/// Checks the files of the samples in use every SAMPLE_WATCH_INTERVAL and
+/// reloads the ones that changed, so a hit being worked on in an editor is
+/// heard as soon as it's saved
+fn watch_samples(ctx_handle: ContextHandle) {
+    thread::spawn(move || loop {
+        thread::sleep(SAMPLE_WATCH_INTERVAL);
+        let in_use = ctx_handle.with_lock(|ctx| ctx.samples.in_use());
+        let changed: Vec<String> = in_use.into_iter()
+            .filter(|(sample_path, loaded)| {
+                let modified = sample_cache::modified(sample_path);
+                modified != *loaded && modified.is_some_and(|modified| {
+                    modified.elapsed().is_ok_and(|age| age >= SAMPLE_SETTLE)
+                })
+            })
+            .map(|(sample_path, _)| sample_path)
+            .collect();
+        if !changed.is_empty() {
+            reload_samples_from_disk(&ctx_handle, changed);
+        }
+    });
+}
+
 /// Struct wrapping sequencer Context allowing us to modify it
 /// without taking ownership of it
 /// 
@@ -674,15 +4649,28 @@ impl Context {
 /// the solution of prop handlers is used here to solve them
 /// The wrapper will take care of mutex locks and allows many threads to safely access the struct
 /// without violating ownership principles (An Arc smart pointer is used)
+///
+/// Commands are received outside the lock, so the command loop can wait
+/// on them without holding up the pulse thread
+///
+/// The pulse thread goes first, while it waits for the lock nothing else
+/// takes it and the command loop lets go after the command it's on
 #[derive(Clone)]
 pub struct ContextHandle {
-    inner: Arc<Mutex<Context>>
+    inner: Arc<Mutex<Context>>,
+    pulse_waiting: Arc<AtomicBool>,
+    /// for midi inputs opened from the command loop to send on
+    command_tx: mpsc::Sender<Command>,
+    command_rx: Arc<Mutex<mpsc::Receiver<Command>>>,
 }
 
 impl ContextHandle {
-    pub fn new(ctx: Context) -> Self {
+    pub fn new(ctx: Context, command_tx: mpsc::Sender<Command>, command_rx: mpsc::Receiver<Command>) -> Self {
         Self {
-            inner: Arc::new(Mutex::new(ctx))
+            inner: Arc::new(Mutex::new(ctx)),
+            pulse_waiting: Arc::new(AtomicBool::new(false)),
+            command_tx,
+            command_rx: Arc::new(Mutex::new(command_rx)),
         }
     }
 
@@ -690,15 +4678,84 @@ impl ContextHandle {
     where
         F: FnOnce(&mut Context) -> T,
     {
+        while self.pulse_waiting() {
+            thread::yield_now();
+        }
         let mut lock = self.inner.lock().unwrap();
         let result = func(&mut *lock);
         drop(lock);
         result
     }
 
+    /// Takes the lock for the pulse thread, ahead of anything else waiting
+    fn with_pulse_lock<F, T>(&self, func: F) -> T
+    where
+        F: FnOnce(&mut Context) -> T,
+    {
+        self.pulse_waiting.store(true, Ordering::Relaxed);
+        let mut lock = self.inner.lock().unwrap();
+        self.pulse_waiting.store(false, Ordering::Relaxed);
+        let result = func(&mut lock);
+        drop(lock);
+        result
+    }
+
+    fn pulse_waiting(&self) -> bool {
+        self.pulse_waiting.load(Ordering::Relaxed)
+    }
+
+    /// Handles what comes in on a midi input, transport and notes go
+    /// through the command queue like a controller's would
+    fn midi_input_handler(&self) -> impl FnMut(u64, &[u8], &mut ()) + Send + 'static {
+        let ctx = self.clone();
+        let command_tx = self.command_tx.clone();
+        move |_, message, _| {
+            ctx.with_lock(|ctx| {
+                let following = ctx.clock_in.source == ClockSource::Midi;
+                match message.first() {
+                    Some(0xF8) => {
+                        if let Some(bpm) = ctx.clock_in.tick().filter(|_| following) {
+                            // rounded so jitter doesn't keep the tempo ramping
+                            ctx.slew_tempo((bpm * 10.0).round() / 10.0);
+                        }
+                    },
+                    Some(0xFA) => {
+                        ctx.clock_in.start();
+                        if following {
+                            let _ = command_tx.send(Command::PlaySequencer);
+                        }
+                    },
+                    Some(0xFB) if following => { let _ = command_tx.send(Command::ContinueSequencer); },
+                    Some(0xFC) if following => { let _ = command_tx.send(Command::PauseSequencer); },
+                    _ => {
+                        // bound notes and ccs only run their command
+                        if let Some((trigger, pressed)) = ctx.midi_learn.press(message) {
+                            if let Some(command) = ctx.midi_learn.armed.take_if(|_| pressed) {
+                                let _ = command_tx.send(Command::BindMidi(trigger, Some(Box::new(command))));
+                                return;
+                            }
+                            if let Some(command) = ctx.project.midi_map.get(&trigger) {
+                                if pressed {
+                                    let _ = command_tx.send(command.clone());
+                                }
+                                return;
+                            }
+                        }
+                        if let Some(trk_id) = ctx.note_input_track(message) {
+                            let _ = command_tx.send(Command::PlaySound(trk_id, message[2]));
+                        }
+                        if let Some(message) = ctx.project.midi_thru.remap(message) {
+                            ctx.midi_out.send(MidiKind::Note, &message);
+                        }
+                    },
+                }
+            });
+        }
+    }
+
     // we should put these methods on the ctx struct and just wrap for handler maybe?
     // so redundant though....
-    pub fn set_tempo(&self, t: u8) {
+    pub fn set_tempo(&self, t: f32) {
         self.with_lock(|ctx| {
             ctx.set_tempo(t)
         })
@@ -780,18 +4837,39 @@ pub struct Sequencer {
     /// Average of current and last cycle time
     latency: Duration,
     /// the actual sleep time, which may differ from pulse interval
-    /// if, for example, processing latency is high
+    /// if, for example, processing latency is high. Only used while stopped
+    /// or when the stream isn't moving, playback goes by the stream's clock
     sleep_interval: Duration,
+    /// frame of the stream the next pulse is due at, fractional so the
+    /// pulses don't drift from the tempo. None until playback starts
+    next_pulse: Option<f64>,
     // pulses per bar, always gonna be 24*4 for midi clock purposes
     ppb: u8,
     pulse_idx: u8,
+    /// position of the last pulse played
+    position: TransportPosition,
+    /// when the last pulse was played, for the elapsed time
+    last_pulse: Option<Instant>,
     /// Command receiver channel
     /// 
     /// Multi producer single consumer means we can
     /// have multiple controllers (producers) on the sequencer (consumer) at once
     command_tx_ch: mpsc::Sender<Command>,
     sleeper: spin_sleep::SpinSleeper,
-    pub midi_conn: Option<Arc<MidiOutputConnection>>,
+    /// note offs for the notes sent on the last pulse
+    note_offs: Vec<[u8; 3]>,
+    /// midi worked out with a pulse, held back until the stream gets to
+    /// the frame the pulse's audio is due at so the two line up. Midi sent
+    /// off the pulse goes in behind it to keep the order
+    midi_queue: VecDeque<(f64, MidiKind, Vec<u8>)>,
+    /// where the last pulse left the transport, for the state to be built
+    /// and sent off the pulse thread
+    state_pulse_tx: mpsc::SyncSender<(TransportPosition, Duration)>,
+    /// Kept so the input stays open, midir closes it on drop
+    _midi_in_conns: Vec<MidiInputConnection<()>>,
+    audio: AudioOutput,
+    /// set while the output is lost and we're trying to get it back
+    reacquire: Option<Reacquire>,
     // We need this since the midi library is not thread-safe. If we keep track
     // of when the play is toggled we can send the midi on the main thread
     // instead of the command thread via the context handler
@@ -801,41 +4879,112 @@ pub struct Sequencer {
 // Maybe tracks should have independent lengths?
 impl Sequencer {
     /// Creates a new sequencer instance
-    pub fn new(stream: Arc<OutputStreamHandle>) -> Sequencer {
+    pub fn new(audio: AudioOutput) -> Sequencer {
         let (command_tx, command_rx) = mpsc::channel();
-        let s = Sequencer {
+        let (state_pulse_tx, state_pulse_rx) = mpsc::sync_channel(1);
+        let stream = audio.handle.clone();
+        let mut s = Sequencer {
             ctx: ContextHandle::new(Context {
                 patterns: vec![Pattern {
                     tracks: vec![],
                     choke_grps: vec![],
                     name: "Pattern 1".to_string(),
                     division: Division::E,
+                    humanize: Humanize::default(),
+                    groove: None,
+                    cc_lanes: vec![],
+                    fill: None,
+                    notes: String::new(),
+                    swing_grid: SwingGrid::Division,
                 }],
                 pattern_id: 0,
                 queued_pattern_id: 0,
+                pattern_bar: 0,
+                bars_left: None,
+                pattern_queue: VecDeque::new(),
+                song: Song::default(),
+                swap_policy: SwapPolicy::Crossfade,
+                anti_click: true,
+                anti_click_len: Duration::from_millis(2),
+                choke_fade: Duration::from_millis(DEFAULT_CHOKE_FADE_MS as u64),
+                metronome: Metronome::default(),
+                samples: SampleCache::new(audio.sample_rate),
+                budget: PulseBudget::default(),
+                jitter: PulseJitter::default(),
+                preload: None,
+                pending_load: None,
+                project: Project::default(),
+                history: History::default(),
+                midi_out: MidiOutputs::default(),
+                midi_in: MidiInputs::default(),
+                midi_learn: MidiLearn::default(),
+                clock_in: ClockFollower::default(),
+                audio_error: None,
+                audio_device: audio.device_name.clone(),
+                stream_request: audio.request,
+                buffer_frames: audio.buffer_frames,
+                sample_rate: audio.sample_rate,
+                output_latency: audio.latency(),
+                audio_device_request: None,
+                recording: false,
+                record_mode: RecordMode::Overdub,
+                pending_division: None,
+                pending_swing_grid: None,
+                fill_held: false,
+                fill_pending: false,
+                fill_return: None,
+                deck_b: None,
+                crossfade: 0.0,
+                pending_cmds: VecDeque::new(),
+                project_changed: false,
+                disk_writes: vec![],
+                persist: true,
+                accent_velocity: 32,
                 saved_patterns: vec![],
+                similar_patterns: vec![],
                 sample_files: vec![],
+                groove_files: vec![],
+                grooves: BTreeMap::new(),
+                last_export: None,
+                master_recording: None,
+                sample_capture: None,
+                capture_targets: BTreeMap::new(),
+                next_capture: 0,
+                next_recording: 0,
+                audition: None,
                 default_len: 8,
                 swing: Swing::Off,
                 swing_offset: 0,
-                tempo: 120,
+                tempo: 120.0,
+                tempo_slew: Duration::ZERO,
+                tempo_ramp: None,
                 // corresponds to 120 bpm
                 pulse_interval: Duration::from_secs_f32(2.5/120.0),
                 playing: false,
-                command_rx_ch: command_rx,
+                paused: false,
+                resuming: false,
+                rewind: false,
                 last_cmd: Command::Unspecified,
                 stream,
                 state_tx_ch: vec![]
-            }),
+            }, command_tx.clone(), command_rx),
             latency: Duration::ZERO,
             sleep_interval: Duration::from_secs_f32(1.0/24.0),
+            next_pulse: None,
             // pulses per bar, 24 per quarter note
             // afaik this is the rate to send midi clock signals
-            ppb: 24*4,
+            ppb: PPB,
             pulse_idx: 0,
+            position: TransportPosition::default(),
+            last_pulse: None,
             command_tx_ch: command_tx,
             sleeper: spin_sleep::SpinSleeper::new(1_012_550_000).with_spin_strategy(spin_sleep::SpinStrategy::SpinLoopHint),
-            midi_conn: None,
+            note_offs: vec![],
+            midi_queue: VecDeque::new(),
+            state_pulse_tx,
+            _midi_in_conns: vec![],
+            audio,
+            reacquire: None,
             last_play_status: false,
         };
         s.ctx.with_lock(|ctx| {
@@ -845,12 +4994,49 @@ impl Sequencer {
             if let Err(e) = ctx.refresh_sample_files() {
                 println!("Failed to refresh sample files: {}", e);
             }
+            if let Err(e) = ctx.refresh_groove_files() {
+                println!("Failed to refresh groove files: {}", e);
+            }
+            match Project::load() {
+                Ok(project) => ctx.project = project,
+                Err(e) => println!("Failed to load project: {}", e),
+            }
+            ctx.clock_in.source = ctx.project.clock_source;
+            ctx.samples.set_cap(ctx.project.sample_cache_mb);
+            ctx.apply_sync_out();
+            ctx.apply_delay();
+            ctx.stream.set_reverb(ctx.project.reverb);
+            ctx.apply_master_volume();
+            let audio_device = ctx.project.audio_device.clone().unwrap_or(ctx.audio_device.clone());
+            if audio_device != ctx.audio_device || ctx.stream_request() != ctx.stream_request {
+                ctx.stream_request = ctx.stream_request();
+                ctx.audio_device_request = Some(audio_device);
+            }
+            for destination in ctx.project.midi_outputs.clone() {
+                ctx.midi_out.add(destination);
+            }
+            if ctx.project.virtual_ports && !ctx.midi_out.has_virtual() {
+                ctx.midi_out.add(MidiDestination::virtual_port());
+            }
+            for port_name in ctx.project.midi_inputs.clone() {
+                ctx.midi_in.add(port_name, s.ctx.midi_input_handler());
+            }
+            match History::load() {
+                Ok(history) => ctx.history = history,
+                Err(e) => println!("Failed to load history: {}", e),
+            }
         });
+        publish_state(s.ctx.clone(), state_pulse_rx);
+        if s.ctx.with_lock(|ctx| ctx.project.virtual_ports) {
+            if let Err(e) = s.open_virtual_input() {
+                println!("Failed to open virtual midi input: {}", e);
+            }
+        }
         s
     }
 
     /// Sets tempo via ctx handle
-    pub fn set_tempo(&mut self, bpm: u8) {
+    pub fn set_tempo(&mut self, bpm: f32) {
         self.ctx.set_tempo(bpm);
     }
 
@@ -864,15 +5050,39 @@ impl Sequencer {
 
     // Starts an active midi connection to the specified port
     /// 
-    /// I've not yet quite figured out how to share MidiOutput so I'm just
-    /// persisting the connection, which should accomplish what we need
+    /// It gets clock and transport like a destination added by command, but
+    /// isn't saved with the project
     pub fn connect_midi(&mut self, port: MidiOutputPort) -> Result<(), Box<dyn Error>> {
-        let midi_output = MidiOutput::new("Sequencer")?;
-        let conn = midi_output.connect(&port, "Sequencer")?;
-        self.midi_conn = Some(Arc::new(conn));
+        let port_name = MidiOutput::new("Sequencer")?.port_name(&port)?;
+        self.ctx.with_lock(|ctx| {
+            ctx.midi_out.add(MidiDestination::new(port_name));
+        });
+        Ok(())
+    }
+
+    /// Listens on a midi input, messages are echoed to the output when thru is on
+    pub fn connect_midi_input(&mut self, port: MidiInputPort) -> Result<(), Box<dyn Error>> {
+        let midi_input = MidiInput::new("Sequencer")?;
+        let conn = midi_input.connect(&port, "Sequencer", self.ctx.midi_input_handler(), ())?;
+        self._midi_in_conns.push(conn);
+        Ok(())
+    }
+
+    /// Opens a virtual input other apps on the machine can send to
+    #[cfg(unix)]
+    fn open_virtual_input(&mut self) -> Result<(), Box<dyn Error>> {
+        use midir::os::unix::VirtualInput;
+        let midi_input = MidiInput::new(VIRTUAL_PORT_NAME)?;
+        let conn = midi_input.create_virtual(VIRTUAL_PORT_NAME, self.ctx.midi_input_handler(), ())?;
+        self._midi_in_conns.push(conn);
         Ok(())
     }
 
+    #[cfg(not(unix))]
+    fn open_virtual_input(&mut self) -> Result<(), Box<dyn Error>> {
+        Err("virtual midi ports aren't supported on this platform".into())
+    }
+
     /// Adds an empty track to the sequencer at the current pattern
     /// 
     /// Because trks are wrapped in a property handler to ensure thread safety, we can't directly return access
@@ -882,122 +5092,273 @@ impl Sequencer {
     /// playhead position of the track's slots.
     pub fn add_track(&mut self, sample_path: String) -> Result<TrackHandle, Box<dyn Error>> {
         self.ctx.with_lock(|ctx| {
-            ctx.patterns[ctx.pattern_id].add_track(ctx.stream.clone(), ctx.default_len, sample_path)?;
+            ctx.patterns[ctx.pattern_id].add_track(ctx.default_len, sample_path, &mut ctx.samples)?;
             Ok(TrackHandle::new(self.ctx.clone(), ctx.patterns[ctx.pattern_id].tracks.len() as u8 - 1))
         })
     }
 
-    /// Helper function that plays a sample on the playback stream sink
-    /// 
-    /// We circumvent the rodio sink queueing, only instant plays! It's a little clunky perhaps to repeatedly clone
-    /// the Arc pointer but optimization is a later thing
-    fn append_sample_to_sink(snk: Arc<Sink>, samp: Arc<BufferedSample>, vel: &mut u8) {
-        snk.append((*samp).clone().amplify(*vel as f32 / 127.0));
-        if snk.len() > 1 {
-            snk.skip_one();
+    /// Sends as soon as the midi already held back has gone
+    fn send_midi(&mut self, kind: MidiKind, message: &[u8]) {
+        self.queue_midi(self.audio.handle.stream_position(), kind, message);
+    }
+
+    /// Holds the message back until the stream is on the frame
+    fn queue_midi(&mut self, frame: f64, kind: MidiKind, message: &[u8]) {
+        self.midi_queue.push_back((frame, kind, message.to_vec()));
+    }
+
+    /// Sends the midi held back for frames up to the one given, in order
+    fn flush_midi(&mut self, until: f64) {
+        let due = self.midi_queue.iter().take_while(|(frame, _, _)| *frame <= until).count();
+        if due == 0 {
+            return;
         }
+        self.ctx.with_pulse_lock(|ctx| {
+            for (_, kind, message) in self.midi_queue.drain(..due) {
+                ctx.midi_out.send(kind, &message);
+            }
+        });
     }
 
-    fn send_midi_byte(&mut self, byte: u8) {
-        if let Some(midi_conn) = &mut self.midi_conn {
-            let conn = Arc::<MidiOutputConnection>::get_mut(midi_conn).unwrap();
-            conn.send(&[byte]).unwrap();
-        }
+    /// Time until the next midi held back is due, if there's any
+    fn midi_wait(&self) -> Option<Duration> {
+        let (frame, _, _) = self.midi_queue.front()?;
+        let frames = (frame - self.audio.handle.stream_position()).max(0.0);
+        Some(Duration::from_secs_f64(frames / self.audio.sample_rate as f64))
     }
 
     /// The VIP function. Plays tracks, sends state, sends midi, updates latency
     pub fn play_next(&mut self) {
-        let playing = self.ctx.with_lock(|ctx| { ctx.playing });
+        self.watch_audio();
+        self.flush_midi(self.audio.handle.stream_position());
+        let (playing, paused, counting_in, resuming) = self.ctx.with_pulse_lock(|ctx| {
+            if ctx.rewind {
+                ctx.rewind = false;
+                self.pulse_idx = 0;
+                self.position = TransportPosition::default();
+                ctx.reset_playheads();
+                ctx.restart_deck_b();
+            }
+            (ctx.playing, ctx.paused, ctx.metronome.counting_in(), std::mem::take(&mut ctx.resuming))
+        });
 
-        // Send midi start/stop signals if play is toggled
-        if playing != self.last_play_status {
-            if playing {
+        // Send midi start/stop signals if play is toggled, a count in
+        // holds off the start until the pattern actually plays
+        let running = playing && !counting_in;
+        if running != self.last_play_status {
+            if running {
+                if !resuming {
+                    self.ctx.with_pulse_lock(|ctx| ctx.clock_in.restart_pulses());
+                }
                 self.last_play_status = true;
-                self.send_midi_byte(0xFA);
+                self.send_midi(MidiKind::Transport, &[if resuming { 0xFB } else { 0xFA }]);
             } else {
                 self.last_play_status = false;
-                self.send_midi_byte(0xFC);
+                self.send_midi(MidiKind::Transport, &[0xFC]);
             }
         }
 
         if playing {
             let start = Instant::now();
+            let sample_rate = self.audio.sample_rate as f64;
+            let pulled = self.audio.handle.frames_pulled() as f64;
+            let due = self.pulse_due();
+            // Against when it was due to be scheduled, there's nothing to be
+            // late against when playback is starting
+            let late_us = self.next_pulse.map(|next_pulse| {
+                let ahead = self.schedule_ahead() as f64;
+                ((self.audio.handle.stream_position() + ahead - next_pulse) / sample_rate * 1_000_000.0) as i64
+            });
+            let missed = self.next_pulse.is_some_and(|next_pulse| next_pulse < pulled);
             // If pattern is queued, we switch to it on the 0 to maintain
             // the expected beat (this is similar to default Ableton behavior
             // in session mode for instance)
-            self.ctx.with_lock(|ctx| {
-                if self.pulse_idx == 0 {
-                    if ctx.queued_pattern_id != ctx.pattern_id {
-                        ctx.pattern_id = ctx.queued_pattern_id;
-                        ctx.reset_playheads();
+            // Like the audio the pulse's midi goes out at the frame it's due
+            for note_off in std::mem::take(&mut self.note_offs) {
+                self.queue_midi(due, MidiKind::Note, &note_off);
+            }
+            let (midi_messages, pulse_interval) = self.ctx.with_pulse_lock(|ctx| {
+                // Everything started on the pulse plays from the frame it's due at
+                ctx.stream.set_pulse_frame(Some(due as u64));
+                ctx.jitter.record(late_us, missed, self.pulse_idx == self.ppb - 1);
+                ctx.advance_tempo_ramp();
+
+                if self.pulse_idx.is_multiple_of(PPQ) {
+                    ctx.metronome.click(&ctx.stream, self.pulse_idx / PPQ);
+                }
+                // Only the click plays during the count in
+                let sync_out = ctx.project.sync_out;
+                if sync_out.enabled && !counting_in && self.pulse_idx.is_multiple_of(PPQ / sync_out.ppqn) {
+                    ctx.stream.sync_pulse();
+                }
+                if counting_in {
+                    if self.pulse_idx == self.ppb - 1 {
+                        ctx.metronome.end_bar();
                     }
+                    ctx.stream.set_pulse_frame(None);
+                    return (vec![], ctx.pulse_interval);
                 }
 
-                let pattern = &mut ctx.patterns[ctx.pattern_id];
-
-                // We use this later to see if we need to choke
-                // any track
-                let mut triggered_ids: Vec<usize> = vec![];
-                let tracks = &mut pattern.tracks;
-
-                for (i, t) in tracks.into_iter().enumerate() {
-                    // If we're on an odd beat we need to swing it
-                    // ie 1 triplet 2. Midi pulse remains on the straight
-                    let swing_offset = {
-                        if t.idx % 2 == 1 {
-                            ctx.swing_offset
-                        } else {
-                            0
-                        }
-                    };
-                    
-                    // To handle swing which delays a note, we actually
-                    // run the pulse idx to half a bar
-                    // Example: The 8th note is allotted 12 pulses
-                    // so the pulse counts 0-12 on a straight rhythm which is
-                    // ok for that. However, on swinging, we need to delay
-                    // to pulse 16 and so have to count two 8th notes 0-24
-                    let pulse_idx = self.pulse_idx % (self.ppb / pattern.division as u8 * 2);
-                    if pulse_idx == 0 || pulse_idx == swing_offset + (self.ppb / pattern.division as u8) {
-                        let vel = &mut t.slots[t.idx].velocity;
-                        if *vel > 0 {
-                            Sequencer::append_sample_to_sink(t.sink.clone(), t.sample.clone(), vel);
-                            triggered_ids.push(i);
-                        }
-
-                        t.idx = (t.idx + 1) % t.len;
+                // Song mode queues the next pattern on the last pulse of the
+                // pattern so the regular queue switch below picks it up on the 0
+                let loop_end = self.pulse_idx == self.ppb - 1 && ctx.last_bar();
+                if ctx.song.enabled && loop_end {
+                    match ctx.song.advance() {
+                        Some(pattern_id) => ctx.queued_pattern_id = pattern_id,
+                        None => ctx.disable_play(),
                     }
                 }
-                    
-                // Redefine as immutable to prevent triggering borrow checker
-                let pattern = &ctx.patterns[ctx.pattern_id];
-                let tracks = &pattern.tracks;
-                for i in 0..tracks.len() {
-                    if pattern.is_trk_choked(&triggered_ids, i) {
-                        tracks[i].sink.skip_one();
+                if self.pulse_idx == self.ppb - 1 && ctx.stop_due(self.position.elapsed) {
+                    ctx.disable_play();
+                }
+
+                // Patterns longer than a bar play through before switching
+                if self.pulse_idx == 0 {
+                    ctx.apply_pending_load();
+                    ctx.apply_pending_division();
+                    ctx.apply_pending_swing_grid();
+                    if ctx.pattern_bar == 0 {
+                        ctx.switch_patterns();
                     }
                 }
+                if self.pulse_idx == self.ppb - 1 {
+                    ctx.pattern_bar = if loop_end { 0 } else { ctx.pattern_bar + 1 };
+                }
+
+                ctx.clock_in.pulse();
+
+                let pattern_id = ctx.pattern_id;
+                let mut midi_messages: Vec<(MidiKind, [u8; 3])> = ctx.patterns[pattern_id].tick_cc(self.pulse_idx, PPB)
+                    .into_iter()
+                    .map(|message| (MidiKind::Cc, message))
+                    .collect();
+
+                // In deck mode B runs alongside with the crossfader setting
+                // the level of each, its cc lanes are left out so the two
+                // don't fight over the same controllers
+                let mut notes = match ctx.deck_b_playing(self.pulse_idx) {
+                    Some(deck_b_id) => {
+                        let (gain_a, gain_b) = ctx.deck_gains();
+                        let mut notes = ctx.play_pattern(pattern_id, self.pulse_idx, gain_a);
+                        notes.extend(ctx.play_pattern(deck_b_id, self.pulse_idx, gain_b));
+                        notes
+                    },
+                    None => ctx.play_pattern(pattern_id, self.pulse_idx, 1.0),
+                };
+                // Notes go out on the pulse, humanize and groove delays only move the audio
+                for note in notes.drain(..) {
+                    midi_messages.push((MidiKind::Note, note));
+                    self.note_offs.push([0x80 | DRUM_CHANNEL, note[1], 0]);
+                }
+                ctx.stream.set_pulse_frame(None);
+                (midi_messages, ctx.pulse_interval)
             });
+            self.next_pulse = Some(due + pulse_interval.as_secs_f64() * sample_rate);
+
+            self.queue_midi(due, MidiKind::Clock, &[0xF8]);
+            for (kind, message) in midi_messages {
+                self.queue_midi(due, kind, &message);
+            }
 
-            self.send_midi_byte(0xF8);
+            // The count in doesn't move the transport
+            if running {
+                let now = Instant::now();
+                self.position.elapsed += now.duration_since(self.last_pulse.unwrap_or(now));
+                self.last_pulse = Some(now);
+                self.position.beat = self.pulse_idx / PPQ;
+                self.position.pulse = self.pulse_idx % PPQ;
+            }
             self.pulse_idx = (self.pulse_idx + 1) % self.ppb;
+            if running && self.pulse_idx == 0 {
+                self.position.bar += 1;
+            }
 
             self.set_latency(Instant::now().duration_since(start));
 
-        } else if self.pulse_idx != 0 {
-            self.pulse_idx = 0;
-            self.ctx.with_lock(|ctx| {
-                ctx.patterns[ctx.pattern_id].reset_playheads();
-            });
+        } else if !paused {
+            self.position = TransportPosition::default();
+            if self.pulse_idx != 0 {
+                self.pulse_idx = 0;
+                self.ctx.with_pulse_lock(|ctx| {
+                    ctx.reset_playheads();
+                    ctx.restart_deck_b();
+                });
+            }
+        }
+
+        if !playing {
+            self.next_pulse = None;
+        }
+        if !running {
+            self.last_pulse = None;
+            for note_off in std::mem::take(&mut self.note_offs) {
+                self.send_midi(MidiKind::Note, &note_off);
+            }
         }
 
         self.tx_state();
     }
 
+    /// Stops the transport when the audio output dies rather than playing
+    /// on into nothing, then keeps trying to open it again
+    ///
+    /// The midi stop goes out with the usual play toggle on the next pulse
+    fn watch_audio(&mut self) {
+        let (request, stream_request) = self.ctx.with_pulse_lock(|ctx| {
+            ctx.output_latency = self.audio.latency();
+            ctx.widen_output();
+            (ctx.audio_device_request.take(), ctx.stream_request)
+        });
+        if let Some(name) = request {
+            match AudioOutput::open(&name, stream_request) {
+                Ok(audio) => self.switch_audio(audio),
+                Err(e) => println!("Failed to switch audio output to {}: {}", name, e),
+            }
+            return;
+        }
+        if let Some(reacquire) = &mut self.reacquire {
+            if let Some(audio) = reacquire.poll() {
+                println!("Audio output back on {}", audio.device_name);
+                self.switch_audio(audio);
+            }
+            return;
+        }
+        if !self.audio.is_alive() {
+            println!("Stopping, lost audio output {}", self.audio.device_name);
+            self.ctx.with_pulse_lock(|ctx| {
+                ctx.disable_play();
+                ctx.paused = false;
+                ctx.audio_error = Some(ctx.project.language.text_with(Msg::LostAudioOutput, &self.audio.device_name));
+            });
+            self.reacquire = Some(Reacquire::new(self.audio.device_name.clone(), stream_request));
+        }
+    }
+
+    /// Moves playback onto a newly opened output, the old stream closes
+    /// when it's replaced
+    fn switch_audio(&mut self, audio: AudioOutput) {
+        self.ctx.with_pulse_lock(|ctx| {
+            ctx.set_stream(audio.handle.clone());
+            ctx.audio_device = audio.device_name.clone();
+            ctx.stream_request = audio.request;
+            ctx.buffer_frames = audio.buffer_frames;
+            ctx.sample_rate = audio.sample_rate;
+            ctx.audio_error = None;
+        });
+        // the new stream's clock starts from 0, anything held back against
+        // the old one goes now
+        self.flush_midi(f64::INFINITY);
+        self.audio = audio;
+        self.reacquire = None;
+        self.next_pulse = None;
+    }
+
     /// Attempts to keep timing tight by subtracting processing time from overall wait between beats
     fn set_latency(&mut self, t: Duration) {
         self.latency = Duration::from_nanos(((self.latency + t).as_nanos() / 2) as u64);
-        self.ctx.with_lock(|ctx| {
+        let bar_done = self.pulse_idx == 0;
+        self.ctx.with_pulse_lock(|ctx| {
+            ctx.budget.record(t, ctx.pulse_interval, bar_done);
             self.sleep_interval = ctx.pulse_interval - ctx.pulse_interval.min(self.latency)
         })
     }
@@ -1026,41 +5387,11 @@ impl Sequencer {
         self.command_tx_ch.clone()
     }
 
-    /// Transmits a subset of internal sequencer state
+    /// Hands the position over for the state to be sent, it's built on a
+    /// thread of its own, see publish_state. If the last one isn't sent
+    /// yet this one is skipped, the next pulse has a newer position anyway
     fn tx_state(&self) {
-        self.ctx.with_lock(|ctx| {
-            let trks: Vec<TrackState> = ctx
-                .patterns[ctx.pattern_id]
-                .tracks
-                .iter()
-                .map(|t| {
-                    TrackState {
-                        slots: t.slots.iter().map(|s| { s.velocity }).collect(),
-                        name: t.name.clone(),
-                        idx: t.idx,
-                        len: t.len,
-                        sample_path: t.sample_path.clone(),
-                    }
-                })
-                .collect();
-
-            for tx in &ctx.state_tx_ch {
-                let _ = tx.send(StateUpdate::SeqState(SeqState {
-                    tempo: ctx.tempo,
-                    trks: trks.clone(),
-                    division: ctx.patterns[ctx.pattern_id].division as u8,
-                    default_len: ctx.default_len,
-                    latency: self.latency,
-                    last_cmd: ctx.last_cmd.clone(),
-                    playing: ctx.playing,
-                    pattern_id: ctx.pattern_id,
-                    pattern_len: ctx.patterns.len(),
-                    pattern_name: ctx.patterns[ctx.pattern_id].name.clone(),
-                    queued_pattern_id: ctx.queued_pattern_id,
-                    swing: ctx.swing as u8,
-                }));
-            }
-        })
+        let _ = self.state_pulse_tx.try_send((self.position, self.latency));
     }
 
     /// Receives commands and modifies sequencer state accordingly
@@ -1068,32 +5399,162 @@ impl Sequencer {
     /// You can run this in its own thread. It does not own the sequencer
     /// instance hence we use a ctx handle to modify the sequencer state
     /// There's a slight weirdness with this paradigm in that one shot
-    /// sample playing will directly add to the track playback sink, instead
+    /// sample playing will directly start a voice on the track, instead
     /// of modifying a property. Maybe tracks are not fully definable as properties
     /// but we gain functionality treating them as such
     pub fn run_command_loop(ctx: ContextHandle) {
+        let ctx_handle = ctx.clone();
+        watch_samples(ctx.clone());
+        let command_rx = ctx.command_rx.clone();
+        let command_rx = command_rx.lock().unwrap();
         loop {
-            ctx.with_lock(|ctx| {
-                if let Ok(cmd) = ctx.command_rx_ch.try_recv() {
+            // Waits for a command without the lock, then takes whatever
+            // came in behind it along with the rest of a macro or a batch
+            // cut short last time
+            let mut cmds = ctx.with_lock(|ctx| std::mem::take(&mut ctx.pending_cmds));
+            if cmds.is_empty() {
+                let Ok(cmd) = command_rx.recv() else {
+                    return;
+                };
+                cmds.push_back(cmd);
+            }
+            while cmds.len() < MAX_COMMAND_BATCH {
+                let Ok(cmd) = command_rx.try_recv() else {
+                    break;
+                };
+                cmds.push_back(cmd);
+            }
+            let prefetched = prefetch(&ctx, &cmds);
+            let (snapshot, project, writes) = ctx.with_lock(|ctx| {
+                // Everything waiting is handled under the one lock, so a
+                // burst of slot edits from a UI doesn't fight the pulse
+                // thread for it once per edit. A batch that runs long,
+                // expands a macro or has a pulse waiting on it hands the
+                // rest back to keep the order
+                let start = Instant::now();
+                while let Some(cmd) = cmds.pop_front() {
+                    if !ctx.pending_cmds.is_empty() || ctx_handle.pulse_waiting() || start.elapsed() >= MAX_COMMAND_LOCK {
+                        cmds.push_front(cmd);
+                        ctx.pending_cmds.extend(cmds.drain(..));
+                        break;
+                    }
                     ctx.last_cmd = cmd.clone();
                     match cmd {
-                        Command::SetTempo(bpm) => ctx.set_tempo(bpm),
-                        Command::PlaySound(trk_id, vel) => (|trk_id, vel| {
+                        Command::SetTempo(bpm) => ctx.slew_tempo(bpm),
+                        Command::SetTempoSlew(ms) => ctx.tempo_slew = Duration::from_millis(ms as u64),
+                        Command::PlaySound(trk_id, vel) if ctx.has_track(trk_id) => (|trk_id, vel| {
+                            let recording = ctx.recording && ctx.playing;
+                            let record_mode = ctx.record_mode;
+                            let step = PPB / ctx.patterns[ctx.pattern_id].division as u8;
+                            let voice_settings = ctx.voice_settings();
+                            let tempo = ctx.loop_tempo();
                             let trk: &mut Track = &mut ctx.patterns[ctx.pattern_id].tracks[trk_id];
-                            let mut vel = vel;
-                            let v = &mut vel;
-                            Sequencer::append_sample_to_sink(trk.sink.clone(), trk.sample.clone(), v);
-                            let trks = &ctx.patterns[ctx.pattern_id].tracks;
-                            for i in 0..trks.len() {
-                                if ctx.patterns[ctx.pattern_id].is_trk_choked(&vec![trk_id], i) {
-                                    trks[i].sink.skip_one();
+                            if recording {
+                                trk.record(vel, step, record_mode);
+                            }
+                            trk.fit_loop(tempo);
+                            trk.play(&ctx.stream, vel, Duration::ZERO, trk.reverse, voice_settings);
+                            let pattern = &mut ctx.patterns[ctx.pattern_id];
+                            for i in 0..pattern.tracks.len() {
+                                if pattern.is_trk_choked(&vec![trk_id], i) {
+                                    pattern.tracks[i].choke(voice_settings);
                                 }
                             }
                         })(trk_id, vel),
-                        Command::PlaySequencer => ctx.enable_play(),
-                        Command::StopSequencer => ctx.disable_play(),
-                        Command::SetDivision(div) => ctx.patterns[ctx.pattern_id].division = div,
-                        Command::SetSlotVelocity(trk, slot, vel) => {
+                        Command::PlaySequencer => ctx.play(),
+                        Command::PlayForBars(bars) if bars > 0 => {
+                            ctx.play();
+                            ctx.bars_left = Some(bars);
+                        },
+                        Command::SetStopTimer(secs) => {
+                            ctx.project.stop_timer_secs = secs;
+                            ctx.save_project();
+                        },
+                        Command::StopSequencer => {
+                            ctx.disable_play();
+                            ctx.paused = false;
+                        },
+                        Command::PauseSequencer if ctx.playing => {
+                            ctx.disable_play();
+                            ctx.paused = true;
+                        },
+                        Command::ContinueSequencer if ctx.paused => {
+                            ctx.paused = false;
+                            ctx.resuming = true;
+                            ctx.enable_play();
+                        },
+                        Command::SetDivision(div) => ctx.set_division(div),
+                        Command::SetTrackDirection(trk, direction) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].set_direction(direction);
+                        },
+                        Command::SetTrackEq(trk, eq) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].eq = eq.clamped();
+                        },
+                        Command::SetTrackFxBypass(trk, bypass) if ctx.has_track(trk) => {
+                            let track = &mut ctx.patterns[ctx.pattern_id].tracks[trk];
+                            track.fx_bypass = bypass;
+                            track.update_voices();
+                        },
+                        Command::SetTrackTimingOffset(trk, offset_ms) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].timing_offset_ms = offset_ms;
+                        },
+                        Command::SetTrackLoop(trk, start, end) if ctx.has_track(trk) => {
+                            if let Err(e) = ctx.patterns[ctx.pattern_id].tracks[trk].set_loop(start, end) {
+                                println!("Failed to set track loop: {}", e);
+                            }
+                        },
+                        Command::ClearTrackLoop(trk) if ctx.has_track(trk) => {
+                            let track = &mut ctx.patterns[ctx.pattern_id].tracks[trk];
+                            track.stop_loop();
+                            track.loop_points = None;
+                        },
+                        Command::RandomizeTrack(trk, density, vel_min, vel_max) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].randomize(density, vel_min, vel_max);
+                        },
+                        Command::ClearTrack(trk) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].reset_slots();
+                        },
+                        Command::ClearPattern => ctx.patterns[ctx.pattern_id].zero_all_tracks(),
+                        Command::RandomizePattern(density, vel_min, vel_max) => {
+                            ctx.patterns[ctx.pattern_id].randomize(density, vel_min, vel_max);
+                        },
+                        Command::SetHumanize(timing_ms, velocity) => {
+                            ctx.patterns[ctx.pattern_id].humanize = Humanize { timing_ms, velocity };
+                        },
+                        Command::SetGroove(name, strength) => {
+                            ctx.patterns[ctx.pattern_id].groove = Some(ctx.pattern_groove(name, strength));
+                        },
+                        Command::ClearGroove => {
+                            ctx.patterns[ctx.pattern_id].groove = None;
+                        },
+                        Command::AddCcLane(channel, controller) => {
+                            let pattern = &mut ctx.patterns[ctx.pattern_id];
+                            let len = pattern.tracks.first().map_or(ctx.default_len, |track| track.len);
+                            pattern.cc_lanes.push(CcLane::new(channel, controller, len));
+                        },
+                        Command::RemoveCcLane(lane) => {
+                            let cc_lanes = &mut ctx.patterns[ctx.pattern_id].cc_lanes;
+                            if lane < cc_lanes.len() {
+                                cc_lanes.remove(lane);
+                            }
+                        },
+                        Command::SetCcStep(lane, step, value) if lane < ctx.patterns[ctx.pattern_id].cc_lanes.len() => {
+                            ctx.patterns[ctx.pattern_id].cc_lanes[lane].set_value(step, value);
+                        },
+                        Command::SetSlotAccent(trk, slot, accent) if ctx.has_slot(trk, slot) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].slots[slot].accent = accent;
+                        },
+                        Command::SetSlotReverse(trk, slot, reverse) if ctx.has_slot(trk, slot) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].slots[slot].reverse = reverse;
+                        },
+                        Command::SetSlotRatchet(trk, slot, ratchet) if ctx.has_slot(trk, slot) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].slots[slot].ratchet = ratchet.min(MAX_RATCHET);
+                        },
+                        Command::SetSlotRatchetDecay(trk, slot, decay) if ctx.has_slot(trk, slot) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].slots[slot].ratchet_decay = decay.clamp(-100, 100);
+                        },
+                        Command::SetAccentVelocity(vel) => ctx.accent_velocity = vel,
+                        Command::SetSlotVelocity(trk, slot, vel) if ctx.has_slot(trk, slot) => {
                             ctx.patterns[ctx.pattern_id].tracks[trk].slots[slot].velocity = vel;
                         },
                         // Adding a new pattern will duplicate the current pattern
@@ -1102,6 +5563,7 @@ impl Sequencer {
                             let new_id = ctx.patterns.len();
                             ctx.patterns.push(ctx.patterns[ctx.pattern_id].clone());
                             ctx.patterns[new_id].zero_all_tracks();
+                            ctx.patterns[new_id].notes.clear();
                             ctx.patterns[new_id].name = format!("Pattern {}", new_id + 1);
                             if ctx.playing {
                                 ctx.queued_pattern_id = new_id;
@@ -1109,20 +5571,68 @@ impl Sequencer {
                                 ctx.pattern_id = new_id;
                             }
                         },
-                        Command::RemovePattern(idx) => {
+                        Command::DuplicatePattern(idx) if idx < ctx.patterns.len() => {
+                            ctx.duplicate_pattern(idx);
+                        },
+                        Command::CopyTrack(src_pattern, src_track, dst_pattern) => {
+                            if let Err(e) = ctx.copy_track(src_pattern, src_track, dst_pattern) {
+                                println!("Failed to copy track: {}", e);
+                            }
+                        },
+                        Command::CopyTrackFeel(src, dst) if ctx.has_track(src) && ctx.has_track(dst) => {
+                            let tracks = &mut ctx.patterns[ctx.pattern_id].tracks;
+                            let src = tracks[src].clone();
+                            tracks[dst].copy_feel(&src);
+                        },
+                        // The last pattern stays, there has to be something to play
+                        Command::RemovePattern(idx) if idx < ctx.patterns.len() && ctx.patterns.len() > 1 => {
                             ctx.patterns.remove(idx);
-                            if idx < ctx.pattern_id {
+                            if idx < ctx.pattern_id || ctx.pattern_id == ctx.patterns.len() {
                                 ctx.pattern_id -= 1;
                             }
+                            ctx.queued_pattern_id = match ctx.queued_pattern_id {
+                                id if id == idx => ctx.pattern_id,
+                                id if id > idx => id - 1,
+                                id => id,
+                            };
+                            ctx.song.remove_pattern(idx);
+                            ctx.patterns.iter_mut().for_each(|pattern| {
+                                pattern.fill = match pattern.fill {
+                                    Some(fill_id) if fill_id == idx => None,
+                                    Some(fill_id) if fill_id > idx => Some(fill_id - 1),
+                                    fill => fill,
+                                };
+                            });
+                            ctx.fill_return = ctx.fill_return
+                                .filter(|&id| id != idx)
+                                .map(|id| if id > idx { id - 1 } else { id });
+                            ctx.deck_b = ctx.deck_b
+                                .filter(|deck| deck.pattern_id != idx)
+                                .map(|deck| Deck {
+                                    pattern_id: if deck.pattern_id > idx { deck.pattern_id - 1 } else { deck.pattern_id },
+                                    ..deck
+                                });
+                            ctx.pattern_queue.retain(|&id| id != idx);
+                            ctx.pattern_queue.iter_mut().for_each(|id| {
+                                if *id > idx {
+                                    *id -= 1;
+                                }
+                            });
                         },
-                        Command::SelectPattern(idx) => {
-                            if !ctx.playing {
-                                ctx.pattern_id = idx;
-                            } else {
-                                ctx.queued_pattern_id = idx;
-                            }
+                        Command::SelectPattern(idx) if idx < ctx.patterns.len() => {
+                            ctx.select_pattern(idx);
                         },
-                        Command::SetPatternLength(len) => {
+                        Command::QueuePattern(idx) if idx < ctx.patterns.len() => {
+                            ctx.queue_pattern(idx);
+                        },
+                        Command::ClearPatternQueue => {
+                            ctx.pattern_queue.clear();
+                        },
+                        Command::SetTrackLength(trk, len) if ctx.has_track(trk) && len > 0 => {
+                            let pattern_id = ctx.pattern_id;
+                            ctx.patterns[pattern_id].tracks[trk].set_len(len);
+                        },
+                        Command::SetPatternLength(len) if len > 0 => {
                             ctx.patterns[ctx.pattern_id].set_len(len);
                         },
                         Command::SavePattern => {
@@ -1130,46 +5640,579 @@ impl Sequencer {
                                 println!("Failed to save pattern: {}", e);
                             }
                         },
-                        Command::LoadPattern(pattern_fname) => {
-                            if let Err(e) = ctx.load_pattern(pattern_fname.clone()) {
-                                println!("Failed to load pattern: {}", e);
-                            }
-                        },
+                        Command::LoadPattern(pattern_fname) => ctx.preload_pattern(ctx_handle.clone(), pattern_fname),
                         Command::ListPatterns => {
                             ctx.send_file_state(FileType::Pattern);
                         },
+                        // Reading every saved pattern can take a while so it's done off
+                        // the command thread like exports
+                        Command::FindSimilarPatterns => {
+                            let pattern = ctx.patterns[ctx.pattern_id].to_saved();
+                            let fnames = ctx.saved_patterns.clone();
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || {
+                                let matches = similarity::closest(&pattern, &fnames);
+                                ctx_handle.with_lock(|ctx| {
+                                    ctx.similar_patterns = matches;
+                                    ctx.send_file_state(FileType::SimilarPattern);
+                                });
+                            });
+                        },
+                        Command::ListTriggerCounts => ctx.send_trigger_counts(),
+                        Command::ReportBudget => {
+                            let report = ctx.budget.report();
+                            for tx in &ctx.state_tx_ch {
+                                let _ = tx.send(StateUpdate::Budget(report.clone()));
+                            }
+                        },
+                        Command::ResetTriggerCounts => {
+                            ctx.patterns[ctx.pattern_id].tracks.iter_mut().for_each(|track| {
+                                track.trigger_counts.fill(0);
+                            });
+                            ctx.send_trigger_counts();
+                        },
                         Command::ListSamples => {
                             ctx.send_file_state(FileType::Sample);
                         },
+                        Command::SetSampleCacheSize(mb) => {
+                            ctx.samples.set_cap(mb);
+                            ctx.project.sample_cache_mb = mb;
+                            ctx.save_project();
+                        },
+                        Command::ReportSamplePool => ctx.send_sample_pool(ctx_handle.clone(), false),
+                        Command::CleanSamplePool => ctx.send_sample_pool(ctx_handle.clone(), true),
+                        Command::ListGrooves => {
+                            ctx.send_file_state(FileType::Groove);
+                        },
                         Command::AddTrack => {
                             let last_sample = ctx.patterns[ctx.pattern_id].tracks.last().unwrap().sample_path.clone();
                             let last_trk_len = ctx.patterns[ctx.pattern_id].tracks.last().unwrap().len;
                             let old_len = ctx.patterns[ctx.pattern_id].tracks.len();
-                            ctx.patterns[ctx.pattern_id].add_track(ctx.stream.clone(), last_trk_len, last_sample).unwrap();
-                            if ctx.playing {
+                            if let Err(e) = ctx.patterns[ctx.pattern_id].add_track(last_trk_len, last_sample, &mut ctx.samples) {
+                                println!("Failed to add track: {}", e);
+                            } else if ctx.playing {
                                 let last_trk_idx = ctx.patterns[ctx.pattern_id].tracks[old_len-1].idx;
                                 ctx.patterns[ctx.pattern_id].tracks[old_len].idx = last_trk_idx;
                             }
                         },
-                        Command::SetTrackSample(trk_id, sample_path) => {
-                            ctx.patterns[ctx.pattern_id].set_track_sample(trk_id, sample_path).unwrap();
+                        Command::RemoveTrack(trk_id) => {
+                            match ctx.patterns[ctx.pattern_id].remove_track(trk_id) {
+                                Ok(()) => ctx.remap_tracks(|id| removed_track_id(trk_id, id)),
+                                Err(e) => println!("Failed to remove track: {}", e),
+                            }
+                        },
+                        Command::MoveTrack(from, to) => {
+                            match ctx.patterns[ctx.pattern_id].move_track(from, to) {
+                                Ok(()) => ctx.remap_tracks(|id| Some(moved_track_id(from, to, id))),
+                                Err(e) => println!("Failed to move track: {}", e),
+                            }
+                        },
+                        Command::SetTrackSample(trk_id, sample_path) if ctx.has_track(trk_id) => {
+                            let pattern = &mut ctx.patterns[ctx.pattern_id];
+                            let res = if ctx.playing {
+                                pattern.queue_track_sample(trk_id, sample_path, &mut ctx.samples)
+                            } else {
+                                pattern.set_track_sample(trk_id, sample_path, &mut ctx.samples)
+                            };
+                            if let Err(e) = res {
+                                println!("Failed to set track sample: {}", e);
+                            }
+                        },
+                        // The picks run like a macro of SetTrackSample, so
+                        // they're decoded before the lock like any other
+                        Command::RandomizeKit => {
+                            let current: Vec<String> = ctx.patterns[ctx.pattern_id].tracks.iter().map(|track| track.sample_path.clone()).collect();
+                            let picks = kit::randomize(&current, &ctx.sample_files);
+                            ctx.pending_cmds.extend(picks.into_iter().enumerate().filter_map(|(trk_id, sample_path)| {
+                                Some(Command::SetTrackSample(trk_id, sample_path?))
+                            }));
+                        },
+                        Command::SetSwapPolicy(policy) => {
+                            ctx.swap_policy = policy;
+                        },
+                        Command::DefineMacro(name, commands) => {
+                            ctx.project.set_macro(name, commands);
+                            ctx.save_project();
+                        },
+                        Command::RemoveMacro(name) => {
+                            ctx.project.remove_macro(&name);
+                            ctx.save_project();
+                        },
+                        Command::RunMacro(name) => {
+                            match ctx.project.get_macro(&name) {
+                                Some(m) => ctx.pending_cmds.extend(m.commands.iter().cloned()),
+                                None => println!("No macro named {}", name),
+                            }
+                        },
+                        Command::SetMetronome(enabled) => ctx.metronome.enabled = enabled,
+                        Command::SetMetronomeSample(sample_path) => {
+                            if let Err(e) = ctx.metronome.set_sample(sample_path, &mut ctx.samples) {
+                                println!("Failed to set metronome sample: {}", e);
+                            }
+                        },
+                        Command::SetCountIn(bars) => ctx.metronome.count_in_bars = bars,
+                        Command::SetAntiClick(enabled, ms) => {
+                            ctx.anti_click = enabled;
+                            ctx.anti_click_len = Duration::from_millis(ms.clamp(1, 20) as u64);
+                        },
+                        Command::SetChokeFade(ms) => {
+                            ctx.choke_fade = Duration::from_millis(ms.clamp(MIN_CHOKE_FADE_MS, MAX_CHOKE_FADE_MS) as u64);
                         },
                         Command::SetSwing(swing) => {
                             ctx.set_swing(swing);
                         },
+                        Command::SetSwingGrid(swing_grid) => ctx.set_swing_grid(swing_grid),
+                        Command::SetSongMode(enabled) => {
+                            ctx.song.enabled = enabled;
+                            if enabled {
+                                ctx.restart_song();
+                            }
+                        },
+                        Command::AddSongEntry(pattern_id, repeats) if pattern_id < ctx.patterns.len() => {
+                            ctx.song.add_entry(pattern_id, repeats);
+                        },
+                        Command::SetSongEntry(idx, pattern_id, repeats) if pattern_id < ctx.patterns.len() => {
+                            ctx.song.set_entry(idx, pattern_id, repeats);
+                        },
+                        Command::RemoveSongEntry(idx) => {
+                            ctx.song.remove_entry(idx);
+                        },
+                        Command::ClearSong => {
+                            ctx.song.clear();
+                        },
+                        Command::SetSongLoop(start, end) => {
+                            ctx.song.set_loop_region(start, end);
+                        },
+                        Command::ClearSongLoop => {
+                            ctx.song.clear_loop_region();
+                        },
+                        // Rendering can take a while so it's done off the command thread
+                        // with its own copy of the patterns
+                        Command::ExportSong(fname, settings) => {
+                            ctx.fit_loops();
+                            let renderer = Renderer::new(ctx.patterns.clone(), ctx.tick_params(), ctx.voice_settings(), ctx.project.delay, ctx.project.reverb);
+                            let entries = ctx.song.entries.clone();
+                            let pattern_id = ctx.pattern_id;
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || {
+                                match renderer.export_song(&entries, pattern_id, &fname, &settings) {
+                                    Ok(report) => ctx_handle.with_lock(|ctx| ctx.last_export = Some(report)),
+                                    Err(e) => println!("Failed to export song: {}", e),
+                                }
+                            });
+                        },
+                        Command::ExportAllPatterns(dir, bars_each) => {
+                            ctx.fit_loops();
+                            let renderer = Renderer::new(ctx.patterns.clone(), ctx.tick_params(), ctx.voice_settings(), ctx.project.delay, ctx.project.reverb);
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || {
+                                match renderer.export_patterns(&dir, bars_each) {
+                                    Ok(mut reports) => ctx_handle.with_lock(|ctx| ctx.last_export = reports.pop()),
+                                    Err(e) => println!("Failed to export patterns: {}", e),
+                                }
+                            });
+                        },
+                        // The file is created off the lock
+                        Command::StartRecording(fname) => {
+                            if let Some(recording) = ctx.master_recording.take() {
+                                recording.stop();
+                            }
+                            ctx.next_recording += 1;
+                            let recording_id = ctx.next_recording;
+                            let stream = ctx.stream.clone();
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || start_recording(&ctx_handle, &stream, recording_id, &fname));
+                        },
+                        Command::StopRecording => {
+                            if let Some(recording) = ctx.master_recording.take() {
+                                recording.stop();
+                            }
+                            ctx.next_recording += 1;
+                        },
+                        Command::CaptureSample(trk, fname, threshold_db, overwrite) if ctx.has_track(trk) => {
+                            if let Some(capture) = ctx.sample_capture.take() {
+                                capture.stop();
+                            }
+                            let capture_id = ctx.next_capture;
+                            ctx.next_capture += 1;
+                            ctx.capture_targets.insert(capture_id, (ctx.pattern_id, trk));
+                            let threshold_db = threshold_db.clamp(MIN_THRESHOLD_DB, 0);
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || start_capture(&ctx_handle, capture_id, &fname, threshold_db, overwrite, trk));
+                        },
+                        Command::StopCapture => {
+                            if let Some(capture) = ctx.sample_capture.take() {
+                                capture.stop();
+                            }
+                            // one still opening is stopped once it's open
+                            ctx.next_capture += 1;
+                        },
+                        Command::SetTrackLayer(trk, min_velocity, sample_path) if ctx.has_track(trk) => {
+                            let pattern = &mut ctx.patterns[ctx.pattern_id];
+                            if let Err(e) = pattern.tracks[trk].set_layer(min_velocity, sample_path, &mut ctx.samples) {
+                                println!("Failed to set track layer: {}", e);
+                            }
+                        },
+                        Command::RemoveTrackLayer(trk, min_velocity) if ctx.has_track(trk) => {
+                            if let Err(e) = ctx.patterns[ctx.pattern_id].tracks[trk].remove_layer(min_velocity) {
+                                println!("Failed to remove track layer: {}", e);
+                            }
+                        },
+                        Command::ClearTrackLayers(trk) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].layers.clear();
+                        },
+                        Command::SetTrackOutput(trk, output) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].output = output.min(MAX_OUTPUT_PAIRS - 1);
+                        },
+                        // Saved and worked out off the lock, normalizing goes over the whole sample
+                        Command::SetSampleGain(sample_path, gain) => {
+                            if ctx.sample_files.contains(&sample_path) {
+                                let loaded = ctx.samples.contains(&sample_path).then(|| ctx.samples.load(&sample_path).ok()).flatten();
+                                let ctx_handle = ctx_handle.clone();
+                                thread::spawn(move || set_sample_gain(&ctx_handle, sample_path, loaded, gain.clamped()));
+                            } else {
+                                println!("Failed to set sample gain: {} isn't in the samples folder", sample_path);
+                            }
+                        },
+                        Command::SetTrackLoopSync(trk, loop_sync) if ctx.has_track(trk) => {
+                            let tempo = ctx.loop_tempo();
+                            let track = &mut ctx.patterns[ctx.pattern_id].tracks[trk];
+                            match loop_sync {
+                                Some(sync) if !(MIN_TEMPO..=MAX_TEMPO).contains(&sync.bpm) => {
+                                    println!("Failed to set track loop tempo: {} BPM isn't {} to {}", sync.bpm, MIN_TEMPO, MAX_TEMPO);
+                                },
+                                Some(_) if track.sample.streamed() => {
+                                    println!("Failed to set track loop tempo: {} is streamed from disk, it can't be fitted", track.sample_path);
+                                },
+                                _ => {
+                                    track.loop_sync = loop_sync;
+                                    track.fit_loop(tempo);
+                                },
+                            }
+                        },
+                        Command::SliceSample(sample_path, slices) => {
+                            let len = ctx.patterns[ctx.pattern_id].tracks.iter().map(|track| track.len).max().unwrap_or(0);
+                            if !(MIN_SLICES..=MAX_SLICES.min(len)).contains(&slices) {
+                                println!("Failed to slice sample: {} slices won't fit a pattern of {} steps", slices, len);
+                            } else {
+                                let pattern_id = ctx.pattern_id;
+                                let ctx_handle = ctx_handle.clone();
+                                thread::spawn(move || {
+                                    let res = slicer::slice(&sample_path, slices).map_err(|e| e.to_string());
+                                    let new_samples = NewSamples::load(&ctx_handle, res.as_deref().unwrap_or_default());
+                                    ctx_handle.with_lock(|ctx| {
+                                        ctx.add_new_samples(new_samples);
+                                        ctx.finish_slicing(res, pattern_id);
+                                    });
+                                });
+                            }
+                        },
+                        Command::PreviewSample(sample_path, vel) => {
+                            if let Err(e) = ctx.preview_sample(sample_path, vel) {
+                                println!("Failed to preview sample: {}", e);
+                            }
+                        },
+                        Command::ReloadSamples => {
+                            let sample_paths = ctx.samples.in_use().into_iter().map(|(sample_path, _)| sample_path).collect();
+                            let ctx_handle = ctx_handle.clone();
+                            thread::spawn(move || reload_samples_from_disk(&ctx_handle, sample_paths));
+                        },
+                        Command::SetMidiThru(enabled, channel) => {
+                            ctx.project.midi_thru = MidiThru {
+                                enabled,
+                                channel: channel.map(|channel| channel.min(15)),
+                            };
+                            ctx.save_project();
+                        },
+                        Command::SetPatternFill(fill_id) => {
+                            let main_id = ctx.main_pattern_id();
+                            ctx.patterns[main_id].fill = fill_id.filter(|&fill_id| fill_id < ctx.patterns.len());
+                        },
+                        Command::TriggerFill(held) => ctx.trigger_fill(held),
+                        Command::SetDeckB(pattern_id) => ctx.set_deck_b(pattern_id),
+                        Command::SetCrossfade(crossfade) if crossfade.is_finite() => {
+                            ctx.crossfade = crossfade.clamp(0.0, 1.0);
+                        },
+                        Command::ListAudioDevices => {
+                            ctx.send_file_state(FileType::AudioDevice);
+                        },
+                        Command::SetAudioDevice(name) => {
+                            ctx.project.audio_device = Some(name.clone());
+                            ctx.audio_device_request = Some(name);
+                            ctx.save_project();
+                        },
+                        Command::MeasureLatency => ctx.stream.measure_latency(),
+                        Command::SetSyncOut(enabled, channel, ppqn) => {
+                            if let Err(e) = ctx.set_sync_out(SyncOut { enabled, channel, ppqn }) {
+                                println!("Failed to set sync out: {}", e);
+                            }
+                        },
+                        Command::SetBufferMode(mode) => {
+                            ctx.project.buffer_mode = mode;
+                            ctx.reopen_stream();
+                        },
+                        Command::SetBufferFrames(frames) => {
+                            ctx.project.buffer_frames = (frames > 0).then(|| frames.clamp(MIN_BUFFER_FRAMES, MAX_BUFFER_FRAMES));
+                            ctx.reopen_stream();
+                        },
+                        Command::SetSampleRate(rate) => {
+                            ctx.project.sample_rate = (rate > 0).then(|| rate.clamp(MIN_SAMPLE_RATE, MAX_SAMPLE_RATE));
+                            ctx.reopen_stream();
+                        },
+                        Command::ListMidiPorts => {
+                            ctx.send_file_state(FileType::MidiPort);
+                        },
+                        Command::AddMidiOutput(port_name) => {
+                            ctx.midi_out.add(MidiDestination::new(port_name));
+                            ctx.save_midi_outputs();
+                        },
+                        Command::RemoveMidiOutput(idx) => {
+                            ctx.midi_out.remove(idx);
+                            ctx.save_midi_outputs();
+                        },
+                        Command::SetMidiOutput(idx, clock, transport, notes, cc) => {
+                            if let Some(destination) = ctx.midi_out.get_mut(idx) {
+                                destination.clock = clock;
+                                destination.transport = transport;
+                                destination.notes = notes;
+                                destination.cc = cc;
+                                ctx.save_midi_outputs();
+                            }
+                        },
+                        Command::SetTrackMidiNote(trk, note) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].midi_note = note.min(127);
+                        },
+                        Command::ListMidiInputPorts => {
+                            ctx.send_file_state(FileType::MidiInputPort);
+                        },
+                        Command::AddMidiInput(port_name) => {
+                            ctx.midi_in.add(port_name, ctx_handle.midi_input_handler());
+                            ctx.save_midi_inputs();
+                        },
+                        Command::RemoveMidiInput(idx) => {
+                            // closing waits on the input's handler, which
+                            // may be waiting on the lock held here
+                            if let Some(conn) = ctx.midi_in.remove(idx) {
+                                thread::spawn(move || conn.close());
+                            }
+                            ctx.save_midi_inputs();
+                        },
+                        Command::SetNoteInput(enabled, channel) => {
+                            ctx.project.note_input.enabled = enabled;
+                            ctx.project.note_input.channel = channel.map(|channel| channel.min(15));
+                            ctx.save_project();
+                        },
+                        Command::MapNoteToTrack(note, trk) => {
+                            match trk {
+                                Some(trk) => ctx.project.note_input.notes.insert(note.min(127), trk),
+                                None => ctx.project.note_input.notes.remove(&note),
+                            };
+                            ctx.save_project();
+                        },
+                        Command::LearnMidi(command) => ctx.midi_learn.armed = command.map(|command| *command),
+                        Command::BindMidi(trigger, command) => {
+                            ctx.project.midi_map.bind(trigger, command.map(|command| *command));
+                            ctx.save_project();
+                        },
+                        Command::ListMidiMaps => {
+                            ctx.send_file_state(FileType::MidiMap);
+                        },
+                        Command::SaveMidiMap(name) => {
+                            let midi_map = ctx.project.midi_map.clone();
+                            ctx.disk_writes.push(DiskWrite::MidiMap(name, midi_map));
+                        },
+                        Command::LoadMidiMap(name) => {
+                            match prefetched.midi_map(&name) {
+                                Ok(midi_map) => {
+                                    ctx.project.midi_map = midi_map;
+                                    ctx.save_project();
+                                },
+                                Err(e) => println!("Failed to load midi map: {}", e),
+                            }
+                        },
+                        Command::SetRecord(recording) => ctx.set_record(recording),
+                        Command::SetRecordMode(mode) => ctx.record_mode = mode,
+                        Command::SetPatternName(idx, name) => {
+                            if let Err(e) = ctx.set_pattern_name(idx, name) {
+                                println!("Failed to name pattern: {}", e);
+                            }
+                        },
+                        Command::SetPatternNotes(notes) => ctx.patterns[ctx.pattern_id].notes = notes,
+                        Command::SetProjectNotes(notes) => {
+                            ctx.project.notes = notes;
+                            ctx.save_project();
+                        },
+                        Command::SetTrackArchived(trk, archived) => {
+                            if let Some(track) = ctx.patterns[ctx.pattern_id].tracks.get_mut(trk) {
+                                track.archived = archived;
+                                track.stop_loop();
+                            }
+                        },
+                        Command::MuteTracks(trks, muted) => {
+                            ctx.selected_tracks(&trks).for_each(|track| {
+                                track.muted = muted;
+                                track.stop_loop();
+                            });
+                        },
+                        Command::SetTracksGain(trks, gain_db) => {
+                            ctx.selected_tracks(&trks).for_each(|track| {
+                                track.gain_db = gain_db.clamp(MIN_TRACK_GAIN_DB, MAX_TRACK_GAIN_DB);
+                                track.update_voices();
+                            });
+                        },
+                        Command::ClearTracks(trks) => {
+                            ctx.selected_tracks(&trks).for_each(|track| track.reset_slots());
+                        },
+                        Command::SetTrackErase(trk, erase) => {
+                            if let Some(track) = ctx.patterns[ctx.pattern_id].tracks.get_mut(trk) {
+                                track.erasing = erase;
+                            }
+                        },
+                        Command::SetClockSource(source) => {
+                            ctx.clock_in.source = source;
+                            ctx.project.clock_source = source;
+                            ctx.save_project();
+                        },
+                        Command::SetLanguage(language) => {
+                            ctx.project.language = language;
+                            ctx.save_project();
+                        },
+                        Command::SetMasterVolume(volume_db) => {
+                            ctx.project.master_volume_db = volume_db.clamp(MIN_MASTER_VOLUME_DB, MAX_MASTER_VOLUME_DB);
+                            ctx.apply_master_volume();
+                            ctx.save_project();
+                        },
+                        Command::SetTrackVelocityVariation(trk, amount) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].velocity_variation = amount.min(127);
+                        },
+                        Command::SetTrackPitch(trk, cents) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].pitch_cents = cents.clamp(-MAX_PITCH_CENTS, MAX_PITCH_CENTS);
+                        },
+                        Command::SetTrackReverse(trk, reverse) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].reverse = reverse;
+                        },
+                        Command::SetTrackEnvelope(trk, envelope) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].envelope = envelope;
+                        },
+                        Command::SetTrackFilter(trk, filter) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].filter = Filter {
+                                resonance: filter.resonance.min(MAX_RESONANCE),
+                                ..filter
+                            };
+                        },
+                        Command::SetTrackPolyphony(trk, polyphony) if ctx.has_track(trk) => {
+                            ctx.patterns[ctx.pattern_id].tracks[trk].polyphony = Polyphony {
+                                max_voices: polyphony.max_voices.clamp(1, MAX_POLYPHONY),
+                                ..polyphony
+                            };
+                        },
+                        Command::SetDelay(division, feedback_pct) => {
+                            ctx.project.delay = Delay { division, feedback_pct: feedback_pct.min(MAX_DELAY_FEEDBACK) };
+                            ctx.apply_delay();
+                            ctx.save_project();
+                        },
+                        Command::SetTrackDelaySend(trk, send) if ctx.has_track(trk) => {
+                            let track = &mut ctx.patterns[ctx.pattern_id].tracks[trk];
+                            track.delay_send = send.min(MAX_SEND);
+                            track.update_voices();
+                        },
+                        Command::LoadDemo(style) => ctx.load_demo(style),
+                        Command::SetReverb(reverb) => {
+                            ctx.project.reverb = reverb.clamped();
+                            ctx.stream.set_reverb(ctx.project.reverb);
+                            ctx.save_project();
+                        },
+                        Command::SetTrackReverbSend(trk, send) if ctx.has_track(trk) => {
+                            let track = &mut ctx.patterns[ctx.pattern_id].tracks[trk];
+                            track.reverb_send = send.min(MAX_SEND);
+                            track.update_voices();
+                        },
+                        Command::ListHistory => {
+                            ctx.send_file_state(FileType::History);
+                        },
+                        // Read and decoded off the command loop like a pattern load
+                        Command::RestoreSnapshot(id) => {
+                            match ctx.history.fname(id) {
+                                Ok(fname) => {
+                                    let ctx_handle = ctx_handle.clone();
+                                    thread::spawn(move || restore_snapshot(&ctx_handle, id, &fname));
+                                },
+                                Err(e) => println!("Failed to restore snapshot: {}", e),
+                            }
+                        },
                         _ => ()
                     }
-                } else {
-                    // do nothing
                 }
+                // Anything the commands let go of is evicted now rather
+                // than waiting on the next sample load
+                ctx.samples.trim();
+                if !ctx.persist {
+                    ctx.project_changed = false;
+                    ctx.disk_writes.clear();
+                }
+                let project = std::mem::take(&mut ctx.project_changed).then(|| ctx.project.clone());
+                let writes = std::mem::take(&mut ctx.disk_writes);
+                let snapshot = (ctx.persist && ctx.history.is_due()).then(|| ctx.snapshot_state());
+                (snapshot, project, writes)
             });
-            yield_now();
+            if let Some(project) = project {
+                if let Err(e) = project.save() {
+                    println!("Failed to save project: {}", e);
+                }
+            }
+            write_files(&ctx, writes);
+            if let Some(state) = snapshot {
+                take_snapshot(&ctx, state);
+            }
+        }
+    }
+
+    /// Frames ahead of the stream pulses are scheduled
+    fn schedule_ahead(&self) -> u64 {
+        (self.audio.handle.last_buffer_frames() * SCHEDULE_AHEAD_BUFFERS).max(MIN_SCHEDULE_AHEAD)
+    }
+
+    /// Frame of the stream the pulse about to be played is due at
+    ///
+    /// That's a pulse on from the last one, unless playback is starting or
+    /// it's fallen so far behind that catching up would rush the pulses,
+    /// then it's scheduled ahead from where the stream is now
+    fn pulse_due(&self) -> f64 {
+        let pulled = self.audio.handle.frames_pulled() as f64;
+        let ahead = self.schedule_ahead() as f64;
+        match self.next_pulse {
+            // never further ahead than that either, for pulses played
+            // back to back without sleeping
+            Some(due) if due >= pulled - ahead => due.min(pulled + ahead),
+            _ => pulled + ahead,
         }
     }
 
     /// Sleep between pulses
-    pub fn sleep(&self) {
-        self.sleeper.sleep(self.sleep_interval);
+    ///
+    /// While playing it waits on the stream's clock until the next pulse is
+    /// due to be scheduled, so when the thread wakes only decides how far
+    /// ahead the hits are queued and not when they're heard. The midi held
+    /// back is sent as it comes due in between
+    pub fn sleep(&mut self) {
+        let Some(next_pulse) = self.next_pulse else {
+            self.sleeper.sleep(self.midi_wait().map_or(self.sleep_interval, |wait| wait.min(self.sleep_interval)));
+            self.flush_midi(self.audio.handle.stream_position());
+            return;
+        };
+        let ahead = self.schedule_ahead() as f64;
+        let sample_rate = self.audio.sample_rate as f64;
+        let give_up = Instant::now() + MAX_PULSE_WAIT;
+        loop {
+            self.flush_midi(self.audio.handle.stream_position());
+            let wait = next_pulse - ahead - self.audio.handle.stream_position();
+            let now = Instant::now();
+            if wait <= 0.0 || now >= give_up {
+                return;
+            }
+            let wait = Duration::from_secs_f64(wait / sample_rate).min(give_up - now);
+            self.sleeper.sleep(self.midi_wait().map_or(wait, |midi_wait| midi_wait.min(wait)));
+        }
     }
 
     /// Runs the sequencer
@@ -1181,4 +6224,117 @@ impl Sequencer {
         }
     }
 
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture(name: &str) -> BufferedSample {
+        BufferedSample::load_from_file(&format!("{PWD}/fixtures/{name}"), true)
+            .unwrap_or_else(|e| panic!("{}: {}", name, e))
+    }
+
+    #[test]
+    fn loads_flac() {
+        let sample = fixture("tone.flac");
+        assert_eq!((sample.channels, sample.sample_rate), (2, 44100));
+        assert_eq!(sample.file_frames(), 1024);
+        assert!(sample.buffer.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn loads_aiff() {
+        let sample = fixture("tone.aiff");
+        assert_eq!((sample.channels, sample.sample_rate), (1, 22050));
+        assert_eq!(sample.file_frames(), 500);
+        assert!(sample.buffer.iter().any(|s| s.abs() > 0.1));
+    }
+
+    #[test]
+    fn loads_ogg() {
+        let sample = fixture("silence.ogg");
+        assert_eq!((sample.channels, sample.sample_rate), (2, 48000));
+        assert_eq!(sample.file_frames(), 40 * 128);
+    }
+
+    #[test]
+    fn loads_mp3() {
+        let sample = fixture("silence.mp3");
+        assert_eq!((sample.channels, sample.sample_rate), (1, 44100));
+        assert_eq!(sample.file_frames(), 4 * 1152);
+    }
+
+    #[test]
+    fn reports_a_file_it_cant_decode() {
+        let err = BufferedSample::load_from_file(&format!("{PWD}/Cargo.toml"), true).err().unwrap();
+        assert!(err.to_string().starts_with("can't be decoded"));
+    }
+
+    #[test]
+    fn a_demos_samples_are_decoded_before_the_lock() {
+        let sample_paths = samples_to_load(&Command::LoadDemo(DemoStyle::House));
+        let demo = Demo::new(DemoStyle::House);
+        for (_, saved, _) in &demo.patterns {
+            for track in &saved.tracks {
+                assert!(sample_paths.contains(&track.sample_path));
+            }
+        }
+    }
+
+    #[test]
+    fn resamples_to_the_output_rate() {
+        let sample = fixture("tone.aiff").resampled(44100);
+        assert_eq!(sample.sample_rate, 44100);
+        assert_eq!(sample.frames(), 1000);
+        assert_eq!(sample.file_frames(), 500);
+    }
+
+    fn song(patterns: &[(usize, usize)]) -> Song {
+        let mut song = Song::default();
+        patterns.iter().for_each(|&(pattern_id, repeats)| song.add_entry(pattern_id, repeats));
+        song
+    }
+
+    #[test]
+    fn removing_song_entries_moves_the_loop_region() {
+        let mut s = song(&[(0, 1), (1, 1), (2, 1), (3, 1), (4, 1)]);
+        s.set_loop_region(1, 3);
+        s.remove_entry(4);
+        assert_eq!(s.loop_region, Some((1, 3)));
+        s.remove_entry(0);
+        assert_eq!(s.loop_region, Some((0, 2)));
+        s.remove_entry(1);
+        assert_eq!(s.loop_region, Some((0, 1)));
+        s.remove_entry(0);
+        assert_eq!(s.loop_region, Some((0, 0)));
+        s.remove_entry(0);
+        assert_eq!(s.loop_region, None);
+    }
+
+    #[test]
+    fn removing_the_playing_entry_starts_the_next_one_over() {
+        let mut s = song(&[(0, 4), (1, 4), (2, 4)]);
+        s.position = 1;
+        s.bars_played = 2;
+        s.remove_entry(0);
+        assert_eq!((s.position, s.bars_played), (0, 2));
+        s.remove_entry(0);
+        assert_eq!((s.position, s.bars_played), (0, 0));
+    }
+
+    #[test]
+    fn the_song_advances_through_repeats_and_the_loop_region() {
+        let mut s = song(&[(5, 2), (6, 1), (7, 1)]);
+        assert_eq!(s.advance(), Some(5));
+        assert_eq!(s.advance(), Some(6));
+        assert_eq!(s.next_position(), Some(2));
+        s.set_loop_region(1, 2);
+        assert_eq!(s.advance(), Some(7));
+        assert_eq!(s.next_position(), Some(1));
+        assert_eq!(s.advance(), Some(6));
+        s.clear_loop_region();
+        assert_eq!(s.advance(), Some(7));
+        assert_eq!(s.next_position(), None);
+        assert_eq!(s.advance(), None);
+    }
+}
@@ -0,0 +1,66 @@
+use crate::sequencer::{SavedPattern, PPB};
+use std::collections::HashSet;
+use std::error::Error;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+/// Saved patterns returned by a search, closest first
+const MAX_MATCHES: usize = 10;
+
+/// Which samples hit where, velocity and the finer details are left out
+/// so two takes of the same idea still line up
+///
+/// Hits are keyed by the track's sample rather than its position so
+/// reordered tracks still match, and by pulse rather than step so the
+/// same beat written at a different division does too
+pub struct Fingerprint {
+    hits: HashSet<(String, usize)>,
+}
+
+impl Fingerprint {
+    pub fn new(pattern: &SavedPattern) -> Self {
+        let step = PPB as usize / pattern.division as usize;
+        let hits = pattern.tracks.iter()
+            .filter(|track| !track.archived)
+            .flat_map(|track| {
+                track.slots.iter().enumerate()
+                    .filter(|(_, slot)| slot.velocity > 0)
+                    .map(move |(i, _)| (track.sample_path.clone(), i * step))
+            })
+            .collect();
+        Fingerprint { hits }
+    }
+
+    /// Share of hits the two have in common, 1 is the same beat and 0 has
+    /// nothing in common
+    pub fn similarity(&self, other: &Fingerprint) -> f32 {
+        let union = self.hits.union(&other.hits).count();
+        if union == 0 {
+            return 0.0;
+        }
+        self.hits.intersection(&other.hits).count() as f32 / union as f32
+    }
+}
+
+/// Saved pattern files closest to the pattern given, best first
+///
+/// Files that can't be read are skipped, as are ones with nothing in common
+pub fn closest(pattern: &SavedPattern, fnames: &[String]) -> Vec<String> {
+    let fingerprint = Fingerprint::new(pattern);
+    let mut scores: Vec<(f32, &String)> = fnames.iter()
+        .filter_map(|fname| match load(fname) {
+            Ok(saved) => Some((fingerprint.similarity(&Fingerprint::new(&saved)), fname)),
+            Err(e) => {
+                println!("Failed to read pattern {}: {}", fname, e);
+                None
+            }
+        })
+        .filter(|(score, _)| *score > 0.0)
+        .collect();
+    scores.sort_by(|a, b| b.0.total_cmp(&a.0));
+    scores.into_iter().take(MAX_MATCHES).map(|(_, fname)| fname.clone()).collect()
+}
+
+fn load(fname: &str) -> Result<SavedPattern, Box<dyn Error>> {
+    let file = std::fs::File::open(format!("{PWD}/patterns/{}", fname))?;
+    Ok(serde_json::from_reader(std::io::BufReader::new(file))?)
+}
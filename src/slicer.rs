@@ -0,0 +1,54 @@
+use rodio::Source;
+use std::error::Error;
+use std::fs::File;
+
+const PWD: &str = env!("CARGO_MANIFEST_DIR");
+pub const MIN_SLICES: usize = 2;
+pub const MAX_SLICES: usize = 32;
+/// Folder in samples/ slices are saved to, one folder per loop
+const SLICE_DIR: &str = "slices";
+/// Fade over the end of each slice, so cutting mid waveform doesn't click
+const SLICE_FADE_SECS: f64 = 0.002;
+
+/// Chops a loop from the samples folder into equal slices, saved as
+/// samples/slices/{name}/{name}_{n}.wav at the loop's rate
+///
+/// Returns the paths of the slices within the samples folder, in order.
+/// Slicing the same loop again writes over the last slices
+pub fn slice(sample_path: &str, slices: usize) -> Result<Vec<String>, Box<dyn Error>> {
+    let decoder = rodio::Decoder::new(File::open(format!("{PWD}/samples/{}", sample_path))?)
+        .map_err(|e| format!("{} can't be decoded, {}", sample_path, e))?;
+    let channels = decoder.channels();
+    let sample_rate = decoder.sample_rate();
+    let samples: Vec<f32> = decoder.convert_samples::<f32>().collect();
+    let frames = samples.len() / channels as usize;
+    if frames < slices {
+        return Err(format!("{} is too short for {} slices", sample_path, slices).into());
+    }
+    let name = sample_path.rsplit('/').next().unwrap_or(sample_path);
+    let name = name.split('.').next().unwrap_or(name).replace(" ", "_");
+    std::fs::create_dir_all(format!("{PWD}/samples/{}/{}", SLICE_DIR, name))?;
+    let fade = (SLICE_FADE_SECS * sample_rate as f64) as usize;
+    let spec = hound::WavSpec {
+        channels,
+        sample_rate,
+        bits_per_sample: 32,
+        sample_format: hound::SampleFormat::Float,
+    };
+    let mut slice_paths = vec![];
+    for i in 0..slices {
+        let (start, end) = (frames * i / slices, frames * (i + 1) / slices);
+        let slice_path = format!("{}/{}/{}_{}.wav", SLICE_DIR, name, name, i + 1);
+        let mut writer = hound::WavWriter::create(format!("{PWD}/samples/{}", slice_path), spec)?;
+        let fade = fade.min(end - start);
+        for frame in start..end {
+            let gain = ((end - frame) as f32 / fade.max(1) as f32).min(1.0);
+            for ch in 0..channels as usize {
+                writer.write_sample(samples[frame * channels as usize + ch] * gain)?;
+            }
+        }
+        writer.finalize()?;
+        slice_paths.push(slice_path);
+    }
+    Ok(slice_paths)
+}
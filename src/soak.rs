@@ -0,0 +1,294 @@
+use crate::eq::TrackEq;
+use crate::sequencer::{Command, Direction, Division, Envelope, Filter, FilterMode, FitMode, FxBypass, LoopSync, Polyphony, RecordMode, Sequencer, StealPolicy, SwapPolicy, Swing, SwingGrid, VoiceMode};
+use rand::rngs::StdRng;
+use rand::{Rng, SeedableRng};
+use std::collections::VecDeque;
+use std::error::Error;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// Soak mode, for checking the command loop holds up before leaving a
+// box running for weeks. Random commands, some valid and some not, are
+// fed in while the sequencer runs pulse after pulse without sleeping, so
+// an hour of soak covers a lot more bars than an hour of playing.
+//
+// Build with --features soak and run with `rdum --soak [minutes] [seed]`.
+// The seed is printed at the start so a failure can be run again.
+//
+// Anything that saves patterns, exports or touches the audio and midi
+// devices is left out, and project.json and the history snapshots are
+// left alone while it runs, so it's safe to run on the real install.
+
+/// Longest a pulse may take, waiting on the lock included. At the top
+/// tempo pulses are about 2.5ms apart so anything longer falls behind
+const MAX_PULSE: Duration = Duration::from_micros(2500);
+/// Commands sent between pulses
+const BURST: usize = 8;
+/// Commands kept to print when something goes wrong
+const HISTORY: usize = 32;
+/// Past these the generator only removes, so memory stays flat
+const MAX_PATTERNS: usize = 16;
+const MAX_TRACKS: usize = 16;
+
+/// Sizes of the current project so indexes can be picked around them
+struct Shape {
+    patterns: usize,
+    tracks: usize,
+    slots: usize,
+    cc_lanes: usize,
+    song_entries: usize,
+}
+
+#[derive(Default)]
+struct PulseStats {
+    pulses: u64,
+    commands: u64,
+    total: Duration,
+    max: Duration,
+    over: u64,
+}
+
+impl PulseStats {
+    fn report(&self) {
+        let mean = self.total.checked_div(self.pulses as u32).unwrap_or_default();
+        println!(
+            "{} pulses, {} commands, pulse mean {:?} max {:?}, {} over {:?}",
+            self.pulses, self.commands, mean, self.max, self.over, MAX_PULSE,
+        );
+    }
+}
+
+/// Runs the soak and returns an error if the command loop panicked or a
+/// pulse took longer than MAX_PULSE
+pub fn run(mut seq: Sequencer, duration: Duration, seed: u64) -> Result<(), Box<dyn Error>> {
+    println!("Soaking for {:?} with seed {}", duration, seed);
+    seq.ctx.with_lock(|ctx| ctx.persist = false);
+    let ctx = seq.ctx.clone();
+    let cmd_loop = thread::spawn(move || Sequencer::run_command_loop(ctx));
+    let cmd_tx = seq.get_command_tx();
+
+    // The default hook prints the panic, this adds what led up to it
+    let sent: Arc<Mutex<VecDeque<Command>>> = Arc::new(Mutex::new(VecDeque::with_capacity(HISTORY)));
+    let hook_sent = sent.clone();
+    let default_hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        default_hook(info);
+        if let Ok(sent) = hook_sent.lock() {
+            println!("Last {} commands sent, oldest first:", sent.len());
+            sent.iter().for_each(|cmd| println!("  {:?}", cmd));
+        }
+    }));
+
+    let mut rng = StdRng::seed_from_u64(seed);
+    let mut stats = PulseStats::default();
+    let start = Instant::now();
+    cmd_tx.send(Command::PlaySequencer)?;
+    while start.elapsed() < duration {
+        if cmd_loop.is_finished() {
+            stats.report();
+            return Err("command loop panicked".into());
+        }
+        let shape = seq.ctx.with_lock(|ctx| {
+            let pattern = &ctx.patterns[ctx.pattern_id];
+            Shape {
+                patterns: ctx.patterns.len(),
+                tracks: pattern.tracks.len(),
+                slots: pattern.tracks.first().map_or(0, |track| track.slots.len()),
+                cc_lanes: pattern.cc_lanes.len(),
+                song_entries: ctx.song.entries.len(),
+            }
+        });
+        for _ in 0..BURST {
+            let cmd = random_command(&mut rng, &shape);
+            {
+                let mut sent = sent.lock().unwrap();
+                if sent.len() == HISTORY {
+                    sent.pop_front();
+                }
+                sent.push_back(cmd.clone());
+            }
+            cmd_tx.send(cmd)?;
+            stats.commands += 1;
+        }
+
+        let pulse_start = Instant::now();
+        if panic::catch_unwind(AssertUnwindSafe(|| seq.play_next())).is_err() {
+            stats.report();
+            return Err("sequencer panicked".into());
+        }
+        let pulse = pulse_start.elapsed();
+        stats.pulses += 1;
+        stats.total += pulse;
+        stats.max = stats.max.max(pulse);
+        if pulse > MAX_PULSE {
+            stats.over += 1;
+        }
+        if stats.pulses % 100_000 == 0 {
+            stats.report();
+        }
+    }
+
+    stats.report();
+    if stats.over > 0 {
+        return Err(format!("{} pulses took longer than {:?}", stats.over, MAX_PULSE).into());
+    }
+    println!("Soak passed");
+    Ok(())
+}
+
+/// An index that's usually in range but now and then one or two past the end
+fn index(rng: &mut StdRng, len: usize) -> usize {
+    rng.random_range(0..len + 2)
+}
+
+/// A few track indices, duplicates and out of range ones included
+fn selection(rng: &mut StdRng, len: usize) -> Vec<usize> {
+    (0..rng.random_range(0..4)).map(|_| index(rng, len)).collect()
+}
+
+/// Velocities are mostly sensible with the odd out of range one
+fn velocity(rng: &mut StdRng) -> u8 {
+    if rng.random_bool(0.9) { rng.random_range(0..=127) } else { rng.random() }
+}
+
+fn tempo(rng: &mut StdRng) -> f32 {
+    match rng.random_range(0..10) {
+        0 => f32::NAN,
+        1 => f32::INFINITY,
+        2 => -rng.random_range(0.0..1000.0),
+        3 => rng.random_range(1000.0..100_000.0),
+        _ => rng.random_range(40.0..300.0),
+    }
+}
+
+fn density(rng: &mut StdRng) -> f32 {
+    match rng.random_range(0..10) {
+        0 => f32::NAN,
+        1 => rng.random_range(-2.0..3.0),
+        _ => rng.random(),
+    }
+}
+
+fn sample_path(rng: &mut StdRng) -> String {
+    match rng.random_range(0..4) {
+        0 => "soak/missing.wav".to_string(),
+        1 => String::new(),
+        _ => format!("kit0/{}.wav", ["kick", "snare", "hat", "open_hat"][rng.random_range(0..4)]),
+    }
+}
+
+fn random_command(rng: &mut StdRng, shape: &Shape) -> Command {
+    let trk = index(rng, shape.tracks);
+    let slot = index(rng, shape.slots);
+    let pattern = index(rng, shape.patterns);
+    match rng.random_range(0..48) {
+        0 => Command::PlaySequencer,
+        1 => Command::StopSequencer,
+        2 => Command::PauseSequencer,
+        3 => Command::ContinueSequencer,
+        4 => Command::SetTempo(tempo(rng)),
+        5 => Command::SetTempoSlew(rng.random()),
+        6 => Command::SetAccentVelocity(velocity(rng)),
+        7 => Command::PlaySound(trk, velocity(rng)),
+        8 => Command::SetSlotVelocity(trk, slot, velocity(rng)),
+        9 => Command::SetSlotAccent(trk, slot, rng.random()),
+        10 => Command::SetSlotRatchet(trk, slot, rng.random()),
+        11 => Command::SetSlotRatchetDecay(trk, slot, rng.random()),
+        12 => Command::SetTrackDirection(trk, Direction::from(rng.random_range(-1..5i64))),
+        13 => Command::SetTrackTimingOffset(trk, rng.random()),
+        14 => Command::RandomizeTrack(trk, density(rng), rng.random(), rng.random()),
+        15 => Command::SetTrackLoop(trk, rng.random_range(0..100_000), rng.random_range(0..100_000)),
+        16 => Command::ClearTrackLoop(trk),
+        17 if shape.patterns < MAX_PATTERNS => Command::AddPattern,
+        18 if shape.patterns < MAX_PATTERNS => Command::DuplicatePattern(pattern),
+        19 if shape.patterns < MAX_PATTERNS => Command::CopyTrack(pattern, trk, index(rng, shape.patterns)),
+        17..=19 => Command::RemovePattern(pattern),
+        20 => Command::RemovePattern(pattern),
+        21 => Command::SelectPattern(pattern),
+        22 => Command::QueuePattern(pattern),
+        23 => Command::ClearPatternQueue,
+        24 => Command::SetPatternLength(rng.random_range(0..=64)),
+        25 => Command::LoadPattern("soak-missing.json".to_string()),
+        26 => Command::SetSwing(Swing::from(rng.random_range(-1..10i64))),
+        27 => Command::SetSongMode(rng.random()),
+        28 => Command::AddSongEntry(pattern, rng.random_range(0..4)),
+        29 => Command::SetSongEntry(index(rng, shape.song_entries), pattern, rng.random_range(0..4)),
+        30 => Command::RemoveSongEntry(index(rng, shape.song_entries)),
+        31 => Command::SetSongLoop(index(rng, shape.song_entries), index(rng, shape.song_entries)),
+        32 => Command::SetDivision(Division::from(rng.random_range(0..40i64))),
+        33 => Command::RandomizePattern(density(rng), rng.random(), rng.random()),
+        34 => Command::SetHumanize(rng.random(), rng.random()),
+        35 => Command::SetGroove("soak-missing".to_string(), rng.random()),
+        36 => Command::AddCcLane(rng.random(), rng.random()),
+        37 => Command::RemoveCcLane(index(rng, shape.cc_lanes)),
+        38 => Command::SetCcStep(index(rng, shape.cc_lanes), slot, rng.random_bool(0.8).then(|| rng.random())),
+        39 if shape.tracks < MAX_TRACKS => Command::AddTrack,
+        39 | 40 => Command::RemoveTrack(trk),
+        41 => Command::MoveTrack(trk, index(rng, shape.tracks)),
+        42 => Command::SetTrackSample(trk, sample_path(rng)),
+        43 => Command::SetSwapPolicy(SwapPolicy::from(rng.random_range(-1..4i64))),
+        44 => Command::SetPatternFill(rng.random_bool(0.7).then_some(pattern)),
+        45 => Command::TriggerFill(rng.random()),
+        46 => Command::SetRecord(rng.random()),
+        _ => match rng.random_range(0..33) {
+            0 => Command::SetRecordMode(RecordMode::from(rng.random_range(-1..3i64))),
+            1 => Command::SetTrackErase(trk, rng.random()),
+            2 => Command::SetTrackArchived(trk, rng.random()),
+            3 => Command::SetPatternName(pattern, if rng.random() { "soak".to_string() } else { " ".to_string() }),
+            4 => Command::SetTrackMidiNote(trk, rng.random()),
+            5 => Command::ClearTrack(trk),
+            6 => Command::ClearPattern,
+            7 => Command::PlayForBars(rng.random_range(0..4)),
+            8 => Command::SetDeckB(rng.random_bool(0.7).then_some(pattern)),
+            9 => Command::SetCrossfade(density(rng)),
+            10 => Command::SetTrackLength(trk, rng.random_range(0..=64)),
+            11 => Command::SetSwingGrid(SwingGrid::from(rng.random_range(-1..20i64))),
+            12 => Command::SetTrackPitch(trk, rng.random()),
+            13 => Command::SetTrackReverse(trk, rng.random()),
+            14 => Command::SetSlotReverse(trk, slot, rng.random()),
+            15 => Command::MuteTracks(selection(rng, shape.tracks), rng.random()),
+            16 => Command::SetTracksGain(selection(rng, shape.tracks), rng.random()),
+            17 => Command::ClearTracks(selection(rng, shape.tracks)),
+            18 => Command::SetTrackEnvelope(trk, Envelope {
+                attack_ms: rng.random(),
+                hold_ms: rng.random(),
+                decay_ms: rng.random(),
+            }),
+            19 => Command::SetTrackFilter(trk, Filter {
+                mode: FilterMode::from(rng.random_range(-1..4i64)),
+                cutoff_hz: rng.random(),
+                resonance: rng.random(),
+            }),
+            20 => Command::ReportBudget,
+            21 => Command::SetTrackDelaySend(trk, rng.random()),
+            22 => Command::SetTrackReverbSend(trk, rng.random()),
+            23 => Command::SetTrackEq(trk, TrackEq {
+                low_db: rng.random(),
+                high_db: rng.random(),
+            }),
+            24 => Command::SetTrackFxBypass(trk, FxBypass {
+                eq: rng.random(),
+                filter: rng.random(),
+                delay: rng.random(),
+                reverb: rng.random(),
+            }),
+            25 => Command::CopyTrackFeel(trk, index(rng, shape.tracks)),
+            26 => Command::SetTrackPolyphony(trk, Polyphony {
+                mode: VoiceMode::from(rng.random_range(-1..3i64)),
+                max_voices: rng.random(),
+                steal: StealPolicy::from(rng.random_range(-1..3i64)),
+            }),
+            27 => Command::SetChokeFade(rng.random()),
+            28 => Command::PreviewSample(sample_path(rng), velocity(rng)),
+            29 => Command::SetTrackLayer(trk, velocity(rng), sample_path(rng)),
+            30 => Command::RemoveTrackLayer(trk, velocity(rng)),
+            31 => Command::SetTrackLoopSync(trk, rng.random_bool(0.7).then_some(LoopSync {
+                bpm: rng.random_range(0.0..300.0),
+                mode: FitMode::from(rng.random_range(-1..3i64)),
+            })),
+            _ => Command::SetTrackVelocityVariation(trk, rng.random()),
+        },
+    }
+}
@@ -0,0 +1,64 @@
+/// Length of each grain, long enough to hold a cycle of a low note
+const GRAIN_SECS: f64 = 0.04;
+/// Furthest either way a grain moves to line up with the one before
+const SEEK_SECS: f64 = 0.008;
+/// Frames skipped between the ones compared when lining grains up, the
+/// waveforms only need to roughly agree
+const SEEK_STRIDE: usize = 4;
+
+/// Changes the length of interleaved audio by ratio without changing its
+/// pitch, 2 makes it twice as long
+///
+/// Windowed grains are overlapped half a grain apart (WSOLA). Each is taken
+/// from about where the ratio puts it in the input, moved to whichever
+/// spot nearby best carries on from the grain before so the waveforms
+/// line up rather than cancelling out. The first grain isn't faded in, so
+/// a hit right at the start keeps its attack
+pub fn stretch(samples: &[f32], channels: usize, sample_rate: u32, ratio: f64) -> Vec<f32> {
+    let frames = samples.len() / channels;
+    if ratio == 1.0 || frames == 0 {
+        return samples.to_vec();
+    }
+    let grain = ((GRAIN_SECS * sample_rate as f64) as usize).max(4) & !1;
+    let hop = grain / 2;
+    let seek = (SEEK_SECS * sample_rate as f64) as usize;
+    let out_frames = (frames as f64 * ratio).round() as usize;
+    // a periodic Hann window, grains half a window apart sum to 1
+    let window: Vec<f32> = (0..grain)
+        .map(|i| 0.5 - 0.5 * (std::f32::consts::TAU * i as f32 / grain as f32).cos())
+        .collect();
+    let mono: Vec<f32> = samples.chunks(channels).map(|frame| frame.iter().sum()).collect();
+    let mono_at = |frame: usize| mono.get(frame).copied().unwrap_or(0.0);
+    let mut out = vec![0.0; (out_frames + grain) * channels];
+    let mut last_from: Option<usize> = None;
+    let mut out_pos = 0;
+    while out_pos < out_frames {
+        let nominal = ((out_pos as f64 / ratio) as usize).min(frames - 1);
+        let from = match last_from {
+            None => nominal,
+            Some(last_from) => {
+                // where the last grain would have carried on to
+                let natural = last_from + hop;
+                let lo = nominal.saturating_sub(seek);
+                let hi = (nominal + seek).min(frames - 1);
+                let score = |from: usize| -> f32 {
+                    (0..hop).step_by(SEEK_STRIDE).map(|i| mono_at(natural + i) * mono_at(from + i)).sum()
+                };
+                (lo..=hi).step_by(SEEK_STRIDE / 2)
+                    .map(|from| (from, score(from)))
+                    .max_by(|a, b| a.1.total_cmp(&b.1))
+                    .map_or(nominal, |(from, _)| from)
+            }
+        };
+        for i in 0..grain.min(frames - from) {
+            let gain = if out_pos == 0 && i < hop { 1.0 } else { window[i] };
+            for ch in 0..channels {
+                out[(out_pos + i) * channels + ch] += samples[(from + i) * channels + ch] * gain;
+            }
+        }
+        last_from = Some(from);
+        out_pos += hop;
+    }
+    out.truncate(out_frames * channels);
+    out
+}
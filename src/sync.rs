@@ -0,0 +1,150 @@
+use serde::{Serialize, Deserialize};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+/// Midi clock runs at 24 ticks a beat, the same as our pulses
+const TICKS_PER_BEAT: usize = 24;
+/// Without a tick for this long the external clock counts as gone
+const CLOCK_TIMEOUT: Duration = Duration::from_millis(250);
+/// Drift under this still counts as locked
+const LOCK_TOLERANCE_MS: f32 = 5.0;
+/// Tick and pulse times kept for matching them up, a bar's worth
+const TIMES_KEPT: usize = TICKS_PER_BEAT * 4;
+
+/// Where tempo and transport come from
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize, PartialEq)]
+pub enum ClockSource {
+    /// free running on our own clock
+    #[default]
+    Internal = 0,
+    /// follows the tempo and start/stop of clock on the midi input
+    Midi = 1,
+}
+
+impl From<i64> for ClockSource {
+    fn from(value: i64) -> Self {
+        match value {
+            1 => ClockSource::Midi,
+            _ => ClockSource::Internal,
+        }
+    }
+}
+
+impl From<u32> for ClockSource {
+    fn from(value: u32) -> Self {
+        match value {
+            1 => ClockSource::Midi,
+            _ => ClockSource::Internal,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct SyncStatus {
+    pub source: ClockSource,
+    /// tempo of the clock on the midi input, None if there isn't one
+    pub external_bpm: Option<f32>,
+    /// how far our pulses land behind the external ticks, negative is ahead
+    pub drift_ms: f32,
+    pub locked: bool,
+}
+
+/// Watches midi clock on the input to estimate its tempo and how far
+/// our own pulses drift from it
+#[derive(Default)]
+pub struct ClockFollower {
+    pub source: ClockSource,
+    /// times of recent external ticks and our pulses, by count since start
+    ticks: VecDeque<(u64, Instant)>,
+    pulses: VecDeque<(u64, Instant)>,
+    tick_count: u64,
+    pulse_count: u64,
+    drift: f32,
+}
+
+impl ClockFollower {
+    /// Records an external clock tick, returning the tempo estimate on
+    /// every beat so the caller can follow it
+    pub fn tick(&mut self) -> Option<f32> {
+        let now = Instant::now();
+        if !self.receiving() {
+            self.ticks.clear();
+        }
+        self.record_tick(self.tick_count, now);
+        self.tick_count += 1;
+        if self.tick_count.is_multiple_of(TICKS_PER_BEAT as u64) {
+            self.external_bpm()
+        } else {
+            None
+        }
+    }
+
+    /// External start, tick counting starts over to line up with our first pulse
+    pub fn start(&mut self) {
+        self.tick_count = 0;
+        self.ticks.clear();
+    }
+
+    /// Our own transport started, pulse counting starts over
+    pub fn restart_pulses(&mut self) {
+        self.pulse_count = 0;
+        self.pulses.clear();
+        self.drift = 0.0;
+    }
+
+    /// Records one of our pulses
+    pub fn pulse(&mut self) {
+        let now = Instant::now();
+        if let Some(&(_, tick_time)) = self.ticks.iter().find(|(n, _)| *n == self.pulse_count) {
+            self.drift = duration_ms(now, tick_time);
+        }
+        self.pulses.push_back((self.pulse_count, now));
+        if self.pulses.len() > TIMES_KEPT {
+            self.pulses.pop_front();
+        }
+        self.pulse_count += 1;
+    }
+
+    fn record_tick(&mut self, n: u64, time: Instant) {
+        if let Some(&(_, pulse_time)) = self.pulses.iter().find(|(p, _)| *p == n) {
+            self.drift = duration_ms(pulse_time, time);
+        }
+        self.ticks.push_back((n, time));
+        if self.ticks.len() > TIMES_KEPT {
+            self.ticks.pop_front();
+        }
+    }
+
+    fn receiving(&self) -> bool {
+        self.ticks.back().is_some_and(|(_, time)| time.elapsed() < CLOCK_TIMEOUT)
+    }
+
+    /// Averaged over the ticks kept, which smooths out usb jitter
+    pub fn external_bpm(&self) -> Option<f32> {
+        if !self.receiving() || self.ticks.len() < 2 {
+            return None;
+        }
+        let (first, last) = (self.ticks.front()?.1, self.ticks.back()?.1);
+        let tick_len = last.duration_since(first).as_secs_f32() / (self.ticks.len() - 1) as f32;
+        Some(60.0 / (tick_len * TICKS_PER_BEAT as f32))
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        let following = self.source == ClockSource::Midi && self.receiving();
+        SyncStatus {
+            source: self.source,
+            external_bpm: self.external_bpm(),
+            drift_ms: if following { self.drift } else { 0.0 },
+            locked: following && self.drift.abs() < LOCK_TOLERANCE_MS,
+        }
+    }
+}
+
+/// Signed difference a - b in ms
+fn duration_ms(a: Instant, b: Instant) -> f32 {
+    if a >= b {
+        a.duration_since(b).as_secs_f32() * 1000.0
+    } else {
+        -(b.duration_since(a).as_secs_f32() * 1000.0)
+    }
+}
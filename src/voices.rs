@@ -0,0 +1,273 @@
+use crate::audio::SEND_BUSES;
+use rodio::source::UniformSourceIterator;
+use rodio::Source;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use std::time::Duration;
+
+/// Voices summed at once, ones started past this wait for one to end
+const MAX_VOICES: usize = 128;
+
+type VoiceSource = Box<dyn Source<Item = f32> + Send>;
+
+/// How a voice starts out, everything but the delay and fades can be
+/// changed through its handle as it plays
+#[derive(Clone, Copy)]
+pub struct VoiceStart {
+    /// silence before the voice, for timing offsets and swing
+    pub delay: Duration,
+    pub gain: f32,
+    /// level into each effect bus on top of gain, by bus, 1 is all of it
+    pub sends: [f32; SEND_BUSES],
+    pub fade_in: Duration,
+    /// how long a fade out takes, zero cuts like a stop
+    pub fade_out: Duration,
+    /// pair of output channels it plays on, 0 is the first two. A pair
+    /// the stream doesn't have plays on the first
+    pub output: usize,
+}
+
+impl Default for VoiceStart {
+    fn default() -> Self {
+        VoiceStart {
+            delay: Duration::ZERO,
+            gain: 1.0,
+            sends: [0.0; SEND_BUSES],
+            fade_in: Duration::ZERO,
+            fade_out: Duration::ZERO,
+            output: 0,
+        }
+    }
+}
+
+/// Shared between a voice in the mixer and the sequencer's handle to it
+struct VoiceControl {
+    /// bits of the f32 levels
+    gain: AtomicU32,
+    sends: [AtomicU32; SEND_BUSES],
+    fading: AtomicBool,
+    /// frames the fade out takes, read when it starts
+    fade_len: AtomicU32,
+    sample_rate: u32,
+    stopped: AtomicBool,
+    /// set by the mixer once the voice has left it
+    ended: AtomicBool,
+}
+
+/// The sequencer's hold on a playing voice
+#[derive(Clone)]
+pub struct VoiceHandle(Arc<VoiceControl>);
+
+impl VoiceHandle {
+    pub fn set_gain(&self, gain: f32) {
+        self.0.gain.store(gain.to_bits(), Ordering::Relaxed);
+    }
+
+    pub fn set_sends(&self, sends: [f32; SEND_BUSES]) {
+        for (send, level) in self.0.sends.iter().zip(sends) {
+            send.store(level.to_bits(), Ordering::Relaxed);
+        }
+    }
+
+    /// Ramps the voice to silence over its fade out and ends it
+    pub fn fade_out(&self) {
+        self.0.fading.store(true, Ordering::Relaxed);
+    }
+
+    /// Ramps the voice to silence over the given time instead of its own
+    /// fade out, a voice already fading carries on as it was
+    pub fn fade_out_over(&self, time: Duration) {
+        if self.0.fading.load(Ordering::Relaxed) {
+            return;
+        }
+        let frames = (time.as_secs_f64() * self.0.sample_rate as f64) as u32;
+        self.0.fade_len.store(frames, Ordering::Relaxed);
+        self.fade_out();
+    }
+
+    /// Ends the voice on the next frame, wherever it is in its waveform
+    pub fn stop(&self) {
+        self.0.stopped.store(true, Ordering::Relaxed);
+    }
+
+    /// Whether it's still sounding and hasn't been told to end
+    pub fn playing(&self) -> bool {
+        !self.0.ended.load(Ordering::Relaxed)
+            && !self.0.stopped.load(Ordering::Relaxed)
+            && !self.0.fading.load(Ordering::Relaxed)
+    }
+}
+
+/// A voice as the mixer sees it, lengths are in output frames
+struct Voice {
+    source: UniformSourceIterator<VoiceSource, f32>,
+    /// first channel of the frame it plays on, and how many
+    offset: usize,
+    width: usize,
+    control: Arc<VoiceControl>,
+    /// stream frame the delay counts from, None is wherever the mixer
+    /// picks it up
+    at: Option<u64>,
+    delay_left: usize,
+    fade_in_len: usize,
+    played: usize,
+    /// length of the fade out once it's started
+    fade_len: usize,
+    /// frames left of the fade out once it's started
+    fading: Option<usize>,
+}
+
+impl Voice {
+    /// Adds the next frame into the mix and the buses, returns false once
+    /// the voice has ended
+    fn mix_into(&mut self, frame: &mut [f32], channels: usize) -> bool {
+        if self.end_of_voice() {
+            return false;
+        }
+        if self.delay_left > 0 {
+            self.delay_left -= 1;
+            return true;
+        }
+        let mut gain = f32::from_bits(self.control.gain.load(Ordering::Relaxed));
+        if self.played < self.fade_in_len {
+            gain *= self.played as f32 / self.fade_in_len as f32;
+            self.played += 1;
+        }
+        if let Some(left) = self.fading.as_mut() {
+            *left -= 1;
+            gain *= *left as f32 / self.fade_len as f32;
+        }
+        let sends = self.control.sends.each_ref().map(|send| f32::from_bits(send.load(Ordering::Relaxed)));
+        for ch in self.offset..self.offset + self.width {
+            let Some(s) = self.source.next() else {
+                return false;
+            };
+            let s = s * gain;
+            frame[ch] += s;
+            for (bus, send) in sends.iter().enumerate() {
+                frame[(bus + 1) * channels + ch] += s * send;
+            }
+        }
+        true
+    }
+
+    /// Whether it's been stopped, or faded all the way out
+    fn end_of_voice(&mut self) -> bool {
+        if self.control.stopped.load(Ordering::Relaxed) {
+            return true;
+        }
+        if self.fading.is_none() && self.control.fading.load(Ordering::Relaxed) {
+            self.fade_len = self.control.fade_len.load(Ordering::Relaxed) as usize;
+            self.fading = Some(self.fade_len);
+        }
+        self.fading == Some(0)
+    }
+}
+
+/// The sequencer's end of the voice mixer, starts voices on it
+#[derive(Clone)]
+pub struct Voices {
+    tx: mpsc::Sender<Voice>,
+    channels: u16,
+    sample_rate: u32,
+}
+
+impl Voices {
+    /// Starts the voice at frame at of the stream plus its delay, or as
+    /// soon as the mixer takes it. One that turns up after its frame has
+    /// passed starts straight away
+    pub fn play<S: Source<Item = f32> + Send + 'static>(&self, source: S, start: VoiceStart, at: Option<u64>) -> VoiceHandle {
+        let frames = |time: Duration| (time.as_secs_f64() * self.sample_rate as f64) as usize;
+        let handle = VoiceHandle(Arc::new(VoiceControl {
+            gain: AtomicU32::new(start.gain.to_bits()),
+            sends: start.sends.map(|send| AtomicU32::new(send.to_bits())),
+            fading: AtomicBool::new(false),
+            fade_len: AtomicU32::new(frames(start.fade_out) as u32),
+            sample_rate: self.sample_rate,
+            stopped: AtomicBool::new(false),
+            ended: AtomicBool::new(false),
+        }));
+        // voices are stereo at most, on their pair of a wider stream
+        let width = self.channels.min(2);
+        let offset = start.output * 2;
+        let offset = if offset + width as usize <= self.channels as usize { offset } else { 0 };
+        let source: VoiceSource = Box::new(source);
+        let voice = Voice {
+            source: UniformSourceIterator::new(source, width, self.sample_rate),
+            offset,
+            width: width as usize,
+            control: handle.0.clone(),
+            at,
+            delay_left: frames(start.delay),
+            fade_in_len: frames(start.fade_in),
+            played: 0,
+            fade_len: 0,
+            fading: None,
+        };
+        // fails once the stream is gone, the voice goes with it
+        let _ = self.tx.send(voice);
+        handle
+    }
+}
+
+/// Sums every track's voices in the stream callback
+///
+/// Voices are played straight from here rather than through a sink each,
+/// so the sequencer can change a voice's levels or end it cleanly at any
+/// point through its handle
+pub struct VoiceMixer {
+    rx: mpsc::Receiver<Voice>,
+    voices: Vec<Voice>,
+    /// where finished voices go to be dropped, letting go of a source can
+    /// free its sample which isn't something to do in the stream callback
+    ended_tx: mpsc::SyncSender<Voice>,
+    channels: usize,
+    /// frames mixed so far, the stream's clock
+    frame: u64,
+}
+
+impl VoiceMixer {
+    pub fn new(channels: u16, sample_rate: u32) -> (Voices, VoiceMixer) {
+        let (tx, rx) = mpsc::channel();
+        // room for every voice ending at once, a few times over
+        let (ended_tx, ended_rx) = mpsc::sync_channel(MAX_VOICES * 4);
+        // goes once the mixer does
+        thread::spawn(move || for voice in ended_rx {
+            drop(voice);
+        });
+        (
+            Voices { tx, channels, sample_rate },
+            VoiceMixer { rx, voices: Vec::with_capacity(MAX_VOICES), ended_tx, channels: channels as usize, frame: 0 },
+        )
+    }
+
+    /// Length of the frame mix_frame fills
+    pub fn frame_len(&self) -> usize {
+        self.channels * (1 + SEND_BUSES)
+    }
+
+    /// Mixes the next frame of every voice, laid out as the main mix and
+    /// then each effect bus in turn
+    pub fn mix_frame(&mut self, frame: &mut [f32]) {
+        while self.voices.len() < MAX_VOICES {
+            match self.rx.try_recv() {
+                Ok(mut voice) => {
+                    if let Some(at) = voice.at.take() {
+                        voice.delay_left += at.saturating_sub(self.frame) as usize;
+                    }
+                    self.voices.push(voice);
+                },
+                Err(_) => break,
+            }
+        }
+        frame.fill(0.0);
+        let channels = self.channels;
+        for voice in self.voices.extract_if(.., |voice| !voice.mix_into(frame, channels)) {
+            voice.control.ended.store(true, Ordering::Relaxed);
+            // only dropped here if the other thread is that far behind
+            let _ = self.ended_tx.try_send(voice);
+        }
+        self.frame += 1;
+    }
+}
@@ -1,8 +1,9 @@
-import { LitElement, html, css } from 'lit';
+import { LitElement, html, css, nothing } from 'lit';
 import { customElement, state } from 'lit/decorators.js';
 import '@material/web/labs/navigationbar/navigation-bar.js';
 import '@material/web/labs/navigationtab/navigation-tab.js';
 import '@material/web/icon/icon.js';
+import '@material/web/button/outlined-button.js';
 import '@material/web/textfield/filled-text-field.js';
 import '@material/web/select/filled-select.js';
 import '@material/web/select/select-option.js';
@@ -31,9 +32,13 @@ export class DrumMachineApp extends LitElement {
     swing: 0,
   };
 
+  // Page of a long pattern picked to edit, null follows the playhead
+  @state() private viewPage: number | null = null;
+
   @state() private fileState: FileState = {
     patterns: [],
     samples: [],
+    similarPatterns: [],
   };
 
   // Since the backend doesn't use pattern IDs, we'll use a fixed value
@@ -177,13 +182,17 @@ export class DrumMachineApp extends LitElement {
           <div class="pattern-selector-container glass-card">
             <pattern-selector
               .patternName=${currentPattern}
+              .patternNames=${this.drumState.pattern_names ?? []}
               .patternLen=${this.drumState.pattern_len}
               .currentPatternId=${this.drumState.pattern_id}
               .queuedPatternId=${this.drumState.queued_pattern_id}
               .savedPatterns=${this.fileState.patterns}
+              .similarPatterns=${this.fileState.similarPatterns}
               @pattern-selected=${this.handlePatternSelected}
               @add-pattern=${this.handleAddPattern}
+              @pattern-renamed=${this.handlePatternRenamed}
               @save-pattern=${this._handleSavePattern}
+              @find-similar=${this._handleFindSimilar}
               @load-pattern=${this._handleLoadPattern}
             ></pattern-selector>
           </div>
@@ -192,15 +201,28 @@ export class DrumMachineApp extends LitElement {
               .isPlaying=${this.drumState.playing}
               .tempo=${this.drumState.tempo}
               .swing=${this.drumState.swing}
+              .swingGrid=${this.drumState.swing_grid ?? 0}
               @play=${this.handlePlay}
               @stop=${this.handleStop}
               @tempo-change=${this.handleTempoChange}
               @swing-change=${this._handleSwingChange}
+              @swing-grid-change=${this._handleSwingGridChange}
             ></transport-controls>
           </div>
           
           <div class="tracks-container glass-card">
             <div class="tracks-title">Pattern: ${currentPattern || 'None'}</div>
+            ${(this.drumState.pages ?? 1) > 1 ? html`
+              <div class="page-controls">
+                ${Array.from({ length: this.drumState.pages ?? 1 }, (_, i) => html`
+                  <md-outlined-button
+                    ?disabled=${i === this._shownPage()}
+                    @click=${() => this.viewPage = i}
+                  >${i + 1}${i === this.drumState.page ? ' ▶' : ''}</md-outlined-button>
+                `)}
+                <md-outlined-button ?disabled=${this.viewPage === null} @click=${() => this.viewPage = null}>Follow</md-outlined-button>
+              </div>
+            ` : nothing}
             <div class="pattern-length-controls">
               <label for="patternLengthInput">Steps:</label>
               <md-filled-text-field
@@ -230,17 +252,26 @@ export class DrumMachineApp extends LitElement {
               </md-filled-select>
             </div>
             <div class="drum-grid-container">
-              ${this.drumState.trks.map((track, idx) => html`
+              ${this.drumState.trks.map((track, idx) => track.archived ? nothing : html`
                 <drum-track
                   .track=${track}
                   .trkId=${idx}
                   .samples=${this.fileState.samples}
+                  .page=${this._shownPage()}
+                  .pageLen=${this.drumState.page_len ?? 0}
                   @track-pad-toggled=${this.handlePadToggled}
                   @sample-changed=${this.handleSampleChanged}
+                  @track-length-changed=${this.handleTrackLengthChanged}
+                  @track-pitch-changed=${this.handleTrackPitchChanged}
+                  @track-reverse-changed=${this.handleTrackReverseChanged}
+                  @track-mute-changed=${this.handleTrackMuteChanged}
+                  @track-cleared=${this.handleTrackCleared}
+                  @track-removed=${this.handleTrackRemoved}
                 ></drum-track>
               `)}
             </div>
             <md-filled-button @click=${this.handleAddTrack}>Add Track</md-filled-button>
+            <md-outlined-button @click=${this.handleClearPattern}>Clear Pattern</md-outlined-button>
           </div>
         </div>
       </div>
@@ -265,9 +296,17 @@ export class DrumMachineApp extends LitElement {
         this.fileState.samples = stateMsg.files;
         break;
       }
+      case FileType.SIMILAR_PATTERN: {
+        this.fileState = { ...this.fileState, similarPatterns: stateMsg.files };
+        break;
+      }
     }
   }
 
+  _handleFindSimilar() {
+    this.webSocketService.findSimilarPatterns();
+  }
+
   handlePatternSelected(e: CustomEvent) {
     const { patternId } = e.detail;
     this.webSocketService.selectPattern(patternId);
@@ -277,6 +316,11 @@ export class DrumMachineApp extends LitElement {
     this.webSocketService.addPattern();
   }
 
+  handlePatternRenamed(e: CustomEvent) {
+    const { patternId, name } = e.detail;
+    this.webSocketService.setPatternName(patternId, name);
+  }
+
   handlePlay() {
     this.webSocketService.play();
   }
@@ -300,10 +344,43 @@ export class DrumMachineApp extends LitElement {
     );
   }
 
+  _shownPage() {
+    const pages = this.drumState.pages ?? 1;
+    return Math.min(this.viewPage ?? this.drumState.page ?? 0, pages - 1);
+  }
+
   handleAddTrack() {
     this.webSocketService.addTrack();
   }
 
+  handleTrackLengthChanged(e: CustomEvent) {
+    this.webSocketService.setTrackLength(e.detail.trackId, e.detail.length);
+  }
+
+  handleTrackPitchChanged(e: CustomEvent) {
+    this.webSocketService.setTrackPitch(e.detail.trackId, e.detail.cents);
+  }
+
+  handleTrackReverseChanged(e: CustomEvent) {
+    this.webSocketService.setTrackReverse(e.detail.trackId, e.detail.reverse);
+  }
+
+  handleTrackMuteChanged(e: CustomEvent) {
+    this.webSocketService.muteTracks([e.detail.trackId], e.detail.muted);
+  }
+
+  handleTrackCleared(e: CustomEvent) {
+    this.webSocketService.clearTrack(e.detail.trackId);
+  }
+
+  handleClearPattern() {
+    this.webSocketService.clearPattern();
+  }
+
+  handleTrackRemoved(e: CustomEvent) {
+    this.webSocketService.removeTrack(e.detail.trackId);
+  }
+
   handleSampleChanged(e: CustomEvent) {
     const { trackId, samplePath } = e.detail;
     this.webSocketService.setTrackSample(trackId, samplePath);
@@ -349,6 +426,10 @@ export class DrumMachineApp extends LitElement {
     const { swing } = e.detail;
     this.webSocketService.setSwing(swing);
   }
+
+  private _handleSwingGridChange(e: CustomEvent) {
+    this.webSocketService.setSwingGrid(e.detail.grid);
+  }
   
   _handleThemeChanged(e: CustomEvent) {
     const { theme } = e.detail;
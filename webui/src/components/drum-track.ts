@@ -1,4 +1,4 @@
-import { LitElement, html, css } from 'lit';
+import { LitElement, html, css, nothing } from 'lit';
 import { customElement, property, state } from 'lit/decorators.js';
 import { Track } from '../models/types';
 import './drum-pad';
@@ -8,6 +8,9 @@ export class DrumTrack extends LitElement {
   @property({ type: Object }) track!: Track;
   @property({ type: Number }) trkId = -1;
   @property({ type: Array }) samples!: string[];
+  // Long patterns are shown a page at a time, a page length of 0 shows every step
+  @property({ type: Number }) page = 0;
+  @property({ type: Number }) pageLen = 0;
 
   static styles = css`
     :host {
@@ -43,6 +46,15 @@ export class DrumTrack extends LitElement {
       }
     }
 
+    .length-input {
+      width: 3.5rem;
+      margin-left: 8px;
+      background: none;
+      border: none;
+      color: var(--md-sys-color-on-surface-variant);
+      font-size: 1rem;
+    }
+
     .sample-select {
       max-width: 12rem;
       min-width: 8rem;
@@ -71,6 +83,15 @@ export class DrumTrack extends LitElement {
     .sample-select:hover {
       border-color: var(--md-sys-color-on-surface-variant);
     }
+
+    .remove-button {
+      margin-left: 12px;
+      border: none;
+      background: none;
+      color: var(--md-sys-color-on-surface-variant);
+      font-size: 1rem;
+      cursor: pointer;
+    }
   `;
 
   @state() private selectedSample: string = '';
@@ -96,6 +117,7 @@ export class DrumTrack extends LitElement {
         </select>
         <div class="pads-container">
           ${this.track.slots.map((vel, index) => {
+            if (this.pageLen && Math.floor(index / this.pageLen) !== this.page) return nothing;
             let idx = (index + 1) % this.track.slots.length;
             return html`
               <drum-pad 
@@ -105,6 +127,37 @@ export class DrumTrack extends LitElement {
               ></drum-pad>`
           })}
         </div>
+        <input
+          class="length-input"
+          type="number"
+          min="1"
+          max="256"
+          title="Track steps"
+          .value=${String(this.track.len)}
+          @change=${this._handleLengthChange}
+        />
+        <input
+          class="length-input"
+          type="number"
+          min="-24"
+          max="24"
+          step="0.01"
+          title="Pitch in semitones"
+          .value=${String((this.track.pitch_cents ?? 0) / 100)}
+          @change=${this._handlePitchChange}
+        />
+        <button
+          class="remove-button"
+          title=${this.track.reverse ? 'Play forwards' : 'Play backwards'}
+          @click=${this._handleReverseToggle}
+        >${this.track.reverse ? '◀' : '▶'}</button>
+        <button
+          class="remove-button"
+          title=${this.track.muted ? 'Unmute' : 'Mute'}
+          @click=${this._handleMuteToggle}
+        >${this.track.muted ? 'M̶' : 'M'}</button>
+        <button class="remove-button" title="Clear track" @click=${this._handleClear}>⌫</button>
+        <button class="remove-button" title="Remove track" @click=${this._handleRemove}>✕</button>
       </div>
     `;
   }
@@ -121,6 +174,82 @@ export class DrumTrack extends LitElement {
     }));
   }
 
+  _handleLengthChange(event: Event) {
+    const input = event.target as HTMLInputElement;
+    const length = parseInt(input.value, 10);
+    if (isNaN(length) || length < 1 || length > 256) {
+      input.value = String(this.track.len);
+      return;
+    }
+    this.dispatchEvent(new CustomEvent('track-length-changed', {
+      detail: {
+        trackId: this.trkId,
+        length
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
+  _handleReverseToggle() {
+    this.dispatchEvent(new CustomEvent('track-reverse-changed', {
+      detail: {
+        trackId: this.trkId,
+        reverse: !this.track.reverse
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
+  _handleMuteToggle() {
+    this.dispatchEvent(new CustomEvent('track-mute-changed', {
+      detail: {
+        trackId: this.trkId,
+        muted: !this.track.muted
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
+  _handlePitchChange(event: Event) {
+    const input = event.target as HTMLInputElement;
+    const semitones = parseFloat(input.value);
+    if (isNaN(semitones) || Math.abs(semitones) > 24) {
+      input.value = String((this.track.pitch_cents ?? 0) / 100);
+      return;
+    }
+    this.dispatchEvent(new CustomEvent('track-pitch-changed', {
+      detail: {
+        trackId: this.trkId,
+        cents: Math.round(semitones * 100)
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
+  _handleClear() {
+    this.dispatchEvent(new CustomEvent('track-cleared', {
+      detail: {
+        trackId: this.trkId
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
+  _handleRemove() {
+    this.dispatchEvent(new CustomEvent('track-removed', {
+      detail: {
+        trackId: this.trkId
+      },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
   _handleSampleChange(event: Event) {
     const selectElement = event.target as HTMLSelectElement;
     const selectedValue = selectElement.value;
@@ -4,6 +4,7 @@ import '@material/web/button/filled-button.js';
 import '@material/web/button/outlined-button.js';
 import '@material/web/select/filled-select.js';
 import '@material/web/select/select-option.js';
+import '@material/web/textfield/filled-text-field.js';
 
 @customElement('pattern-selector')
 export class PatternSelector extends LitElement {
@@ -11,7 +12,9 @@ export class PatternSelector extends LitElement {
   @property({ type: Number }) currentPatternId = 0;
   @property({ type: Number }) queuedPatternId = 0;
   @property({ type: String }) patternName = '';
+  @property({ type: Array }) patternNames: string[] = [];
   @property({ type: Array }) savedPatterns: string[] = [];
+  @property({ type: Array }) similarPatterns: string[] = [];
 
   private selectedPatternName: string = '';
 
@@ -72,21 +75,26 @@ export class PatternSelector extends LitElement {
             if (isSelected) {
               return html`
                 <md-filled-button class="pattern-button selected" @click=${() => this._handlePatternSelect(i)}>
-                  Pattern ${i + 1}
+                  ${this._label(i)}
                 </md-filled-button>`;
             } else if (isQueuedAndNotSelected) {
               return html`
                 <md-filled-button class="pattern-button queued" @click=${() => this._handlePatternSelect(i)}>
-                  Pattern ${i + 1}
+                  ${this._label(i)}
                 </md-filled-button>`;
             } else {
               return html`
                 <md-outlined-button class="pattern-button" @click=${() => this._handlePatternSelect(i)}>
-                  Pattern ${i + 1}
+                  ${this._label(i)}
                 </md-outlined-button>`;
             }
           })}
         </div>
+        <md-filled-text-field
+          label="Name"
+          .value=${this.patternName}
+          @change=${this._handlePatternRename}
+        ></md-filled-text-field>
         <md-filled-button @click=${() => this._handleAddPattern()}>
           <md-icon slot="icon">add</md-icon>
           Add Pattern
@@ -104,10 +112,35 @@ export class PatternSelector extends LitElement {
           <md-icon slot="icon">download</md-icon>
           Load Pattern
         </md-filled-button>
+        <md-filled-button @click=${() => this._handleFindSimilar()}>
+          <md-icon slot="icon">search</md-icon>
+          Find Similar
+        </md-filled-button>
+        ${this.similarPatterns.length ? html`
+          <md-filled-select label="Similar" @change=${this._handleSavedPatternSelect}>
+            ${this.similarPatterns.map((pattern) => html`
+              <md-select-option .value=${pattern} .label=${pattern}>${pattern}</md-select-option>
+            `)}
+          </md-filled-select>
+        ` : ''}
       </div>
     `;
   }
 
+  _label(i: number) {
+    return this.patternNames[i] ?? `Pattern ${i + 1}`;
+  }
+
+  _handlePatternRename(e: Event) {
+    const name = (e.target as HTMLInputElement).value.trim();
+    if (!name) return;
+    this.dispatchEvent(new CustomEvent('pattern-renamed', {
+      detail: { patternId: this.currentPatternId, name },
+      bubbles: true,
+      composed: true
+    }));
+  }
+
   _handleAddPattern() {
     this.dispatchEvent(new CustomEvent('add-pattern', {
       bubbles: true,
@@ -130,6 +163,13 @@ export class PatternSelector extends LitElement {
     }));
   }
 
+  _handleFindSimilar() {
+    this.dispatchEvent(new CustomEvent('find-similar', {
+      bubbles: true,
+      composed: true
+    }));
+  }
+
   _handleSavedPatternSelect(e: Event) {
     const select = e.target as HTMLSelectElement;
     const selectedOption = select.options[select.selectedIndex];
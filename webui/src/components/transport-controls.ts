@@ -7,12 +7,17 @@ import '@material/web/slider/slider.js';
 import '@material/web/select/filled-select.js';
 import '@material/web/select/select-option.js';
 
+// same range the sequencer clamps tempos to
+const MIN_TEMPO = 20;
+const MAX_TEMPO = 999;
+const TEMPO_STEP = 0.1;
 
 @customElement('transport-controls')
 export class TransportControls extends LitElement {
   @property({ type: Boolean }) isPlaying = false;
   @property({ type: Number }) tempo = 120;
   @property({ type: Number }) swing = 0;
+  @property({ type: Number }) swingGrid = 0;
 
   static styles = css`
     :host {
@@ -96,13 +101,14 @@ export class TransportControls extends LitElement {
         <div class="tempo-control">
           <span>Tempo:</span>
           <md-slider
-            min="20"
-            max="200"
+            min=${MIN_TEMPO}
+            max=${MAX_TEMPO}
+            step=${TEMPO_STEP}
             value=${this.tempo}
             @change=${this._handleTempoChange}
             labeled
           ></md-slider>
-          <div class="tempo-value">${this.tempo} BPM</div>
+          <div class="tempo-value">${this.tempo.toFixed(1)} BPM</div>
         </div>
 
         <div class="swing-control">
@@ -111,6 +117,11 @@ export class TransportControls extends LitElement {
             <md-select-option value="1">50%</md-select-option>
             <md-select-option value="2">100%</md-select-option>
           </md-filled-select>
+          <md-filled-select label="Swing Grid" value=${this.swingGrid} @change=${this._handleSwingGridChange}>
+            <md-select-option value="0">Steps</md-select-option>
+            <md-select-option value="8">8ths</md-select-option>
+            <md-select-option value="16">16ths</md-select-option>
+          </md-filled-select>
         </div>
       </div>
     `;
@@ -131,7 +142,7 @@ export class TransportControls extends LitElement {
   }
 
   _handleTempoChange(e: CustomEvent) {
-    const newTempo = parseInt((e.target as HTMLInputElement).value);
+    const newTempo = parseFloat((e.target as HTMLInputElement).value);
     this.dispatchEvent(new CustomEvent('tempo-change', {
       detail: { tempo: newTempo },
       bubbles: true,
@@ -147,6 +158,15 @@ export class TransportControls extends LitElement {
       composed: true
     }));
   }
+
+  _handleSwingGridChange(e: CustomEvent) {
+    const grid = parseInt((e.target as HTMLInputElement).value);
+    this.dispatchEvent(new CustomEvent('swing-grid-change', {
+      detail: { grid },
+      bubbles: true,
+      composed: true
+    }));
+  }
 }
 
 declare global {
@@ -4,6 +4,105 @@ export interface Track {
   idx: number;
   len: number;
   sample_path: string;
+  // why the sample didn't load, the track is silent until it has one
+  load_error?: string | null;
+  archived?: boolean;
+  // effects bypassed in the current pattern
+  fx_bypass?: FxBypass;
+  // tuning in cents, 100 to a semitone
+  pitch_cents?: number;
+  // plays the sample backwards
+  reverse?: boolean;
+  muted?: boolean;
+  // level in whole dB, -60 is silent
+  gain_db?: number;
+  // hit envelope in ms, all 0 is off
+  envelope?: Envelope;
+  filter?: Filter;
+  eq?: TrackEq;
+  // percent sent to the delay and reverb buses
+  delay_send?: number;
+  reverb_send?: number;
+  polyphony?: Polyphony;
+  // samples for harder hits, the track's own plays under the first
+  layers?: VelocityLayer[];
+  // the sample is a loop played at a tempo, fitted to ours
+  loop_sync?: LoopSync | null;
+  // pair of output channels it plays on, 0 is outs 1/2
+  output?: number;
+  // gain kept with the sample, wherever it's used
+  sample_gain?: SampleGain;
+}
+
+export interface VelocityLayer {
+  min_velocity: number;
+  sample_path: string;
+}
+
+export enum FitMode {
+  STRETCH = 'Stretch',
+  REPITCH = 'Repitch'
+}
+
+export interface LoopSync {
+  bpm: number;
+  mode: FitMode;
+}
+
+export enum Normalize {
+  OFF = 'Off',
+  PEAK = 'Peak',
+  RMS = 'Rms'
+}
+
+export interface SampleGain {
+  normalize: Normalize;
+  // dBFS normalizing brings it to
+  target_db: number;
+  // dB on top of any normalizing
+  offset_db: number;
+}
+
+export enum VoiceMode {
+  MONO = 'Mono',
+  POLY = 'Poly'
+}
+
+export enum StealPolicy {
+  OLDEST = 'Oldest',
+  QUIETEST = 'Quietest'
+}
+
+export interface Polyphony {
+  mode: VoiceMode;
+  // only read in poly mode, 1 to 16
+  max_voices: number;
+  steal: StealPolicy;
+}
+
+export enum FilterMode {
+  OFF = 'Off',
+  LOW_PASS = 'LowPass',
+  HIGH_PASS = 'HighPass'
+}
+
+export interface Filter {
+  mode: FilterMode;
+  cutoff_hz: number;
+  // 0 to 100
+  resonance: number;
+}
+
+// shelf gains in dB, -12 to 12, 0 is flat
+export interface TrackEq {
+  low_db: number;
+  high_db: number;
+}
+
+export interface Envelope {
+  attack_ms: number;
+  hold_ms: number;
+  decay_ms: number;
 }
 
 export interface Pattern {
@@ -23,13 +122,64 @@ export interface DrumMachineState {
   pattern_len: number;
   pattern_id: number;
   pattern_name: string;
+  pattern_names?: string[];
+  page?: number;
+  pages?: number;
+  page_len?: number;
   queued_pattern_id: number;
   swing: number;
+  // 0 swings every other step, 8 or 16 every other 8th or 16th
+  swing_grid?: number;
+  // set while the audio output is lost, in the language below
+  audio_error?: string;
+  language?: Language;
+  // whole dB, -60 is silent
+  master_volume_db?: number;
+  // quarter notes to a bar
+  beats_per_bar?: number;
+  // fade out of a choked voice, 5 to 15
+  choke_fade_ms?: number;
+  // file in recordings/ the master mix is being written to
+  master_recording?: string | null;
+  // track a sample is being captured for from the input
+  capture_track?: number | null;
+  // the capture has heard something and is recording
+  capture_triggered?: boolean;
+  // sample files that failed to load and why
+  sample_errors?: SampleError[];
+  // channels the output runs with, tracks can go on any pair of them
+  output_channels?: number;
+  // how late pulses were scheduled over the last 16 bars in us, early is negative
+  jitter_min_us?: number;
+  jitter_max_us?: number;
+  jitter_p99_us?: number;
+  // pulses whose hits were heard late, over the last 16 bars and in total
+  missed_pulses?: number;
+  total_missed_pulses?: number;
+}
+
+export interface SampleError {
+  path: string;
+  error: string;
+}
+
+export enum Language {
+  ENGLISH = 0,
+  SPANISH = 1,
+  GERMAN = 2,
+  FRENCH = 3
+}
+
+export enum DemoStyle {
+  HOUSE = 0,
+  HIP_HOP = 1,
+  BREAKBEAT = 2
 }
 
 export enum FileType {
   PATTERN = 'pattern',
   SAMPLE = 'sample',
+  SIMILAR_PATTERN = 'similar_pattern',
 }
 
 export interface FileStateMsg {
@@ -40,11 +190,13 @@ export interface FileStateMsg {
 export interface FileState {
   patterns: string[];
   samples: string[];
+  similarPatterns: string[];
 }
 
 export enum MessageType {
   STATE_UPDATE = 'state_update',
   FILE_STATE_UPDATE = 'file_state_update',
+  STATE_GAP = 'state_gap',
   SET_SLOT_VELOCITY = 'set_slot_velocity',
   CHANGE_PATTERN = 'change_pattern',
   PLAY_SEQUENCER = 'play_sequencer',
@@ -54,14 +206,54 @@ export enum MessageType {
   REMOVE_PATTERN = 'remove_pattern',
   SELECT_PATTERN = 'select_pattern',
   SET_PATTERN_LENGTH = 'set_pattern_length',
+  SET_TRACK_LENGTH = 'set_track_length',
+  SET_TRACK_PITCH = 'set_track_pitch',
+  SET_TRACK_REVERSE = 'set_track_reverse',
+  SET_TRACK_ENVELOPE = 'set_track_envelope',
+  SET_TRACK_FILTER = 'set_track_filter',
+  SET_TRACK_EQ = 'set_track_eq',
+  SET_TRACK_POLYPHONY = 'set_track_polyphony',
+  SET_TRACK_LAYER = 'set_track_layer',
+  REMOVE_TRACK_LAYER = 'remove_track_layer',
+  CLEAR_TRACK_LAYERS = 'clear_track_layers',
+  SET_TRACK_LOOP_SYNC = 'set_track_loop_sync',
+  SET_TRACK_OUTPUT = 'set_track_output',
+  SET_SAMPLE_GAIN = 'set_sample_gain',
+  SET_DELAY = 'set_delay',
+  SET_TRACK_DELAY_SEND = 'set_track_delay_send',
+  SET_LANGUAGE = 'set_language',
+  SET_MASTER_VOLUME = 'set_master_volume',
+  SET_CHOKE_FADE = 'set_choke_fade',
+  START_RECORDING = 'start_recording',
+  STOP_RECORDING = 'stop_recording',
+  CAPTURE_SAMPLE = 'capture_sample',
+  STOP_CAPTURE = 'stop_capture',
+  RELOAD_SAMPLES = 'reload_samples',
+  PREVIEW_SAMPLE = 'preview_sample',
+  SLICE_SAMPLE = 'slice_sample',
+  COPY_TRACK_FEEL = 'copy_track_feel',
+  SET_REVERB = 'set_reverb',
+  SET_TRACK_REVERB_SEND = 'set_track_reverb_send',
+  LOAD_DEMO = 'load_demo',
+  MUTE_TRACKS = 'mute_tracks',
+  SET_TRACKS_GAIN = 'set_tracks_gain',
+  CLEAR_TRACKS = 'clear_tracks',
   SET_DIVISION = 'set_division',
   SAVE_PATTERN = 'save_pattern',
   LOAD_PATTERN = 'load_pattern',
   LIST_PATTERNS = 'list_patterns',
   LIST_SAMPLES = 'list_samples',
   SET_TRACK_SAMPLE = 'set_track_sample',
+  RANDOMIZE_KIT = 'randomize_kit',
   ADD_TRACK = 'add_track',
+  REMOVE_TRACK = 'remove_track',
+  SET_PATTERN_NAME = 'set_pattern_name',
+  CLEAR_TRACK = 'clear_track',
+  CLEAR_PATTERN = 'clear_pattern',
+  FIND_SIMILAR_PATTERNS = 'find_similar_patterns',
   SET_SWING = 'set_swing',
+  SET_TRACK_FX_BYPASS = 'set_track_fx_bypass',
+  SET_SWING_GRID = 'set_swing_grid',
 }
 
 export interface WebSocketMessage {
@@ -92,6 +284,167 @@ export interface SetPatternLengthPayload {
   length: number;
 }
 
+export interface SetTrackLengthPayload {
+  trackId: number;
+  length: number;
+}
+
+export interface SetTrackPitchPayload {
+  trackId: number;
+  cents: number;
+}
+
+export interface SetTrackReversePayload {
+  trackId: number;
+  reverse: boolean;
+}
+
+export interface SetTrackEnvelopePayload {
+  trackId: number;
+  attackMs: number;
+  holdMs: number;
+  decayMs: number;
+}
+
+export interface SetTrackPolyphonyPayload {
+  trackId: number;
+  // 0 mono, 1 poly
+  mode: number;
+  maxVoices: number;
+  // 0 oldest, 1 quietest
+  steal: number;
+}
+
+export interface SetTrackLoopSyncPayload {
+  trackId: number;
+  // 0 untags the loop
+  bpm: number;
+  // 0 stretch, 1 repitch
+  mode: number;
+}
+
+export interface SetTrackOutputPayload {
+  trackId: number;
+  // pair of output channels, 0 is outs 1/2
+  output: number;
+}
+
+export interface SetSampleGainPayload {
+  samplePath: string;
+  // 0 off, 1 peak, 2 RMS
+  normalize: number;
+  // -40 to 0 dBFS
+  targetDb: number;
+  // -24 to 24 dB
+  offsetDb: number;
+}
+
+export interface TrackLayerPayload {
+  trackId: number;
+  // 1 to 127
+  minVelocity: number;
+  // only read when setting a layer
+  samplePath?: string;
+}
+
+export interface SetTrackFilterPayload {
+  trackId: number;
+  // 0 off, 1 low pass, 2 high pass
+  mode: number;
+  cutoffHz: number;
+  resonance: number;
+}
+
+export interface SetTrackEqPayload {
+  trackId: number;
+  lowDb: number;
+  highDb: number;
+}
+
+export interface SetDelayPayload {
+  // echo time as a division, 8 is an eighth
+  division: number;
+  // percent, capped at 90
+  feedback: number;
+}
+
+export interface SetTrackDelaySendPayload {
+  trackId: number;
+  send: number;
+}
+
+// all in percent
+export interface SetReverbPayload {
+  size: number;
+  damping: number;
+  wet: number;
+}
+
+export interface SetTrackReverbSendPayload {
+  trackId: number;
+  send: number;
+}
+
+export interface SetLanguagePayload {
+  language: Language;
+}
+
+export interface CopyTrackFeelPayload {
+  srcTrackId: number;
+  dstTrackId: number;
+}
+
+export interface SetMasterVolumePayload {
+  volumeDb: number;
+}
+
+export interface PreviewSamplePayload {
+  // empty stops the last preview
+  samplePath: string;
+  vel: number;
+}
+
+export interface SliceSamplePayload {
+  samplePath: string;
+  // 2 to 32, no more than the pattern's steps
+  slices: number;
+}
+
+export interface CaptureSamplePayload {
+  trackId: number;
+  fname: string;
+  // dBFS, -60 or lower starts straight away
+  thresholdDb: number;
+  // write over a sample with the same name
+  overwrite?: boolean;
+}
+
+export interface StartRecordingPayload {
+  fname: string;
+}
+
+export interface SetChokeFadePayload {
+  fadeMs: number;
+}
+
+export interface LoadDemoPayload {
+  style: DemoStyle;
+}
+
+export interface MuteTracksPayload {
+  trackIds: number[];
+  muted: boolean;
+}
+
+export interface SetTracksGainPayload {
+  trackIds: number[];
+  gainDb: number;
+}
+
+export interface ClearTracksPayload {
+  trackIds: number[];
+}
+
 export interface SetDivisionPayload {
   division: number;
 }
@@ -105,6 +458,38 @@ export interface SetTrackSamplePayload {
   samplePath: string;
 }
 
+export interface ClearTrackPayload {
+  trackId: number;
+}
+
+export interface SetPatternNamePayload {
+  patternId: number;
+  name: string;
+}
+
+export interface RemoveTrackPayload {
+  trackId: number;
+}
+
 export interface SetSwingPayload {
   swing: number;
 }
+
+export interface FxBypass {
+  eq: boolean;
+  filter: boolean;
+  delay: boolean;
+  reverb: boolean;
+}
+
+export interface SetTrackFxBypassPayload {
+  trackId: number;
+  eq: boolean;
+  filter: boolean;
+  delay: boolean;
+  reverb: boolean;
+}
+
+export interface SetSwingGridPayload {
+  grid: number;
+}
@@ -47,6 +47,15 @@ export class WebSocketService {
           return;
         }
         
+        // Updates were dropped because we fell behind. The next state
+        // update is complete anyway, but a file list may have been lost
+        if (data.type === types.MessageType.STATE_GAP) {
+          console.log('Missed updates:', data.payload?.skipped);
+          this.listPatterns();
+          this.listSamples();
+          return;
+        }
+
         // Case 2: The message is a welcome message or other non-state message with a type field
         if (data.type === 'connection') {
           console.log('Connection status:', data.status);
@@ -142,6 +151,10 @@ export class WebSocketService {
     this.sendMessage(types.MessageType.SET_DIVISION, payload);
   }
 
+  public randomizeKit(): void {
+    this.sendMessage(types.MessageType.RANDOMIZE_KIT, {});
+  }
+
   public savePattern(): void {
     this.sendMessage(types.MessageType.SAVE_PATTERN, {});
   }
@@ -173,6 +186,287 @@ export class WebSocketService {
     this.sendMessage(types.MessageType.ADD_TRACK, {});
   }
 
+  public setTrackLength(trackId: number, length: number): void {
+    const payload: types.SetTrackLengthPayload = {
+      trackId,
+      length
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_LENGTH, payload);
+  }
+
+  public setTrackPitch(trackId: number, cents: number): void {
+    const payload: types.SetTrackPitchPayload = {
+      trackId,
+      cents
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_PITCH, payload);
+  }
+
+  public setTrackReverse(trackId: number, reverse: boolean): void {
+    const payload: types.SetTrackReversePayload = {
+      trackId,
+      reverse
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_REVERSE, payload);
+  }
+
+  public setTrackEnvelope(trackId: number, attackMs: number, holdMs: number, decayMs: number): void {
+    const payload: types.SetTrackEnvelopePayload = {
+      trackId,
+      attackMs,
+      holdMs,
+      decayMs
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_ENVELOPE, payload);
+  }
+
+  public setTrackFilter(trackId: number, mode: number, cutoffHz: number, resonance: number): void {
+    const payload: types.SetTrackFilterPayload = {
+      trackId,
+      mode,
+      cutoffHz,
+      resonance
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_FILTER, payload);
+  }
+
+  public setTrackEq(trackId: number, lowDb: number, highDb: number): void {
+    const payload: types.SetTrackEqPayload = {
+      trackId,
+      lowDb,
+      highDb
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_EQ, payload);
+  }
+
+  public setTrackPolyphony(trackId: number, mode: number, maxVoices: number, steal: number): void {
+    const payload: types.SetTrackPolyphonyPayload = {
+      trackId,
+      mode,
+      maxVoices,
+      steal
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_POLYPHONY, payload);
+  }
+
+  public setTrackLayer(trackId: number, minVelocity: number, samplePath: string): void {
+    const payload: types.TrackLayerPayload = {
+      trackId,
+      minVelocity,
+      samplePath
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_LAYER, payload);
+  }
+
+  public removeTrackLayer(trackId: number, minVelocity: number): void {
+    const payload: types.TrackLayerPayload = {
+      trackId,
+      minVelocity
+    };
+    this.sendMessage(types.MessageType.REMOVE_TRACK_LAYER, payload);
+  }
+
+  public clearTrackLayers(trackId: number): void {
+    const payload: types.ClearTrackPayload = {
+      trackId
+    };
+    this.sendMessage(types.MessageType.CLEAR_TRACK_LAYERS, payload);
+  }
+
+  public setTrackLoopSync(trackId: number, bpm: number, mode: number): void {
+    const payload: types.SetTrackLoopSyncPayload = {
+      trackId,
+      bpm,
+      mode
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_LOOP_SYNC, payload);
+  }
+
+  public setTrackOutput(trackId: number, output: number): void {
+    const payload: types.SetTrackOutputPayload = {
+      trackId,
+      output
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_OUTPUT, payload);
+  }
+
+  public setSampleGain(samplePath: string, normalize: number, targetDb: number, offsetDb: number): void {
+    const payload: types.SetSampleGainPayload = {
+      samplePath,
+      normalize,
+      targetDb,
+      offsetDb
+    };
+    this.sendMessage(types.MessageType.SET_SAMPLE_GAIN, payload);
+  }
+
+  public setDelay(division: number, feedback: number): void {
+    const payload: types.SetDelayPayload = {
+      division,
+      feedback
+    };
+    this.sendMessage(types.MessageType.SET_DELAY, payload);
+  }
+
+  public setTrackDelaySend(trackId: number, send: number): void {
+    const payload: types.SetTrackDelaySendPayload = {
+      trackId,
+      send
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_DELAY_SEND, payload);
+  }
+
+  public setReverb(size: number, damping: number, wet: number): void {
+    const payload: types.SetReverbPayload = {
+      size,
+      damping,
+      wet
+    };
+    this.sendMessage(types.MessageType.SET_REVERB, payload);
+  }
+
+  public setTrackReverbSend(trackId: number, send: number): void {
+    const payload: types.SetTrackReverbSendPayload = {
+      trackId,
+      send
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_REVERB_SEND, payload);
+  }
+
+  public setLanguage(language: types.Language): void {
+    const payload: types.SetLanguagePayload = {
+      language
+    };
+    this.sendMessage(types.MessageType.SET_LANGUAGE, payload);
+  }
+
+  public copyTrackFeel(srcTrackId: number, dstTrackId: number): void {
+    const payload: types.CopyTrackFeelPayload = {
+      srcTrackId,
+      dstTrackId
+    };
+    this.sendMessage(types.MessageType.COPY_TRACK_FEEL, payload);
+  }
+
+  public setMasterVolume(volumeDb: number): void {
+    const payload: types.SetMasterVolumePayload = {
+      volumeDb
+    };
+    this.sendMessage(types.MessageType.SET_MASTER_VOLUME, payload);
+  }
+
+  public startRecording(fname: string): void {
+    const payload: types.StartRecordingPayload = {
+      fname
+    };
+    this.sendMessage(types.MessageType.START_RECORDING, payload);
+  }
+
+  public stopRecording(): void {
+    this.sendMessage(types.MessageType.STOP_RECORDING, {});
+  }
+
+  public captureSample(trackId: number, fname: string, thresholdDb: number, overwrite = false): void {
+    const payload: types.CaptureSamplePayload = {
+      trackId,
+      fname,
+      thresholdDb,
+      overwrite
+    };
+    this.sendMessage(types.MessageType.CAPTURE_SAMPLE, payload);
+  }
+
+  public stopCapture(): void {
+    this.sendMessage(types.MessageType.STOP_CAPTURE, {});
+  }
+
+  public reloadSamples(): void {
+    this.sendMessage(types.MessageType.RELOAD_SAMPLES, {});
+  }
+
+  public previewSample(samplePath: string, vel: number): void {
+    const payload: types.PreviewSamplePayload = {
+      samplePath,
+      vel
+    };
+    this.sendMessage(types.MessageType.PREVIEW_SAMPLE, payload);
+  }
+
+  public sliceSample(samplePath: string, slices: number): void {
+    const payload: types.SliceSamplePayload = {
+      samplePath,
+      slices
+    };
+    this.sendMessage(types.MessageType.SLICE_SAMPLE, payload);
+  }
+
+  public setChokeFade(fadeMs: number): void {
+    const payload: types.SetChokeFadePayload = {
+      fadeMs
+    };
+    this.sendMessage(types.MessageType.SET_CHOKE_FADE, payload);
+  }
+
+  public loadDemo(style: types.DemoStyle): void {
+    const payload: types.LoadDemoPayload = {
+      style
+    };
+    this.sendMessage(types.MessageType.LOAD_DEMO, payload);
+  }
+
+  public muteTracks(trackIds: number[], muted: boolean): void {
+    const payload: types.MuteTracksPayload = {
+      trackIds,
+      muted
+    };
+    this.sendMessage(types.MessageType.MUTE_TRACKS, payload);
+  }
+
+  public setTracksGain(trackIds: number[], gainDb: number): void {
+    const payload: types.SetTracksGainPayload = {
+      trackIds,
+      gainDb
+    };
+    this.sendMessage(types.MessageType.SET_TRACKS_GAIN, payload);
+  }
+
+  public clearTracks(trackIds: number[]): void {
+    const payload: types.ClearTracksPayload = {
+      trackIds
+    };
+    this.sendMessage(types.MessageType.CLEAR_TRACKS, payload);
+  }
+
+  public clearTrack(trackId: number): void {
+    const payload: types.ClearTrackPayload = {
+      trackId
+    };
+    this.sendMessage(types.MessageType.CLEAR_TRACK, payload);
+  }
+
+  public clearPattern(): void {
+    this.sendMessage(types.MessageType.CLEAR_PATTERN, {});
+  }
+
+  public findSimilarPatterns(): void {
+    this.sendMessage(types.MessageType.FIND_SIMILAR_PATTERNS, {});
+  }
+
+  public setPatternName(patternId: number, name: string): void {
+    const payload: types.SetPatternNamePayload = {
+      patternId,
+      name
+    };
+    this.sendMessage(types.MessageType.SET_PATTERN_NAME, payload);
+  }
+
+  public removeTrack(trackId: number): void {
+    const payload: types.RemoveTrackPayload = {
+      trackId
+    };
+    this.sendMessage(types.MessageType.REMOVE_TRACK, payload);
+  }
+
   public setSwing(swing: number): void {
     const payload: types.SetSwingPayload = {
       swing
@@ -180,6 +474,24 @@ export class WebSocketService {
     this.sendMessage(types.MessageType.SET_SWING, payload);
   }
 
+  public setTrackFxBypass(trackId: number, bypass: types.FxBypass): void {
+    const payload: types.SetTrackFxBypassPayload = {
+      trackId,
+      eq: bypass.eq,
+      filter: bypass.filter,
+      delay: bypass.delay,
+      reverb: bypass.reverb
+    };
+    this.sendMessage(types.MessageType.SET_TRACK_FX_BYPASS, payload);
+  }
+
+  public setSwingGrid(grid: number): void {
+    const payload: types.SetSwingGridPayload = {
+      grid
+    };
+    this.sendMessage(types.MessageType.SET_SWING_GRID, payload);
+  }
+
   private sendMessage(type: types.MessageType, payload: any): void {
     if (this.socket && this.socket.readyState === WebSocket.OPEN) {
       const message: types.WebSocketMessage = {